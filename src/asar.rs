@@ -5,22 +5,148 @@ use std::{
     collections::HashMap,
     fmt,
     io::{self, Cursor, Read, Seek, SeekFrom, Write},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
-use console::style;
-use indicatif::{ProgressBar, ProgressStyle};
-use serde_json::{json, Map, Value};
+use indicatif::ProgressBar;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::ui;
+
+/// The `Phase` enum is which part of an archive [Archive::read_with_progress] is reading
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// The sizes at the start of the archive and the JSON header
+    Header,
+    /// The data of every packed file
+    Files,
+}
+
+/// The `ReadEvent` enum is what [Archive::read_with_progress] reports to a [ProgressSink] while it reads an archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadEvent<'a> {
+    /// A new phase of reading started
+    Phase(Phase),
+    /// The header was parsed. It is `json_bytes` long and lists `files` packed files with `bytes` bytes of data
+    Header { json_bytes: u64, files: u64, bytes: u64 },
+    /// The data of the file at `path` was read
+    File { path: &'a str, bytes: u64 },
+    /// The whole archive was read
+    Done,
+}
+
+/// The `ProgressSink` trait is told how reading an archive is going, like a progress bar or a test that records the events
+pub trait ProgressSink {
+    fn event(&mut self, event: ReadEvent<'_>);
+}
+
+/// Reading without showing progress
+impl ProgressSink for () {
+    fn event(&mut self, _: ReadEvent<'_>) {}
+}
+
+/// A bar turns into a transfer bar once the header says how many bytes of file data there are, and advances after every file
+impl ProgressSink for ProgressBar {
+    fn event(&mut self, event: ReadEvent<'_>) {
+        match event {
+            ReadEvent::Header { bytes, .. } => {
+                self.set_length(bytes);
+                self.set_position(0);
+                self.set_style(ui::style(ui::Kind::Transfer));
+            }
+            ReadEvent::File { bytes, .. } => self.inc(bytes),
+            ReadEvent::Phase(_) | ReadEvent::Done => (),
+        }
+    }
+}
 
 /// The `FileEntry` struct is contained in the [Entry] enum's [File](Entry::File) variant and contains information about a
-/// file's location
-#[derive(Debug)]
+/// file's location. A file is always kept in its [DirEntry] under its own name, so it can't be renamed after it is made
+#[derive(Debug, Clone)]
 pub struct FileEntry {
     /// The name of the file
     name: String,
 
     /// The raw bytes of this file
     data: Cursor<Vec<u8>>,
+
+    /// What the header says about this file besides where its data is
+    meta: Meta,
+
+    /// Where this file's data started after the header in the archive that it was read from, see [FileEntry::offset]
+    offset: Option<u64>,
+}
+
+/// The size of the blocks that [Integrity] hashes, which is what the asar tool uses
+pub const INTEGRITY_BLOCK_SIZE: usize = 4 * 1024 * 1024;
+
+/// The `Meta` struct is what an archive's header records about a file other than where its data is, which is kept when the archive is
+/// packed again
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Meta {
+    /// Wether the file can be run as a program
+    pub executable: bool,
+    /// The size of the file if its data is in the `.asar.unpacked` folder next to the archive instead of in the archive
+    pub unpacked: Option<u64>,
+    /// The hashes that Electron can check the file against, which are computed again for the file's contents when the archive is packed
+    /// unless it is unpacked
+    pub integrity: Option<Integrity>,
+}
+
+/// The `Integrity` struct is the `integrity` object of a file in an archive's header, which has the SHA-256 hash of the whole file and
+/// of every block of it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Integrity {
+    /// The hash algorithm, which is always `SHA256` for archives made by the asar tool
+    pub algorithm: String,
+    /// The hash of the whole file in lowercase hex
+    pub hash: String,
+    /// The size of each block in bytes
+    pub block_size: usize,
+    /// The hash of every block in lowercase hex. The last block is hashed even if it is empty, like the asar tool does
+    pub blocks: Vec<String>,
+}
+
+impl Integrity {
+    /// Hash the contents of a file like the asar tool does
+    pub fn of(data: &[u8]) -> Self {
+        let hex = |data: &[u8]| format!("{:x}", Sha256::digest(data));
+        let full = data.len() / INTEGRITY_BLOCK_SIZE * INTEGRITY_BLOCK_SIZE;
+        Self {
+            algorithm: "SHA256".to_owned(),
+            hash: hex(data),
+            block_size: INTEGRITY_BLOCK_SIZE,
+            blocks: data[..full].chunks(INTEGRITY_BLOCK_SIZE).chain(std::iter::once(&data[full..])).map(hex).collect(),
+        }
+    }
+
+    /// Read the `integrity` object of a file entry, returning `None` if it isn't shaped like the ones that the asar tool writes
+    fn from_json(value: &Value) -> Option<Self> {
+        Some(Self {
+            algorithm: value.get("algorithm")?.as_str()?.to_owned(),
+            hash: value.get("hash")?.as_str()?.to_owned(),
+            block_size: value.get("blockSize")?.as_u64()? as usize,
+            blocks: value.get("blocks")?.as_array()?.iter().map(|block| block.as_str().map(str::to_owned)).collect::<Option<_>>()?,
+        })
+    }
+
+    /// Write the `integrity` object with its keys in the order that the asar tool writes them
+    fn write_json(&self, json: &mut String) {
+        let blocks = self.blocks.iter().map(|block| json_string(block)).collect::<Vec<_>>().join(",");
+        json.push_str(&format!(
+            "{{\"algorithm\":{},\"hash\":{},\"blockSize\":{},\"blocks\":[{}]}}",
+            json_string(&self.algorithm),
+            json_string(&self.hash),
+            self.block_size,
+            blocks
+        ));
+    }
+}
+
+/// Write text as a JSON string, escaped the same way as `JSON.stringify` does it
+fn json_string(text: &str) -> String {
+    Value::from(text).to_string()
 }
 
 impl Write for FileEntry {
@@ -53,6 +179,44 @@ impl Seek for FileEntry {
 }
 
 impl FileEntry {
+    /// Make a file named `name` with the contents `data`. Panics if `name` isn't a single path component, because a file's name is
+    /// also its key in the directory that contains it
+    pub fn new(name: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            name: checked_name(name.into()),
+            data: Cursor::new(data.into()),
+            meta: Meta::default(),
+            offset: None,
+        }
+    }
+
+    /// Get where this file's data started after the header in the archive that it was read from. This is `None` for files that were
+    /// added after the archive was read and for unpacked files, whose data isn't in the archive. Changing the file's contents keeps
+    /// the offset, which is only updated in the archive that is written by packing it
+    #[inline(always)]
+    pub const fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+
+    /// Get what the header says about this file
+    #[inline(always)]
+    pub const fn meta(&self) -> &Meta {
+        &self.meta
+    }
+
+    /// Get a mutable reference to what the header says about this file
+    #[inline(always)]
+    pub fn meta_mut(&mut self) -> &mut Meta {
+        &mut self.meta
+    }
+
+    /// Get the name of this file
+    #[must_use]
+    #[inline(always)]
+    pub const fn name(&self) -> &String {
+        &self.name
+    }
+
     /// Get the size of this file
     #[inline(always)]
     pub fn size(&self) -> usize {
@@ -75,17 +239,104 @@ impl AsRef<[u8]> for FileEntry {
     }
 }
 
+/// Check that `name` can be the name of an entry, which is a single non-empty path component
+fn checked_name(name: String) -> String {
+    assert!(
+        !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\']),
+        "'{}' can't be the name of a file or directory in an archive",
+        name
+    );
+    name
+}
+
+/// Split a path in an archive into the names of the directories and the file in it, skipping empty parts and `.` so that `./app/`,
+/// `app\`, and `app` are the same path. The top of the archive is an empty list, and `None` is returned if a part is `..` or the path
+/// isn't UTF-8
+fn path_parts(path: &Path) -> Option<Vec<&str>> {
+    path.to_str()?
+        .split(['/', '\\'])
+        .filter(|part| !part.is_empty() && *part != ".")
+        .map(|part| (part != "..").then_some(part))
+        .collect()
+}
+
+/// Keep only the entries of `dir` that are under one of the paths in `keep`, each given as its parts below `dir`. Directories that
+/// are only on the way to a kept path are removed if nothing under them is kept
+fn retain_parts(dir: &mut DirEntry, keep: &[&[&str]]) {
+    if keep.iter().any(|parts| parts.is_empty()) {
+        return; //Everything under a kept directory is kept
+    }
+    dir.items.retain(|name, entry| {
+        let under = keep.iter().filter(|parts| parts[0] == name).map(|parts| &parts[1..]).collect::<Vec<_>>();
+        match entry {
+            _ if under.is_empty() => false,
+            Entry::Dir(dir) => {
+                retain_parts(dir, &under);
+                under.iter().any(|parts| parts.is_empty()) || !dir.items.is_empty()
+            }
+            Entry::File(_) => under.iter().any(|parts| parts.is_empty()),
+        }
+    });
+}
+
 /// The `DirEntry` struct is contained in the [Dir](Entry::Dir) variant of the [Entry] enum and contains information like contained
 /// files and directories and name of the dir
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DirEntry {
     /// The name of this directory
     name: String,
-    /// The files or directories that this directory contains
+    /// The files or directories that this directory contains, keyed by their names. Every entry is added with [DirEntry::insert] so
+    /// that a key is always the name of the entry under it
     items: HashMap<String, Entry>,
 }
 
 impl DirEntry {
+    /// Make an empty directory named `name`. Panics if `name` isn't a single path component like [FileEntry::new]
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: checked_name(name.into()),
+            items: HashMap::new(),
+        }
+    }
+
+    /// Add a file or directory to this directory under its own name, returning the entry that had the same name if there was one
+    pub fn insert(&mut self, entry: impl Into<Entry>) -> Option<Entry> {
+        let entry = entry.into();
+        self.items.insert(entry.name().clone(), entry)
+    }
+
+    /// Get the file or directory with the given name in this directory
+    pub fn get(&self, name: &str) -> Option<&Entry> {
+        self.items.get(name)
+    }
+
+    /// Get a mutable reference to the file or directory with the given name in this directory
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Entry> {
+        self.items.get_mut(name)
+    }
+
+    /// Write the `files` object of this directory to the header JSON, and add the files in it whose data is packed to `packed` in the
+    /// order of their names, which is the order that their data follows the header in
+    fn write_files<'a>(&'a self, packed: &mut Vec<&'a FileEntry>, offset: &mut u32, json: &mut String) {
+        let mut items = self.items.values().collect::<Vec<_>>();
+        items.sort_by(|a, b| a.name().cmp(b.name()));
+        json.push_str("{\"files\":{");
+        for (idx, entry) in items.into_iter().enumerate() {
+            if idx != 0 {
+                json.push(',');
+            }
+            json.push_str(&json_string(entry.name()));
+            json.push(':');
+            entry.write(packed, offset, json);
+        }
+        json.push_str("}}");
+    }
+
+    /// Remove the file or directory with the given name from this directory and return it
+    pub fn remove(&mut self, name: &str) -> Option<Entry> {
+        self.items.remove(name)
+    }
+
     /// Get the name of this directory
     #[must_use]
     #[inline(always)]
@@ -116,7 +367,7 @@ impl DirEntry {
 }
 
 /// The `Entry` struct represents one file or directory in an asar archive's header portion
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Entry {
     /// The `Dir` variant contains a list of more entries that the directory contains
     Dir(DirEntry),
@@ -125,18 +376,60 @@ pub enum Entry {
     File(FileEntry),
 }
 
+impl From<FileEntry> for Entry {
+    fn from(file: FileEntry) -> Self {
+        Self::File(file)
+    }
+}
+
+impl From<DirEntry> for Entry {
+    fn from(dir: DirEntry) -> Self {
+        Self::Dir(dir)
+    }
+}
+
 impl Entry {
-    /// Read an entry from JSON, either a directory or a file
+    /// Get the name of this file or directory
+    pub fn name(&self) -> &String {
+        match self {
+            Self::File(file) => &file.name,
+            Self::Dir(dir) => &dir.name,
+        }
+    }
+
+    /// Read an entry from JSON, either a directory or a file, telling `sink` about every file whose data is read. `parent` is the
+    /// path of the directory that the entry is in, which is empty at the top of the archive
     pub fn from_json(
         name: &str,
         obj: &Map<String, Value>,
         file: &mut (impl Read + Seek),
         header_size: u32,
+        parent: &str,
+        sink: &mut dyn ProgressSink,
     ) -> Result<Self, Error> {
+        let path = match parent {
+            "" => name.to_owned(),
+            parent => format!("{}/{}", parent, name),
+        };
         //See if this is a file by checking for the 'size' item
         match obj.get("size") {
             //This is a file
             Some(Value::Number(size)) => {
+                let meta = Meta {
+                    executable: obj.get("executable").and_then(Value::as_bool).unwrap_or(false),
+                    unpacked: obj.get("unpacked").and_then(Value::as_bool).filter(|unpacked| *unpacked).and(size.as_u64()),
+                    integrity: obj.get("integrity").and_then(Integrity::from_json),
+                };
+                //Unpacked files are in a folder next to the archive, so there is no data to read for them
+                if meta.unpacked.is_some() {
+                    return Ok(Self::File(FileEntry {
+                        name: name.to_owned(),
+                        data: Cursor::new(Vec::new()),
+                        meta,
+                        offset: None,
+                    }));
+                }
+
                 let mut data = vec![0u8; size.as_u64().unwrap() as usize]; //Get a vector of bytes to read the file
                 let offset = obj
                     .get("offset")
@@ -153,13 +446,16 @@ impl Entry {
                             name
                         ))
                     })?; //Read the string offset
-                let offset: u64 = offset.parse::<u64>().map_err(|e| Error::InvalidJsonFormat(format!("The 'offset' field is present and is a string in file {}, but could not be parsed as an integer value: {}", name, e)))? + header_size as u64; //Get the offset as a number, I hate JS
-                file.seek(SeekFrom::Start(offset))?; //Seek to the offset of the file's data
+                let offset: u64 = offset.parse::<u64>().map_err(|e| Error::InvalidJsonFormat(format!("The 'offset' field is present and is a string in file {}, but could not be parsed as an integer value: {}", name, e)))?; //Get the offset as a number, I hate JS
+                file.seek(SeekFrom::Start(offset + header_size as u64))?; //Seek to the offset of the file's data
                 file.read_exact(&mut data)?; //Read the file's bytes from the reader
+                sink.event(ReadEvent::File { path: &path, bytes: data.len() as u64 });
 
                 Ok(Self::File(FileEntry {
                     name: name.to_owned(),
                     data: Cursor::new(data),
+                    meta,
+                    offset: Some(offset),
                 }))
             }
             //This is a directory, read all child nodes
@@ -188,7 +484,7 @@ impl Entry {
                                 name
                             ))
                         })?;
-                        match Self::from_json(name, object, file, header_size) {
+                        match Self::from_json(name, object, file, header_size, &path, sink) {
                             Ok(child) => Ok((name.clone(), child)),
                             Err(e) => Err(e),
                         }
@@ -265,39 +561,35 @@ impl Entry {
         }
     }
 
-    /// Write this `Entry`'s metadata to a header JSON structure, and if this `Entry` is a [File](Entry::File), writing the file's data
-    /// to the writer
-    fn write<W: Write + Seek>(
-        &self,
-        ar: &mut W,
-        progress: ProgressBar,
-        offset: &mut u32,
-    ) -> Result<(String, Value), Error> {
+    /// Write this `Entry`'s metadata to the header JSON like the asar tool does, with its keys in the same order and the entries of a
+    /// directory sorted by name, and if this `Entry` is a [File](Entry::File) whose data is packed, add it to `packed`
+    fn write<'a>(&'a self, packed: &mut Vec<&'a FileEntry>, offset: &mut u32, json: &mut String) {
         match self {
-            Self::Dir(dir) => {
-                //Start building a JSON value for this
-                let dir_item = json!({
-                    "files": dir.items
-                    .iter()
-                    .map(|(_, entry)| match entry.write(ar, progress.clone(), offset) {
-                        Ok(val) => Ok(val),
-                        Err(e) => Err(e)
-                    })
-                    .collect::<Result<HashMap<String, Value>, _>>()?,
-                });
-
-                Ok((dir.name.clone(), dir_item))
-            }
+            Self::Dir(dir) => dir.write_files(packed, offset, json),
             Self::File(file) => {
-                let file_item = json!({
-                    "offset": offset.to_string(),
-                    "size": file.size()
-                }); //Make a JSON item for the
-                *offset += file.size() as u32; //Increment the offset by the amount of bytes written to the vec
-                progress.set_message(format!("Archiving file {}", style(&file.name).yellow())); //Set the message
-                ar.write_all(file.as_ref())?; //Write the file data to the buffer
-                progress.inc(1);
-                Ok((file.name.clone(), file_item))
+                json.push_str(&format!("{{\"size\":{}", file.meta.unpacked.unwrap_or(file.size() as u64)));
+                match file.meta.unpacked {
+                    Some(_) => json.push_str(",\"unpacked\":true"),
+                    None => json.push_str(&format!(",\"offset\":\"{}\"", offset)),
+                }
+                //The hashes of unpacked files are of the files next to the archive, which aren't changed
+                let integrity = match file.meta.unpacked {
+                    Some(_) => file.meta.integrity.clone(),
+                    None => file.meta.integrity.as_ref().map(|_| Integrity::of(file.as_ref())),
+                };
+                if let Some(integrity) = integrity {
+                    json.push_str(",\"integrity\":");
+                    integrity.write_json(json);
+                }
+                if file.meta.executable {
+                    json.push_str(",\"executable\":true");
+                }
+                json.push('}');
+
+                if file.meta.unpacked.is_none() {
+                    *offset += file.size() as u32; //The next file's data starts after this one's
+                    packed.push(file);
+                }
             }
         }
     }
@@ -314,22 +606,72 @@ impl Entry {
     }
 }
 
+/// Count the packed files in the `files` object of a directory in the header and the bytes of their data, without reading them
+fn packed_totals(files: &Map<String, Value>) -> (u64, u64) {
+    files.values().filter_map(Value::as_object).fold((0, 0), |(count, bytes), obj| {
+        let unpacked = obj.get("unpacked").and_then(Value::as_bool).unwrap_or(false);
+        match (obj.get("size").and_then(Value::as_u64), obj.get("files").and_then(Value::as_object)) {
+            (Some(_), _) if unpacked => (count, bytes),
+            (Some(size), _) => (count + 1, bytes + size),
+            (None, Some(files)) => {
+                let (dir_count, dir_bytes) = packed_totals(files);
+                (count + dir_count, bytes + dir_bytes)
+            }
+            (None, None) => (count, bytes),
+        }
+    })
+}
+
 /// The `Archive` struct contains all information stored in an asar archive file and methods to both unpack
 /// an archive into the struct and pack a struct into an archive file.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Archive {
-    /// The `data` field contains information like the directory layout and sizes of files
-    data: HashMap<String, Entry>,
+    /// The directory that holds the top level files and directories. It has no name of its own and isn't written to the header
+    root: DirEntry,
+    /// The size of the JSON that the header of the archive that this was read from declared and its real size, if they were
+    /// different, see [Archive::check_header]
+    header_mismatch: Option<(u64, u64)>,
+}
+
+/// Find where the JSON object at the start of `bytes` ends by matching its braces and brackets, skipping the ones in strings.
+/// Returns the length of the object, or `None` if it doesn't end within `bytes`
+fn json_object_len(bytes: &[u8]) -> Option<usize> {
+    if bytes.first() != Some(&b'{') {
+        return None;
+    }
+    let (mut depth, mut in_string, mut escaped) = (0usize, false, false);
+    for (i, &byte) in bytes.iter().enumerate() {
+        match byte {
+            _ if escaped => escaped = false,
+            b'\\' if in_string => escaped = true,
+            b'"' => in_string = !in_string,
+            _ if in_string => (),
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
 }
 
 impl Archive {
     /// Open an asar file from the given path and return an `Archive` that contains it as backing storage. Returns errors if any occurred when
     /// parsing the archive or opening the file
     pub fn read<R: Read + Seek>(asar: &mut R) -> Result<Self, Error> {
-        //let mut asar = std::fs::OpenOptions::new().read(true).open(path)?; //Open the file from the given path
-        Ok(Self {
-            data: Self::read_headers(asar)?,
-        })
+        Self::read_with_progress(asar, &mut ())
+    }
+
+    /// Read an archive like [Archive::read], telling `sink` when the header and the files' data start being read, how much data
+    /// the header lists, and every file that was read
+    pub fn read_with_progress<R: Read + Seek>(asar: &mut R, sink: &mut dyn ProgressSink) -> Result<Self, Error> {
+        let archive = Self::read_headers(asar, sink)?;
+        sink.event(ReadEvent::Done);
+        Ok(archive)
     }
 
     /// Read two u32s from the beginning 16 bytes, returning the (json size, header size)
@@ -352,15 +694,63 @@ impl Archive {
         Ok((json_size, header_size + 8)) //Get a u32 from the data
     }
 
-    /// Read headers from a file and return a hashmap of directories and file data
-    fn read_headers<R: Read + Seek>(file: &mut R) -> Result<HashMap<String, Entry>, Error> {
+    /// Check that the first 16 bytes of a file are the sizes that [Archive::pack] writes and that the header fits in the file, so that an
+    /// empty, truncated, or unrelated file is reported as one instead of being read as garbage
+    fn check_sizes(read: &mut (impl Read + Seek)) -> Result<(), Error> {
+        let len = read.seek(SeekFrom::End(0))?;
+        if len < 16 {
+            return Err(Error::InvalidHeader(format!("the file is {} bytes long, which is too short for a header", len)));
+        }
+        read.seek(SeekFrom::Start(0))?;
+        let mut buf = [0u8; 16];
+        read.read_exact(&mut buf)?;
+        let field = |i: usize| u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]) as u64;
+
+        //The header is a pickle: the size of the size field, the header's size, the size of the JSON string, and its length
+        if field(0) != 4 || field(8) + 4 != field(4) {
+            return Err(Error::InvalidHeader("the size fields don't match, so this is not an asar archive".to_owned()));
+        }
+        if field(12) + 8 > field(4) || field(4) + 8 > len {
+            return Err(Error::InvalidHeader(format!("the header is {} bytes but the file is only {} bytes long", field(4) + 8, len)));
+        }
+        Ok(())
+    }
+
+    /// Parse the JSON at the start of the header's `bytes`, which should be `json_size` bytes long. Some packers declare the wrong
+    /// size, so if the declared JSON doesn't parse, the real end of the JSON is found within the header by matching its braces.
+    /// Returns the JSON and its real size
+    fn parse_header(bytes: &[u8], json_size: usize) -> Result<(Value, usize), Error> {
+        let err = match serde_json::from_slice(&bytes[..json_size]) {
+            Ok(header) => return Ok((header, json_size)),
+            Err(err) => err,
+        };
+        match json_object_len(bytes) {
+            Some(len) if len != json_size => match serde_json::from_slice(&bytes[..len]) {
+                Ok(header) => Ok((header, len)),
+                Err(_) => Err(err.into()),
+            },
+            Some(_) => Err(err.into()),
+            None => Err(Error::InvalidHeader(format!(
+                "the header declares {} bytes of JSON that can't be read ({}), and no JSON object ends within the header's {} bytes",
+                json_size,
+                err,
+                bytes.len()
+            ))),
+        }
+    }
+
+    /// Read headers from a file and return an archive with the directories and file data in them
+    fn read_headers<R: Read + Seek>(file: &mut R, sink: &mut dyn ProgressSink) -> Result<Self, Error> {
+        sink.event(ReadEvent::Phase(Phase::Header));
+        Self::check_sizes(file)?;
         let (json_size, header_size) = Self::read_sizes(file)?; //Read the header and json size from the file
 
         file.seek(SeekFrom::Start(16))?; //Skip the rest of the header (why is it 16 bytes?)
-        let mut bytes = vec![0u8; json_size as usize]; //Make a vector for reading the json bytes
-        file.read_exact(&mut bytes)?; //Read the json into the vector of bytes
+        let mut bytes = vec![0u8; header_size as usize - 16]; //The JSON can't go past the end of the header, whatever its size says
+        file.read_exact(&mut bytes)?;
 
-        let header: Value = serde_json::from_slice(bytes.as_ref())?; //Parse the header as JSON
+        let (header, real_size) = Self::parse_header(&bytes, json_size as usize)?;
+        let mismatch = (real_size != json_size as usize).then_some((json_size as u64, real_size as u64));
         let header = header
             .get("files")
             .ok_or_else(|| {
@@ -375,6 +765,10 @@ impl Archive {
                         .to_owned(),
                 )
             })?;
+        let (files, bytes) = packed_totals(header);
+        sink.event(ReadEvent::Header { json_bytes: real_size as u64, files, bytes });
+
+        sink.event(ReadEvent::Phase(Phase::Files));
         let mut data = HashMap::new(); //Make a new hashmap for the JSON data
         for (name, val) in header {
             data.insert(
@@ -389,48 +783,41 @@ impl Archive {
                     })?,
                     file,
                     header_size,
+                    "",
+                    sink,
                 )?,
             );
         }
-        Ok(data)
+        Ok(Self {
+            root: DirEntry {
+                name: String::new(),
+                items: data,
+            },
+            header_mismatch: mismatch,
+        })
+    }
+
+    /// Check that the header of the archive that this was read from declared the size of its JSON correctly. An archive with the
+    /// wrong size is still read, but this returns [Error::HeaderSizeMismatch] so that applying a theme can warn about it and `verify` can report it
+    pub fn check_header(&self) -> Result<(), Error> {
+        match self.header_mismatch {
+            Some((declared, actual)) => Err(Error::HeaderSizeMismatch { declared, actual }),
+            None => Ok(()),
+        }
     }
 
     /// Get an entry from the given path, used in [get_file] and [get_dir] functions
     fn get_entry(&self, path: impl AsRef<Path>) -> Option<&Entry> {
-        let path = path.as_ref();
-        match path.parent() {
-            Some(dir) if !dir.as_os_str().is_empty() => {
-                let mut entry = self
-                    .data
-                    .get(dir.components().next()?.as_os_str().to_str().unwrap())?; //Get the directory at the first path
-                                                                                   //Get all the rest of the directories
-                for part in dir.components().skip(1) {
-                    entry = entry.get_entry(part.as_os_str().to_str().unwrap())?;
-                    //Get the directory
-                }
-                entry.get_entry(path.file_name().unwrap().to_str().unwrap())
-            }
-            None | Some(_) => self.data.get(path.to_str().unwrap()),
-        }
+        let parts = path_parts(path.as_ref())?;
+        let (first, rest) = parts.split_first()?;
+        rest.iter().try_fold(self.root.items.get(*first)?, |entry, part| entry.get_entry(part))
     }
 
     /// Get a mutable reference to the given entry
     fn get_entry_mut(&mut self, path: impl AsRef<Path>) -> Option<&mut Entry> {
-        let path = path.as_ref();
-        match path.parent() {
-            Some(dir) if !dir.as_os_str().is_empty() => {
-                let mut entry = self
-                    .data
-                    .get_mut(dir.components().next()?.as_os_str().to_str().unwrap())?; //Get the directory at the first path
-                                                                                       //Get all the rest of the directories
-                for part in dir.components().skip(1) {
-                    entry = entry.get_entry_mut(part.as_os_str().to_str().unwrap())?;
-                    //Get the directory
-                }
-                entry.get_entry_mut(path.file_name().unwrap().to_str().unwrap())
-            }
-            None | Some(_) => self.data.get_mut(path.to_str().unwrap()),
-        }
+        let parts = path_parts(path.as_ref())?;
+        let (first, rest) = parts.split_first()?;
+        rest.iter().try_fold(self.root.items.get_mut(*first)?, |entry, part| entry.get_entry_mut(part))
     }
 
     /// Get a [file](FileEntry) using an absolute path
@@ -475,29 +862,39 @@ impl Archive {
     }
 
     /// Pack this archive's contents into any type implementing `Write` and `Seek`
-    /// This will display progress of packing files, then progress of writing the file
+    /// This will display the progress of writing the archive if `progressbar` is set
     pub fn pack<W: Write + Seek>(&self, ar: &mut W, progressbar: bool) -> Result<(), Error> {
-        let mut json = json!({"files": {}}); //Create a new JSON for the header data
-        let mut buffer: Cursor<Vec<u8>> = Cursor::new(Vec::new()); //Create a vector to hold the temporarily saved file data
-
-        let num_files: u32 = self.data.iter().map(|(_, e)| e.count()).sum(); //Get the total number of files in the archive
-
         let progress = match progressbar {
-            true => ProgressBar::new(num_files as u64).with_style(
-                ProgressStyle::default_bar()
-                    .template("[{bar}] {pos}/{len} files - {per_sec}: {msg}")
-                    .progress_chars("=>."),
-            ),
+            true => ui::bar(ui::Kind::Transfer, self.packed_size_estimate()),
             false => ProgressBar::hidden(),
         };
+        self.pack_with_progress(ar, &progress)?;
+        progress.finish_with_message("Re-packed archive!");
+        Ok(())
+    }
 
-        let mut offset = 0;
-        for (_, entry) in self.data.iter() {
-            let (name, saved) = entry.write(&mut buffer, progress.clone(), &mut offset)?;
-            json["files"][name] = saved; //Write the header JSON
-        }
+    /// Get about how many bytes [Archive::pack] writes, which is exact for the files' data but estimates the size of the header so
+    /// that the hashes in it don't have to be made first
+    pub fn packed_size_estimate(&self) -> u64 {
+        self.files()
+            .into_iter()
+            .map(|(_, file)| {
+                let integrity = file.meta.integrity.as_ref().map(|_| 160 + 67 * (file.size() / INTEGRITY_BLOCK_SIZE + 1)).unwrap_or(0);
+                let data = file.meta.unpacked.map(|_| 0).unwrap_or(file.size());
+                (data + file.name.len() + 40 + integrity) as u64
+            })
+            .sum::<u64>()
+            + 16
+    }
+
+    /// Pack this archive's contents like [Archive::pack], counting the bytes that are written on `progress`. The bar's length is set
+    /// to the exact size of the archive once the header is made, and it advances after every file's data is written
+    pub fn pack_with_progress<W: Write + Seek>(&self, ar: &mut W, progress: &ProgressBar) -> Result<(), Error> {
+        let mut json = String::new(); //Create a new JSON for the header data
+        let (mut packed, mut offset) = (Vec::new(), 0);
+        self.root.write_files(&mut packed, &mut offset, &mut json);
 
-        let mut header = serde_json::to_vec(&json)?; //Save the JSON header as a vector of bytes
+        let mut header = json.into_bytes(); //Save the JSON header as a vector of bytes
         let json_size = header.len(); //Get the size of the JSON
         let header_size = header.len() + (4 - (header.len() % 4)) % 4; //Get the size of the JSON header and round it up to 4
         header.resize(header_size + 16, 0); //Resize the header to fit the size bytes
@@ -507,69 +904,216 @@ impl Archive {
         header[4..8].copy_from_slice(&u32::to_le_bytes((header_size + 8) as u32));
         header[8..12].copy_from_slice(&u32::to_le_bytes((header_size + 4) as u32));
         header[12..16].copy_from_slice(&u32::to_le_bytes(json_size as u32));
-        progress.finish_with_message("Re-packed archive!");
 
+        progress.set_length(header.len() as u64 + offset as u64);
         ar.write_all(header.as_ref())?; //Write the header bytes to the file
-        ar.write_all(buffer.into_inner().as_ref())?; //Write the buffer bytes to the file
+        progress.inc(header.len() as u64);
+        for file in packed {
+            ar.write_all(file.as_ref())?; //The data of every packed file follows the header in the order of the entries
+            progress.inc(file.size() as u64);
+        }
 
         Ok(())
     }
 
-    /// Add a file or directory to the archive at the specified path
-    fn add_entry<P: AsRef<Path>>(&mut self, path: P, item: Entry) -> Option<()> {
+    /// Add a file or directory to the directory at `dir`, or to the top of the archive if `dir` is empty. Returns `None` if there is no
+    /// directory at `dir`
+    pub fn insert<P: AsRef<Path>>(&mut self, dir: P, entry: impl Into<Entry>) -> Option<()> {
+        let dir = dir.as_ref();
+        match dir.as_os_str().is_empty() {
+            true => self.root.insert(entry),
+            false => self.get_dir_mut(dir)?.insert(entry),
+        };
+        Some(())
+    }
+
+    /// Add a file or directory to the archive at the specified path, named after the last part of the path
+    fn add_entry<P: AsRef<Path>>(&mut self, path: P, make: impl FnOnce(&str) -> Entry) -> Option<()> {
         let path = path.as_ref();
-        match path.parent() {
-            Some(s) if !s.as_os_str().is_empty() => {
-                let dir = self.get_dir_mut(s)?;
-                dir.items
-                    .insert(path.file_name()?.to_str()?.to_string(), item);
-                Some(())
-            }
-            _ => {
-                self.data
-                    .insert(path.file_name()?.to_str()?.to_string(), item);
-                Some(())
-            }
-        }
+        let name = path.file_name()?.to_str()?;
+        self.insert(path.parent().unwrap_or_else(|| Path::new("")), make(name))
     }
 
-    /// Add a file at the specified location
+    /// Add an empty file at the specified location
     pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> bool {
-        let path = path.as_ref();
-        self.add_entry(
-            path,
-            Entry::File(FileEntry {
-                name: path.file_name().unwrap().to_str().unwrap().to_owned(),
-                data: Cursor::new(Vec::new()),
-            }),
-        )
-        .is_some()
+        self.add_entry(path, |name| FileEntry::new(name, Vec::new()).into()).is_some()
     }
 
     /// Add a directory at the specified location
     pub fn add_dir<P: AsRef<Path>>(&mut self, path: P) -> bool {
-        let path = path.as_ref();
-        self.add_entry(
-            path,
-            Entry::Dir(DirEntry {
-                name: path.file_name().unwrap().to_str().unwrap().to_owned(),
-                items: HashMap::new(),
-            }),
-        )
-        .is_some()
+        self.add_entry(path, |name| DirEntry::new(name).into()).is_some()
     }
 
     /// Return a new `Archive` with no entries
     pub fn new() -> Self {
         Self {
-            data: HashMap::new(),
+            root: DirEntry {
+                name: String::new(),
+                items: HashMap::new(),
+            },
+            header_mismatch: None,
+        }
+    }
+
+    /// Copy the directory at `path` into a new archive whose top level is that directory's files and directories, so that `app` of
+    /// Discord's archive becomes an archive with `mainScreen.js` at its top. An empty path copies the whole archive
+    pub fn subtree<P: AsRef<Path>>(&self, path: P) -> Result<Archive, Error> {
+        let parts = path_parts(path.as_ref()).ok_or(Error::NoFile)?;
+        match parts.is_empty() {
+            true => Ok(self.clone()),
+            false => match self.get_entry(parts.join("/")).ok_or(Error::NoFile)? {
+                Entry::Dir(dir) => Ok(Self::rooted(dir.clone())),
+                Entry::File(_) => Err(Error::NotDir(parts.join("/"))),
+            },
+        }
+    }
+
+    /// Move the directory at `path` out of this archive into a new archive like [Archive::subtree], without copying the data of the
+    /// files in it
+    pub fn into_subtree<P: AsRef<Path>>(mut self, path: P) -> Result<Archive, Error> {
+        let parts = path_parts(path.as_ref()).ok_or(Error::NoFile)?;
+        let (name, parents) = match parts.split_last() {
+            Some(split) => split,
+            None => return Ok(self),
+        };
+        let parent = match parents.is_empty() {
+            true => Some(&mut self.root),
+            false => self.get_dir_mut(parents.join("/")),
+        };
+        match parent.ok_or(Error::NoFile)?.remove(name) {
+            Some(Entry::Dir(dir)) => Ok(Self::rooted(dir)),
+            Some(Entry::File(_)) => Err(Error::NotDir(parts.join("/"))),
+            None => Err(Error::NoFile),
+        }
+    }
+
+    /// Remove every file and directory that isn't under one of the paths in `keep`, comparing whole names so that keeping `app`
+    /// doesn't keep `app2`. The directories that a kept path is in are kept too, and paths that aren't in the archive keep nothing
+    pub fn retain_paths(&mut self, keep: &[&str]) {
+        let keep = keep.iter().filter_map(|path| path_parts(Path::new(path))).collect::<Vec<_>>();
+        retain_parts(&mut self.root, &keep.iter().map(Vec::as_slice).collect::<Vec<_>>());
+    }
+
+    /// Make an archive whose top level is the files and directories of `dir`
+    fn rooted(dir: DirEntry) -> Self {
+        Self {
+            root: DirEntry {
+                name: String::new(),
+                items: dir.items,
+            },
+            header_mismatch: None,
+        }
+    }
+
+    /// Get the directory that holds the top level files and directories of the archive. Its name is empty
+    pub fn root(&self) -> &DirEntry {
+        &self.root
+    }
+
+    /// Get a mutable reference to the directory that holds the top level files and directories of the archive
+    pub fn root_mut(&mut self) -> &mut DirEntry {
+        &mut self.root
+    }
+}
+
+/// The `Change` enum describes how one file differs between two archives, returned from [Archive::diff]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// The file only exists in the other archive
+    Added {
+        /// The size of the file in the other archive
+        size: usize,
+    },
+
+    /// The file only exists in this archive
+    Removed {
+        /// The size of the file in this archive
+        size: usize,
+    },
+
+    /// The file exists in both archives but its contents are different
+    Modified {
+        /// The size of the file in this archive
+        old_size: usize,
+        /// The size of the file in the other archive
+        new_size: usize,
+    },
+}
+
+impl Change {
+    /// Get how many bytes larger the file is in the other archive than in this one
+    pub fn size_delta(&self) -> i64 {
+        match *self {
+            Self::Added { size } => size as i64,
+            Self::Removed { size } => -(size as i64),
+            Self::Modified { old_size, new_size } => new_size as i64 - old_size as i64,
+        }
+    }
+}
+
+impl Archive {
+    /// Get every file in this archive along with its full path like `app/mainScreen.js`, sorted by path
+    pub fn files(&self) -> Vec<(String, &FileEntry)> {
+        /// Push every file in an entry to the list with the given path prefix
+        fn walk<'a>(prefix: &str, entry: &'a Entry, files: &mut Vec<(String, &'a FileEntry)>) {
+            match entry {
+                Entry::File(file) => files.push((format!("{}{}", prefix, file.name), file)),
+                Entry::Dir(dir) => dir
+                    .items
+                    .values()
+                    .for_each(|item| walk(&format!("{}{}/", prefix, dir.name), item, files)),
+            }
         }
+
+        let mut files = Vec::new();
+        self.root.items.values().for_each(|entry| walk("", entry, &mut files));
+        files.sort_by(|(a, _), (b, _)| a.cmp(b));
+        files
+    }
+
+    /// Get every file in this archive along with its full path, sorted by where its data is in the archive that it was read from
+    /// instead of by path. Files without an offset, which were inserted after the archive was read or are unpacked, sort last by path
+    pub fn entries_by_offset(&self) -> Vec<(PathBuf, &FileEntry)> {
+        let mut files = self.files();
+        files.sort_by_key(|(_, file)| (file.offset.is_none(), file.offset)); //The sort is stable, so ties stay sorted by path
+        files.into_iter().map(|(path, file)| (PathBuf::from(path), file)).collect()
+    }
+
+    /// Compare this archive to another one, returning every file that was added, removed, or modified in `other` sorted by path
+    pub fn diff(&self, other: &Self) -> Vec<(String, Change)> {
+        let (mine, theirs) = (
+            self.files().into_iter().collect::<HashMap<_, _>>(),
+            other.files().into_iter().collect::<HashMap<_, _>>(),
+        );
+
+        let mut changes = mine
+            .iter()
+            .filter_map(|(path, file)| match theirs.get(path) {
+                Some(other) if other.as_ref() == file.as_ref() => None,
+                Some(other) => Some((
+                    path.clone(),
+                    Change::Modified {
+                        old_size: file.size(),
+                        new_size: other.size(),
+                    },
+                )),
+                None => Some((path.clone(), Change::Removed { size: file.size() })),
+            })
+            .chain(
+                theirs
+                    .iter()
+                    .filter(|(path, _)| !mine.contains_key(*path))
+                    .map(|(path, file)| (path.clone(), Change::Added { size: file.size() })),
+            )
+            .collect::<Vec<_>>();
+        changes.sort_by(|(a, _), (b, _)| a.cmp(b));
+        changes
     }
 }
 
 impl fmt::Display for Archive {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for (_, entry) in self.data.iter() {
+        for (_, entry) in self.root.items.iter() {
             entry.display(0, f)?;
             writeln!(f)?;
         }
@@ -594,6 +1138,18 @@ pub enum Error {
 
     /// The file at the requested asar archive path doesn't exist
     NoFile,
+
+    /// The entry at the given path is a file where a directory was expected
+    NotDir(String),
+
+    /// A file in a written archive isn't as big as the contents that were written to it
+    Truncated { path: String, expected: usize, found: usize },
+
+    /// The file is empty, truncated, or not an asar archive at all
+    InvalidHeader(String),
+
+    /// The header declares a different size for its JSON than the JSON really has, given as both sizes in bytes
+    HeaderSizeMismatch { declared: u64, actual: u64 },
 }
 
 impl From<serde_json::Error> for Error {
@@ -622,6 +1178,14 @@ impl fmt::Display for Error {
             Self::InvalidJsonFormat(err) => write!(f, "Invalid header JSON format: {}", err),
             Self::InvalidUTF8 => write!(f, "Invalid UTF-8"),
             Self::NoFile => write!(f, "The specified file or directory does not exist"),
+            Self::NotDir(path) => write!(f, "{} is a file, not a directory", path),
+            Self::Truncated { path, expected, found } => {
+                write!(f, "{} in the written archive is {} bytes instead of {} bytes", path, found, expected)
+            }
+            Self::InvalidHeader(err) => write!(f, "Invalid archive header: {}", err),
+            Self::HeaderSizeMismatch { declared, actual } => {
+                write!(f, "The archive header declares {} bytes of JSON but the JSON is {} bytes long", declared, actual)
+            }
         }
     }
 }
@@ -630,8 +1194,8 @@ impl std::error::Error for Error {}
 
 #[cfg(test)]
 mod tests {
-    use super::Archive;
-    use std::io::Write;
+    use super::{json_object_len, Archive, Change, DirEntry, Error, FileEntry, Phase, ProgressSink, ReadEvent};
+    use std::io::{Cursor, Write};
 
     #[test]
     pub fn loading() {
@@ -646,7 +1210,256 @@ mod tests {
         //panic!();
         //std::fs::write("out.png", &asar.get_file("Banner.png").unwrap()).unwrap();
 
-        let mut writer = std::fs::File::create("write.asar").unwrap();
+        let mut writer = std::io::Cursor::new(Vec::new());
         archive.pack(&mut writer, false).unwrap();
+        writer.set_position(0);
+        let read = Archive::read(&mut writer).unwrap();
+        assert_eq!(read.get_file("test/test.txt").unwrap().size(), "Testing!".len());
+    }
+
+    #[test]
+    pub fn build_by_hand() {
+        let mut app = DirEntry::new("app");
+        app.insert(FileEntry::new("index.js", "require('./main');"));
+        let mut nested = DirEntry::new("lib");
+        nested.insert(FileEntry::new("main.js", b"module.exports = 1;".to_vec()));
+        app.insert(nested);
+
+        let mut archive = Archive::new();
+        archive.root_mut().insert(app);
+        archive.root_mut().insert(FileEntry::new("package.json", "{}"));
+        assert!(archive.insert("app/lib", FileEntry::new("extra.js", "")).is_some());
+        assert!(archive.insert("missing", FileEntry::new("extra.js", "")).is_none());
+        //Inserting an entry with a name that is already taken replaces it
+        assert!(archive.root_mut().insert(FileEntry::new("package.json", "{\"main\": \"app\"}")).is_some());
+
+        let mut packed = Cursor::new(Vec::new());
+        archive.pack(&mut packed, false).unwrap();
+        packed.set_position(0);
+        let read = Archive::read(&mut packed).unwrap();
+        let files = read.files().into_iter().map(|(path, file)| (path, file.as_ref().to_vec())).collect::<Vec<_>>();
+        assert_eq!(files, [
+            ("app/index.js".to_owned(), b"require('./main');".to_vec()),
+            ("app/lib/extra.js".to_owned(), Vec::new()),
+            ("app/lib/main.js".to_owned(), b"module.exports = 1;".to_vec()),
+            ("package.json".to_owned(), b"{\"main\": \"app\"}".to_vec()),
+        ]);
+        //Every entry is kept under its own name
+        assert!(read.root().entries().all(|entry| read.root().get(entry.name()).is_some()));
+        assert_eq!(read.root().name(), "");
+        assert_eq!(read.get_dir("app/lib").map(DirEntry::name).map(String::as_str), Some("lib"));
+    }
+
+    /// Make an archive with `app` and `app2` directories and a file at the top to carve up
+    fn carved() -> Archive {
+        let mut archive = Archive::new();
+        for dir in ["app", "app/lib", "app2"] {
+            archive.add_dir(dir);
+        }
+        archive.insert("app", FileEntry::new("index.js", "require('./lib/main');")).unwrap();
+        archive.insert("app/lib", FileEntry::new("main.js", "module.exports = 1;")).unwrap();
+        archive.insert("app2", FileEntry::new("other.js", "")).unwrap();
+        archive.insert("", FileEntry::new("package.json", "{}")).unwrap();
+        archive
+    }
+
+    /// Pack an archive and read it back, returning the paths of its files
+    fn repacked(archive: &Archive) -> Vec<String> {
+        let mut packed = Cursor::new(Vec::new());
+        archive.pack(&mut packed, false).unwrap();
+        packed.set_position(0);
+        Archive::read(&mut packed).unwrap().files().into_iter().map(|(path, _)| path).collect()
+    }
+
+    #[test]
+    pub fn subtree() {
+        let archive = carved();
+        let app = archive.subtree("./app/").unwrap();
+        assert_eq!(repacked(&app), ["index.js", "lib/main.js"]);
+        assert_eq!(app.get_file("lib/main.js").map(|file| file.as_ref().to_vec()), Some(b"module.exports = 1;".to_vec()));
+        assert_eq!(app.root().name(), "");
+        //The archive that was copied from is unchanged
+        assert_eq!(repacked(&archive), ["app/index.js", "app/lib/main.js", "app2/other.js", "package.json"]);
+        assert_eq!(repacked(&archive.subtree("").unwrap()), repacked(&archive));
+
+        assert!(matches!(archive.subtree("package.json"), Err(Error::NotDir(path)) if path == "package.json"));
+        assert!(matches!(archive.subtree("missing"), Err(Error::NoFile)));
+        assert!(matches!(archive.subtree("app/../app2"), Err(Error::NoFile)));
+
+        let lib = carved().into_subtree("app\\lib").unwrap();
+        assert_eq!(repacked(&lib), ["main.js"]);
+        assert!(matches!(carved().into_subtree("app/index.js"), Err(Error::NotDir(_))));
+        assert!(matches!(carved().into_subtree("app/missing"), Err(Error::NoFile)));
+    }
+
+    #[test]
+    pub fn retain() {
+        let mut archive = carved();
+        archive.retain_paths(&["app/lib/", "package.json", "app2/missing.js"]);
+        //Whole names are compared, and a directory that nothing is kept in is removed
+        assert_eq!(repacked(&archive), ["app/lib/main.js", "package.json"]);
+        assert!(archive.get_dir("app2").is_none() && archive.get_file("app/index.js").is_none());
+
+        let mut archive = carved();
+        archive.retain_paths(&["app"]);
+        assert_eq!(repacked(&archive), ["app/index.js", "app/lib/main.js"]);
+        archive.retain_paths(&[]);
+        assert_eq!(repacked(&archive), Vec::<String>::new());
+        let mut archive = carved();
+        archive.retain_paths(&[""]);
+        assert_eq!(repacked(&archive).len(), 4);
+    }
+
+    /// A sink that records every event, with the path of every file that was read
+    #[derive(Default)]
+    struct Recorder(Vec<ReadEvent<'static>>, Vec<(String, u64)>);
+
+    impl ProgressSink for Recorder {
+        fn event(&mut self, event: ReadEvent<'_>) {
+            match event {
+                ReadEvent::File { path, bytes } => {
+                    self.1.push((path.to_owned(), bytes));
+                    self.0.push(ReadEvent::File { path: "", bytes });
+                }
+                ReadEvent::Phase(phase) => self.0.push(ReadEvent::Phase(phase)),
+                ReadEvent::Header { json_bytes, files, bytes } => self.0.push(ReadEvent::Header { json_bytes, files, bytes }),
+                ReadEvent::Done => self.0.push(ReadEvent::Done),
+            }
+        }
+    }
+
+    #[test]
+    pub fn read_progress() {
+        let mut archive = Archive::new();
+        archive.add_dir("app");
+        archive.insert("app", FileEntry::new("index.js", "require('./main');")).unwrap();
+        archive.insert("", FileEntry::new("package.json", "{}")).unwrap();
+        let mut unpacked = FileEntry::new("native.node", "");
+        unpacked.meta_mut().unpacked = Some(4096);
+        archive.insert("app", unpacked).unwrap();
+        let mut packed = Cursor::new(Vec::new());
+        archive.pack(&mut packed, false).unwrap();
+
+        let mut recorder = Recorder::default();
+        Archive::read_with_progress(&mut Cursor::new(packed.get_ref()), &mut recorder).unwrap();
+        let json_bytes = {
+            let header = packed.get_ref();
+            u32::from_le_bytes([header[12], header[13], header[14], header[15]]) as u64
+        };
+        //Unpacked files have no data in the archive, so they are neither counted nor read
+        assert_eq!(recorder.0[..3], [ReadEvent::Phase(Phase::Header), ReadEvent::Header { json_bytes, files: 2, bytes: 20 }, ReadEvent::Phase(Phase::Files)]);
+        assert_eq!(recorder.0.last(), Some(&ReadEvent::Done));
+        assert_eq!(recorder.0.len(), 6);
+        recorder.1.sort();
+        assert_eq!(recorder.1, [("app/index.js".to_owned(), 18), ("package.json".to_owned(), 2)]);
+
+        //A broken header stops before any file is read
+        let mut recorder = Recorder::default();
+        assert!(Archive::read_with_progress(&mut Cursor::new(&packed.get_ref()[..20]), &mut recorder).is_err());
+        assert_eq!(recorder.0, [ReadEvent::Phase(Phase::Header)]);
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn path_as_name() {
+        FileEntry::new("app/index.js", "");
+    }
+
+    #[test]
+    pub fn broken_headers() {
+        let mut archive = Archive::new();
+        archive.add_dir("app");
+        archive.add_file("app/index.js");
+        let mut packed = Cursor::new(Vec::new());
+        archive.pack(&mut packed, false).unwrap();
+        let packed = packed.into_inner();
+
+        //An interrupted update leaves an empty or cut off archive, and other files don't start with the pickle sizes
+        let mut not_asar = packed.clone();
+        not_asar[0] = 5;
+        for broken in [Vec::new(), packed[..10].to_vec(), packed[..20].to_vec(), not_asar, b"PK\x03\x04 a zip file".to_vec()] {
+            assert!(matches!(Archive::read(&mut Cursor::new(&broken)), Err(Error::InvalidHeader(_))), "{:?}", broken);
+        }
+        assert!(Archive::read(&mut Cursor::new(&packed)).is_ok());
+    }
+
+    #[test]
+    pub fn header_size_mismatch() {
+        let basic = Archive::read(&mut Cursor::new(include_bytes!("../tests/fixtures/asar/basic.asar"))).unwrap();
+        assert!(basic.check_header().is_ok());
+        let fixtures: [&[u8]; 2] = [
+            include_bytes!("../tests/fixtures/asar/short-json-size.asar"),
+            include_bytes!("../tests/fixtures/asar/long-json-size.asar"),
+        ];
+        for (fixture, declared) in fixtures.iter().zip([987, 1051]) {
+            let archive = Archive::read(&mut Cursor::new(*fixture)).unwrap();
+            assert!(archive.diff(&basic).is_empty() && archive.files().len() == 4);
+            assert!(matches!(archive.check_header(), Err(Error::HeaderSizeMismatch { declared: d, actual: 1027 }) if d == declared));
+
+            //Packing it again writes the right size
+            let mut packed = Cursor::new(Vec::new());
+            archive.pack(&mut packed, false).unwrap();
+            packed.set_position(0);
+            assert!(Archive::read(&mut packed).unwrap().check_header().is_ok());
+        }
+
+        //Without a whole JSON object in the header, both sizes are reported
+        let mut broken = fixtures[0].to_vec();
+        let end = 16 + 1027 - 1;
+        assert_eq!(broken[end], b'}');
+        broken[end] = b' ';
+        match Archive::read(&mut Cursor::new(&broken)) {
+            Err(Error::InvalidHeader(err)) => assert!(err.contains("987 bytes") && err.contains("1028 bytes"), "{}", err),
+            other => panic!("Expected an invalid header, got {:?}", other),
+        }
+
+        assert_eq!(json_object_len(br#"{"a":{"b":"}\"{"},"c":[1]}   "#), Some(26));
+        assert_eq!(json_object_len(br#"{"a":"}"#), None);
+        assert_eq!(json_object_len(b"  {}"), None);
+    }
+
+    #[test]
+    pub fn diff() {
+        let build = |files: &[(&str, &[u8])]| {
+            let mut archive = Archive::new();
+            archive.add_dir("app");
+            for (path, contents) in files {
+                archive.add_file(path);
+                archive.get_file_mut(path).unwrap().write_all(contents).unwrap();
+            }
+            archive
+        };
+        let old = build(&[
+            ("app/mainScreen.js", b"original"),
+            ("app/same.js", b"same"),
+            ("app/removed.js", b"gone"),
+        ]);
+        let new = build(&[
+            ("app/mainScreen.js", b"patched!!"),
+            ("app/same.js", b"same"),
+            ("app/added.js", b"new"),
+        ]);
+
+        assert_eq!(
+            old.files().iter().map(|(path, _)| path.as_str()).collect::<Vec<_>>(),
+            vec!["app/mainScreen.js", "app/removed.js", "app/same.js"]
+        );
+        let changes = old.diff(&new);
+        assert_eq!(
+            changes,
+            vec![
+                ("app/added.js".to_owned(), Change::Added { size: 3 }),
+                (
+                    "app/mainScreen.js".to_owned(),
+                    Change::Modified {
+                        old_size: 8,
+                        new_size: 9
+                    }
+                ),
+                ("app/removed.js".to_owned(), Change::Removed { size: 4 }),
+            ]
+        );
+        assert_eq!(changes[1].1.size_delta(), 1);
+        assert!(old.diff(&old).is_empty());
     }
 }
@@ -0,0 +1,937 @@
+//! The `backup` module knows where Discord's `core.asar` backup files are stored and how to read them, including
+//! backups that were compressed with gzip. Backups live outside of Discord's installation in a directory laid out
+//! like `<backup dir>/<flavor>/<version folder>/core.asar.backup`, because Discord's updater deletes old version folders
+
+use std::{
+    env, fmt, fs,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use indicatif::ProgressBar;
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+use crate::{asar, manifest::{self, MANIFEST_NAME}, messages::msg, userdata};
+
+/// The file name of an uncompressed backup of `core.asar`
+pub const BACKUP_NAME: &str = "core.asar.backup";
+
+/// The file name of a gzip compressed backup of `core.asar`
+pub const COMPRESSED_BACKUP_NAME: &str = "core.asar.backup.gz";
+
+/// The file name of a backup of only the file that is patched inside of `core.asar`, made when [BackupMode::File] is used
+pub const FILE_BACKUP_NAME: &str = "mainScreen.js.backup";
+
+/// The file name of the backup of Discord's icon, stored without an extension so the same name works on every platform
+pub const ICON_BACKUP_NAME: &str = "icon-backup";
+
+/// The file name of the backup of the dock icon in Discord's macOS app bundle
+pub const ICNS_BACKUP_NAME: &str = "icns-backup";
+
+/// The folder in a version's backup folder that backups of Discord's other icon files are stored in, see [icon_variant_path]
+pub const ICON_VARIANTS_DIR: &str = "icons";
+
+/// The file name of the backup of the icon that Discord's Linux launcher entry points to
+pub const DESKTOP_ICON_BACKUP_NAME: &str = "desktop-icon-backup";
+
+/// The file name of the backup of a launcher entry for only this user that was overwritten on Linux
+pub const DESKTOP_BACKUP_NAME: &str = "desktop-backup";
+
+/// The start of the names of the temporary files that archives are written to in the [work_dir] before they replace Discord's archive,
+/// which is how the ones that an interrupted run left behind are found, see [guard](crate::guard)
+pub const TEMP_PREFIX: &str = ".discord-theme-";
+
+/// The folder from the `work-dir` option that temporary files are written to, see [set_work_dir]
+static WORK_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// The magic bytes that every gzip stream starts with
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// The `ReplaceError` enum is why [replace_checked] didn't replace an archive
+#[derive(Debug)]
+pub enum ReplaceError {
+    /// The archive couldn't be written, or couldn't be moved over the one that it replaces
+    Write(asar::Error),
+    /// The archive was written, but it couldn't be read back or the check rejected it
+    Check(asar::Error),
+}
+
+/// The `BackupMode` enum is what is backed up before patching, selected with the `backup-mode` config option
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupMode {
+    /// Back up the whole `core.asar` archive, which is the default
+    Archive,
+
+    /// Back up only the file that is patched inside of the archive, which is much faster on slow disks
+    File,
+}
+
+impl BackupMode {
+    /// Parse a backup mode from its name in the config file
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "archive" => Some(Self::Archive),
+            "file" => Some(Self::File),
+            _ => None,
+        }
+    }
+
+    /// Get the name of this backup mode as it is written in the config file
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Archive => "archive",
+            Self::File => "file",
+        }
+    }
+}
+
+/// Get the name of the backup file that should be created in the given format
+#[inline]
+pub const fn backup_name(compressed: bool) -> &'static str {
+    match compressed {
+        true => COMPRESSED_BACKUP_NAME,
+        false => BACKUP_NAME,
+    }
+}
+
+/// Get the default directory to store backups in, which is in the user's data directory like `%APPDATA%/discord-theme/backups`, see
+/// [userdata::data_dir]
+pub fn default_dir() -> PathBuf {
+    userdata::data_dir().join("backups")
+}
+
+/// Get the directory that backups for one Discord version folder of the given flavor (like `Discord` or `DiscordCanary`) are stored in
+pub fn version_dir(backup_dir: &Path, flavor: &str, version: &str) -> PathBuf {
+    backup_dir.join(flavor).join(version)
+}
+
+/// Get the path to the icon backup for the flavor that the given version backup directory belongs to. Icons are shared between
+/// all versions of a flavor because they are stored in Discord's root folder
+pub fn icon_backup_path(version_dir: &Path) -> PathBuf {
+    version_dir
+        .parent()
+        .unwrap_or(version_dir)
+        .join(ICON_BACKUP_NAME)
+}
+
+/// Get the path to the backup of one of Discord's other icon files from its path relative to Discord's root folder. These icons are
+/// in the version folder, so they are backed up separately for every version
+pub fn icon_variant_path(version_dir: &Path, relative: &str) -> PathBuf {
+    version_dir.join(ICON_VARIANTS_DIR).join(relative)
+}
+
+/// Find an unchanged copy of Discord's main icon with the given file name among the backups of its other icon files, which can be used when
+/// the main icon's backup is missing because Discord keeps a copy of its icon in every version folder. The given version is searched first
+pub fn stock_icon(version_dir: &Path, name: &str) -> Option<PathBuf> {
+    let others = fs::read_dir(version_dir.parent().unwrap_or(version_dir))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|e| e.path()));
+    std::iter::once(version_dir.to_owned())
+        .chain(others)
+        .find_map(|dir| find_named(&dir.join(ICON_VARIANTS_DIR), name))
+}
+
+/// Recursively search a directory for a file with the given name
+fn find_named(dir: &Path, name: &str) -> Option<PathBuf> {
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .find_map(|path| match path.is_dir() {
+            true => find_named(&path, name),
+            false if path.file_name() == Some(name.as_ref()) => Some(path),
+            false => None,
+        })
+}
+
+/// Get the path to the backup of the macOS dock icon for the flavor that the given version backup directory belongs to
+pub fn icns_backup_path(version_dir: &Path) -> PathBuf {
+    version_dir
+        .parent()
+        .unwrap_or(version_dir)
+        .join(ICNS_BACKUP_NAME)
+}
+
+/// Get the path to the backup of the icon that Discord's Linux launcher entry points to for the flavor that the given version backup
+/// directory belongs to
+pub fn desktop_icon_backup_path(version_dir: &Path) -> PathBuf {
+    version_dir
+        .parent()
+        .unwrap_or(version_dir)
+        .join(DESKTOP_ICON_BACKUP_NAME)
+}
+
+/// Get the path to the backup of an overwritten Linux launcher entry for the flavor that the given version backup directory belongs to
+pub fn desktop_backup_path(version_dir: &Path) -> PathBuf {
+    version_dir
+        .parent()
+        .unwrap_or(version_dir)
+        .join(DESKTOP_BACKUP_NAME)
+}
+
+/// Get every version directory of a flavor that contains a backup, sorted so that the most recently modified backup is first
+pub fn versions(flavor_dir: &Path) -> Vec<PathBuf> {
+    let mut found = match fs::read_dir(flavor_dir) {
+        Ok(dir) => dir
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter_map(|path| {
+                let modified = fs::metadata(find(&path).or_else(|| find_file(&path))?)
+                    .ok()?
+                    .modified()
+                    .ok()?;
+                Some((modified, path))
+            })
+            .collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+    found.sort_by(|(a, _), (b, _)| b.cmp(a));
+    found.into_iter().map(|(_, path)| path).collect()
+}
+
+/// The `Plan` enum is what [plan] decides should happen to the backup of a Discord version before it is patched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Plan {
+    /// A backup of this version already exists at the given path, so it is kept instead of overwriting it with a patched file
+    Existing(PathBuf),
+
+    /// This version has never been backed up, so a pristine backup must be made
+    Create {
+        /// The backup directory of the most recent other version that was backed up, if Discord was updated since then
+        previous: Option<PathBuf>,
+    },
+}
+
+/// Decide if the Discord version with the given backup directory needs a new backup. Backups are keyed by version so a
+/// version that we haven't seen before always gets a fresh backup, even if older versions were backed up
+pub fn plan(version_dir: &Path) -> Plan {
+    match find(version_dir).or_else(|| find_file(version_dir)) {
+        Some(existing) => Plan::Existing(existing),
+        None => Plan::Create {
+            previous: versions(version_dir.parent().unwrap_or(version_dir))
+                .into_iter()
+                .find(|dir| dir != version_dir),
+        },
+    }
+}
+
+/// Move backups that older versions of this program stored inside of Discord's installation into the new backup directory,
+/// returning the paths that they were moved to. Files that already exist in the destination are left alone
+pub fn migrate_legacy(core_dir: &Path, version_dir: &Path, root: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut moved = Vec::new();
+    let legacy = [BACKUP_NAME, COMPRESSED_BACKUP_NAME, FILE_BACKUP_NAME, MANIFEST_NAME]
+        .iter()
+        .map(|name| (core_dir.join(name), version_dir.join(name)))
+        .chain(std::iter::once((
+            root.join(ICON_BACKUP_NAME),
+            icon_backup_path(version_dir),
+        )));
+
+    for (from, to) in legacy {
+        if from.exists() && !to.exists() {
+            move_file(&from, &to)?;
+            moved.push(to);
+        }
+    }
+    Ok(moved)
+}
+
+/// Copy every backup in `backup_dir` into a `backups` folder inside of `dest` so that they can be moved to another machine, returning
+/// the paths of the copied files
+pub fn export(backup_dir: &Path, dest: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut copied = Vec::new();
+    copy_dir(backup_dir, &dest.join("backups"), &mut copied)?;
+    Ok(copied)
+}
+
+/// Copy backups that were exported with [export] from `src` into `backup_dir`. Every Discord version folder and icon backup is imported
+/// as one unit, and `overwrite` is called with the destination of any unit that already exists to decide if it should be replaced.
+/// Returns the destinations of the units that were imported
+pub fn import(
+    src: &Path,
+    backup_dir: &Path,
+    mut overwrite: impl FnMut(&Path) -> bool,
+) -> io::Result<Vec<PathBuf>> {
+    let mut imported = Vec::new();
+    for flavor in fs::read_dir(src.join("backups"))? {
+        let flavor = flavor?.path();
+        if !flavor.is_dir() {
+            continue;
+        }
+        let flavor_dest = backup_dir.join(flavor.file_name().unwrap_or_default());
+        fs::create_dir_all(&flavor_dest)?;
+
+        for unit in fs::read_dir(&flavor)? {
+            let unit = unit?.path();
+            let dest = flavor_dest.join(unit.file_name().unwrap_or_default());
+            if dest.exists() {
+                if !overwrite(&dest) {
+                    continue;
+                }
+                match dest.is_dir() {
+                    true => fs::remove_dir_all(&dest)?,
+                    false => fs::remove_file(&dest)?,
+                }
+            }
+
+            match unit.is_dir() {
+                true => copy_dir(&unit, &dest, &mut Vec::new())?,
+                false => drop(fs::copy(&unit, &dest)?),
+            }
+            imported.push(dest);
+        }
+    }
+    Ok(imported)
+}
+
+/// Recursively copy a directory, pushing every copied file's destination to `copied`
+fn copy_dir(from: &Path, to: &Path, copied: &mut Vec<PathBuf>) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?.path();
+        let dest = to.join(entry.file_name().unwrap_or_default());
+        match entry.is_dir() {
+            true => copy_dir(&entry, &dest, copied)?,
+            false => {
+                fs::copy(&entry, &dest)?;
+                copied.push(dest);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Move a file, copying and then removing it if it can't be renamed because the destination is on a different filesystem
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(_) => {
+            fs::copy(from, to)?;
+            fs::remove_file(from)
+        }
+    }
+}
+
+/// Find an existing backup file in the given directory in either format, preferring the uncompressed backup
+/// because older versions of this program only made those
+pub fn find(dir: &Path) -> Option<PathBuf> {
+    [BACKUP_NAME, COMPRESSED_BACKUP_NAME]
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.exists())
+}
+
+/// Find an existing backup of only the patched file in the given directory
+pub fn find_file(dir: &Path) -> Option<PathBuf> {
+    Some(dir.join(FILE_BACKUP_NAME)).filter(|path| path.exists())
+}
+
+/// Open a backup file for reading, transparently decompressing it if it is gzip compressed. The format is detected
+/// from the contents and not the file name, so a renamed backup still restores
+pub fn open(path: &Path) -> io::Result<Box<dyn Read>> {
+    let mut file = BufReader::new(fs::File::open(path)?);
+    match file.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        true => Ok(Box::new(GzDecoder::new(file))),
+        false => Ok(Box::new(file)),
+    }
+}
+
+/// The `BackupOutcome` enum is what [backup_asar] or [backup_icon] did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackupOutcome {
+    /// A new backup was made at the given path
+    Created(PathBuf),
+    /// There already was a backup at the given path, which was kept because it has Discord's original files
+    Skipped(PathBuf),
+}
+
+/// The `Error` enum is every error that can happen when making a backup
+#[derive(Debug)]
+pub enum Error {
+    /// The file that should be backed up couldn't be opened
+    Open(io::Error),
+    /// The archive that should be backed up can't be read, so a backup of it would be useless
+    Corrupt(asar::Error),
+    /// The backup file couldn't be created
+    Create(io::Error),
+    /// Copying the file to the backup failed part of the way through
+    Copy(io::Error),
+    /// An uncompressed backup isn't as big as the file that was backed up
+    Length { original: u64, backup: u64 },
+    /// The backup doesn't contain the same data as the file that was backed up
+    Contents,
+}
+
+/// Back up Discord's `core.asar` file at `src` to `dest`, compressing the backup with gzip if `compress` is set. An existing backup is never
+/// overwritten because it has Discord's original files, and an archive that can't be read is never backed up. The backup is checked against `src` after it is written, and is removed if it
+/// couldn't be written completely or doesn't match
+pub fn backup_asar(src: &Path, dest: &Path, compress: bool, progress: &ProgressBar) -> Result<BackupOutcome, Error> {
+    if dest.exists() {
+        return Ok(BackupOutcome::Skipped(dest.to_owned()));
+    }
+    let mut original = fs::File::open(src).map_err(Error::Open)?;
+    //An interrupted update can leave an empty or cut off archive, which must never be kept as if it had Discord's original files
+    asar::Archive::read(&mut original).map_err(Error::Corrupt)?;
+    original.seek(SeekFrom::Start(0)).map_err(Error::Open)?;
+    let backup = fs::File::create(dest).map_err(Error::Create)?;
+
+    //The progress bar counts the bytes written, so it finishes before reaching the original file's size when compressing
+    let copied = match compress {
+        true => {
+            let mut encoder = GzEncoder::new(progress.wrap_write(backup), Compression::default());
+            io::copy(&mut original, &mut encoder).and_then(|_| encoder.finish().map(drop))
+        }
+        false => io::copy(&mut original, &mut progress.wrap_write(backup)).map(drop),
+    };
+    match copied.map_err(Error::Copy).and_then(|()| verify(src, dest)) {
+        Ok(()) => Ok(BackupOutcome::Created(dest.to_owned())),
+        Err(e) => {
+            let _ = fs::remove_file(dest);
+            Err(e)
+        }
+    }
+}
+
+/// Check that a backup contains the same data as the file that was backed up, decompressing the backup if needed
+pub fn verify(original: &Path, backup: &Path) -> Result<(), Error> {
+    //Uncompressed backups can be checked by their length before reading anything
+    let original_len = fs::metadata(original).map_err(Error::Open)?.len();
+    let backup_len = fs::metadata(backup).map_err(Error::Copy)?.len();
+    if backup.ends_with(BACKUP_NAME) && original_len != backup_len {
+        return Err(Error::Length {
+            original: original_len,
+            backup: backup_len,
+        });
+    }
+
+    let original = manifest::hash_file(original).map_err(Error::Open)?;
+    match open(backup).and_then(manifest::hash_reader).map_err(Error::Copy)? == original {
+        true => Ok(()),
+        false => Err(Error::Contents),
+    }
+}
+
+/// Back up one of Discord's icon files at `icon` to `dest`, keeping an existing backup because it has Discord's original icon
+pub fn backup_icon(icon: &Path, dest: &Path) -> Result<BackupOutcome, Error> {
+    if dest.exists() {
+        return Ok(BackupOutcome::Skipped(dest.to_owned()));
+    }
+    fs::copy(icon, dest).map_err(Error::Copy)?;
+    Ok(BackupOutcome::Created(dest.to_owned()))
+}
+
+/// Restore a backup made by [backup_asar] or [backup_file] to the archive at `archive`. A backup of only the patched file is put back at
+/// `file` inside of the archive, and a backup of the whole archive replaces it
+pub fn restore_asar(backup: &Path, archive: &Path, file: &str, progress: &ProgressBar) -> Result<(), asar::Error> {
+    match backup.ends_with(FILE_BACKUP_NAME) {
+        true => restore_file(backup, archive, file),
+        false => restore(backup, archive, progress),
+    }
+}
+
+/// Restore the backup file at `backup` to `dest`, copying it to a temporary file in the [work_dir] and checking that it is a valid asar archive
+/// before renaming it over `dest`. If the backup is unreadable or corrupt then `dest` is never touched
+pub fn restore(backup: &Path, dest: &Path, progress: &ProgressBar) -> Result<(), asar::Error> {
+    let mut temp = temp_for(dest)?;
+    io::copy(&mut open(backup)?, &mut progress.wrap_write(temp.as_file_mut()))?;
+
+    //Parse the copied archive to make sure that it is valid before it replaces anything
+    temp.as_file_mut().seek(SeekFrom::Start(0))?;
+    asar::Archive::read(temp.as_file_mut())?;
+
+    persist(temp, dest)?;
+    Ok(())
+}
+
+/// Save the file at `path` inside of the archive at `archive` to `dest`, returning [asar::Error::NoFile] if the archive doesn't contain it
+pub fn backup_file(archive: &Path, path: &str, dest: &Path) -> Result<(), asar::Error> {
+    let archive = asar::Archive::read(&mut fs::File::open(archive)?)?;
+    let file = archive.get_file(path).ok_or(asar::Error::NoFile)?;
+
+    let mut temp = NamedTempFile::new_in(dest.parent().unwrap_or_else(|| Path::new(".")))?;
+    io::Write::write_all(&mut temp, file.as_ref())?;
+    temp.persist(dest).map_err(|e| e.error)?;
+    Ok(())
+}
+
+/// Restore a backup made with [backup_file] by replacing the file at `path` inside of the archive at `archive` with the backup's contents
+/// and re-packing the archive. The archive is packed to a temporary file and checked before it replaces the original
+pub fn restore_file(backup: &Path, archive_path: &Path, path: &str) -> Result<(), asar::Error> {
+    let mut archive = asar::Archive::read(&mut fs::File::open(archive_path)?)?;
+    archive
+        .get_file_mut(path)
+        .ok_or(asar::Error::NoFile)?
+        .replace_contents(&fs::read(backup)?)?;
+
+    replace_archive(archive_path, |temp| archive.pack(temp, true), |_| Ok(()))
+}
+
+/// Write a new archive with `write` to a temporary file in the [work_dir], and replace `dest` with it only if writing succeeded and `check`
+/// accepts the archive that was written. A write that fails part of the way through, like when the disk is full, leaves `dest` as it was
+/// instead of leaving a truncated archive that Discord can't start with
+pub fn replace_archive(
+    dest: &Path,
+    write: impl FnOnce(&mut fs::File) -> Result<(), asar::Error>,
+    check: impl FnOnce(&asar::Archive) -> Result<(), asar::Error>,
+) -> Result<(), asar::Error> {
+    replace_checked(dest, write, check).map_err(asar::Error::from)
+}
+
+/// Replace `dest` like [replace_archive], but tell an archive that was written and then failed to read back or was rejected by `check`
+/// apart from one that couldn't be written or moved over `dest`
+pub fn replace_checked(
+    dest: &Path,
+    write: impl FnOnce(&mut fs::File) -> Result<(), asar::Error>,
+    check: impl FnOnce(&asar::Archive) -> Result<(), asar::Error>,
+) -> Result<(), ReplaceError> {
+    let mut temp = temp_for(dest).map_err(|e| ReplaceError::Write(e.into()))?;
+    write(temp.as_file_mut()).map_err(ReplaceError::Write)?;
+    temp.as_file_mut().sync_all().map_err(|e| ReplaceError::Write(e.into()))?;
+
+    //Parse the written archive to make sure that it is valid before it replaces anything
+    let read = temp.as_file_mut().seek(SeekFrom::Start(0)).map_err(asar::Error::from);
+    read.and_then(|_| asar::Archive::read(temp.as_file_mut())).and_then(|written| check(&written)).map_err(ReplaceError::Check)?;
+
+    persist(temp, dest).map_err(|e| ReplaceError::Write(e.into()))?;
+    Ok(())
+}
+
+/// Make a temporary file for replacing `dest` in the [work_dir], whose name starts with [TEMP_PREFIX]
+pub fn temp_for(dest: &Path) -> io::Result<NamedTempFile> {
+    tempfile::Builder::new().prefix(TEMP_PREFIX).tempfile_in(work_dir(dest))
+}
+
+/// Rename a finished temporary file over `dest`. Files can't be renamed from one drive to another, so that error says how to pick a
+/// folder on the same drive
+fn persist(temp: NamedTempFile, dest: &Path) -> io::Result<()> {
+    temp.persist(dest).map(drop).map_err(|e| match e.error.kind() {
+        io::ErrorKind::CrossesDevices => {
+            let work = e.file.path().parent().unwrap_or_else(|| Path::new(".")).to_owned();
+            io::Error::new(e.error.kind(), msg!("cross-device-rename", work.display(), dest.display(), e.error))
+        }
+        _ => e.error,
+    })
+}
+
+/// Write the temporary files for replacing Discord's files to `dir`, or pick a folder for every file with [work_dir] if it is `None`
+pub fn set_work_dir(dir: Option<PathBuf>) {
+    *WORK_DIR.lock().unwrap_or_else(|e| e.into_inner()) = dir;
+}
+
+/// Get the folder that the temporary file for replacing `dest` is written to: the `work-dir` option if it is set, or else the folder
+/// that `dest` is in, so that the file can be renamed over `dest`, and the OS temporary folder if nothing can be created there
+pub fn work_dir(dest: &Path) -> PathBuf {
+    pick_work_dir(WORK_DIR.lock().unwrap_or_else(|e| e.into_inner()).as_deref(), dest)
+}
+
+/// Pick the work folder for `dest` with the `configured` folder, see [work_dir]
+fn pick_work_dir(configured: Option<&Path>, dest: &Path) -> PathBuf {
+    if let Some(dir) = configured {
+        return dir.to_owned();
+    }
+    let beside = dest.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+    match tempfile::Builder::new().prefix(TEMP_PREFIX).tempfile_in(beside) {
+        Ok(_) => beside.to_owned(),
+        Err(_) => env::temp_dir(),
+    }
+}
+
+/// Check that a file written in the `work` folder can be renamed into the `dest` folder, which fails when they are on different drives,
+/// by renaming a small file and deleting it again
+pub fn probe_rename(work: &Path, dest: &Path) -> io::Result<()> {
+    let mut probe = tempfile::Builder::new().prefix(TEMP_PREFIX).tempfile_in(work)?;
+    probe.write_all(b"probe")?;
+    let renamed = dest.join(format!("{}probe", TEMP_PREFIX));
+    probe.persist(&renamed).map_err(|e| e.error)?;
+    fs::remove_file(renamed)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Open(e) => write!(f, "Failed to open the file to back up: {}", e),
+            Self::Corrupt(e) => write!(f, "The archive to back up is broken: {}", e),
+            Self::Create(e) => write!(f, "Failed to create the backup file: {}", e),
+            Self::Copy(e) => write!(f, "Failed to copy the file to the backup: {}", e),
+            Self::Length { original, backup } => write!(f, "The original is {} bytes but the backup is {} bytes", original, backup),
+            Self::Contents => write!(f, "The contents of the backup differ from the original"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ReplaceError> for asar::Error {
+    fn from(e: ReplaceError) -> Self {
+        match e {
+            ReplaceError::Write(e) | ReplaceError::Check(e) => e,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, FakeDiscord, MAIN_SCREEN_JS};
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    /// A writer that fails like a full disk after `left` bytes
+    struct FailAfter<'a> {
+        file: &'a mut fs::File,
+        left: usize,
+    }
+
+    impl Write for FailAfter<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            match self.left {
+                0 => Err(io::Error::other("No space left on device")),
+                left => {
+                    let written = self.file.write(&buf[..buf.len().min(left)])?;
+                    self.left -= written;
+                    Ok(written)
+                }
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.file.flush()
+        }
+    }
+
+    impl Seek for FailAfter<'_> {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.file.seek(pos)
+        }
+    }
+
+    #[test]
+    pub fn failed_write_keeps_archive() {
+        let fake = FakeDiscord::create();
+        let original = fs::read(fake.asar()).unwrap();
+        let mut archive = asar::Archive::read(&mut fs::File::open(fake.asar()).unwrap()).unwrap();
+        archive.get_file_mut(crate::MAIN_SCREEN).unwrap().replace_contents(&b"patched"[..]).unwrap();
+
+        //The disk fills up part of the way through packing
+        let failed = replace_archive(&fake.asar(), |temp| archive.pack(&mut FailAfter { file: temp, left: 100 }, false), |_| Ok(()));
+        assert!(matches!(failed, Err(asar::Error::IOErr(_))));
+        assert_eq!(fs::read(fake.asar()).unwrap(), original);
+
+        //The archive was written completely but the check found something wrong with it
+        let rejected = replace_archive(&fake.asar(), |temp| archive.pack(temp, false), |_| Err(asar::Error::NoFile));
+        assert!(matches!(rejected, Err(asar::Error::NoFile)));
+        assert_eq!(fake.main_screen(), MAIN_SCREEN_JS);
+
+        //Only the check failing is told apart from failing to write or to move the archive into place
+        let failed = replace_checked(&fake.asar(), |temp| archive.pack(&mut FailAfter { file: temp, left: 100 }, false), |_| Ok(()));
+        assert!(matches!(failed, Err(ReplaceError::Write(asar::Error::IOErr(_)))));
+        let rejected = replace_checked(&fake.asar(), |temp| archive.pack(temp, false), |_| Err(asar::Error::NoFile));
+        assert!(matches!(rejected, Err(ReplaceError::Check(asar::Error::NoFile))));
+        let cut = replace_checked(
+            &fake.asar(),
+            |temp| {
+                archive.pack(temp, false)?;
+                Ok(temp.set_len(temp.metadata()?.len() - 1)?)
+            },
+            |_| Ok(()),
+        );
+        assert!(matches!(cut, Err(ReplaceError::Check(_))));
+        let blocked = fake.dir("blocked");
+        fs::create_dir_all(blocked.join("inside")).unwrap();
+        let unmovable = replace_checked(&blocked, |temp| archive.pack(temp, false), |_| Ok(()));
+        assert!(matches!(unmovable, Err(ReplaceError::Write(_))));
+        assert!(blocked.join("inside").is_dir());
+        assert_eq!(fs::read(fake.asar()).unwrap(), original);
+
+        //No temporary files are left next to the archive
+        assert_eq!(fs::read_dir(fake.core()).unwrap().count(), 2);
+        replace_archive(&fake.asar(), |temp| archive.pack(temp, false), |_| Ok(())).unwrap();
+        assert_eq!(testutil::main_screen(&fake.asar()), "patched");
+    }
+
+    #[test]
+    pub fn work_folder() {
+        let (discord, work) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let dest = discord.path().join("core.asar");
+        assert_eq!(pick_work_dir(None, &dest), discord.path());
+        assert_eq!(pick_work_dir(Some(work.path()), &dest), work.path());
+        //Nothing can be created in a folder that doesn't exist, so the OS temporary folder is used
+        assert_eq!(pick_work_dir(None, &discord.path().join("gone/core.asar")), env::temp_dir());
+
+        probe_rename(work.path(), discord.path()).unwrap();
+        assert_eq!(fs::read_dir(discord.path()).unwrap().count(), 0);
+        assert!(probe_rename(work.path(), &discord.path().join("gone")).is_err());
+        assert!(probe_rename(&work.path().join("gone"), discord.path()).is_err());
+    }
+
+    #[test]
+    pub fn open_both_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = b"asar archive contents".repeat(64);
+
+        fs::write(dir.path().join(BACKUP_NAME), &data).unwrap();
+        let mut encoder = GzEncoder::new(
+            fs::File::create(dir.path().join(COMPRESSED_BACKUP_NAME)).unwrap(),
+            Compression::default(),
+        );
+        encoder.write_all(&data).unwrap();
+        encoder.finish().unwrap();
+
+        for name in [BACKUP_NAME, COMPRESSED_BACKUP_NAME].iter() {
+            let mut read = Vec::new();
+            open(&dir.path().join(name))
+                .unwrap()
+                .read_to_end(&mut read)
+                .unwrap();
+            assert_eq!(read, data);
+        }
+    }
+
+    #[test]
+    pub fn find_prefers_uncompressed() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(find(dir.path()), None);
+
+        fs::write(dir.path().join(COMPRESSED_BACKUP_NAME), b"").unwrap();
+        assert_eq!(find(dir.path()), Some(dir.path().join(COMPRESSED_BACKUP_NAME)));
+
+        fs::write(dir.path().join(BACKUP_NAME), b"").unwrap();
+        assert_eq!(find(dir.path()), Some(dir.path().join(BACKUP_NAME)));
+    }
+
+    #[test]
+    pub fn migrate() {
+        let discord = tempfile::tempdir().unwrap();
+        let backups = tempfile::tempdir().unwrap();
+        let core = discord.path().join("app-1.0.9001").join("core");
+        let version = version_dir(backups.path(), "Discord", "app-1.0.9001");
+        fs::create_dir_all(&core).unwrap();
+        fs::write(core.join(BACKUP_NAME), b"backup").unwrap();
+        fs::write(core.join(MANIFEST_NAME), b"{}").unwrap();
+        fs::write(discord.path().join(ICON_BACKUP_NAME), b"icon").unwrap();
+
+        let moved = migrate_legacy(&core, &version, discord.path()).unwrap();
+        assert_eq!(moved.len(), 3);
+        assert!(!core.join(BACKUP_NAME).exists());
+        assert_eq!(fs::read(version.join(BACKUP_NAME)).unwrap(), b"backup");
+        assert_eq!(fs::read(icon_backup_path(&version)).unwrap(), b"icon");
+        assert_eq!(versions(&backups.path().join("Discord")), vec![version.clone()]);
+
+        //Migrating again does nothing, and never overwrites a backup that is already in the backup directory
+        fs::write(core.join(BACKUP_NAME), b"patched").unwrap();
+        assert!(migrate_legacy(&core, &version, discord.path())
+            .unwrap()
+            .is_empty());
+        assert_eq!(fs::read(version.join(BACKUP_NAME)).unwrap(), b"backup");
+    }
+
+    #[test]
+    pub fn upgrade() {
+        let backups = tempfile::tempdir().unwrap();
+        let (old, new) = (
+            version_dir(backups.path(), "Discord", "app-1.0.9001"),
+            version_dir(backups.path(), "Discord", "app-1.0.9002"),
+        );
+        fs::create_dir_all(&old).unwrap();
+        fs::create_dir_all(&new).unwrap();
+
+        //The first version that is patched has nothing to compare to
+        assert_eq!(plan(&old), Plan::Create { previous: None });
+        fs::write(old.join(BACKUP_NAME), b"old").unwrap();
+        assert_eq!(plan(&old), Plan::Existing(old.join(BACKUP_NAME)));
+
+        //After Discord updates, the new version gets its own backup instead of reusing the old version's
+        assert_eq!(
+            plan(&new),
+            Plan::Create {
+                previous: Some(old.clone())
+            }
+        );
+        fs::write(new.join(FILE_BACKUP_NAME), b"new").unwrap();
+        assert_eq!(plan(&new), Plan::Existing(new.join(FILE_BACKUP_NAME)));
+        assert_eq!(plan(&old), Plan::Existing(old.join(BACKUP_NAME)));
+    }
+
+    #[test]
+    pub fn export_import() {
+        let (backups, exported, other) = (
+            tempfile::tempdir().unwrap(),
+            tempfile::tempdir().unwrap(),
+            tempfile::tempdir().unwrap(),
+        );
+        let version = version_dir(backups.path(), "Discord", "app-1.0.9001");
+        fs::create_dir_all(&version).unwrap();
+        fs::write(version.join(BACKUP_NAME), b"backup").unwrap();
+        fs::write(version.join(MANIFEST_NAME), b"{}").unwrap();
+        fs::write(icon_backup_path(&version), b"icon").unwrap();
+
+        assert_eq!(export(backups.path(), exported.path()).unwrap().len(), 3);
+
+        //Importing into an empty backup directory never asks to overwrite anything
+        let imported = import(exported.path(), other.path(), |_| panic!()).unwrap();
+        assert_eq!(imported.len(), 2);
+        let other_version = version_dir(other.path(), "Discord", "app-1.0.9001");
+        assert_eq!(fs::read(other_version.join(BACKUP_NAME)).unwrap(), b"backup");
+        assert_eq!(fs::read(icon_backup_path(&other_version)).unwrap(), b"icon");
+
+        //Existing backups are only replaced when the callback allows it
+        fs::write(other_version.join(BACKUP_NAME), b"newer").unwrap();
+        let mut asked = Vec::new();
+        let imported = import(exported.path(), other.path(), |path| {
+            asked.push(path.to_owned());
+            false
+        })
+        .unwrap();
+        assert!(imported.is_empty());
+        assert_eq!(asked.len(), 2);
+        assert_eq!(fs::read(other_version.join(BACKUP_NAME)).unwrap(), b"newer");
+
+        import(exported.path(), other.path(), |_| true).unwrap();
+        assert_eq!(fs::read(other_version.join(BACKUP_NAME)).unwrap(), b"backup");
+    }
+
+    /// Pack an archive containing one file with the given contents
+    fn archive(contents: &[u8]) -> Vec<u8> {
+        let mut archive = asar::Archive::new();
+        archive.add_dir("app");
+        archive.add_file("app/mainScreen.js");
+        archive
+            .get_file_mut("app/mainScreen.js")
+            .unwrap()
+            .write_all(contents)
+            .unwrap();
+        let mut packed = io::Cursor::new(Vec::new());
+        archive.pack(&mut packed, false).unwrap();
+        packed.into_inner()
+    }
+
+    #[test]
+    pub fn restore_valid() {
+        let dir = tempfile::tempdir().unwrap();
+        let (backup, dest) = (dir.path().join(BACKUP_NAME), dir.path().join("core.asar"));
+        fs::write(&backup, archive(b"original")).unwrap();
+        fs::write(&dest, archive(b"patched")).unwrap();
+
+        restore(&backup, &dest, &ProgressBar::hidden()).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), fs::read(&backup).unwrap());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    pub fn file_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let (backup, dest) = (dir.path().join(FILE_BACKUP_NAME), dir.path().join("core.asar"));
+        fs::write(&dest, archive(b"original")).unwrap();
+
+        backup_file(&dest, "app/mainScreen.js", &backup).unwrap();
+        assert_eq!(fs::read(&backup).unwrap(), b"original");
+        assert!(matches!(
+            backup_file(&dest, "app/missing.js", &backup),
+            Err(asar::Error::NoFile)
+        ));
+
+        fs::write(&dest, archive(b"patched")).unwrap();
+        restore_file(&backup, &dest, "app/mainScreen.js").unwrap();
+        let restored = asar::Archive::read(&mut fs::File::open(&dest).unwrap()).unwrap();
+        assert_eq!(
+            restored.get_file("app/mainScreen.js").unwrap().as_ref(),
+            b"original"
+        );
+    }
+
+    #[test]
+    pub fn restore_corrupt() {
+        let dir = tempfile::tempdir().unwrap();
+        let (backup, dest) = (dir.path().join(BACKUP_NAME), dir.path().join("core.asar"));
+        let original = archive(b"patched");
+        fs::write(&dest, &original).unwrap();
+
+        //A truncated backup and one that isn't an archive at all must both leave the current archive untouched
+        let truncated = archive(b"original");
+        for corrupt in [&truncated[..truncated.len() - 4], &b"not an archive"[..]].iter() {
+            fs::write(&backup, corrupt).unwrap();
+            assert!(restore(&backup, &dest, &ProgressBar::hidden()).is_err());
+            assert_eq!(fs::read(&dest).unwrap(), original);
+            assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+        }
+    }
+
+    #[test]
+    pub fn backup_outcomes() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("core.asar");
+        fs::write(&src, archive(b"original")).unwrap();
+
+        for (name, compress) in [(BACKUP_NAME, false), (COMPRESSED_BACKUP_NAME, true)] {
+            let dest = dir.path().join(name);
+            assert_eq!(backup_asar(&src, &dest, compress, &ProgressBar::hidden()).unwrap(), BackupOutcome::Created(dest.clone()));
+            verify(&src, &dest).unwrap();
+
+            //An existing backup is kept even if Discord's files changed since it was made
+            fs::write(&src, archive(b"modified")).unwrap();
+            assert_eq!(backup_asar(&src, &dest, compress, &ProgressBar::hidden()).unwrap(), BackupOutcome::Skipped(dest.clone()));
+            assert!(matches!(verify(&src, &dest), Err(Error::Contents)));
+            fs::write(&src, archive(b"original")).unwrap();
+        }
+
+        let dest = dir.path().join("missing").join(BACKUP_NAME);
+        assert!(matches!(backup_asar(&src, &dest, false, &ProgressBar::hidden()), Err(Error::Create(_))));
+        let dest = dir.path().join("other").with_extension("backup");
+        assert!(matches!(backup_asar(&dir.path().join("nothing"), &dest, false, &ProgressBar::hidden()), Err(Error::Open(_))));
+        assert!(!dest.exists());
+
+        //An archive left empty by an interrupted update is never backed up
+        fs::write(&src, b"").unwrap();
+        assert!(matches!(backup_asar(&src, &dest, false, &ProgressBar::hidden()), Err(Error::Corrupt(_))));
+        assert!(!dest.exists());
+
+        let (icon, icon_backup) = (dir.path().join("app.ico"), dir.path().join(ICON_BACKUP_NAME));
+        assert!(matches!(backup_icon(&icon, &icon_backup), Err(Error::Copy(_))));
+        fs::write(&icon, "old icon").unwrap();
+        assert_eq!(backup_icon(&icon, &icon_backup).unwrap(), BackupOutcome::Created(icon_backup.clone()));
+        fs::write(&icon, "new icon").unwrap();
+        assert_eq!(backup_icon(&icon, &icon_backup).unwrap(), BackupOutcome::Skipped(icon_backup.clone()));
+        assert_eq!(fs::read(&icon_backup).unwrap(), b"old icon");
+    }
+
+    #[test]
+    pub fn restore_either_kind() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("core.asar");
+        fs::write(&dest, archive(b"original")).unwrap();
+        backup_file(&dest, "app/mainScreen.js", &dir.path().join(FILE_BACKUP_NAME)).unwrap();
+        backup_asar(&dest, &dir.path().join(BACKUP_NAME), false, &ProgressBar::hidden()).unwrap();
+
+        for name in [FILE_BACKUP_NAME, BACKUP_NAME] {
+            fs::write(&dest, archive(b"patched")).unwrap();
+            restore_asar(&dir.path().join(name), &dest, "app/mainScreen.js", &ProgressBar::hidden()).unwrap();
+            assert_eq!(testutil::main_screen(&dest), "original", "{}", name);
+        }
+    }
+
+    #[test]
+    pub fn stock_icon_from_variants() {
+        let dir = tempfile::tempdir().unwrap();
+        let version = version_dir(dir.path(), "Discord", "app-1.0.9002");
+        let other = version_dir(dir.path(), "Discord", "app-1.0.9001");
+        fs::create_dir_all(&version).unwrap();
+        assert_eq!(stock_icon(&version, "app.ico"), None);
+
+        let old = icon_variant_path(&other, "app-1.0.9001/app.ico");
+        fs::create_dir_all(old.parent().unwrap()).unwrap();
+        fs::write(&old, b"icon").unwrap();
+        assert_eq!(stock_icon(&version, "app.ico"), Some(old));
+
+        let current = icon_variant_path(&version, "app-1.0.9002/app.ico");
+        fs::create_dir_all(current.parent().unwrap()).unwrap();
+        fs::write(&current, b"icon").unwrap();
+        assert_eq!(stock_icon(&version, "app.ico"), Some(current));
+        assert_eq!(stock_icon(&version, "app.png"), None);
+    }
+}
@@ -0,0 +1,674 @@
+//! The `cache` module is the one store that every download is kept in: themes, custom javascript, the tweaks index, the catalog,
+//! icons, and the fonts and stylesheets that themes import. Files are stored once under their SHA-256 hash in the [OBJECTS_DIR]
+//! folder, and [INDEX_NAME] maps every URL to the hash of what it served last along with the `ETag` and `Last-Modified` headers
+//! it was served with, so that the server can answer `304 Not Modified` instead of sending the file again. Whenever something is
+//! stored, entries that weren't fetched for `cache-max-age-days` are evicted, then the oldest entries until the store is smaller
+//! than `cache-max-mib`
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use indicatif::ProgressBar;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, manifest, net, userdata};
+
+/// The folder in the data folder that the store is kept in
+pub const CACHE_DIR: &str = "cache";
+
+/// The folder in the store that the downloaded files are kept in, named after their SHA-256 hash
+pub const OBJECTS_DIR: &str = "objects";
+
+/// The file in the store that maps URLs to the files that they served
+pub const INDEX_NAME: &str = "index.json";
+
+/// The files and folder in the data folder that older versions of this program kept downloads in, see [legacy_paths]
+const LEGACY_NAMES: [&str; 3] = ["theme.css", "theme.json", "downloads"];
+
+/// The limits that [Store::open] evicts entries with, see [init]
+static LIMITS: OnceLock<Limits> = OnceLock::new();
+
+/// The `Limits` struct is how old and how big the store can get before entries are evicted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// How long an entry is kept after it was last fetched, or `None` to keep entries forever
+    pub max_age: Option<Duration>,
+    /// How many bytes the stored files can take up, or `None` for no limit
+    pub max_bytes: Option<u64>,
+}
+
+impl Limits {
+    /// Get the limits from the `cache-max-age-days` and `cache-max-mib` options, where 0 means no limit
+    pub fn from_config(cfg: &Config) -> Self {
+        Self {
+            max_age: Some(cfg.cache_max_age_days).filter(|days| *days > 0).map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+            max_bytes: Some(cfg.cache_max_mib).filter(|mib| *mib > 0).map(|mib| mib * 1024 * 1024),
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::from_config(&Config::default())
+    }
+}
+
+/// Set the limits of the store from the config. Only the first call has any effect
+pub fn init(cfg: &Config) {
+    let _ = LIMITS.set(Limits::from_config(cfg));
+}
+
+/// The `Response` struct is the parts of an HTTP response that the store uses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// The `Transport` trait makes a GET request with extra request headers, which is done with the shared agent in [Http] or faked
+/// in tests
+pub trait Transport {
+    fn get(&self, url: &str, headers: &[(&str, &str)]) -> Result<Response, net::Error>;
+}
+
+/// The `Http` struct downloads with the agent built by [net::init], refusing bodies bigger than `limit` bytes and showing the
+/// progress of every download on `bar`
+pub struct Http<'a> {
+    pub bar: &'a ProgressBar,
+    pub limit: u64,
+}
+
+impl Transport for Http<'_> {
+    fn get(&self, url: &str, headers: &[(&str, &str)]) -> Result<Response, net::Error> {
+        let res = net::get_with(url, headers)?;
+        let status = res.status();
+        let header = |name| res.header(name).map(str::to_owned);
+        let (etag, last_modified, content_type) = (header("ETag"), header("Last-Modified"), header("Content-Type"));
+        let body = net::read_body(url, res, self.limit, self.bar)?;
+        Ok(Response {
+            status,
+            etag,
+            last_modified,
+            content_type,
+            body,
+        })
+    }
+}
+
+/// The `Policy` enum is when [fetch] asks the server for a file instead of using the stored copy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// Use the stored copy without asking the server, only downloading files that aren't stored
+    PreferCache,
+    /// Ask the server if the stored copy changed, so that the file is only downloaded again if it did
+    PreferFresh,
+    /// Download the whole file without sending the stored copy's headers, for `--refresh`
+    Refresh,
+    /// Never ask the server, failing for files that aren't stored
+    OfflineOnly,
+}
+
+impl Policy {
+    /// Get the policy for downloads that should be current, from the `--refresh` flag and the `offline` option
+    pub fn fresh(refresh: bool, offline: bool) -> Self {
+        match (offline, refresh) {
+            (true, _) => Self::OfflineOnly,
+            (false, true) => Self::Refresh,
+            (false, false) => Self::PreferFresh,
+        }
+    }
+}
+
+/// The `Origin` enum is where the file returned by [fetch_checked] came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// The stored copy was used without asking the server
+    Stored,
+    /// The server said that the stored copy is still current
+    Unchanged,
+    /// The file was downloaded
+    Downloaded,
+}
+
+/// The `Fetched` struct is a file returned by [fetch_checked]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fetched {
+    pub data: Vec<u8>,
+    pub content_type: Option<String>,
+    pub origin: Origin,
+    /// Why a downloaded file couldn't be stored, which only means that it will be downloaded again next time
+    pub unstored: Option<String>,
+}
+
+/// The `Entry` struct is what the index records about a URL
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Entry {
+    /// The SHA-256 hash of the file that the URL served last, which is its name in [OBJECTS_DIR]
+    pub hash: String,
+    pub size: u64,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_type: Option<String>,
+    /// When the file was last downloaded or confirmed to be current, in seconds since the Unix epoch
+    pub fetched_at: u64,
+}
+
+/// The `Stats` struct is how much the store holds, for `cache stats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats {
+    /// How many URLs are in the index
+    pub urls: usize,
+    /// How many files are stored, which is fewer than `urls` when URLs served the same file
+    pub objects: usize,
+    /// How many bytes the stored files take up
+    pub bytes: u64,
+    /// When the entry that was fetched longest ago was fetched, in seconds since the Unix epoch
+    pub oldest: Option<u64>,
+}
+
+/// The `Store` struct is the folder that downloads are stored in
+#[derive(Debug, Clone)]
+pub struct Store {
+    dir: PathBuf,
+    limits: Limits,
+}
+
+impl Store {
+    /// Use the given folder for the store, evicting entries past `limits`
+    pub fn new(dir: impl Into<PathBuf>, limits: Limits) -> Self {
+        Self { dir: dir.into(), limits }
+    }
+
+    /// Use the [CACHE_DIR] folder in the user's data folder with the limits from [init], see [userdata::data_dir]
+    pub fn open() -> Self {
+        Self::new(userdata::data_dir().join(CACHE_DIR), LIMITS.get().copied().unwrap_or_default())
+    }
+
+    /// Get the folder that the store is kept in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Get the path of the stored file with the given hash
+    fn object_path(&self, hash: &str) -> PathBuf {
+        self.dir.join(OBJECTS_DIR).join(hash)
+    }
+
+    /// Read the index, which is empty if it is missing or can't be read
+    fn index(&self) -> BTreeMap<String, Entry> {
+        fs::read(self.dir.join(INDEX_NAME))
+            .ok()
+            .and_then(|index| serde_json::from_slice(&index).ok())
+            .unwrap_or_default()
+    }
+
+    /// Replace the index, writing it next to the old one first so that it can't be cut off
+    fn save_index(&self, index: &BTreeMap<String, Entry>) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let json = serde_json::to_vec_pretty(index).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let temp = self.dir.join(format!("{}.tmp", INDEX_NAME));
+        fs::write(&temp, json)?;
+        fs::rename(&temp, self.dir.join(INDEX_NAME))
+    }
+
+    /// Get the index entry for `url`
+    pub fn entry(&self, url: &str) -> Option<Entry> {
+        self.index().remove(url)
+    }
+
+    /// Read the file that `url` served last, returning `None` if it isn't stored or was changed since it was stored
+    pub fn get(&self, url: &str) -> Option<(Entry, Vec<u8>)> {
+        let entry = self.entry(url)?;
+        let data = fs::read(self.object_path(&entry.hash)).ok()?;
+        match manifest::hash_bytes(&data) == entry.hash {
+            true => Some((entry, data)),
+            false => None,
+        }
+    }
+
+    /// Store the file that `url` served in `res`, then evict entries past the limits
+    pub fn put(&self, url: &str, res: &Response) -> io::Result<Entry> {
+        let hash = manifest::hash_bytes(&res.body);
+        let path = self.object_path(&hash);
+        //Files are named after their contents, so one that is already stored doesn't need to be written again
+        if fs::read(&path).map(|data| manifest::hash_bytes(&data) != hash).unwrap_or(true) {
+            fs::create_dir_all(self.dir.join(OBJECTS_DIR))?;
+            let temp = path.with_extension("tmp");
+            fs::write(&temp, &res.body)?;
+            fs::rename(&temp, &path)?;
+        }
+
+        let entry = Entry {
+            hash,
+            size: res.body.len() as u64,
+            etag: res.etag.clone(),
+            last_modified: res.last_modified.clone(),
+            content_type: res.content_type.clone(),
+            fetched_at: now(),
+        };
+        let mut index = self.index();
+        index.insert(url.to_owned(), entry.clone());
+        self.save_index(&index)?;
+        self.evict(now())?;
+        Ok(entry)
+    }
+
+    /// Record that the server said the stored copy of `url` is current, so that it isn't evicted for its age
+    fn touch(&self, url: &str) -> io::Result<()> {
+        let mut index = self.index();
+        match index.get_mut(url) {
+            Some(entry) => {
+                entry.fetched_at = now();
+                self.save_index(&index)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Count what the store holds
+    pub fn stats(&self) -> Stats {
+        let index = self.index();
+        let objects = index.values().map(|entry| (entry.hash.as_str(), entry.size)).collect::<BTreeMap<_, _>>();
+        Stats {
+            urls: index.len(),
+            objects: objects.len(),
+            bytes: objects.values().sum(),
+            oldest: index.values().map(|entry| entry.fetched_at).min(),
+        }
+    }
+
+    /// Delete everything in the store, returning what it held
+    pub fn clear(&self) -> io::Result<Stats> {
+        let stats = self.stats();
+        match fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(stats),
+        }
+    }
+
+    /// Evict the entries that were fetched longer ago than the age limit at `now`, then the entries that were fetched longest ago
+    /// until the stored files fit in the size limit, and delete the files that no entry uses anymore. Returns how many entries
+    /// were evicted
+    pub fn evict(&self, now: u64) -> io::Result<usize> {
+        let mut index = self.index();
+        let before = index.len();
+        if let Some(max_age) = self.limits.max_age {
+            index.retain(|_, entry| now.saturating_sub(entry.fetched_at) <= max_age.as_secs());
+        }
+        if let Some(max_bytes) = self.limits.max_bytes {
+            let mut by_age = index.iter().map(|(url, entry)| (entry.fetched_at, url.clone())).collect::<Vec<_>>();
+            by_age.sort();
+            for (_, url) in by_age {
+                if stored_bytes(&index) <= max_bytes {
+                    break;
+                }
+                index.remove(&url);
+            }
+        }
+        let evicted = before - index.len();
+        if evicted > 0 {
+            self.save_index(&index)?;
+        }
+
+        //Files that no entry uses anymore, and files left by a write that was cut off
+        let used = index.values().map(|entry| entry.hash.as_str()).collect::<BTreeSet<_>>();
+        if let Ok(dir) = fs::read_dir(self.dir.join(OBJECTS_DIR)) {
+            for file in dir.filter_map(Result::ok) {
+                if !file.file_name().to_str().map(|name| used.contains(name)).unwrap_or(false) {
+                    fs::remove_file(file.path())?;
+                }
+            }
+        }
+        Ok(evicted)
+    }
+}
+
+/// Count the bytes of the files that the entries in `index` use, counting files used by more than one entry once
+fn stored_bytes(index: &BTreeMap<String, Entry>) -> u64 {
+    index
+        .values()
+        .map(|entry| (entry.hash.as_str(), entry.size))
+        .collect::<BTreeMap<_, _>>()
+        .values()
+        .sum()
+}
+
+/// Get the paths in the data folder that older versions of this program kept downloads at, which are deleted along with the store
+pub fn legacy_paths() -> Vec<PathBuf> {
+    LEGACY_NAMES.iter().map(|name| userdata::data_dir().join(name)).collect()
+}
+
+/// Get the current time in seconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or_default()
+}
+
+/// Get the file at `url` from the store or the server as `policy` says, storing it if it was downloaded
+pub fn fetch(transport: &impl Transport, store: &Store, url: &str, policy: Policy) -> Result<Vec<u8>, net::Error> {
+    fetch_checked(transport, store, url, policy, |_| Ok(())).map(|fetched| fetched.data)
+}
+
+/// Get the file at `url` like [fetch], calling `check` on a downloaded file before it is stored so that a file that fails the
+/// check, like a theme that doesn't match its checksum, never replaces the stored copy
+pub fn fetch_checked(
+    transport: &impl Transport,
+    store: &Store,
+    url: &str,
+    policy: Policy,
+    check: impl FnOnce(&[u8]) -> Result<(), net::Error>,
+) -> Result<Fetched, net::Error> {
+    let stored = match policy {
+        Policy::Refresh => None,
+        _ => store.get(url),
+    };
+    let stored = match (policy, stored) {
+        (Policy::PreferCache | Policy::OfflineOnly, Some((entry, data))) => {
+            return Ok(Fetched {
+                data,
+                content_type: entry.content_type,
+                origin: Origin::Stored,
+                unstored: None,
+            })
+        }
+        (Policy::OfflineOnly, None) => return Err(net::Error::Offline { url: url.to_owned() }),
+        (_, stored) => stored,
+    };
+
+    let mut headers = Vec::new();
+    if let Some((entry, _)) = &stored {
+        if let Some(etag) = &entry.etag {
+            headers.push(("If-None-Match", etag.as_str()));
+        }
+        if let Some(modified) = &entry.last_modified {
+            headers.push(("If-Modified-Since", modified.as_str()));
+        }
+    }
+
+    let res = transport.get(url, &headers)?;
+    match (res.status, stored) {
+        (304, Some((entry, data))) => Ok(Fetched {
+            data,
+            content_type: entry.content_type,
+            origin: Origin::Unchanged,
+            unstored: store.touch(url).err().map(|e| e.to_string()),
+        }),
+        //The server shouldn't say that the file is unchanged when it wasn't asked, but don't use an empty body as the file
+        (304, None) => Err(net::Error::Status {
+            url: url.to_owned(),
+            code: 304,
+        }),
+        _ => {
+            check(&res.body)?;
+            let unstored = store.put(url, &res).err().map(|e| e.to_string());
+            Ok(Fetched {
+                content_type: res.content_type,
+                data: res.body,
+                origin: Origin::Downloaded,
+                unstored,
+            })
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} URLs, {} files, {:.1} MiB", self.urls, self.objects, self.bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    const URL: &str = "https://example.com/theme.css";
+
+    /// The URL and headers of a request
+    type Request = (String, Vec<(String, String)>);
+
+    /// A server that serves files from a map, answering conditional requests for the current `ETag` with 304 and recording the
+    /// headers of every request
+    struct Mock {
+        files: RefCell<BTreeMap<String, (String, Vec<u8>)>>,
+        requests: RefCell<Vec<Request>>,
+    }
+
+    impl Mock {
+        fn new() -> Self {
+            Self {
+                files: RefCell::new(BTreeMap::new()),
+                requests: RefCell::new(Vec::new()),
+            }
+        }
+
+        fn serve(&self, url: &str, etag: &str, body: &[u8]) {
+            self.files.borrow_mut().insert(url.to_owned(), (etag.to_owned(), body.to_vec()));
+        }
+
+        fn requests(&self) -> usize {
+            self.requests.borrow().len()
+        }
+    }
+
+    impl Transport for Mock {
+        fn get(&self, url: &str, headers: &[(&str, &str)]) -> Result<Response, net::Error> {
+            self.requests
+                .borrow_mut()
+                .push((url.to_owned(), headers.iter().map(|(name, val)| (name.to_string(), val.to_string())).collect()));
+            let (etag, body) = self.files.borrow().get(url).cloned().ok_or_else(|| net::Error::Status {
+                url: url.to_owned(),
+                code: 404,
+            })?;
+            let unchanged = headers.iter().any(|(name, val)| *name == "If-None-Match" && *val == etag);
+            Ok(Response {
+                status: if unchanged { 304 } else { 200 },
+                etag: Some(etag),
+                last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".into()),
+                content_type: Some("text/css".into()),
+                body: if unchanged { Vec::new() } else { body },
+            })
+        }
+    }
+
+    fn store(dir: &Path, max_age_days: u64, max_bytes: Option<u64>) -> Store {
+        let max_age = Some(Duration::from_secs(max_age_days * 24 * 60 * 60)).filter(|age| !age.is_zero());
+        Store::new(dir, Limits { max_age, max_bytes })
+    }
+
+    /// Set when an entry was fetched, to test eviction without waiting
+    fn age(store: &Store, url: &str, fetched_at: u64) {
+        let mut index = store.index();
+        index.get_mut(url).unwrap().fetched_at = fetched_at;
+        store.save_index(&index).unwrap();
+    }
+
+    #[test]
+    pub fn policies() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path(), 0, None);
+        let server = Mock::new();
+        server.serve(URL, "\"v1\"", b"old");
+
+        //Nothing is stored, so nothing can be used offline and the first request is unconditional
+        assert!(matches!(fetch(&server, &store, URL, Policy::OfflineOnly), Err(net::Error::Offline { .. })));
+        assert_eq!(server.requests(), 0);
+        let fetched = fetch_checked(&server, &store, URL, Policy::PreferFresh, |_| Ok(())).unwrap();
+        assert_eq!((fetched.data.as_slice(), fetched.origin), (&b"old"[..], Origin::Downloaded));
+        assert!(server.requests.borrow()[0].1.is_empty());
+
+        //The stored copy is used without a request, or after the server says it is unchanged
+        assert_eq!(fetch(&server, &store, URL, Policy::PreferCache).unwrap(), b"old");
+        assert_eq!(fetch(&server, &store, URL, Policy::OfflineOnly).unwrap(), b"old");
+        assert_eq!(server.requests(), 1);
+        let fetched = fetch_checked(&server, &store, URL, Policy::PreferFresh, |_| Ok(())).unwrap();
+        assert_eq!((fetched.data.as_slice(), fetched.origin), (&b"old"[..], Origin::Unchanged));
+        assert_eq!(
+            server.requests.borrow()[1].1,
+            [
+                ("If-None-Match".to_owned(), "\"v1\"".to_owned()),
+                ("If-Modified-Since".to_owned(), "Wed, 21 Oct 2015 07:28:00 GMT".to_owned())
+            ]
+        );
+
+        //A changed file is downloaded and replaces the stored copy, and a refresh never sends the stored headers
+        server.serve(URL, "\"v2\"", b"new");
+        assert_eq!(fetch(&server, &store, URL, Policy::PreferCache).unwrap(), b"old");
+        assert_eq!(fetch(&server, &store, URL, Policy::PreferFresh).unwrap(), b"new");
+        assert_eq!(fetch(&server, &store, URL, Policy::Refresh).unwrap(), b"new");
+        assert!(server.requests.borrow().last().unwrap().1.is_empty());
+        assert_eq!(store.entry(URL).unwrap().etag.as_deref(), Some("\"v2\""));
+
+        assert_eq!(Policy::fresh(true, true), Policy::OfflineOnly);
+        assert_eq!(Policy::fresh(true, false), Policy::Refresh);
+    }
+
+    #[test]
+    pub fn unrequested_not_modified() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path(), 0, None);
+        let server = Mock::new();
+        server.serve(URL, "\"v1\"", b"old");
+        fetch(&server, &store, URL, Policy::PreferFresh).unwrap();
+
+        //The stored copy was deleted, so a 304 can't be used
+        fs::remove_dir_all(dir.path().join(OBJECTS_DIR)).unwrap();
+        struct NotModified;
+        impl Transport for NotModified {
+            fn get(&self, _: &str, _: &[(&str, &str)]) -> Result<Response, net::Error> {
+                Ok(Response { status: 304, etag: None, last_modified: None, content_type: None, body: Vec::new() })
+            }
+        }
+        assert!(matches!(fetch(&NotModified, &store, URL, Policy::PreferFresh), Err(net::Error::Status { code: 304, .. })));
+    }
+
+    #[test]
+    pub fn failed_check_isnt_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path(), 0, None);
+        let server = Mock::new();
+        server.serve(URL, "\"v1\"", b"good");
+        fetch(&server, &store, URL, Policy::PreferFresh).unwrap();
+
+        server.serve(URL, "\"v2\"", b"tampered");
+        let refused = fetch_checked(&server, &store, URL, Policy::PreferFresh, |_| {
+            Err(net::Error::InvalidChecksum { url: URL.into() })
+        });
+        assert!(matches!(refused, Err(net::Error::InvalidChecksum { .. })));
+        assert_eq!(store.get(URL).unwrap().1, b"good");
+    }
+
+    #[test]
+    pub fn content_addressed() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path(), 0, None);
+        let server = Mock::new();
+        server.serve(URL, "\"a\"", b"same");
+        server.serve("https://mirror.example.com/theme.css", "\"b\"", b"same");
+        fetch(&server, &store, URL, Policy::PreferFresh).unwrap();
+        fetch(&server, &store, "https://mirror.example.com/theme.css", Policy::PreferFresh).unwrap();
+
+        let hash = manifest::hash_bytes(b"same");
+        assert!(dir.path().join(OBJECTS_DIR).join(&hash).is_file());
+        let stats = store.stats();
+        assert_eq!((stats.urls, stats.objects, stats.bytes), (2, 1, 4));
+
+        //A stored file that was changed is not used, so it is downloaded again
+        fs::write(dir.path().join(OBJECTS_DIR).join(&hash), b"edited").unwrap();
+        assert_eq!(store.get(URL), None);
+        assert_eq!(fetch(&server, &store, URL, Policy::PreferCache).unwrap(), b"same");
+        assert_eq!(server.requests(), 3);
+        assert_eq!(fs::read(dir.path().join(OBJECTS_DIR).join(&hash)).unwrap(), b"same");
+    }
+
+    #[test]
+    pub fn evict_by_age() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path(), 30, None);
+        let server = Mock::new();
+        server.serve(URL, "\"a\"", b"theme");
+        server.serve("https://example.com/font.woff2", "\"b\"", b"font");
+        fetch(&server, &store, URL, Policy::PreferFresh).unwrap();
+        fetch(&server, &store, "https://example.com/font.woff2", Policy::PreferFresh).unwrap();
+
+        let day = 24 * 60 * 60;
+        age(&store, URL, now() - 31 * day);
+        age(&store, "https://example.com/font.woff2", now() - 29 * day);
+        assert_eq!(store.evict(now()).unwrap(), 1);
+        assert_eq!(store.entry(URL), None);
+        assert!(store.entry("https://example.com/font.woff2").is_some());
+        //The evicted file is deleted with its entry
+        assert!(!dir.path().join(OBJECTS_DIR).join(manifest::hash_bytes(b"theme")).exists());
+
+        //An unchanged file counts as fetched again
+        age(&store, "https://example.com/font.woff2", now() - 29 * day);
+        fetch(&server, &store, "https://example.com/font.woff2", Policy::PreferFresh).unwrap();
+        assert_eq!(store.evict(now() + 2 * day).unwrap(), 0);
+    }
+
+    #[test]
+    pub fn evict_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let unlimited = store(dir.path(), 0, None);
+        let server = Mock::new();
+        for (i, url) in ["https://example.com/1", "https://example.com/2", "https://example.com/3"].iter().enumerate() {
+            server.serve(url, "\"a\"", &[i as u8; 4]);
+            fetch(&server, &unlimited, url, Policy::PreferFresh).unwrap();
+            age(&unlimited, url, 1000 + i as u64);
+        }
+        //12 bytes don't fit in 10, so the oldest entry is evicted
+        let store = store(dir.path(), 0, Some(10));
+        assert_eq!(store.evict(now()).unwrap(), 1);
+        assert_eq!(store.entry("https://example.com/1"), None);
+        assert_eq!(store.stats().bytes, 8);
+
+        //A file bigger than the limit evicts everything and isn't kept either, but is still returned
+        server.serve(URL, "\"a\"", &[9; 11]);
+        assert_eq!(fetch(&server, &store, URL, Policy::PreferFresh).unwrap().len(), 11);
+        assert_eq!(store.entry(URL), None);
+        assert_eq!(fs::read_dir(dir.path().join(OBJECTS_DIR)).unwrap().count(), 0);
+    }
+
+    #[test]
+    pub fn clear_and_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(&dir.path().join(CACHE_DIR), 0, None);
+        assert_eq!(store.stats(), Stats::default());
+        assert_eq!(store.clear().unwrap(), Stats::default());
+
+        let server = Mock::new();
+        server.serve(URL, "\"a\"", b"theme");
+        fetch(&server, &store, URL, Policy::PreferFresh).unwrap();
+        let stats = store.stats();
+        assert_eq!((stats.urls, stats.objects, stats.bytes), (1, 1, 5));
+        assert!(stats.oldest.unwrap() <= now());
+
+        assert_eq!(store.clear().unwrap(), stats);
+        assert!(!store.dir().exists());
+        assert_eq!(store.get(URL), None);
+    }
+
+    #[test]
+    pub fn limits_from_config() {
+        let cfg = Config {
+            cache_max_age_days: 0,
+            cache_max_mib: 2,
+            ..Config::default()
+        };
+        assert_eq!(
+            Limits::from_config(&cfg),
+            Limits {
+                max_age: None,
+                max_bytes: Some(2 * 1024 * 1024)
+            }
+        );
+    }
+}
@@ -0,0 +1,152 @@
+//! The `catalog` module reads the index of hosted themes that can be picked with "Browse online themes" in the menu. Entries that
+//! can't be read are skipped instead of making the whole index unusable
+
+use std::fmt;
+
+use indicatif::ProgressBar;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{cache, manifest, net};
+
+/// The `Entry` struct is one theme in the catalog
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Entry {
+    pub name: String,
+    pub description: String,
+    pub author: String,
+    /// The https URL that the theme's CSS is downloaded from
+    pub css_url: String,
+    /// An https URL to a screenshot of the theme
+    #[serde(default)]
+    pub preview_url: Option<String>,
+    /// The SHA-256 hash that the downloaded CSS must have
+    pub sha256: String,
+}
+
+impl Entry {
+    /// Parse one entry of the catalog, checking that its URLs use https and its hash is a SHA-256 hash
+    fn from_json(val: &Value) -> Result<Self, String> {
+        let entry = Self::deserialize(val).map_err(|e| e.to_string())?;
+        for url in std::iter::once(&entry.css_url).chain(&entry.preview_url) {
+            if !url.starts_with("https://") {
+                return Err(format!("expected an https:// URL, found '{}'", url));
+            }
+        }
+        match manifest::is_hash(&entry.sha256) {
+            true => Ok(entry),
+            false => Err(format!("expected a SHA-256 hash of 64 hex digits, found '{}'", entry.sha256)),
+        }
+    }
+}
+
+/// The `Catalog` struct is every theme in the index that could be read
+#[derive(Debug, Clone, PartialEq)]
+pub struct Catalog {
+    pub themes: Vec<Entry>,
+    /// Why each entry that couldn't be read was skipped
+    pub skipped: Vec<String>,
+}
+
+impl Catalog {
+    /// Parse a catalog from the JSON index, which is an object with a `themes` array
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let val = serde_json::from_str::<Value>(text)?;
+        let entries = val
+            .get("themes")
+            .and_then(Value::as_array)
+            .ok_or_else(|| Error::InvalidFormat("The 'themes' field is missing or not an array".to_owned()))?;
+
+        let mut catalog = Self {
+            themes: Vec::new(),
+            skipped: Vec::new(),
+        };
+        for (i, entry) in entries.iter().enumerate() {
+            match Entry::from_json(entry) {
+                Ok(entry) => catalog.themes.push(entry),
+                Err(e) => {
+                    let name = entry.get("name").and_then(Value::as_str).unwrap_or("unnamed");
+                    catalog.skipped.push(format!("theme {} ({}): {}", i + 1, name, e));
+                }
+            }
+        }
+        Ok(catalog)
+    }
+
+    /// Get the catalog at the given URL from the [cache] as `policy` says and parse it, showing the download's progress on `bar`
+    pub fn download(url: &str, policy: cache::Policy, bar: &ProgressBar) -> Result<Self, Error> {
+        let http = cache::Http { bar, limit: net::MAX_TEXT_SIZE };
+        let text = net::into_text(url, cache::fetch(&http, &cache::Store::open(), url, policy)?)?;
+        Self::parse(&text)
+    }
+}
+
+/// The `Error` enum is every error that can make the whole catalog unusable
+#[derive(Debug)]
+pub enum Error {
+    /// The catalog couldn't be downloaded
+    Net(net::Error),
+    /// The catalog isn't valid JSON
+    Json(serde_json::Error),
+    /// The catalog is valid JSON but not in the format of a catalog
+    InvalidFormat(String),
+}
+
+impl From<net::Error> for Error {
+    fn from(err: net::Error) -> Self {
+        Self::Net(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Net(e) => write!(f, "{}", e),
+            Self::Json(e) => write!(f, "The theme catalog is not valid JSON: {}", e),
+            Self::InvalidFormat(e) => write!(f, "The theme catalog is invalid: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn bundled_catalog() {
+        let catalog = Catalog::parse(include_str!("../assets/catalog.json")).unwrap();
+        assert!(catalog.skipped.is_empty());
+        let old = catalog.themes.iter().find(|entry| entry.css_url == crate::config::STABLE_THEME_URL).unwrap();
+        assert_eq!(old.sha256, manifest::hash_bytes(include_bytes!("../assets/old-compressed.css")));
+    }
+
+    #[test]
+    pub fn invalid_entries() {
+        let catalog = Catalog::parse(
+            r#"{"themes": [
+                {"name": "Good", "description": "", "author": "a", "css-url": "https://example.com/a.css", "sha256": "0000000000000000000000000000000000000000000000000000000000000000"},
+                {"name": "Plain", "description": "", "author": "a", "css-url": "http://example.com/b.css", "sha256": "0000000000000000000000000000000000000000000000000000000000000000"},
+                {"name": "Unhashed", "description": "", "author": "a", "css-url": "https://example.com/c.css", "sha256": "abc"},
+                {"description": 5}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(catalog.themes.len(), 1);
+        assert_eq!(catalog.themes[0].name, "Good");
+        assert_eq!(catalog.themes[0].preview_url, None);
+        assert_eq!(catalog.skipped.len(), 3);
+        assert!(catalog.skipped[0].starts_with("theme 2 (Plain)"));
+
+        assert!(matches!(Catalog::parse("not json"), Err(Error::Json(_))));
+        assert!(matches!(Catalog::parse(r#"{"themes": 5}"#), Err(Error::InvalidFormat(_))));
+    }
+}
@@ -0,0 +1,98 @@
+//! The `components` module splits the old look that is applied from the menu into parts that can be picked separately: the old CSS
+//! theme, the old icon, and the old app name in the title bar. The picks are saved in the config file, with `replace-icon` deciding
+//! if the icon is picked
+
+use crate::config::Config;
+
+/// The CSS that shows the old app name in the title bar in place of Discord's wordmark
+pub const TITLE_CSS: &str = include_str!("../assets/old-title.css");
+
+/// The name that the old app name is recorded as in the manifest when it is applied without the old theme
+pub const TITLE_NAME: &str = "old-title";
+
+/// The `Component` enum is one part of the old look
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    /// The old CSS theme with the tweaks that were picked for it
+    Css,
+    /// The old icon in place of Discord's icon
+    Icon,
+    /// The old app name in the title bar, see [TITLE_CSS]
+    Title,
+}
+
+impl Component {
+    /// Every component in the order that they are listed in the menu
+    pub const ALL: [Self; 3] = [Self::Css, Self::Icon, Self::Title];
+
+    /// Get the name of this component as it is written in the run report
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Css => "css",
+            Self::Icon => "icon",
+            Self::Title => "title",
+        }
+    }
+
+    /// Check if this component is picked in the config
+    pub fn picked(&self, cfg: &Config) -> bool {
+        match self {
+            Self::Css => cfg.old_css,
+            Self::Icon => cfg.replace_icon,
+            Self::Title => cfg.old_title,
+        }
+    }
+
+    /// Pick or unpick this component in the config
+    pub fn set(&self, cfg: &mut Config, picked: bool) {
+        match self {
+            Self::Css => cfg.old_css = picked,
+            Self::Icon => cfg.replace_icon = picked,
+            Self::Title => cfg.old_title = picked,
+        }
+    }
+}
+
+/// Get the components that are picked in the config
+pub fn picked(cfg: &Config) -> Vec<Component> {
+    Component::ALL.iter().copied().filter(|component| component.picked(cfg)).collect()
+}
+
+/// Build the CSS for the picked components from the old theme and its name, returning the CSS and the name to record it as. `None`
+/// is returned if no component that changes the CSS is picked
+pub fn css(picked: &[Component], theme: (String, String)) -> Option<(String, String)> {
+    let (mut css, name) = theme;
+    if picked.contains(&Component::Title) {
+        css.push('\n');
+        css.push_str(TITLE_CSS);
+    }
+    match (picked.contains(&Component::Css), picked.contains(&Component::Title)) {
+        (true, true) => Some((css, format!("{} + {}", name, TITLE_NAME))),
+        (true, false) => Some((css, name)),
+        (false, true) => Some((TITLE_CSS.to_owned(), TITLE_NAME.to_owned())),
+        (false, false) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn pick_components() {
+        let mut cfg = Config::default();
+        assert_eq!(picked(&cfg), vec![Component::Css, Component::Icon]);
+        Component::Title.set(&mut cfg, true);
+        Component::Icon.set(&mut cfg, false);
+        assert_eq!(picked(&cfg), vec![Component::Css, Component::Title]);
+        assert!(!cfg.replace_icon);
+
+        let theme = || ("a { color: red; }".to_owned(), "old".to_owned());
+        let (css, name) = css(&[Component::Css, Component::Title], theme()).unwrap();
+        assert!(css.starts_with("a { color: red; }") && css.contains("DISCORD"));
+        assert_eq!(name, "old + old-title");
+        assert_eq!(super::css(&[Component::Css, Component::Icon], theme()), Some(theme()));
+        assert_eq!(super::css(&[Component::Title], theme()), Some((TITLE_CSS.to_owned(), TITLE_NAME.to_owned())));
+        assert_eq!(super::css(&[Component::Icon], theme()), None);
+    }
+}
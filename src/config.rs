@@ -1,93 +1,2136 @@
-use std::fs;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::io::Write;
+use std::{fmt, fs, io};
 
 use console::style;
-use serde_json::json;
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
 
-/// The path to the configuration file that we will load options from
-const CONFIG_PATH: &str = "config.json";
+use crate::backup::{self, BackupMode};
+use crate::icon;
+use crate::patch::{self, Strategy};
+use crate::schedule::Schedule;
+use crate::{escape, manifest, messages::msg, output, text, userdata};
 
-/// The `Config` struct holds all configuration options given as a .json file to the
+/// The file name of the configuration file that we will load options from
+pub const CONFIG_NAME: &str = "config.toml";
+
+/// The version of the config file layout that this program reads and writes, stored in the `version` option
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Functions that upgrade the options of an older config file, where the function at index `n` upgrades version `n` to version `n + 1`
+const MIGRATIONS: [fn(Options) -> Options; 1] = [migrate_v0];
+
+/// The options in a config file before they are checked, by key
+type Options = serde_json::Map<String, serde_json::Value>;
+
+/// The URL that the newest theme is downloaded from on the [Channel::Stable] channel
+pub const STABLE_THEME_URL: &str = "https://raw.githubusercontent.com/Bendi11/discord-theme/master/assets/old-compressed.css";
+
+/// The URL that the newest theme is downloaded from on the [Channel::Dev] channel
+pub const DEV_THEME_URL: &str = "https://raw.githubusercontent.com/Bendi11/discord-theme/dev/assets/old-compressed.css";
+
+/// The URL of the index of optional tweaks for the theme on the stable channel, see [tweaks](crate::tweaks)
+pub const STABLE_TWEAKS_URL: &str = "https://raw.githubusercontent.com/Bendi11/discord-theme/master/assets/tweaks.json";
+
+/// The URL of the index of optional tweaks for the theme on the dev channel
+pub const DEV_TWEAKS_URL: &str = "https://raw.githubusercontent.com/Bendi11/discord-theme/dev/assets/tweaks.json";
+
+/// The URL of the index of themes that can be picked from the menu when `catalog_url` isn't set
+pub const CATALOG_URL: &str = "https://raw.githubusercontent.com/Bendi11/discord-theme/master/assets/catalog.json";
+
+/// The prefix of environment variables that override config options, like `DISCORD_THEME_MAKE_BACKUP` for `make-backup`
+pub const ENV_PREFIX: &str = "DISCORD_THEME_";
+
+/// The file name of the JSON configuration file that older versions used, which is read if there is no [CONFIG_NAME] file
+pub const LEGACY_CONFIG_NAME: &str = "config.json";
+
+/// The `Config` struct holds all configuration options given as a .toml or .json file to the
 /// program, or default values.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", default, deny_unknown_fields)]
 pub struct Config {
-    /// The custom javascript to run along with the css injection; only for people who know what they're doing
+    /// The version of the config file layout, which is always [CONFIG_VERSION] after the file is loaded
+    pub version: u32,
+    /// The path of the file that this config was loaded from and is saved to
+    #[serde(skip)]
+    pub path: PathBuf,
+    /// Where the value of every option that isn't a default came from, see [Config::source]
+    #[serde(skip)]
+    pub sources: BTreeMap<String, Source>,
+    /// The Discord flavor that this config was loaded for, whose `[flavors.<name>]` table overrides the top level options
+    #[serde(skip)]
+    pub flavor: Flavor,
+    /// The Discord root folder given with the hidden `--root` flag, which is used instead of looking for Discord's installation
+    #[serde(skip)]
+    pub root: Option<PathBuf>,
+    /// The archive given with the `--asar` flag, which is patched instead of Discord's installation
+    #[serde(skip)]
+    pub asar: Option<PathBuf>,
+    /// The custom javascript to run along with the css injection; only for people who know what they're doing. This is empty until
+    /// [Config::resolve_custom_js] reads it from `customjs_path`
+    #[serde(skip)]
     pub customjs: String,
+    /// The paths, https URLs, and inline scripts that the custom javascript is read from, if any, see [CustomJs]
+    #[serde(rename = "custom-js")]
+    pub customjs_path: Option<CustomJs>,
+    /// The javascript that runs when Discord loads the patched file, before its window exists. This is empty until
+    /// [Config::resolve_custom_js] reads it from `preload_path`
+    #[serde(skip)]
+    pub preload_js: String,
+    /// The path or https URL that the preload javascript is read from, if any, see [JsSource]
+    #[serde(rename = "custom-js-preload")]
+    pub preload_path: Option<String>,
+    /// The title that Discord's window is kept at, or `None` to leave Discord's own title, see [patch::title_block](crate::patch::title_block)
+    pub window_title: Option<String>,
+    /// Wether custom javascript can be downloaded from a URL that isn't pinned to a SHA-256 hash
+    pub allow_unpinned_remote_js: bool,
+    /// The SHA-256 hash that the custom javascript must have, which catches the script being changed between runs, see
+    /// [Config::check_js_pin]
+    pub custom_js_sha256: Option<String>,
+    /// Wether to offer to pin the custom javascript's hash when `custom_js_sha256` isn't set, which is turned off when the offer is
+    /// declined so that it is only asked once
+    pub pin_custom_js: bool,
+    /// The SHA-256 hash of the custom javascript that [Config::resolve_custom_js] read, before it was escaped
+    #[serde(skip)]
+    pub customjs_hash: Option<String>,
+    /// Wether to insert the block that runs custom javascript at all, which is left out to only insert the CSS theme
+    pub inject_js: bool,
+    /// How the injection adds the theme to Discord's window
+    pub inject_strategy: Strategy,
     /// Wether or not to make a backup of the original electron .asar file
     pub make_backup: bool,
 
     /// Wether to attempt to replace Discord's desktop icon or not
     pub replace_icon: bool,
+
+    /// The path or URL of an icon file to use instead of the old icon embedded in this executable
+    pub custom_icon: Option<String>,
+
+    /// The path to Discord's app bundle on macOS, or `None` to use [icon::MAC_BUNDLE]
+    pub mac_bundle: Option<PathBuf>,
+
+    /// Wether to compress new backups of the electron .asar file with gzip
+    pub compress_backups: bool,
+
+    /// The directory to store backups in, or `None` to use the default from [backup::default_dir]
+    pub backup_dir: Option<PathBuf>,
+
+    /// The folder that Discord's archive is written to before it replaces the real one, or `None` to use the folder that the archive is
+    /// in, see [backup::work_dir]
+    pub work_dir: Option<PathBuf>,
+
+    /// Wether to back up the whole electron .asar file or only the file that is patched
+    pub backup_mode: BackupMode,
+
+    /// The https URL to download the newest theme from, which takes precedence over `channel`
+    pub theme_url: Option<String>,
+
+    /// The SHA-256 hash that the downloaded theme must have, or `None` to use the checksum file published next to the theme
+    pub theme_sha256: Option<String>,
+
+    /// The path of the CSS theme to use during the day, which is switched with `theme_dark` when both are set
+    pub theme_light: Option<String>,
+
+    /// The path of the CSS theme to use at night, which is switched with `theme_light` when both are set
+    pub theme_dark: Option<String>,
+
+    /// The hours to use `theme_dark` in like `19-7`, or `None` to follow the system's dark mode, see [Schedule](crate::schedule::Schedule)
+    pub dark_hours: Option<String>,
+
+    /// The https URL of the index of themes that can be picked from the menu, or `None` to use [CATALOG_URL]
+    pub catalog_url: Option<String>,
+
+    /// Which branch of the theme to download when `theme_url` isn't set
+    pub channel: Channel,
+
+    /// The URL of the proxy to make downloads through, or `None` to use the `HTTPS_PROXY` or `HTTP_PROXY` environment variables
+    pub proxy: Option<String>,
+
+    /// How many seconds to wait for a server before a download fails
+    pub network_timeout_secs: u64,
+
+    /// Wether to accept any TLS certificate when downloading, which is dangerous and only meant for proxies that intercept TLS
+    pub insecure_skip_tls_verify: bool,
+
+    /// How many times to try downloading the theme before using the last downloaded copy
+    pub download_attempts: u32,
+
+    /// Wether to forbid every network access, using the last downloaded or compiled in theme instead of downloading it
+    pub offline: bool,
+
+    /// How many days a download is kept in the [cache](crate::cache) after it was last fetched, or 0 to keep downloads forever
+    pub cache_max_age_days: u64,
+
+    /// How many MiB the downloads in the [cache](crate::cache) can take up before the oldest are evicted, or 0 for no limit
+    pub cache_max_mib: u64,
+
+    /// Which runs show a desktop notification of what they did, see [notify](crate::notify)
+    pub notify: Notify,
+
+    /// How many KiB the escaped theme and custom javascript can be before a warning that they slow down Discord's startup is shown
+    pub theme_size_warn_kib: u64,
+
+    /// How many KiB the escaped theme and custom javascript can be before they are refused unless `--force` is passed
+    pub theme_size_limit_kib: u64,
+
+    /// How many applies the manifest remembers exactly enough to reproduce them with `--from-manifest`, see
+    /// [Manifest::history](crate::manifest::Manifest::history)
+    pub manifest_history: usize,
+
+    /// How many runs are kept in the local run history that the `history` command shows, see [history](crate::history)
+    pub run_history: usize,
+
+    /// The language code or JSON file path of the language to show messages in, or `None` to use the `LANG` environment variable
+    pub language: Option<String>,
+
+    /// The names of the tweaks that were picked to add to the old theme, or `None` if they were never picked, see [tweaks](crate::tweaks)
+    pub tweaks: Option<Vec<String>>,
+
+    /// Wether the old CSS theme is picked when the old look is applied from the menu, see [components](crate::components)
+    pub old_css: bool,
+
+    /// Wether the old app name in the title bar is picked when the old look is applied from the menu
+    pub old_title: bool,
+
+    /// The values of the variables that themes declare, by name, see [variables](crate::variables). These are saved in the
+    /// `[variables]` table after every other option
+    pub variables: BTreeMap<String, String>,
 }
 
-impl Config {
-    /// Create a default config file with default values and return a default instance of self
-    fn default_file() -> Self {
-        let toml = json! ({
-            "custom-js": null,
-            "make-backup": true,
-            "replace-icon": true
-        });
-        //Write the TOML configuration to the default file location
-        std::fs::write(CONFIG_PATH, serde_json::to_vec_pretty(&toml).unwrap()).unwrap();
+/// The `Channel` enum is which branch of this repository the newest theme is downloaded from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    /// The master branch, which is the default
+    Stable,
+    /// The dev branch, which has theme changes before they are released
+    Dev,
+}
+
+impl Channel {
+    /// Get the URL that the newest theme is downloaded from on this channel
+    pub const fn url(&self) -> &'static str {
+        match self {
+            Self::Stable => STABLE_THEME_URL,
+            Self::Dev => DEV_THEME_URL,
+        }
+    }
+
+    /// Get the URL that the index of tweaks for the theme is downloaded from on this channel
+    pub const fn tweaks_url(&self) -> &'static str {
+        match self {
+            Self::Stable => STABLE_TWEAKS_URL,
+            Self::Dev => DEV_TWEAKS_URL,
+        }
+    }
+
+    /// Get the name of this channel as it is written in the config file
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Stable => "stable",
+            Self::Dev => "dev",
+        }
+    }
+}
+
+/// The `Notify` enum is which runs show a desktop notification when they finish
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Notify {
+    /// Never show notifications
+    Never,
+    /// Only the scheduled `check`, which runs without a window, shows notifications. This is the default
+    Background,
+    /// Every run that applies a theme shows a notification
+    Always,
+}
+
+impl Notify {
+    /// Get the name of this setting as it is written in the config file
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Never => "never",
+            Self::Background => "background",
+            Self::Always => "always",
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
         Self {
+            version: CONFIG_VERSION,
+            path: PathBuf::new(),
+            sources: BTreeMap::new(),
+            flavor: Flavor::Stable,
+            root: None,
+            asar: None,
             customjs: "".into(),
+            customjs_path: None,
+            preload_js: "".into(),
+            preload_path: None,
+            window_title: None,
+            allow_unpinned_remote_js: false,
+            custom_js_sha256: None,
+            pin_custom_js: true,
+            customjs_hash: None,
+            inject_js: true,
+            inject_strategy: Strategy::ExecuteJs,
             make_backup: true,
             replace_icon: true,
+            custom_icon: None,
+            mac_bundle: None,
+            compress_backups: false,
+            backup_dir: None,
+            work_dir: None,
+            backup_mode: BackupMode::Archive,
+            theme_url: None,
+            theme_sha256: None,
+            theme_light: None,
+            theme_dark: None,
+            dark_hours: None,
+            catalog_url: None,
+            channel: Channel::Stable,
+            proxy: None,
+            network_timeout_secs: 30,
+            insecure_skip_tls_verify: false,
+            download_attempts: 3,
+            offline: false,
+            cache_max_age_days: 90,
+            cache_max_mib: 64,
+            notify: Notify::Background,
+            theme_size_warn_kib: 2048,
+            theme_size_limit_kib: 10240,
+            manifest_history: 5,
+            run_history: 50,
+            language: None,
+            tweaks: None,
+            old_css: true,
+            old_title: false,
+            variables: BTreeMap::new(),
+        }
+    }
+}
+
+/// The `JsSource` enum is where the custom javascript is read from, parsed from the `custom-js` option
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsSource<'a> {
+    /// A local file
+    File(&'a str),
+    /// An https URL, pinned to the SHA-256 hash after `#sha256=` at the end of the URL if there is one
+    Url { url: &'a str, sha256: Option<&'a str> },
+}
+
+impl<'a> JsSource<'a> {
+    /// Parse the value of the `custom-js` option, which is an https URL if it starts with `https://` and a path otherwise
+    pub fn parse(val: &'a str) -> Result<Self, String> {
+        if val.starts_with("http://") {
+            return Err(format!("expected an https:// URL, found '{}'", val));
+        }
+        if !val.starts_with("https://") {
+            return Ok(Self::File(val));
+        }
+        match val.split_once("#sha256=") {
+            Some((url, hash)) if manifest::is_hash(hash) => Ok(Self::Url { url, sha256: Some(hash) }),
+            Some((_, hash)) => Err(format!("expected a SHA-256 hash of 64 hex digits after #sha256=, found '{}'", hash)),
+            None => Ok(Self::Url { url: val, sha256: None }),
+        }
+    }
+}
+
+/// The `JsEntry` enum is one script in the `custom-js` option
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum JsEntry {
+    /// A path or https URL that the script is read from, see [JsSource]
+    Path(String),
+    /// A script written in the config file itself as `{ inline = "..." }`, which is never read from anywhere
+    Inline { inline: String },
+}
+
+impl JsEntry {
+    /// Parse one script from the `custom-js` option. A string is always a path or URL and only the object form is inline, so a
+    /// script is never mistaken for a path or the other way around
+    fn from_value(val: &serde_json::Value) -> Result<Self, String> {
+        match val {
+            serde_json::Value::String(path) => Ok(Self::Path(path.clone())),
+            serde_json::Value::Object(obj) => match (obj.get("inline"), obj.len()) {
+                (Some(serde_json::Value::String(inline)), 1) => Ok(Self::Inline { inline: inline.clone() }),
+                _ => Err(format!("expected {{ inline = \"<javascript>\" }} with no other keys, found {}", val)),
+            },
+            _ => Err(format!("expected a path, an https:// URL, or {{ inline = \"<javascript>\" }}, found {}", val)),
+        }
+    }
+}
+
+/// The `CustomJs` enum is the value of the `custom-js` option, which is one script or an array of them that are inserted in order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum CustomJs {
+    /// A single path, URL, or inline script
+    One(JsEntry),
+    /// An array of paths, URLs, and inline scripts
+    Many(Vec<JsEntry>),
+}
+
+impl CustomJs {
+    /// Get every script in the option, in the order they are inserted
+    pub fn entries(&self) -> &[JsEntry] {
+        match self {
+            Self::One(entry) => std::slice::from_ref(entry),
+            Self::Many(entries) => entries,
+        }
+    }
+
+    /// Get every script along with its index in the array, which is `None` if the option isn't an array
+    pub fn indexed(&self) -> impl Iterator<Item = (Option<usize>, &JsEntry)> {
+        let many = matches!(self, Self::Many(_));
+        self.entries().iter().enumerate().map(move |(i, entry)| (many.then_some(i), entry))
+    }
+
+    /// Get the path or URL if the option is a single one, which is what the settings menu can change
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            Self::One(JsEntry::Path(path)) => Some(path),
+            _ => None,
+        }
+    }
+}
+
+impl From<String> for CustomJs {
+    fn from(path: String) -> Self {
+        Self::One(JsEntry::Path(path))
+    }
+}
+
+impl From<&str> for CustomJs {
+    fn from(path: &str) -> Self {
+        Self::from(path.to_owned())
+    }
+}
+
+impl<'de> Deserialize<'de> for CustomJs {
+    fn deserialize<D: serde::Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        let parsed = match serde_json::Value::deserialize(de)? {
+            serde_json::Value::Array(vals) if vals.is_empty() => Err("expected at least one script in the array".to_owned()),
+            serde_json::Value::Array(vals) => vals
+                .iter()
+                .enumerate()
+                .map(|(i, val)| JsEntry::from_value(val).map_err(|err| element(i, err)))
+                .collect::<Result<_, _>>()
+                .map(Self::Many),
+            val => JsEntry::from_value(&val).map(Self::One),
+        };
+        parsed.map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Display for CustomJs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = self.entries().iter().map(|entry| match entry {
+            JsEntry::Path(path) => path.clone(),
+            JsEntry::Inline { inline } => format!("inline javascript ({} bytes)", inline.len()),
+        });
+        write!(f, "{}", names.collect::<Vec<_>>().join(", "))
+    }
+}
+
+/// Add which element of the `custom-js` array something went wrong with to the error, counting from 1
+fn element(index: usize, err: impl fmt::Display) -> String {
+    format!("element {} of the array: {}", index + 1, err)
+}
+
+/// The `JsPin` enum is what [Config::check_js_pin] decided about the custom javascript that was read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JsPin {
+    /// There is no custom javascript, it has the pinned hash, or it isn't pinned and pinning it isn't offered
+    Unchanged,
+    /// No hash is pinned yet, so pinning the given hash of the javascript should be offered
+    Offer(String),
+    /// The javascript changed and `--accept-js-change` was given, so the given hash replaces the pinned one
+    Accepted(String),
+}
+
+/// The `Flavor` enum is which of Discord's release channels is patched, each of which is installed separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Flavor {
+    /// The normal Discord client
+    #[default]
+    Stable,
+    /// Discord's public test build
+    Ptb,
+    /// Discord's alpha test build
+    Canary,
+}
+
+impl Flavor {
+    /// Parse a flavor from its name in the config file and the `--flavor` flag
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "stable" => Some(Self::Stable),
+            "ptb" => Some(Self::Ptb),
+            "canary" => Some(Self::Canary),
+            _ => None,
+        }
+    }
+
+    /// Get the name of this flavor as it is written in the config file
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Stable => "stable",
+            Self::Ptb => "ptb",
+            Self::Canary => "canary",
+        }
+    }
+
+    /// Get the name of the folder that this flavor is installed to
+    pub const fn folder_name(&self) -> &'static str {
+        match self {
+            Self::Stable => "Discord",
+            Self::Ptb => "DiscordPTB",
+            Self::Canary => "DiscordCanary",
+        }
+    }
+}
+
+/// The `Source` enum is where the value of a config option came from, with later sources overriding earlier ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Source {
+    /// The option isn't set anywhere so its default value is used
+    Default,
+    /// The option is set in the config file
+    File,
+    /// The option is set in the `[flavors.<name>]` table of the selected flavor in the config file
+    Flavor,
+    /// The option is set by a `DISCORD_THEME_` environment variable
+    Env,
+    /// The option is set by a command line flag
+    Cli,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Default => "default",
+            Self::File => "config file",
+            Self::Flavor => "flavor section",
+            Self::Env => "environment variable",
+            Self::Cli => "command line",
+        })
+    }
+}
+
+/// The `Overrides` struct holds config options given by environment variables and command line flags, which take precedence over
+/// the options in the config file
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    /// Every environment variable starting with [ENV_PREFIX], as the variable's name and value
+    pub env: Vec<(String, String)>,
+    /// Options given by command line flags, as the option's key and value
+    pub cli: Vec<(&'static str, serde_json::Value)>,
+}
+
+impl Overrides {
+    /// Get the overrides given by this process's environment variables
+    pub fn from_env() -> Self {
+        Self {
+            env: std::env::vars().filter(|(name, _)| name.starts_with(ENV_PREFIX)).collect(),
+            cli: Vec::new(),
+        }
+    }
+}
+
+/// Get the name of the environment variable that overrides a config option
+pub fn env_var(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.to_uppercase().replace('-', "_"))
+}
+
+/// Parse the value of an environment variable as the same type as `default`, so booleans can be written as `true`/`false`,
+/// `1`/`0`, `yes`/`no`, or `on`/`off` and an empty variable unsets an option that has no value by default
+fn env_value(val: &str, default: &serde_json::Value) -> Result<serde_json::Value, String> {
+    match default {
+        serde_json::Value::Bool(_) => match val.trim().to_lowercase().as_str() {
+            "true" | "1" | "yes" | "on" => Ok(true.into()),
+            "false" | "0" | "no" | "off" => Ok(false.into()),
+            _ => Err(format!("expected a boolean like true or false, found '{}'", val)),
+        },
+        serde_json::Value::Null if val.is_empty() => Ok(serde_json::Value::Null),
+        serde_json::Value::Number(_) => val
+            .trim()
+            .parse::<i64>()
+            .map(Into::into)
+            .map_err(|_| format!("expected a number, found '{}'", val)),
+        _ => Ok(val.into()),
+    }
+}
+
+/// Get what is wrong with the value of one option, or `None` if the option is valid
+fn option_error(key: &str, val: &serde_json::Value) -> Option<String> {
+    let single = serde_json::Value::Object(std::iter::once((key.to_owned(), val.clone())).collect());
+    match Config::deserialize(&single) {
+        Err(e) => Some(e.to_string()),
+        //Themes are inserted into Discord, so they must not be downloaded over an unencrypted connection
+        Ok(config) => match (config.theme_url.or(config.catalog_url), config.theme_sha256.or(config.custom_js_sha256)) {
+            (Some(url), _) if !url.starts_with("https://") => Some(format!("expected an https:// URL, found '{}'", url)),
+            (_, Some(hash)) if !manifest::is_hash(&hash) => {
+                Some(format!("expected a SHA-256 hash of 64 hex digits, found '{}'", hash))
+            }
+            //Icons are written into Discord's installation, so they are held to the same rule as themes
+            _ if config.custom_icon.as_deref().is_some_and(|icon| icon.get(..7).is_some_and(|s| s.eq_ignore_ascii_case("http://"))) => {
+                Some(format!("expected an https:// URL, found '{}'", config.custom_icon.unwrap_or_default()))
+            }
+            _ if config.window_title.as_ref().is_some_and(|title| title.chars().count() > patch::MAX_TITLE_CHARS) => {
+                Some(format!("expected a title of at most {} characters", patch::MAX_TITLE_CHARS))
+            }
+            _ if config.window_title.as_ref().is_some_and(|title| title.chars().any(char::is_control)) => {
+                Some("expected a title on a single line without control characters".to_owned())
+            }
+            _ => match config.dark_hours {
+                Some(hours) => Schedule::parse(Some(&hours)).err(),
+                None => {
+                    let custom = config.customjs_path.iter().flat_map(CustomJs::indexed).filter_map(|(index, entry)| match entry {
+                        JsEntry::Path(path) => Some((index, path.as_str())),
+                        JsEntry::Inline { .. } => None,
+                    });
+                    let mut paths = custom.chain(config.preload_path.as_deref().map(|path| (None, path)));
+                    paths.find_map(|(index, path)| match (JsSource::parse(path), index) {
+                        (Ok(_), _) => None,
+                        (Err(err), Some(index)) => Some(element(index, err)),
+                        (Err(err), None) => Some(err),
+                    })
+                }
+            },
+        },
+    }
+}
+
+/// Upgrade the options of a config file to [CONFIG_VERSION], returning the upgraded options and the version that the file was for.
+/// Files without a `version` option are version 0, and files for a newer version than this program understands are refused
+fn upgrade(mut options: Options) -> Result<(Options, u32), Error> {
+    let version = match options.get("version") {
+        Some(version) => match version.as_u64().and_then(|version| std::convert::TryFrom::try_from(version).ok()) {
+            Some(version) => version,
+            None => return Err(Error::Invalid(vec![("version".into(), format!("expected a version number, found {}", version))])),
+        },
+        None => 0,
+    };
+    if version > CONFIG_VERSION {
+        return Err(Error::TooNew(version));
+    }
+
+    options = MIGRATIONS[version as usize..]
+        .iter()
+        .fold(options, |options, migration| migration(options));
+    options.insert("version".into(), CONFIG_VERSION.into());
+    Ok((options, version))
+}
+
+/// Get an [Error::Invalid] for one option
+fn invalid(key: String, err: String) -> Error {
+    Error::Invalid(vec![(key, err)])
+}
+
+/// Remove the `flavors` table from the options of a config file, returning the top level options and the table of options for each
+/// flavor
+fn split_flavors(mut options: Options) -> Result<(Options, Options), Error> {
+    let flavors = match options.remove("flavors") {
+        None => Options::new(),
+        Some(serde_json::Value::Object(flavors)) => flavors,
+        Some(other) => return Err(invalid("flavors".into(), format!("expected a table of flavors, found {}", other))),
+    };
+    for (name, table) in flavors.iter() {
+        if Flavor::from_name(name).is_none() {
+            return Err(invalid(format!("flavors.{}", name), "unknown Discord flavor, expected stable, ptb, or canary".into()));
+        }
+        match table.as_object() {
+            Some(table) => {
+                if let Some(key) = table.keys().find(|key| *key == "version" || *key == "flavors") {
+                    return Err(invalid(format!("flavors.{}.{}", name, key), "can only be set at the top level".into()));
+                }
+            }
+            None => return Err(invalid(format!("flavors.{}", name), format!("expected a table of options, found {}", table))),
+        }
+    }
+    Ok((options, flavors))
+}
+
+/// Merge the options in the `[flavors.<name>]` table of the given flavor over the top level options of a config file, returning the
+/// merged options and the keys that the flavor's table set
+fn merge_flavor(options: Options, flavor: Flavor) -> Result<(Options, Vec<String>), Error> {
+    let (mut options, mut flavors) = split_flavors(options)?;
+    let table = match flavors.remove(flavor.name()) {
+        Some(serde_json::Value::Object(table)) => table,
+        _ => Options::new(),
+    };
+    let keys = table.keys().cloned().collect();
+    options.extend(table);
+    Ok((options, keys))
+}
+
+/// Upgrade a config file without a version to version 1. Files without a version were read by a parser that ignored unknown options
+/// and used the default value of options that were `null` or had the wrong type, so those options are removed to keep using the
+/// defaults
+fn migrate_v0(options: Options) -> Options {
+    options
+        .into_iter()
+        .filter_map(|(key, val)| match val {
+            //Each flavor's table is cleaned up the same way as the top level
+            serde_json::Value::Object(flavors) if key == "flavors" => {
+                let flavors = flavors.into_iter().map(|(name, table)| match table {
+                    serde_json::Value::Object(table) => (name, serde_json::Value::Object(migrate_v0(table))),
+                    other => (name, other),
+                });
+                Some((key, serde_json::Value::Object(flavors.collect())))
+            }
+            val if !val.is_null() && option_error(&key, &val).is_none() => Some((key, val)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Get the options of a config file that an upgrade removed, with the options of a flavor's table as `flavors.<name>.<key>`
+fn removed_options(old: &Options, upgraded: &Options) -> Vec<String> {
+    let mut removed = Vec::new();
+    for (key, val) in old.iter().filter(|(_, val)| !val.is_null()) {
+        match (val, upgraded.get(key)) {
+            (serde_json::Value::Object(flavors), Some(serde_json::Value::Object(kept))) if key == "flavors" => {
+                for (name, table) in flavors.iter() {
+                    let kept = kept.get(name).and_then(serde_json::Value::as_object);
+                    let gone = table.as_object().into_iter().flatten().filter(|(option, val)| !val.is_null() && !kept.is_some_and(|kept| kept.contains_key(*option)));
+                    removed.extend(gone.map(|(option, _)| format!("flavors.{}.{}", name, option)));
+                }
+            }
+            (_, None) => removed.push(key.clone()),
+            _ => (),
+        }
+    }
+    removed
+}
+
+/// Split an option from [removed_options] that was in a flavor's table into the flavor's name and the option
+fn removed_flavor_option(key: &str) -> Option<(&str, &str)> {
+    key.strip_prefix("flavors.")?.split_once('.')
+}
+
+/// Get the path that a config file is backed up to before it is upgraded from the given version
+pub fn migration_backup_path(path: &Path, version: u32) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(format!(".v{}.backup", version));
+    path.with_file_name(name)
+}
+
+/// Get the directory that the config file is stored in by default
+pub fn default_dir() -> PathBuf {
+    userdata::config_dir()
+}
+
+/// Get the path of the config file that options are loaded from, which is `path` if the `--config` flag was given. Otherwise the
+/// [CONFIG_NAME] file in [default_dir] is preferred over a [LEGACY_CONFIG_NAME] file there, and a config file left in the current
+/// directory by older versions is moved into [default_dir] first
+pub fn resolve_path(path: Option<&Path>) -> PathBuf {
+    if let Some(path) = path {
+        return path.to_owned();
+    }
+
+    let dir = default_dir();
+    if let Some(existing) = find_in(&dir) {
+        return existing;
+    }
+
+    //Older versions kept the config file in whatever directory the program was run from, so move it to the config directory
+    if let Some(legacy) = find_in(Path::new(".")) {
+        let dest = dir.join(legacy.file_name().unwrap_or_default());
+        match move_file(&legacy, &dest) {
+            Ok(()) => {
+                output::info(style(msg!("moved-config-file", legacy.display(), dest.display())).yellow());
+                return dest;
+            }
+            Err(e) => {
+                output::warning(output::Category::Config, style(msg!("failed-move-config-file-using", legacy.display(), dest.display(), e)).fg(console::Color::Color256(172)));
+                return legacy;
+            }
+        }
+    }
+    dir.join(CONFIG_NAME)
+}
+
+/// Find the config file in a directory, preferring [CONFIG_NAME] over [LEGACY_CONFIG_NAME]
+fn find_in(dir: &Path) -> Option<PathBuf> {
+    [CONFIG_NAME, LEGACY_CONFIG_NAME]
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Format a TOML value to fit on the line that sets an option, with tables written inline like `{ inline = "..." }`
+pub fn inline_toml(value: &toml::Value) -> String {
+    match value {
+        toml::Value::Array(items) => format!("[{}]", items.iter().map(inline_toml).collect::<Vec<_>>().join(", ")),
+        toml::Value::Table(table) => {
+            let bare = |key: &str| !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            let pairs = table.iter().map(|(key, val)| match bare(key) {
+                true => format!("{} = {}", key, inline_toml(val)),
+                false => format!("{} = {}", toml::Value::String(key.clone()), inline_toml(val)),
+            });
+            format!("{{ {} }}", pairs.collect::<Vec<_>>().join(", "))
+        }
+        _ => value.to_string(),
+    }
+}
+
+/// Get how many lines the option set on `lines[start]` takes up, which is more than one for an array or string that is split
+/// across lines
+pub fn toml_option_lines(lines: &[String], start: usize) -> usize {
+    (start + 1..=lines.len())
+        .find(|&end| toml::from_str::<toml::Value>(&lines[start..end].join("\n")).is_ok())
+        .map_or(1, |end| end - start)
+}
+
+/// Set a top level option in the contents of a TOML file without touching any other line, replacing the lines that set it or
+/// uncommenting an example line like `# key = value`. If `value` is `None` then the option is commented out
+fn set_toml_option(contents: &str, key: &str, value: Option<&toml::Value>) -> String {
+    //Get the value of a line that sets the key, and wether the line is commented out
+    let parse = |line: &str| -> Option<(bool, String)> {
+        let trimmed = line.trim();
+        let (commented, rest) = match trimmed.strip_prefix('#') {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, trimmed),
+        };
+        let val = rest.strip_prefix(key)?.trim_start().strip_prefix('=')?;
+        Some((commented, val.trim().to_owned()))
+    };
+
+    let mut lines = contents.lines().map(str::to_owned).collect::<Vec<_>>();
+    //Only options before the first table header are top level
+    let top_level = lines.iter().position(|line| line.trim_start().starts_with('[')).unwrap_or(lines.len());
+    let active = (0..top_level).find(|&i| matches!(parse(&lines[i]), Some((false, _))));
+    let example = (0..top_level).find(|&i| matches!(parse(&lines[i]), Some((true, _))));
+
+    match (value, active, example) {
+        (Some(value), Some(i), _) => {
+            let end = i + toml_option_lines(&lines, i);
+            lines.splice(i..end, Some(format!("{} = {}", key, inline_toml(value))));
+        }
+        (Some(value), None, Some(i)) => lines[i] = format!("{} = {}", key, inline_toml(value)),
+        (Some(value), None, None) => lines.insert(top_level, format!("{} = {}", key, inline_toml(value))),
+        (None, Some(i), _) => {
+            let old = parse(&lines[i]).map(|(_, old)| old).unwrap_or_default();
+            let end = i + toml_option_lines(&lines, i);
+            lines[i] = format!("# {} = {}", key, old);
+            lines[i + 1..end].iter_mut().for_each(|line| *line = format!("# {}", line));
+        }
+        (None, None, _) => (),
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Set values of a table in the contents of a TOML file without touching any other line, replacing the lines that set them and adding
+/// the rest at the end of the table. A value of `None` comments out the line that sets it. The table is added to the end of the file if
+/// it isn't there and has values
+fn set_toml_table<'a>(contents: &str, table: &str, values: impl IntoIterator<Item = (&'a str, Option<toml::Value>)>) -> String {
+    let values = values.into_iter().collect::<Vec<_>>();
+    let header = format!("[{}]", table);
+    let mut lines = contents.lines().map(str::to_owned).collect::<Vec<_>>();
+    let start = match lines.iter().position(|line| line.trim() == header) {
+        Some(start) => start + 1,
+        None if values.iter().all(|(_, value)| value.is_none()) => return contents.to_owned(),
+        None => {
+            if lines.last().map(|line| !line.trim().is_empty()).unwrap_or(false) {
+                lines.push(String::new());
+            }
+            lines.push(header);
+            lines.len()
+        }
+    };
+    let mut end = lines[start..]
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .map(|i| i + start)
+        .unwrap_or(lines.len());
+    //Add new values after the table's last value instead of after the blank lines before the next table
+    let mut insert = (start..end).rev().find(|&i| !lines[i].trim().is_empty()).map(|i| i + 1).unwrap_or(start);
+
+    for (key, value) in values.iter() {
+        let existing = (start..end).find(|&i| {
+            let trimmed = lines[i].trim();
+            !trimmed.starts_with('#') && trimmed.strip_prefix(*key).map(|rest| rest.trim_start().starts_with('=')).unwrap_or(false)
+        });
+        match (existing, value) {
+            (Some(i), Some(value)) => {
+                let old = i + toml_option_lines(&lines, i);
+                lines.splice(i..old, Some(format!("{} = {}", key, inline_toml(value))));
+                let removed = old - i - 1;
+                end -= removed;
+                insert -= removed.min(insert.saturating_sub(i + 1));
+            }
+            (Some(i), None) => {
+                let old = i + toml_option_lines(&lines, i);
+                lines[i..old].iter_mut().for_each(|line| *line = format!("# {}", line));
+            }
+            (None, Some(value)) => {
+                lines.insert(insert, format!("{} = {}", key, inline_toml(value)));
+                insert += 1;
+                end += 1;
+            }
+            (None, None) => (),
         }
     }
+    lines.join("\n") + "\n"
+}
+
+/// Move a file, copying it if it can't be renamed because the destination is on another drive
+fn move_file(src: &Path, dest: &Path) -> io::Result<()> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(src, dest).is_err() {
+        fs::copy(src, dest)?;
+        fs::remove_file(src)?;
+    }
+    Ok(())
+}
+
+impl Config {
+    /// Create a default config file at the given path with default values and return a default instance of self
+    fn default_file(path: &Path) -> Self {
+        let config = Self {
+            path: path.to_owned(),
+            ..Self::default()
+        };
+        //Write the TOML configuration to the default file location
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap_or_else(|e| panic!("{}", msg!("failed-create-config-directory", parent.display(), e)));
+        }
+        std::fs::write(path, config.to_toml()).unwrap_or_else(|e| panic!("{}", msg!("failed-write-default-config-file", path.display(), e)));
+        config
+    }
+
+    /// Load a configuration file from the given path, which should come from [resolve_path], or load defaults and create the file.
+    /// Options in the file are overridden by the `[flavors.<name>]` table of the given flavor, then by environment variables, and then
+    /// by command line flags in `overrides`
+    pub fn load(path: &Path, overrides: &Overrides, flavor: Flavor) -> Self {
+        let (file, flavor_keys) = match path.exists() {
+            true => match Self::read_options(path)
+                .and_then(|options| Self::upgrade_file(path, options))
+                .and_then(|options| merge_flavor(options, flavor))
+            {
+                Ok(merged) => merged,
+                Err(e @ Error::TooNew(_)) => panic!("{}", msg!("refusing-use-config-file", path.display(), e)),
+                Err(e) => {
+                    output::warning(output::Category::Config, format!("{} {}", style(msg!("failed-parse-using-default-options", path.display())).red(), e));
+                    (Options::new(), Vec::new())
+                }
+            },
+            false => {
+                Self::default_file(path); //Create the default file and use default values for every option
+                (Options::new(), Vec::new())
+            }
+        };
+
+        let mut config = Self::from_layers(file, overrides).unwrap_or_else(|e| {
+            output::warning(output::Category::Config, format!("{} {}", style(msg!("invalid-options-environment-using-default", path.display())).red(), e));
+            Self::default()
+        });
+        for key in flavor_keys {
+            if config.source(&key) == Source::File {
+                config.sources.insert(key, Source::Flavor);
+            }
+        }
+        Self {
+            path: path.to_owned(),
+            flavor,
+            ..config
+        }
+    }
+
+    /// Upgrade the options read from the config file at `path` to [CONFIG_VERSION]. If the file was for an older version then it is
+    /// backed up with [migration_backup_path] and rewritten with the upgraded options
+    fn upgrade_file(path: &Path, options: Options) -> Result<Options, Error> {
+        let (upgraded, version) = upgrade(options.clone())?;
+        if version == CONFIG_VERSION {
+            return Ok(upgraded);
+        }
+
+        //Comment out the options that were removed so that the user can still see what they were
+        let removed = removed_options(&options, &upgraded);
+        let removed = removed.iter().map(String::as_str).collect::<Vec<_>>();
+        let backup = migration_backup_path(path, version);
+        let result = fs::copy(path, &backup).map_err(Error::from).and_then(|_| {
+            let (top_level, _) = split_flavors(upgraded.clone())?;
+            Self {
+                path: path.to_owned(),
+                ..Self::from_layers(top_level, &Overrides::default())?
+            }
+            .write(&removed, None)
+        });
+        match result {
+            Ok(()) => {
+                output::info(style(msg!("upgraded-config-file-version-old", path.display(), version, CONFIG_VERSION, backup.display())).yellow());
+                if !removed.is_empty() {
+                    output::warning(output::Category::Config, style(msg!("unknown-invalid-options-were-removed", removed.join(", "))).fg(console::Color::Color256(172)));
+                }
+            }
+            Err(e) => output::warning(output::Category::Config, style(msg!("failed-rewrite-upgraded-config-file", path.display(), e)).fg(console::Color::Color256(172))),
+        }
+        Ok(upgraded)
+    }
 
-    /// Load a configuration file from the `CONFIG_PATH` file or load defaults and create the file
-    pub fn load() -> Self {
-        match fs::read_to_string(CONFIG_PATH) {
-            Ok(buf) => {
-                let config =
-                    match buf.parse::<serde_json::Value>() {
-                        //Make a toml from the file's contents
-                        Ok(toml) => toml, //Return the TOML value
-                        Err(e) => {
-                            eprintln!(
-                            "{} {}",
-                            style("Failed to parse config.json, switching to default file. Error: ")
-                                .red(),
-                            e
-                        );
-                            return Self::default_file();
-                        } //Return a default file if there was an error
-                    };
-
-                // Get path to the custom javascript file or null
-                let customjs = config
-                    .get("custom-js")
-                    .map(serde_json::Value::as_str)
-                    .flatten();
-
-                //Read the file from the path or an empty string
-                let customjs = match customjs {
-                    Some(path) => match fs::read_to_string(path) {
-                        Ok(s) => s
-                            .replace("`", "\\`") //Escape any characters that would mess up Discord's files
-                            .replace("\\", "\\\\"),
-                        Err(e) => panic!("Failed to open custom javscript file {}: {}", path, e),
-                    },
-                    None => "".to_owned(),
+    /// Get where the value of an option came from
+    pub fn source(&self, key: &str) -> Source {
+        self.sources.get(key).copied().unwrap_or(Source::Default)
+    }
+
+    /// Read the custom javascript from `customjs_path` into `customjs`, escaping it to be inserted into Discord's files, and the
+    /// preload javascript from `preload_path` into `preload_js`, which runs as it is. Javascript at a URL is downloaded with `download`,
+    /// which is given the URL and the hash it is pinned to. Because the javascript runs inside Discord, URLs that aren't pinned are
+    /// refused unless `allow_unpinned_remote_js` is set. The scripts of a `custom-js` array are joined in order, and inline scripts
+    /// are used as they are
+    pub fn resolve_custom_js(&mut self, mut download: impl FnMut(&str, Option<&str>) -> Result<String, String>) -> Result<(), Error> {
+        if let Some(source) = &self.customjs_path {
+            let mut scripts = Vec::new();
+            for (index, entry) in source.indexed() {
+                let js = match entry {
+                    JsEntry::Path(path) => self.read_js("custom-js", path, &mut download),
+                    JsEntry::Inline { inline } => Ok(inline.clone()),
                 };
+                scripts.push(js.map_err(|err| match index {
+                    Some(index) => Error::JsElement { index, err: Box::new(err) },
+                    None => err,
+                })?);
+            }
+            let js = scripts.join("\n");
+            self.customjs_hash = Some(manifest::hash_bytes(js.as_bytes()));
+            self.customjs = escape::template_literal(&js); //Escape any characters that would mess up Discord's files
+        }
+        if let Some(source) = &self.preload_path {
+            self.preload_js = self.read_js("custom-js-preload", source, &mut download)?;
+        }
+        Ok(())
+    }
+
+    /// Decide if the custom javascript that [Config::resolve_custom_js] read can be inserted. Javascript that doesn't have the hash in
+    /// `custom_js_sha256` is refused unless `accept_change` is set, because a script that changed without the user knowing could be
+    /// stealing their account. Pinning is only offered for a `custom-js` set in the config file, where the hash is saved next to it
+    pub fn check_js_pin(&self, accept_change: bool) -> Result<JsPin, Error> {
+        let actual = match &self.customjs_hash {
+            Some(actual) => actual,
+            None => return Ok(JsPin::Unchanged),
+        };
+        match &self.custom_js_sha256 {
+            Some(expected) if expected.eq_ignore_ascii_case(actual) => Ok(JsPin::Unchanged),
+            Some(_) if accept_change => Ok(JsPin::Accepted(actual.clone())),
+            Some(expected) => Err(Error::JsChanged {
+                source: self.customjs_path.as_ref().map(ToString::to_string).unwrap_or_default(),
+                expected: expected.clone(),
+                actual: actual.clone(),
+            }),
+            None if self.pin_custom_js && self.source("custom-js") == Source::File => Ok(JsPin::Offer(actual.clone())),
+            None => Ok(JsPin::Unchanged),
+        }
+    }
+
+    /// Read javascript from the value of the `key` option, see [Config::resolve_custom_js]
+    fn read_js(
+        &self,
+        key: &str,
+        source: &str,
+        download: &mut impl FnMut(&str, Option<&str>) -> Result<String, String>,
+    ) -> Result<String, Error> {
+        match JsSource::parse(source).map_err(|err| Error::Invalid(vec![(key.into(), err)]))? {
+            JsSource::File(path) => fs::read(path)
+                .and_then(|data| text::load(path, data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+                .map_err(|err| Error::CustomJs { path: path.to_owned(), err }),
+            JsSource::Url { url, sha256: None } if !self.allow_unpinned_remote_js => Err(Error::UnpinnedJs { url: url.to_owned() }),
+            JsSource::Url { url, sha256 } => download(url, sha256).map_err(|err| Error::RemoteJs { url: url.to_owned(), err }),
+        }
+    }
+
+    /// Get the preload javascript to insert at the top of the patched file, or `None` if there is none or javascript isn't inserted
+    pub fn preload(&self) -> Option<&str> {
+        Some(self.preload_js.as_str()).filter(|js| self.inject_js && self.preload_path.is_some() && !js.is_empty())
+    }
+
+    /// Parse the config file at the given path as TOML, or as JSON if it has a `.json` extension
+    fn parse(path: &Path) -> Result<Self, Error> {
+        let (options, _) = upgrade(Self::read_options(path)?)?;
+        let (options, _) = split_flavors(options)?;
+        Self::from_layers(options, &Overrides::default())
+    }
+
+    /// Read the options in the config file at the given path without checking them
+    fn read_options(path: &Path) -> Result<serde_json::Map<String, serde_json::Value>, Error> {
+        let buf = fs::read_to_string(path)?;
+        Self::options(&buf, path.extension().and_then(|ext| ext.to_str()) == Some("json"))
+    }
+
+    /// Parse the contents of a config file as JSON or TOML
+    #[cfg(test)]
+    fn from_str(buf: &str, json: bool) -> Result<Self, Error> {
+        let (options, _) = upgrade(Self::options(buf, json)?)?;
+        let (options, _) = split_flavors(options)?;
+        Self::from_layers(options, &Overrides::default())
+    }
+
+    /// Parse the contents of a config file as JSON or TOML into a map of options without checking them
+    fn options(buf: &str, json: bool) -> Result<serde_json::Map<String, serde_json::Value>, Error> {
+        let options = match json {
+            true => serde_json::from_str::<serde_json::Value>(buf)?,
+            false => serde_json::to_value(toml::from_str::<toml::Value>(buf)?)?,
+        };
+        match options {
+            serde_json::Value::Object(map) => Ok(map),
+            _ => Err(Error::Json(serde::de::Error::custom("the config file must be an object of options"))),
+        }
+    }
 
-                Self {
-                    customjs,
-                    make_backup: config
-                        .get("make-backup")
-                        .unwrap_or(&serde_json::Value::Bool(true))
-                        .as_bool()
-                        .unwrap_or(true), //Get wether or not to make a backup of the electron file
-                    replace_icon: config
-                        .get("replace-icon")
-                        .unwrap_or(&serde_json::Value::Bool(true))
-                        .as_bool()
-                        .unwrap_or(true),
+    /// Build a config from the options in a config file with `overrides` applied on top of them. Every unknown or invalid option is
+    /// reported with its name instead of only the first one
+    fn from_layers(file: serde_json::Map<String, serde_json::Value>, overrides: &Overrides) -> Result<Self, Error> {
+        let defaults = Self::default().to_json();
+        let mut sources = file.keys().map(|key| (key.clone(), Source::File)).collect::<BTreeMap<_, _>>();
+        let mut options = file;
+        let mut invalid = Vec::new();
+
+        for (name, val) in overrides.env.iter() {
+            //Find the option that the environment variable overrides
+            let key = name[ENV_PREFIX.len()..].to_lowercase().replace('_', "-");
+            match defaults.get(&key) {
+                Some(default) => match env_value(val, default) {
+                    Ok(val) => {
+                        options.insert(key.clone(), val);
+                        sources.insert(key, Source::Env);
+                    }
+                    Err(e) => invalid.push((name.clone(), e)),
+                },
+                None => invalid.push((name.clone(), format!("there is no config option named '{}'", key))),
+            }
+        }
+        for (key, val) in overrides.cli.iter() {
+            options.insert((*key).to_owned(), val.clone());
+            sources.insert((*key).to_owned(), Source::Cli);
+        }
+
+        //Deserialize every option by itself to find all of the ones that are wrong
+        invalid.extend(options.iter().filter_map(|(key, val)| option_error(key, val).map(|e| (key.clone(), e))));
+        if !invalid.is_empty() {
+            return Err(Error::Invalid(invalid));
+        }
+
+        let config = Self::deserialize(&serde_json::Value::Object(options))?;
+        Ok(Self { sources, ..config })
+    }
+
+    /// Get a copy of this config with every option that was set by an environment variable or command line flag reset to its value in
+    /// `base`, so that overrides aren't saved to the config file
+    fn without_overrides(&self, base: &Self) -> Self {
+        let (mut options, base_options) = match (self.to_json(), base.to_json()) {
+            (serde_json::Value::Object(options), serde_json::Value::Object(base)) => (options, base),
+            _ => unreachable!("Configs always serialize to objects"),
+        };
+        for (key, _) in self.sources.iter().filter(|(_, source)| **source > Source::File) {
+            if let Some(val) = base_options.get(key) {
+                options.insert(key.clone(), val.clone());
+            }
+        }
+        let config = Self::deserialize(&serde_json::Value::Object(options)).unwrap_or_else(|_| self.clone());
+        Self {
+            path: self.path.clone(),
+            ..config
+        }
+    }
+
+    /// Convert the JSON config file at the given path to a [CONFIG_NAME] file next to it with the same values, returning the path
+    /// that was written
+    pub fn migrate(json: &Path) -> Result<PathBuf, Error> {
+        let config = Self::parse(json)?;
+        let dest = json.with_file_name(CONFIG_NAME);
+        fs::write(&dest, config.to_toml())?;
+        Ok(dest)
+    }
+
+    /// Get every option of this config as a comment explaining it, its key, its value or `None` if it isn't set, and an example value
+    /// to write for options that aren't set
+    fn toml_options(&self) -> Vec<(&'static str, &'static str, Option<toml::Value>, String)> {
+        let string = |s: &str| toml::Value::String(s.to_owned());
+        let path = |p: &PathBuf| string(&p.to_string_lossy());
+        vec![
+            (
+                "The version of this file's layout, which is used to upgrade it when options change. Don't change this",
+                "version",
+                Some(toml::Value::Integer(self.version.into())),
+                String::new(),
+            ),
+            (
+                "Path or https:// URL of a javascript file to run along with the theme, only for people who know what they're doing. Pin URLs to a hash with #sha256=<hash> at the end. Short scripts can be written here as { inline = \"...\" }, and an array of both runs them in order",
+                "custom-js",
+                self.customjs_path.as_ref().and_then(|js| toml::Value::try_from(js).ok()),
+                "\"custom.js\"".into(),
+            ),
+            (
+                "Path or https:// URL of a javascript file to run when Discord starts, before its window is created. Unlike custom-js it runs in Discord's main process",
+                "custom-js-preload",
+                self.preload_path.as_deref().map(string),
+                "\"preload.js\"".into(),
+            ),
+            (
+                "Title to keep Discord's window at instead of the title that Discord sets, like the title of old Discord versions. At most 128 characters",
+                "window-title",
+                self.window_title.as_deref().map(string),
+                "\"Discord\"".into(),
+            ),
+            (
+                "Allow custom-js URLs that aren't pinned to a hash, which runs whatever the server sends inside Discord",
+                "allow-unpinned-remote-js",
+                Some(toml::Value::Boolean(self.allow_unpinned_remote_js)),
+                String::new(),
+            ),
+            (
+                "SHA-256 hash that the custom-js script must have. The script is refused when it changes until it is accepted with --accept-js-change",
+                "custom-js-sha256",
+                self.custom_js_sha256.as_deref().map(string),
+                format!("\"{}\"", "0".repeat(64)),
+            ),
+            (
+                "Offer to pin the custom-js script's hash when custom-js-sha256 isn't set. This is turned off when the offer is declined",
+                "pin-custom-js",
+                Some(toml::Value::Boolean(self.pin_custom_js)),
+                String::new(),
+            ),
+            (
+                "Insert the blocks that run custom javascript into Discord. Set this to false to only insert the CSS theme, which ignores custom-js and custom-js-preload",
+                "inject-js",
+                Some(toml::Value::Boolean(self.inject_js)),
+                String::new(),
+            ),
+            (
+                "How the theme is added to Discord's window: \"execute-js\" runs javascript that adds it, \"insert-css\" adds it with insertCSS, which works when Discord blocks the javascript",
+                "inject-strategy",
+                Some(string(self.inject_strategy.name())),
+                String::new(),
+            ),
+            (
+                "Wether to back up Discord's files before patching them, so that they can be restored later",
+                "make-backup",
+                Some(toml::Value::Boolean(self.make_backup)),
+                String::new(),
+            ),
+            (
+                "Wether to replace Discord's icon with the old icon",
+                "replace-icon",
+                Some(toml::Value::Boolean(self.replace_icon)),
+                String::new(),
+            ),
+            (
+                "Path or https:// URL of an icon to use instead of the old icon, a .png file or a .ico file on Windows",
+                "custom-icon",
+                self.custom_icon.as_deref().map(string),
+                "\"icon.png\"".into(),
+            ),
+            (
+                "Path to Discord's app bundle on macOS",
+                "mac-bundle",
+                self.mac_bundle.as_ref().map(path),
+                format!("{:?}", icon::MAC_BUNDLE),
+            ),
+            (
+                "Wether to compress new backups with gzip",
+                "compress-backups",
+                Some(toml::Value::Boolean(self.compress_backups)),
+                String::new(),
+            ),
+            (
+                "Folder to store backups in, defaults to your platform's data folder",
+                "backup-dir",
+                self.backup_dir.as_ref().map(path),
+                "\"backups\"".into(),
+            ),
+            (
+                "Folder to write Discord's archive to before it replaces the real one, which must be on the same drive as Discord. Defaults to the folder the archive is in",
+                "work-dir",
+                self.work_dir.as_ref().map(path),
+                "\"D:\\\\discord-theme-work\"".into(),
+            ),
+            (
+                "What to back up: \"archive\" for Discord's whole core.asar or \"file\" for only the patched file",
+                "backup-mode",
+                Some(string(self.backup_mode.name())),
+                String::new(),
+            ),
+            (
+                "https:// URL to download the newest theme from instead of the one selected by channel",
+                "theme-url",
+                self.theme_url.as_deref().map(string),
+                "\"https://example.com/theme.css\"".into(),
+            ),
+            (
+                "SHA-256 hash that the downloaded theme must have, instead of the one published next to the theme",
+                "theme-sha256",
+                self.theme_sha256.as_deref().map(string),
+                format!("\"{}\"", "0".repeat(64)),
+            ),
+            (
+                "Path of a CSS theme to use during the day. When theme-dark is also set, both are inserted and switched automatically",
+                "theme-light",
+                self.theme_light.as_deref().map(string),
+                "\"light.css\"".into(),
+            ),
+            (
+                "Path of a CSS theme to use at night. When theme-light is also set, both are inserted and switched automatically",
+                "theme-dark",
+                self.theme_dark.as_deref().map(string),
+                "\"dark.css\"".into(),
+            ),
+            (
+                "Hours to use theme-dark in, like \"19-7\" for 19:00 until 7:00. Defaults to following your system's dark mode",
+                "dark-hours",
+                self.dark_hours.as_deref().map(string),
+                "\"19-7\"".into(),
+            ),
+            (
+                "https:// URL of the index of themes to pick from with \"Browse online themes\" in the menu",
+                "catalog-url",
+                self.catalog_url.as_deref().map(string),
+                "\"https://example.com/catalog.json\"".into(),
+            ),
+            (
+                "Which branch to download the newest theme from: \"stable\" or \"dev\" for changes that aren't released yet",
+                "channel",
+                Some(string(self.channel.name())),
+                String::new(),
+            ),
+            (
+                "URL of a proxy to download through, defaults to the HTTPS_PROXY or HTTP_PROXY environment variables",
+                "proxy",
+                self.proxy.as_deref().map(string),
+                "\"http://proxy.example.com:8080\"".into(),
+            ),
+            (
+                "How many seconds to wait for a server before a download fails",
+                "network-timeout-secs",
+                Some(toml::Value::Integer(self.network_timeout_secs as i64)),
+                String::new(),
+            ),
+            (
+                "Accept any TLS certificate when downloading. This is dangerous, only use it if your network intercepts TLS",
+                "insecure-skip-tls-verify",
+                Some(toml::Value::Boolean(self.insecure_skip_tls_verify)),
+                String::new(),
+            ),
+            (
+                "How many times to try downloading the theme before using the last downloaded copy",
+                "download-attempts",
+                Some(toml::Value::Integer(self.download_attempts.into())),
+                String::new(),
+            ),
+            (
+                "Never connect to the network, using the last downloaded theme or the one this program was compiled with instead",
+                "offline",
+                Some(toml::Value::Boolean(self.offline)),
+                String::new(),
+            ),
+            (
+                "How many days a download is kept in the download cache after it was last fetched. 0 keeps downloads forever",
+                "cache-max-age-days",
+                Some(toml::Value::Integer(self.cache_max_age_days as i64)),
+                String::new(),
+            ),
+            (
+                "How many MiB the download cache can take up before the oldest downloads are evicted. 0 is no limit",
+                "cache-max-mib",
+                Some(toml::Value::Integer(self.cache_max_mib as i64)),
+                String::new(),
+            ),
+            (
+                "When to show a desktop notification of what was done: \"background\" for the scheduled check only, \"always\", or \"never\"",
+                "notify",
+                Some(string(self.notify.name())),
+                String::new(),
+            ),
+            (
+                "Size in KiB of the escaped theme and custom javascript above which a warning that they slow down Discord's startup is shown",
+                "theme-size-warn-kib",
+                Some(toml::Value::Integer(self.theme_size_warn_kib as i64)),
+                String::new(),
+            ),
+            (
+                "Size in KiB of the escaped theme and custom javascript above which they are refused unless --force is passed",
+                "theme-size-limit-kib",
+                Some(toml::Value::Integer(self.theme_size_limit_kib as i64)),
+                String::new(),
+            ),
+            (
+                "How many applies the backup manifest remembers, with the exact theme and javascript, so that --from-manifest <n> can apply them again. 0 remembers none",
+                "manifest-history",
+                Some(toml::Value::Integer(self.manifest_history as i64)),
+                String::new(),
+            ),
+            (
+                "How many runs are kept in history.jsonl in the data folder for the history command. It is never sent anywhere and holds no paths or theme names. 0 keeps none",
+                "run-history",
+                Some(toml::Value::Integer(self.run_history as i64)),
+                String::new(),
+            ),
+            (
+                "Language to show messages in, like \"de\" for languages/de.json next to this file, or the path of a JSON file of messages. Defaults to the LANG environment variable",
+                "language",
+                self.language.as_deref().map(string),
+                "\"de\"".into(),
+            ),
+            (
+                "Optional tweaks to add to the old theme, which are picked from a list when it is applied from the menu",
+                "tweaks",
+                self.tweaks.as_ref().map(|tweaks| toml::Value::Array(tweaks.iter().map(|tweak| string(tweak)).collect())),
+                "[\"compact\", \"old-font\"]".into(),
+            ),
+            (
+                "Wether to apply the old CSS theme when the old look is applied from the menu, where it can be picked separately from the icon",
+                "old-css",
+                Some(toml::Value::Boolean(self.old_css)),
+                String::new(),
+            ),
+            (
+                "Wether to show the old app name in the title bar when the old look is applied from the menu",
+                "old-title",
+                Some(toml::Value::Boolean(self.old_title)),
+                String::new(),
+            ),
+        ]
+    }
+
+    /// Get the options of this config as a TOML file with a comment explaining every option. Options that aren't set are written
+    /// commented out with an example value
+    pub fn to_toml(&self) -> String {
+        let options = self
+            .toml_options()
+            .iter()
+            .map(|(comment, key, value, example)| match value {
+                Some(value) => format!("# {}\n{} = {}\n", comment, key, inline_toml(value)),
+                None => format!("# {}\n# {} = {}\n", comment, key, example),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let variables = "\n# Values of the variables that themes declare with comments like /* @var accent color \"Accent color\" #ff0066 */, \
+            which are asked for the first time a theme is applied\n";
+        set_toml_table(&(options + variables), "variables", self.variable_values())
+    }
+
+    /// Save this config to the file it was loaded from. The values in a TOML file are changed in place so that the user's comments are
+    /// kept, and the file is written to a temporary file that is renamed over the config so that it is never left half written
+    pub fn save(&self) -> Result<(), Error> {
+        //Keep the file's values for options that were overridden, and the defaults if the file doesn't exist yet
+        let base = match self.path.exists() {
+            true => Self::parse(&self.path).unwrap_or_default(),
+            false => Self::default(),
+        };
+        //Options that the selected flavor's table sets are saved back to that table, so the top level keeps its own values for them
+        let flavored = match self.to_json() {
+            serde_json::Value::Object(options) => options.into_iter().filter(|(key, _)| self.source(key) == Source::Flavor).collect(),
+            _ => Options::new(),
+        };
+        self.without_overrides(&base).write(&[], Some((self.flavor, &flavored)))
+    }
+
+    /// Mark an option that the user changed so that [Config::save] writes it even if it was overridden. An option that the selected
+    /// flavor's table sets stays in that table, and every other option is saved at the top level
+    pub fn mark_edited(&mut self, key: &str) {
+        if self.source(key) != Source::Flavor {
+            self.sources.insert(key.to_owned(), Source::File);
+        }
+    }
+
+    /// Get the theme variables as the values of the `[variables]` table
+    fn variable_values(&self) -> impl Iterator<Item = (&str, Option<toml::Value>)> {
+        self.variables.iter().map(|(key, val)| (key.as_str(), Some(toml::Value::String(val.clone()))))
+    }
+
+    /// Write this config to its file, commenting out the `removed` options in a TOML file and writing the `flavored` options to the
+    /// given flavor's table. Only the options that this program knows are changed in the file, so tables like `flavors` are kept. The
+    /// file is written to a temporary file that is renamed over the config so that it is never left half written
+    fn write(&self, removed: &[&str], flavored: Option<(Flavor, &Options)>) -> Result<(), Error> {
+        let flavored = flavored.filter(|(_, values)| !values.is_empty());
+        let contents = match self.path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => {
+                let mut doc = match fs::read_to_string(&self.path) {
+                    Ok(old) => Self::options(&old, true)?,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => Options::new(),
+                    Err(e) => return Err(e.into()),
+                };
+                for key in removed.iter() {
+                    match removed_flavor_option(key) {
+                        Some((name, option)) => {
+                            let table = doc.get_mut("flavors").and_then(|flavors| flavors.get_mut(name)).and_then(serde_json::Value::as_object_mut);
+                            table.map(|table| table.remove(option));
+                        }
+                        None => drop(doc.remove(*key)),
+                    }
+                }
+                if let serde_json::Value::Object(options) = self.to_json() {
+                    doc.extend(options);
                 }
+                if let Some((flavor, values)) = flavored {
+                    let flavors = doc.entry("flavors").or_insert_with(|| serde_json::Value::Object(Options::new()));
+                    let table = flavors.as_object_mut().map(|flavors| flavors.entry(flavor.name()).or_insert_with(|| serde_json::Value::Object(Options::new())));
+                    if let Some(table) = table.and_then(serde_json::Value::as_object_mut) {
+                        table.extend(values.clone());
+                    }
+                }
+                serde_json::to_string_pretty(&doc)?
             }
-            Err(_) => {
-                Self::default_file() //Create the default file and return the defualt instance of Self
+            _ => match fs::read_to_string(&self.path) {
+                Ok(old) => {
+                    let old = removed.iter().fold(old, |contents, key| match removed_flavor_option(key) {
+                        Some((name, option)) => set_toml_table(&contents, &format!("flavors.{}", name), [(option, None)]),
+                        None => set_toml_option(&contents, key, None),
+                    });
+                    let contents = self
+                        .toml_options()
+                        .iter()
+                        .fold(old, |contents, (_, key, value, _)| set_toml_option(&contents, key, value.as_ref()));
+                    let contents = set_toml_table(&contents, "variables", self.variable_values());
+                    match flavored {
+                        Some((flavor, values)) => set_toml_table(
+                            &contents,
+                            &format!("flavors.{}", flavor.name()),
+                            values.iter().map(|(key, val)| (key.as_str(), toml::Value::try_from(val).ok())),
+                        ),
+                        None => contents,
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => self.to_toml(),
+                Err(e) => return Err(e.into()),
+            },
+        };
+
+        let dir = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+        fs::create_dir_all(dir)?;
+        let mut temp = NamedTempFile::new_in(dir)?;
+        temp.write_all(contents.as_bytes())?;
+        temp.persist(&self.path).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    /// Get the options of this config as JSON, in the same format as the config file
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap()
+    }
+
+    /// Get the URL that the newest theme should be downloaded from
+    #[cfg_attr(not(feature = "autoupdate"), allow(dead_code))]
+    pub fn theme_url(&self) -> &str {
+        self.theme_url.as_deref().unwrap_or_else(|| self.channel.url())
+    }
+
+    /// Get the URL that the index of tweaks should be downloaded from, or `None` if the theme is downloaded from `theme_url`, which
+    /// doesn't publish one
+    #[cfg_attr(not(feature = "autoupdate"), allow(dead_code))]
+    pub fn tweaks_url(&self) -> Option<&str> {
+        match self.theme_url {
+            Some(_) => None,
+            None => Some(self.channel.tweaks_url()),
+        }
+    }
+
+    /// Get the URL that the index of themes should be downloaded from
+    #[cfg_attr(not(feature = "autoupdate"), allow(dead_code))]
+    pub fn catalog_url(&self) -> &str {
+        self.catalog_url.as_deref().unwrap_or(CATALOG_URL)
+    }
+
+    /// Get the directory that backups should be stored in
+    pub fn backup_dir(&self) -> PathBuf {
+        self.backup_dir
+            .clone()
+            .unwrap_or_else(backup::default_dir)
+    }
+
+    /// Get the path to Discord's app bundle on macOS
+    pub fn mac_bundle(&self) -> PathBuf {
+        self.mac_bundle
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(icon::MAC_BUNDLE))
+    }
+}
+
+/// The `Error` enum is every error that can happen when reading a config file
+#[derive(Debug)]
+pub enum Error {
+    /// The TOML config file is invalid
+    Toml(toml::de::Error),
+    /// The JSON config file is invalid
+    Json(serde_json::Error),
+    /// Some options in the config file are unknown or have the wrong type, given as the name of each option and what is wrong with it
+    Invalid(Vec<(String, String)>),
+    /// The config file is for a newer version of the config layout than [CONFIG_VERSION]
+    TooNew(u32),
+    /// The custom javascript file couldn't be read
+    CustomJs { path: String, err: io::Error },
+    /// The custom javascript URL isn't pinned to a hash and `allow-unpinned-remote-js` isn't set
+    UnpinnedJs { url: String },
+    /// The custom javascript couldn't be downloaded
+    RemoteJs { url: String, err: String },
+    /// One element of the `custom-js` array couldn't be read, given as its index and what went wrong
+    JsElement { index: usize, err: Box<Error> },
+    /// The custom javascript doesn't have the hash in `custom-js-sha256`
+    JsChanged { source: String, expected: String, actual: String },
+    /// The config file couldn't be read or written
+    IOErr(io::Error),
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Self {
+        Self::Toml(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::IOErr(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Toml(e) => write!(f, "Invalid TOML: {}", e),
+            Self::Json(e) => write!(f, "Invalid JSON: {}", e),
+            Self::Invalid(options) => {
+                write!(f, "Invalid options:")?;
+                options
+                    .iter()
+                    .try_for_each(|(key, err)| write!(f, "\n  '{}': {}", key, err))
+            }
+            Self::TooNew(version) => write!(
+                f,
+                "The config file is for version {} of the config layout but this program only understands up to version {}, update the program to use it",
+                version, CONFIG_VERSION
+            ),
+            Self::CustomJs { path, err } => write!(f, "Failed to open custom javascript file {}: {}", path, err),
+            Self::UnpinnedJs { url } => write!(
+                f,
+                "Refusing to download custom javascript from {} because it would run inside Discord and could be changed by whoever controls the URL. Add #sha256=<hash> to the end of the URL to pin the script you reviewed, or set allow-unpinned-remote-js = true in the config file to trust the URL",
+                url
+            ),
+            Self::RemoteJs { url, err } => write!(f, "Failed to download custom javascript from {}: {}", url, err),
+            Self::JsElement { index, err } => write!(f, "custom-js: {}", element(*index, err)),
+            Self::JsChanged { source, expected, actual } => write!(
+                f,
+                "Refusing to insert the custom javascript from {} because it changed since its hash was pinned:\n  expected: {}\n  actual:   {}\nIf you changed it yourself, check it and run again with --accept-js-change to pin the new hash",
+                source, expected, actual
+            ),
+            Self::IOErr(e) => write!(f, "An I/O error occurred: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn default_toml() {
+        let config = toml::from_str::<Config>(&Config::default().to_toml()).unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(toml::from_str::<Config>("").unwrap(), Config::default());
+    }
+
+    #[test]
+    pub fn valid_file() {
+        let config = Config::from_str("make-backup = false\ncustom-icon = \"icon.png\"\nbackup-mode = \"file\"\n", false).unwrap();
+        assert!(!config.make_backup);
+        assert_eq!(config.custom_icon.as_deref(), Some("icon.png"));
+        assert_eq!(config.backup_mode, BackupMode::File);
+        assert!(config.replace_icon);
+        assert_eq!(Config::from_str("inject-strategy = \"insert-css\"\n", false).unwrap().inject_strategy, Strategy::InsertCss);
+    }
+
+    #[test]
+    pub fn invalid_options() {
+        match Config::from_str("version = 1\nmake-backups = false\nreplace-icon = \"yes\"\ncompress-backups = true\n", false) {
+            Err(Error::Invalid(options)) => {
+                let keys = options.iter().map(|(key, _)| key.as_str()).collect::<Vec<_>>();
+                assert_eq!(keys, ["make-backups", "replace-icon"]);
+                assert!(options[0].1.contains("unknown field"));
+                assert!(options[1].1.contains("expected a boolean"));
             }
+            other => panic!("Expected invalid options, got {:?}", other),
+        }
+        assert!(matches!(Config::from_str(r#"{"version": 1, "backup-mode": "everything"}"#, true), Err(Error::Invalid(_))));
+        assert!(matches!(Config::from_str("version = 1\ndark-hours = \"7-7\"\n", false), Err(Error::Invalid(_))));
+        assert!(matches!(Config::from_str("version = 1\nwindow-title = \"Dis\\ncord\"\n", false), Err(Error::Invalid(_))));
+        let long = format!("version = 1\nwindow-title = \"{}\"\n", "a".repeat(patch::MAX_TITLE_CHARS + 1));
+        assert!(matches!(Config::from_str(&long, false), Err(Error::Invalid(_))));
+        assert_eq!(Config::from_str("version = 1\nwindow-title = \"Discord\"\n", false).unwrap().window_title.as_deref(), Some("Discord"));
+        assert!(matches!(Config::from_str("version = 1\ncustom-icon = \"http://example.com/icon.png\"\n", false), Err(Error::Invalid(_))));
+        assert!(matches!(Config::from_str("version = 1\ncustom-icon = \"HTTP://example.com/icon.png\"\n", false), Err(Error::Invalid(_))));
+        assert!(Config::from_str("version = 1\ncustom-icon = \"https://example.com/icon.png\"\n", false).is_ok());
+        assert!(matches!(Config::from_str("{", true), Err(Error::Json(_))));
+    }
+
+    #[test]
+    pub fn missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("discord-theme").join(CONFIG_NAME);
+        let config = Config::load(&path, &Overrides::default(), Flavor::Stable);
+        assert!(path.is_file());
+        assert_eq!(config, Config { path: path.clone(), ..Config::default() });
+        assert_eq!(Config::load(&path, &Overrides::default(), Flavor::Stable).to_json(), config.to_json());
+    }
+
+    #[test]
+    pub fn save_keeps_comments() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_NAME);
+        fs::write(&path, "# My settings\nmake-backup = true # keep backups\n# custom-js = \"custom.js\"\nbackup-dir = \"old\"\n").unwrap();
+
+        let mut config = Config::load(&path, &Overrides::default(), Flavor::Stable);
+        config.make_backup = false;
+        config.customjs_path = Some("theme.js".into());
+        config.backup_dir = None;
+        config.save().unwrap();
+
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(saved.starts_with("# My settings\nmake-backup = false\ncustom-js = \"theme.js\"\n# backup-dir = \"old\"\n"));
+        assert_eq!(Config::load(&path, &Overrides::default(), Flavor::Stable).to_json(), config.to_json());
+    }
+
+    #[test]
+    pub fn save_variables() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_NAME);
+        fs::write(&path, "make-backup = true\n\n[variables]\naccent = \"red\" # mine\n\n[flavors.ptb]\nmake-backup = false\n").unwrap();
+
+        let mut config = Config::load(&path, &Overrides::default(), Flavor::Stable);
+        assert_eq!(config.variables.get("accent").map(String::as_str), Some("red"));
+        config.variables.insert("accent".into(), "#ff0066".into());
+        config.variables.insert("font".into(), "Comic Sans".into());
+        config.save().unwrap();
+
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(saved.contains("[variables]\naccent = \"#ff0066\"\nfont = \"Comic Sans\"\n\n[flavors.ptb]\n"), "{}", saved);
+        assert_eq!(Config::load(&path, &Overrides::default(), Flavor::Stable).to_json(), config.to_json());
+
+        //A new file gets the table after every other option
+        let path = dir.path().join("new.toml");
+        let config = Config { path: path.clone(), variables: config.variables, ..Config::default() };
+        config.save().unwrap();
+        assert_eq!(Config::load(&path, &Overrides::default(), Flavor::Stable).variables, config.variables);
+    }
+
+    /// Build a config from a TOML file's contents with environment variables and command line flags applied
+    fn layered(file: &str, env: &[(&str, &str)], cli: Vec<(&'static str, serde_json::Value)>) -> Result<Config, Error> {
+        let overrides = Overrides {
+            env: env.iter().map(|(name, val)| ((*name).to_owned(), (*val).to_owned())).collect(),
+            cli,
+        };
+        Config::from_layers(Config::options(file, false)?, &overrides)
+    }
+
+    #[test]
+    pub fn override_precedence() {
+        let file = "make-backup = false\nreplace-icon = false\nbackup-dir = \"file\"\n";
+        let config = layered(file, &[], vec![]).unwrap();
+        assert!(!config.make_backup);
+        assert_eq!(config.source("make-backup"), Source::File);
+        assert_eq!(config.source("compress-backups"), Source::Default);
+
+        let env = [("DISCORD_THEME_MAKE_BACKUP", "true"), ("DISCORD_THEME_BACKUP_DIR", "env")];
+        let config = layered(file, &env, vec![]).unwrap();
+        assert!(config.make_backup && !config.replace_icon);
+        assert_eq!(config.backup_dir, Some(PathBuf::from("env")));
+        assert_eq!(config.source("make-backup"), Source::Env);
+        assert_eq!(config.source("replace-icon"), Source::File);
+
+        let config = layered(file, &env, vec![("make-backup", false.into()), ("backup-dir", "cli".into())]).unwrap();
+        assert!(!config.make_backup);
+        assert_eq!(config.backup_dir, Some(PathBuf::from("cli")));
+        assert_eq!(config.source("backup-dir"), Source::Cli);
+    }
+
+    #[test]
+    pub fn env_booleans() {
+        for (val, expected) in [("1", true), ("yes", true), ("ON", true), ("True", true), ("0", false), ("no", false), ("off", false), ("FALSE", false)].iter() {
+            let config = layered("", &[("DISCORD_THEME_COMPRESS_BACKUPS", val)], vec![]).unwrap();
+            assert_eq!(config.compress_backups, *expected, "DISCORD_THEME_COMPRESS_BACKUPS={}", val);
         }
+        assert!(matches!(layered("", &[("DISCORD_THEME_MAKE_BACKUP", "maybe")], vec![]), Err(Error::Invalid(_))));
+        assert!(matches!(layered("", &[("DISCORD_THEME_MAKE_BACKUPS", "true")], vec![]), Err(Error::Invalid(_))));
+
+        let config = layered("custom-js = \"file.js\"", &[("DISCORD_THEME_CUSTOM_JS", "")], vec![]).unwrap();
+        assert_eq!(config.customjs_path, None);
+        assert_eq!(env_var("make-backup"), "DISCORD_THEME_MAKE_BACKUP");
+    }
+
+    #[test]
+    pub fn save_skips_overrides() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_NAME);
+        fs::write(&path, "make-backup = true\n").unwrap();
+
+        let overrides = Overrides {
+            env: Vec::new(),
+            cli: vec![("make-backup", false.into())],
+        };
+        let mut config = Config::load(&path, &overrides, Flavor::Stable);
+        assert!(!config.make_backup);
+        config.compress_backups = true;
+        config.save().unwrap();
+
+        let saved = Config::load(&path, &Overrides::default(), Flavor::Stable);
+        assert!(saved.make_backup && saved.compress_backups);
+    }
+
+    /// Upgrade the options in a JSON document and get them back as JSON
+    fn upgraded(json: &str) -> serde_json::Value {
+        let (options, _) = upgrade(Config::options(json, true).unwrap()).unwrap();
+        serde_json::Value::Object(options)
+    }
+
+    #[test]
+    pub fn migrate_original_json() {
+        //The config.json that the first versions of the program wrote
+        let upgraded = upgraded(r#"{"custom-js": null, "make-backup": true, "replace-icon": false}"#);
+        assert_eq!(upgraded, serde_json::json!({"version": 1, "make-backup": true, "replace-icon": false}));
+        assert!(!Config::from_layers(upgraded.as_object().unwrap().clone(), &Overrides::default()).unwrap().replace_icon);
+    }
+
+    #[test]
+    pub fn migrate_lenient_json() {
+        //Versionless files were read by a parser that ignored unknown options and used defaults for invalid ones
+        let upgraded = upgraded(
+            r#"{"make-backup": "no", "make-backups": false, "replace-icon": null, "backup-mode": "zip", "compress-backups": true, "backup-dir": "/backups"}"#,
+        );
+        assert_eq!(upgraded, serde_json::json!({"version": 1, "compress-backups": true, "backup-dir": "/backups"}));
+    }
+
+    #[test]
+    pub fn migrate_flavors() {
+        //Flavor tables of versionless files are kept, with their invalid options removed like the top level ones
+        let upgraded = upgraded(r#"{"make-backup": true, "flavors": {"canary": {"make-backup": false, "make-backups": false}}}"#);
+        assert_eq!(upgraded, serde_json::json!({"version": 1, "make-backup": true, "flavors": {"canary": {"make-backup": false}}}));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_NAME);
+        fs::write(&path, "make-backup = true
+
+[flavors.canary]
+make-backup = false
+old-option = 1
+").unwrap();
+        assert!(!Config::load(&path, &Overrides::default(), Flavor::Canary).make_backup);
+        assert!(fs::read_to_string(&path).unwrap().ends_with("[flavors.canary]
+make-backup = false
+# old-option = 1
+"));
+        assert!(!Config::load(&path, &Overrides::default(), Flavor::Canary).make_backup);
+        assert!(Config::load(&path, &Overrides::default(), Flavor::Stable).make_backup);
+    }
+
+    #[test]
+    pub fn refuse_newer_version() {
+        assert!(matches!(upgrade(Config::options("version = 2", false).unwrap()), Err(Error::TooNew(2))));
+        assert!(matches!(upgrade(Config::options("version = \"one\"", false).unwrap()), Err(Error::Invalid(_))));
+        assert!(matches!(upgrade(Config::options("version = 4294967296", false).unwrap()), Err(Error::Invalid(_))));
+        let (options, version) = upgrade(Config::options("version = 1\nmake-backups = true", false).unwrap()).unwrap();
+        assert_eq!(version, 1);
+        assert!(options.contains_key("make-backups"), "Current files aren't migrated");
+    }
+
+    #[test]
+    pub fn migrate_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_NAME);
+        let old = "# My settings\nmake-backup = false\nold-option = 1\n";
+        fs::write(&path, old).unwrap();
+
+        let config = Config::load(&path, &Overrides::default(), Flavor::Stable);
+        assert!(!config.make_backup);
+        assert_eq!(fs::read_to_string(migration_backup_path(&path, 0)).unwrap(), old);
+        let rewritten = fs::read_to_string(&path).unwrap();
+        assert!(rewritten.starts_with("# My settings\nmake-backup = false\n# old-option = 1\n"));
+        assert!(rewritten.contains("version = 1"));
+        assert_eq!(Config::load(&path, &Overrides::default(), Flavor::Stable).to_json(), config.to_json());
+    }
+
+    #[test]
+    pub fn theme_url_channel() {
+        assert_eq!(Config::default().theme_url(), STABLE_THEME_URL);
+        let dev = Config::from_str("version = 1\nchannel = \"dev\"", false).unwrap();
+        assert_eq!(dev.theme_url(), DEV_THEME_URL);
+        let url = Config::from_str("version = 1\nchannel = \"dev\"\ntheme-url = \"https://example.com/a.css\"", false).unwrap();
+        assert_eq!(url.theme_url(), "https://example.com/a.css");
+        assert_eq!((dev.tweaks_url(), url.tweaks_url()), (Some(DEV_TWEAKS_URL), None));
+        assert!(matches!(Config::from_str("version = 1\ntheme-url = \"http://example.com/a.css\"", false), Err(Error::Invalid(_))));
+        assert!(matches!(Config::from_str("version = 1\ntheme-sha256 = \"abc\"", false), Err(Error::Invalid(_))));
+        let pinned = Config::from_str(&format!("version = 1\ntheme-sha256 = \"{}\"", "a".repeat(64)), false).unwrap();
+        assert_eq!(pinned.theme_sha256, Some("a".repeat(64)));
+    }
+
+    /// Merge the given flavor's table over the options in a TOML file's contents
+    fn flavored(file: &str, flavor: Flavor) -> Result<Config, Error> {
+        let (options, _) = merge_flavor(Config::options(file, false)?, flavor)?;
+        Config::from_layers(options, &Overrides::default())
+    }
+
+    #[test]
+    pub fn flavor_sections() {
+        //Without a flavors table every flavor uses the top level options
+        let plain = "replace-icon = false\n";
+        assert!(!flavored(plain, Flavor::Canary).unwrap().replace_icon);
+
+        let file = "replace-icon = false\ncustom-js = \"stable.js\"\n\n[flavors.canary]\ncustom-js = \"canary.js\"\nmake-backup = false\n\n[flavors.ptb]\n";
+        let canary = flavored(file, Flavor::Canary).unwrap();
+        assert_eq!(canary.customjs_path.as_ref().and_then(CustomJs::path), Some("canary.js"));
+        assert!(!canary.make_backup && !canary.replace_icon, "Options missing from the flavor's table come from the top level");
+
+        let stable = flavored(file, Flavor::Stable).unwrap();
+        assert_eq!(stable.customjs_path.as_ref().and_then(CustomJs::path), Some("stable.js"));
+        assert!(stable.make_backup);
+        assert_eq!(flavored(file, Flavor::Ptb).unwrap(), stable);
+
+        assert!(matches!(flavored("[flavors.beta]\nmake-backup = true", Flavor::Stable), Err(Error::Invalid(_))));
+        assert!(matches!(flavored("[flavors.canary]\nversion = 1", Flavor::Stable), Err(Error::Invalid(_))));
+        assert!(matches!(flavored("[flavors.canary]\nmake-backups = true", Flavor::Canary), Err(Error::Invalid(_))));
+    }
+
+    #[test]
+    pub fn flavor_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_NAME);
+        fs::write(&path, "version = 1\nmake-backup = true\n\n[flavors.canary]\nmake-backup = false\nreplace-icon = false\n").unwrap();
+
+        let overrides = Overrides {
+            env: Vec::new(),
+            cli: vec![("replace-icon", true.into())],
+        };
+        let mut config = Config::load(&path, &overrides, Flavor::Canary);
+        assert!(!config.make_backup && config.replace_icon);
+        assert_eq!(config.source("make-backup"), Source::Flavor);
+        assert_eq!(config.source("replace-icon"), Source::Cli);
+
+        //Saving keeps the flavor's options in its own table
+        config.compress_backups = true;
+        config.save().unwrap();
+        let stable = Config::load(&path, &Overrides::default(), Flavor::Stable);
+        assert!(stable.make_backup && stable.replace_icon && stable.compress_backups);
+        assert!(!Config::load(&path, &Overrides::default(), Flavor::Canary).make_backup);
+
+        //Editing an option that the flavor's table sets changes it there, without leaking to the other flavors
+        let mut config = Config::load(&path, &Overrides::default(), Flavor::Canary);
+        config.make_backup = true;
+        config.mark_edited("make-backup");
+        config.replace_icon = true;
+        config.mark_edited("replace-icon");
+        config.save().unwrap();
+        let canary = Config::load(&path, &Overrides::default(), Flavor::Canary);
+        assert!(canary.make_backup && canary.replace_icon && canary.source("make-backup") == Source::Flavor);
+        let mut stable = Config::load(&path, &Overrides::default(), Flavor::Stable);
+        assert!(stable.make_backup && stable.replace_icon);
+        assert!(fs::read_to_string(&path).unwrap().contains("[flavors.canary]\nmake-backup = true\nreplace-icon = true\n"));
+
+        //The top level is saved at the top level, and the flavor's value still wins for it
+        stable.make_backup = false;
+        stable.mark_edited("make-backup");
+        stable.save().unwrap();
+        assert!(!Config::load(&path, &Overrides::default(), Flavor::Stable).make_backup);
+        assert!(Config::load(&path, &Overrides::default(), Flavor::Canary).make_backup);
+    }
+
+    #[test]
+    pub fn save_json_keeps_flavors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        fs::write(&path, r#"{"version": 1, "make-backup": true, "flavors": {"canary": {"make-backup": false, "replace-icon": false}}}"#).unwrap();
+
+        let mut config = Config::load(&path, &Overrides::default(), Flavor::Canary);
+        config.make_backup = true;
+        config.mark_edited("make-backup");
+        config.compress_backups = true;
+        config.mark_edited("compress-backups");
+        config.save().unwrap();
+
+        let canary = Config::load(&path, &Overrides::default(), Flavor::Canary);
+        assert!(canary.make_backup && !canary.replace_icon && canary.compress_backups);
+        assert_eq!(canary.source("replace-icon"), Source::Flavor);
+        let stable = Config::load(&path, &Overrides::default(), Flavor::Stable);
+        assert!(stable.make_backup && stable.replace_icon && stable.compress_backups);
+    }
+
+    #[test]
+    pub fn config_flag_path() {
+        let path = Path::new("somewhere/else.toml");
+        assert_eq!(resolve_path(Some(path)), path);
+        assert_eq!(default_dir().file_name().unwrap(), "discord-theme");
+    }
+
+    #[test]
+    pub fn migrate_json() {
+        let json = r#"{
+            "custom-js": "my \"theme\".js",
+            "make-backup": false,
+            "replace-icon": true,
+            "custom-icon": null,
+            "mac-bundle": null,
+            "compress-backups": true,
+            "backup-dir": "C:\\backups",
+            "backup-mode": "file"
+        }"#;
+        let config = serde_json::from_str::<Config>(json).unwrap();
+        assert_eq!(config.customjs_path.as_ref().and_then(CustomJs::path), Some("my \"theme\".js"));
+        assert_eq!(config.backup_dir, Some(PathBuf::from("C:\\backups")));
+        assert_eq!(config.backup_mode, BackupMode::File);
+        assert!(!config.make_backup && config.compress_backups);
+
+        let migrated = toml::from_str::<Config>(&config.to_toml()).unwrap();
+        assert_eq!(migrated, config);
+    }
+
+    #[test]
+    pub fn custom_js_pin() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, js) = (dir.path().join(CONFIG_NAME), dir.path().join("custom.js"));
+        fs::write(&js, "console.log('hi')").unwrap();
+        fs::write(&path, format!("version = 1\ncustom-js = {:?}\n", js.to_string_lossy())).unwrap();
+        let load = || {
+            let mut config = Config::load(&path, &Overrides::default(), Flavor::Stable);
+            config.resolve_custom_js(|_, _| panic!("nothing is downloaded")).unwrap();
+            config
+        };
+
+        //The first run offers to pin the script, and once its hash is saved the same script is inserted without asking
+        let mut config = load();
+        let hash = manifest::hash_bytes(b"console.log('hi')");
+        assert_eq!(config.check_js_pin(false).unwrap(), JsPin::Offer(hash.clone()));
+        config.custom_js_sha256 = Some(hash.clone());
+        config.save().unwrap();
+        assert_eq!(load().check_js_pin(false).unwrap(), JsPin::Unchanged);
+
+        //A changed script is refused with both hashes until the change is accepted
+        fs::write(&js, "stealToken()").unwrap();
+        let changed = manifest::hash_bytes(b"stealToken()");
+        match load().check_js_pin(false) {
+            Err(e @ Error::JsChanged { .. }) => assert!(e.to_string().contains(&hash) && e.to_string().contains(&changed)),
+            other => panic!("changed javascript was not refused: {:?}", other.map_err(|e| e.to_string())),
+        }
+        assert_eq!(load().check_js_pin(true).unwrap(), JsPin::Accepted(changed));
+
+        //Declining the offer isn't asked again, and there is nothing to pin without custom javascript
+        let config = Config { pin_custom_js: false, custom_js_sha256: None, ..load() };
+        assert_eq!(config.check_js_pin(false).unwrap(), JsPin::Unchanged);
+        assert_eq!(Config::default().check_js_pin(false).unwrap(), JsPin::Unchanged);
+        assert!(matches!(Config::from_str("version = 1\ncustom-js-sha256 = \"abc\"", false), Err(Error::Invalid(_))));
+    }
+
+    #[test]
+    pub fn remote_custom_js() {
+        let hash = "a".repeat(64);
+        let pinned = format!("https://example.com/custom.js#sha256={}", hash);
+        assert_eq!(JsSource::parse("custom.js"), Ok(JsSource::File("custom.js")));
+        assert_eq!(JsSource::parse(&pinned), Ok(JsSource::Url { url: "https://example.com/custom.js", sha256: Some(&hash) }));
+        assert!(JsSource::parse("http://example.com/custom.js").is_err());
+        assert!(JsSource::parse("https://example.com/custom.js#sha256=abc").is_err());
+
+        //Pinned URLs are downloaded with their hash and escaped
+        let mut config = Config {
+            customjs_path: Some(pinned.clone().into()),
+            ..Config::default()
+        };
+        config
+            .resolve_custom_js(|url, sha256| {
+                assert_eq!((url, sha256), ("https://example.com/custom.js", Some(hash.as_str())));
+                Ok("console.log(`hi`)".into())
+            })
+            .unwrap();
+        assert_eq!(config.customjs, "console.log(\\`hi\\`)");
+
+        //Unpinned URLs are only downloaded if they are explicitly allowed
+        let mut config = Config {
+            customjs_path: Some("https://example.com/custom.js".into()),
+            ..Config::default()
+        };
+        let refused = config.resolve_custom_js(|_, _| panic!("unpinned javascript was downloaded"));
+        assert!(matches!(refused, Err(Error::UnpinnedJs { .. })));
+        config.allow_unpinned_remote_js = true;
+        config.resolve_custom_js(|_, sha256| Ok(format!("{:?}", sha256))).unwrap();
+        assert_eq!(config.customjs, "None");
+
+        let download_err = config.resolve_custom_js(|_, _| Err("offline".into()));
+        assert!(matches!(download_err, Err(Error::RemoteJs { .. })));
+        assert!(matches!(Config::from_str("version = 1\ncustom-js = \"http://example.com/a.js\"", false), Err(Error::Invalid(_))));
+        assert!(matches!(Config::from_str("version = 1\ncustom-js-preload = \"http://a.js\"", false), Err(Error::Invalid(_))));
+
+        //Preload javascript isn't escaped because it runs as it is, and is left out along with the custom javascript
+        let mut config = Config {
+            preload_path: Some(pinned),
+            ..Config::default()
+        };
+        config.resolve_custom_js(|_, _| Ok("console.log(`early`)".into())).unwrap();
+        assert_eq!((config.customjs.as_str(), config.preload()), ("", Some("console.log(`early`)")));
+        config.inject_js = false;
+        assert_eq!(config.preload(), None);
+    }
+
+    #[test]
+    pub fn inline_custom_js() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.js");
+        fs::write(&first, "first(`a`);").unwrap();
+        let path = first.display().to_string().replace('\\', "/");
+
+        //Strings are always paths, even ones that look like javascript, and only the object form is inline
+        let config = Config::from_str("version = 1\ncustom-js = \"alert(1)\"\n", false).unwrap();
+        assert_eq!(config.customjs_path, Some(CustomJs::One(JsEntry::Path("alert(1)".into()))));
+        let config = Config::from_str(r#"{"version": 1, "custom-js": {"inline": "alert(1)"}}"#, true).unwrap();
+        assert_eq!(config.customjs_path, Some(CustomJs::One(JsEntry::Inline { inline: "alert(1)".into() })));
+
+        //An array is inserted in order, with inline scripts used as they are and escaped along with the rest
+        let file = format!("version = 1\ncustom-js = [{{ inline = \"zero();\" }}, \"{}\", {{ inline = \"last(`b`);\" }}]\n", path);
+        let mut config = Config::from_str(&file, false).unwrap();
+        config.resolve_custom_js(|_, _| panic!("nothing was downloaded")).unwrap();
+        assert_eq!(config.customjs, "zero();\nfirst(\\`a\\`);\nlast(\\`b\\`);");
+        assert_eq!(config.customjs_hash, Some(manifest::hash_bytes(b"zero();\nfirst(`a`);\nlast(`b`);")));
+
+        //Errors say which element of the array is wrong
+        let invalid = |file: &str| match Config::from_str(file, false) {
+            Err(Error::Invalid(options)) => options[0].1.clone(),
+            other => panic!("Expected invalid options, got {:?}", other),
+        };
+        assert!(invalid("version = 1\ncustom-js = [\"a.js\", 3]\n").contains("element 2 of the array"));
+        assert!(invalid("version = 1\ncustom-js = [\"a.js\", \"http://example.com/b.js\"]\n").contains("element 2 of the array"));
+        assert!(invalid("version = 1\ncustom-js = { inline = \"a();\", path = \"b.js\" }\n").contains("no other keys"));
+        assert!(!invalid("version = 1\ncustom-js = { inline = 1 }\n").contains("element"));
+        assert!(invalid("version = 1\ncustom-js = []\n").contains("at least one"));
+        let mut config = Config::from_str(&format!("version = 1\ncustom-js = [\"{}\", \"missing.js\"]\n", path), false).unwrap();
+        let missing = config.resolve_custom_js(|_, _| panic!("nothing was downloaded")).unwrap_err();
+        assert!(matches!(&missing, Error::JsElement { index: 1, err } if matches!(**err, Error::CustomJs { .. })));
+        assert!(missing.to_string().starts_with("custom-js: element 2 of the array: "));
+    }
+
+    #[test]
+    pub fn save_custom_js_array() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_NAME);
+        fs::write(&path, "custom-js = [\n  \"a.js\",\n  { inline = \"b();\" },\n]\nmake-backup = true\n").unwrap();
+
+        //The array is written back on one line without leaving its old lines behind
+        let mut config = Config::load(&path, &Overrides::default(), Flavor::Stable);
+        assert_eq!(config.customjs_path.as_ref().map(|js| js.entries().len()), Some(2));
+        config.make_backup = false;
+        config.save().unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(saved.starts_with("custom-js = [\"a.js\", { inline = \"b();\" }]\nmake-backup = false\n"), "{}", saved);
+        assert_eq!(Config::load(&path, &Overrides::default(), Flavor::Stable).to_json(), config.to_json());
+
+        config.customjs_path = None;
+        config.save().unwrap();
+        assert_eq!(Config::load(&path, &Overrides::default(), Flavor::Stable).customjs_path, None);
+        assert_eq!(inline_toml(&toml::Value::try_from(CustomJs::One(JsEntry::Inline { inline: "a\"\n".into() })).unwrap()), "{ inline = \"a\\\"\\n\" }");
     }
 }
@@ -0,0 +1,184 @@
+//! The `conformance` module checks [Archive] against archives laid out like the ones that `asar pack` from `@electron/asar` writes,
+//! which are in `tests/fixtures/asar` along with the script that made them. Every fixture is read, packed again, and compared byte for
+//! byte with the original, so an archive that this program writes has the same header that Electron reads from the asar tool's archives
+
+use std::io::Cursor;
+
+use indicatif::ProgressBar;
+
+use crate::asar::{Archive, Integrity, Meta, INTEGRITY_BLOCK_SIZE};
+
+/// A fixture's name, its bytes, and every file in it with its contents sorted by path
+type Fixture = (&'static str, &'static [u8], &'static [(&'static str, &'static str)]);
+
+/// The fixtures that were made by `generate.js`
+const FIXTURES: &[Fixture] = &[
+    (
+        "basic.asar",
+        include_bytes!("../tests/fixtures/asar/basic.asar"),
+        &[
+            ("app/index.js", "require('./lib/util.js').start();\n"),
+            ("app/lib/util.js", "exports.start = () => console.log(\"started\");\n"),
+            ("app/style.css", "body { color: red; }\n"),
+            ("package.json", "{\"name\":\"fixture\",\"main\":\"app/index.js\"}\n"),
+        ],
+    ),
+    (
+        "metadata.asar",
+        include_bytes!("../tests/fixtures/asar/metadata.asar"),
+        &[
+            ("bin/run.sh", "#!/bin/sh\nexec ./discord\n"),
+            ("empty.txt", ""),
+            //The data of unpacked files is in the metadata.asar.unpacked folder, which isn't read
+            ("native/addon.node", ""),
+            ("readme.md", "# metadata\n"),
+        ],
+    ),
+    (
+        "names.asar",
+        include_bytes!("../tests/fixtures/asar/names.asar"),
+        &[("a b/c \"d\".txt", "quoted\n"), ("x.js", "x"), ("ünïcode.js", "module.exports = \"ü\";\n")],
+    ),
+];
+
+/// An archive made by `generate.js` whose file data is in a different order than its header lists the files, with the files in the
+/// order of their data
+const SHUFFLED: (&[u8], &[(&str, &str)]) = (
+    include_bytes!("../tests/fixtures/asar/shuffled.asar"),
+    &[
+        ("lib/c.js", "third in the header, first in the data\n"),
+        ("z.js", "last\n"),
+        ("a.js", "first in the header\n"),
+        ("lib/b.js", "second\n"),
+    ],
+);
+
+/// Read the little endian u32 at `idx`
+fn field(bytes: &[u8], idx: usize) -> usize {
+    u32::from_le_bytes([bytes[idx], bytes[idx + 1], bytes[idx + 2], bytes[idx + 3]]) as usize
+}
+
+/// Pack an archive into a vector of bytes
+fn packed(archive: &Archive) -> Vec<u8> {
+    let mut packed = Cursor::new(Vec::new());
+    archive.pack(&mut packed, false).unwrap();
+    packed.into_inner()
+}
+
+#[test]
+pub fn read_fixtures() {
+    for (name, bytes, expected) in FIXTURES {
+        let archive = Archive::read(&mut Cursor::new(bytes)).unwrap();
+        let files = archive.files().into_iter().map(|(path, file)| (path, String::from_utf8(file.as_ref().to_vec()).unwrap())).collect::<Vec<_>>();
+        let expected = expected.iter().map(|(path, contents)| (path.to_string(), contents.to_string())).collect::<Vec<_>>();
+        assert_eq!(files, expected, "{}", name);
+
+        //Every file that the asar tool packed has the hashes of its contents
+        for (path, file) in archive.files().into_iter().filter(|(_, file)| file.meta().unpacked.is_none()) {
+            assert_eq!(file.meta().integrity, Some(Integrity::of(file.as_ref())), "{} in {}", path, name);
+        }
+    }
+
+    let metadata = Archive::read(&mut Cursor::new(FIXTURES[1].1)).unwrap();
+    assert!(metadata.get_file("bin/run.sh").unwrap().meta().executable);
+    assert!(!metadata.get_file("readme.md").unwrap().meta().executable);
+    let addon = metadata.get_file("native/addon.node").unwrap().meta();
+    assert_eq!(addon.unpacked, Some("not really a native module".len() as u64));
+    assert_eq!(addon.integrity.as_ref().map(|integrity| integrity.hash.len()), Some(64));
+    assert_eq!(metadata.get_dir("emptydir").map(|dir| dir.entries().count()), Some(0));
+}
+
+#[test]
+pub fn repack_fixtures() {
+    for (name, bytes, _) in FIXTURES {
+        let archive = Archive::read(&mut Cursor::new(bytes)).unwrap();
+        let repacked = packed(&archive);
+        assert!(repacked == *bytes, "{} changed when it was packed again", name);
+
+        //The archive that was packed again reads back the same
+        let reread = Archive::read(&mut Cursor::new(&repacked)).unwrap();
+        assert!(archive.diff(&reread).is_empty(), "{}", name);
+        let metas = |archive: &Archive| archive.files().into_iter().map(|(path, file)| (path, file.meta().clone())).collect::<Vec<_>>();
+        assert_eq!(metas(&archive), metas(&reread), "{}", name);
+    }
+}
+
+#[test]
+pub fn header_layout() {
+    let mut archive = Archive::read(&mut Cursor::new(FIXTURES[0].1)).unwrap();
+    archive.get_file_mut("app/style.css").unwrap().replace_contents(b"body { color: blue; background: black; }\n").unwrap();
+    archive.add_file("extra.js");
+    let bytes = packed(&archive);
+
+    //The header is a pickle with the size of the pickle after it, which is a pickle with the length of the JSON string and the string
+    //padded to 4 bytes
+    let json_size = field(&bytes, 12);
+    let padded = json_size.div_ceil(4) * 4;
+    assert_eq!((field(&bytes, 0), field(&bytes, 4), field(&bytes, 8)), (4, padded + 8, padded + 4));
+    assert!(bytes[16 + json_size..16 + padded].iter().all(|b| *b == 0));
+    let json = std::str::from_utf8(&bytes[16..16 + json_size]).unwrap();
+
+    //Entries are sorted by name, files list their size before the offset string, and data is in the same order as the entries
+    let style = b"body { color: blue; background: black; }\n";
+    let offset = FIXTURES[0].2[0].1.len() + FIXTURES[0].2[1].1.len();
+    let integrity = Integrity::of(style);
+    assert!(json.starts_with("{\"files\":{\"app\":{\"files\":{\"index.js\":{\"size\":34,\"offset\":\"0\",\"integrity\":{\"algorithm\":\"SHA256\","));
+    assert!(json.contains(&format!(
+        "\"style.css\":{{\"size\":{},\"offset\":\"{}\",\"integrity\":{{\"algorithm\":\"SHA256\",\"hash\":\"{}\",\"blockSize\":4194304,\"blocks\":[\"{}\"]}}}}}}}},",
+        style.len(),
+        offset,
+        integrity.hash,
+        integrity.blocks[0]
+    )));
+    //A file that was added by hand has no hashes because the asar tool wouldn't have been asked to make them
+    assert!(json.contains(&format!("\"extra.js\":{{\"size\":0,\"offset\":\"{}\"}},\"package.json\"", offset + style.len())));
+    assert_eq!(&bytes[16 + padded + offset..16 + padded + offset + style.len()], style);
+}
+
+#[test]
+pub fn pack_progress() {
+    for (name, bytes, _) in FIXTURES {
+        let archive = Archive::read(&mut Cursor::new(bytes)).unwrap();
+        let progress = ProgressBar::hidden();
+        let mut packed = Cursor::new(Vec::new());
+        archive.pack_with_progress(&mut packed, &progress).unwrap();
+
+        //The bar ends at exactly the size of the archive, and the estimate that it starts with is close to it
+        assert_eq!((progress.position(), progress.length()), (bytes.len() as u64, bytes.len() as u64), "{}", name);
+        let estimate = archive.packed_size_estimate() as f64;
+        assert!((estimate / bytes.len() as f64 - 1.0).abs() < 0.25, "{} was estimated at {} bytes", name, estimate);
+    }
+}
+
+#[test]
+pub fn offset_order() {
+    let (bytes, expected) = SHUFFLED;
+    let mut archive = Archive::read(&mut Cursor::new(bytes)).unwrap();
+    let order = |archive: &Archive| {
+        let entries = archive.entries_by_offset();
+        entries.iter().map(|(path, file)| (path.to_string_lossy().into_owned(), file.as_ref().to_vec())).collect::<Vec<_>>()
+    };
+    let expected = expected.iter().map(|(path, contents)| (path.to_string(), contents.as_bytes().to_vec())).collect::<Vec<_>>();
+    assert_eq!(order(&archive), expected);
+    assert_eq!(archive.get_file("lib/c.js").unwrap().offset(), Some(0));
+
+    //Files inserted after reading have no offset and sort last, and packing writes every file's data in the order of the header
+    archive.add_file("added.js");
+    let entries = archive.entries_by_offset();
+    assert_eq!((entries.last().unwrap().0.to_str(), entries.last().unwrap().1.offset()), (Some("added.js"), None));
+    let reread = Archive::read(&mut Cursor::new(packed(&archive))).unwrap();
+    assert!(archive.diff(&reread).is_empty());
+    let paths = reread.entries_by_offset().into_iter().map(|(path, _)| path.to_string_lossy().into_owned()).collect::<Vec<_>>();
+    assert_eq!(paths, ["a.js", "added.js", "lib/b.js", "lib/c.js", "z.js"]);
+}
+
+#[test]
+pub fn integrity_blocks() {
+    //The asar tool hashes the rest of the file after the last full block even when it is empty
+    let empty = Integrity::of(b"");
+    assert_eq!((empty.blocks.len(), &empty.blocks[0]), (1, &empty.hash));
+    let exact = Integrity::of(&vec![1; INTEGRITY_BLOCK_SIZE]);
+    assert_eq!((exact.blocks.len(), exact.blocks[0] == exact.hash, exact.blocks[1] == empty.hash), (2, true, true));
+    assert_eq!(Integrity::of(&vec![1; INTEGRITY_BLOCK_SIZE + 1]).blocks.len(), 2);
+    assert_eq!(Meta::default(), Meta { executable: false, unpacked: None, integrity: None });
+}
@@ -0,0 +1,163 @@
+//! The `desktop` module finds the `.desktop` launcher entry that Linux desktops use to show Discord's icon, which usually points at an
+//! icon theme file instead of the `discord.png` in Discord's installation folder
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// The file name of Discord's launcher entry
+pub const DESKTOP_NAME: &str = "discord.desktop";
+
+/// The sizes of icon theme folders to look in for an icon name, biggest first
+const THEME_SIZES: [&str; 7] = [
+    "512x512", "256x256", "128x128", "64x64", "48x48", "32x32", "scalable",
+];
+
+/// The group in a `.desktop` file that holds the launcher's icon
+const ENTRY_GROUP: &str = "[Desktop Entry]";
+
+/// Get the folder that launcher entries for only this user are stored in, which overrides system wide entries with the same name
+pub fn user_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("applications"))
+}
+
+/// Get every folder that launcher entries may be stored in, with the user's folder first because it takes priority
+pub fn search_dirs() -> Vec<PathBuf> {
+    user_dir()
+        .into_iter()
+        .chain(std::iter::once(PathBuf::from("/usr/share/applications")))
+        .collect()
+}
+
+/// Find the launcher entry with the given file name in any of the [search_dirs]
+pub fn find(name: &str) -> Option<PathBuf> {
+    search_dirs()
+        .into_iter()
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Get the value of the `Icon=` key in the `[Desktop Entry]` group of a `.desktop` file's contents
+pub fn icon(contents: &str) -> Option<&str> {
+    let mut in_entry = false;
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_entry = line == ENTRY_GROUP;
+        } else if in_entry && is_icon_key(line) {
+            return line.split_once('=').map(|(_, value)| value.trim());
+        }
+    }
+    None
+}
+
+/// Replace the `Icon=` key in the `[Desktop Entry]` group of a `.desktop` file's contents with the given icon path, adding the key if
+/// the group doesn't have one
+pub fn with_icon(contents: &str, icon: &Path) -> String {
+    let icon_line = format!("Icon={}", icon.display());
+    let mut out = Vec::new();
+    let mut in_entry = false;
+    let mut replaced = false;
+    for line in contents.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            //Add the key at the end of the group if it didn't have one
+            if in_entry && !replaced {
+                out.push(icon_line.clone());
+                replaced = true;
+            }
+            in_entry = trimmed == ENTRY_GROUP;
+        } else if in_entry && !replaced && is_icon_key(trimmed) {
+            out.push(icon_line.clone());
+            replaced = true;
+            continue;
+        }
+        out.push(line.to_owned());
+    }
+    if in_entry && !replaced {
+        out.push(icon_line);
+    }
+    out.join("\n") + "\n"
+}
+
+/// Check if a line is the unlocalized `Icon=` key
+fn is_icon_key(line: &str) -> bool {
+    match line.strip_prefix("Icon") {
+        Some(rest) => rest.trim_start().starts_with('='),
+        None => false,
+    }
+}
+
+/// Find the image file that an `Icon=` value refers to, which is either an absolute path or the name of an icon in the hicolor theme
+/// or `/usr/share/pixmaps`
+pub fn resolve_icon(value: &str) -> Option<PathBuf> {
+    let path = Path::new(value);
+    if path.is_absolute() {
+        return Some(path.to_owned()).filter(|path| path.is_file());
+    }
+
+    let themes = dirs::data_dir()
+        .into_iter()
+        .chain(std::iter::once(PathBuf::from("/usr/share")))
+        .map(|dir| dir.join("icons").join("hicolor"));
+    themes
+        .flat_map(|theme| {
+            THEME_SIZES
+                .iter()
+                .map(move |size| theme.join(size).join("apps").join(format!("{}.png", value)))
+        })
+        .chain(std::iter::once(
+            Path::new("/usr/share/pixmaps").join(format!("{}.png", value)),
+        ))
+        .find(|path| path.is_file())
+}
+
+/// Refresh the icon theme cache and the launcher entry database so that the desktop notices a changed icon, skipping any tool that
+/// isn't installed
+pub fn refresh(desktop_dir: &Path) -> io::Result<()> {
+    let tools: [(&str, Vec<PathBuf>); 2] = [
+        (
+            "gtk-update-icon-cache",
+            dirs::data_dir()
+                .map(|dir| dir.join("icons").join("hicolor"))
+                .into_iter()
+                .filter(|dir| dir.is_dir())
+                .collect(),
+        ),
+        ("update-desktop-database", vec![desktop_dir.to_owned()]),
+    ];
+    for (tool, args) in tools.iter() {
+        match Command::new(tool).args(args).output() {
+            Ok(_) => (),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTRY: &str = "[Desktop Entry]\nName=Discord\nIcon=discord\nIcon[de]=discord-de\nExec=/usr/bin/discord\n\n[Desktop Action new]\nIcon=other\n";
+
+    #[test]
+    pub fn icon_key() {
+        assert_eq!(icon(ENTRY), Some("discord"));
+        assert_eq!(icon("[Desktop Action new]\nIcon=other\n"), None);
+        assert_eq!(icon("[Desktop Entry]\nIcon = /opt/discord.png\n"), Some("/opt/discord.png"));
+    }
+
+    #[test]
+    pub fn replace_icon_key() {
+        let replaced = with_icon(ENTRY, Path::new("/opt/Discord/discord.png"));
+        assert_eq!(icon(&replaced), Some("/opt/Discord/discord.png"));
+        assert!(replaced.contains("Icon[de]=discord-de"));
+        assert!(replaced.contains("[Desktop Action new]\nIcon=other"));
+
+        let added = with_icon("[Desktop Entry]\nName=Discord\n[Other]\n", Path::new("/icon.png"));
+        assert_eq!(added, "[Desktop Entry]\nName=Discord\nIcon=/icon.png\n[Other]\n");
+    }
+}
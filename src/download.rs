@@ -0,0 +1,309 @@
+//! The `download` module checks downloaded themes and custom javascript against the SHA-256 hash that was pinned for them or
+//! published next to them, on top of the store in [cache], so that a file that was tampered with is never used or stored
+
+use crate::{
+    cache::{self, Origin, Policy, Store, Transport},
+    manifest, net,
+};
+
+/// The extension of the checksum file that is published next to a theme, containing its SHA-256 hash
+const CHECKSUM_EXT: &str = ".sha256";
+
+/// The `Verified` enum is where the hash that a downloaded theme was checked against came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verified {
+    /// The hash was pinned in the config file
+    Pinned,
+    /// The hash was downloaded from the checksum file next to the theme
+    Published,
+    /// There is no checksum file next to the theme, so the theme wasn't checked
+    Unpublished,
+}
+
+/// The `Download` enum is the result of asking the server for the newest theme
+#[derive(Debug, Clone, PartialEq)]
+pub enum Download {
+    /// The theme changed or there was no usable stored copy, so it was downloaded. `unstored` is why it couldn't be stored
+    Changed { text: String, verified: Verified, unstored: Option<String> },
+    /// The stored copy is still the newest theme, or was used without asking the server
+    Unchanged(String),
+}
+
+impl Download {
+    /// Get the theme CSS
+    pub fn into_text(self) -> String {
+        match self {
+            Self::Changed { text, .. } => text,
+            Self::Unchanged(text) => text,
+        }
+    }
+}
+
+/// Get the theme at `url` from `store` or the server as `policy` says.
+///
+/// A downloaded theme is checked against the `pinned` SHA-256 hash, or the hash in the checksum file published next to it if no
+/// hash is pinned, and refused if the hashes don't match. If `require_checksum` is set, a theme without a published checksum file
+/// is refused too, which is used for the default URLs that always publish one
+pub fn fetch(
+    transport: &impl Transport,
+    store: &Store,
+    url: &str,
+    policy: Policy,
+    pinned: Option<&str>,
+    require_checksum: bool,
+) -> Result<Download, net::Error> {
+    let mut verified = Verified::Unpublished;
+    let fetched = cache::fetch_checked(transport, store, url, policy, |data| {
+        verified = match pinned {
+            Some(pinned) => {
+                verify(url, data, pinned)?;
+                Verified::Pinned
+            }
+            None => match published_hash(transport, url)? {
+                Some(hash) => {
+                    verify(url, data, &hash)?;
+                    Verified::Published
+                }
+                None if require_checksum => {
+                    return Err(net::Error::MissingChecksum {
+                        url: format!("{}{}", url, CHECKSUM_EXT),
+                    })
+                }
+                None => Verified::Unpublished,
+            },
+        };
+        Ok(())
+    })?;
+    let text = net::into_text(url, fetched.data)?;
+
+    match fetched.origin {
+        Origin::Downloaded => Ok(Download::Changed {
+            text,
+            verified,
+            unstored: fetched.unstored,
+        }),
+        Origin::Stored | Origin::Unchanged => {
+            //The stored copy was checked when it was downloaded, but a hash may have been pinned since then
+            if let Some(pinned) = pinned {
+                verify(url, text.as_bytes(), pinned)?;
+            }
+            Ok(Download::Unchanged(text))
+        }
+    }
+}
+
+/// Download the hash in the checksum file published next to `url`, returning `None` if there is no checksum file
+fn published_hash(transport: &impl Transport, url: &str) -> Result<Option<String>, net::Error> {
+    let checksum_url = format!("{}{}", url, CHECKSUM_EXT);
+    match transport.get(&checksum_url, &[]) {
+        Ok(res) => match parse_checksum(&String::from_utf8_lossy(&res.body)) {
+            Some(hash) => Ok(Some(hash)),
+            None => Err(net::Error::InvalidChecksum { url: checksum_url }),
+        },
+        Err(net::Error::Status { code: 404, .. }) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Get the hash from a checksum file in the format written by `sha256sum`, which is the hex hash followed by the file name
+fn parse_checksum(text: &str) -> Option<String> {
+    text.split_whitespace()
+        .next()
+        .filter(|hash| manifest::is_hash(hash))
+        .map(str::to_ascii_lowercase)
+}
+
+/// Check that the SHA-256 hash of a theme downloaded from `url` is `expected`
+fn verify(url: &str, data: &[u8], expected: &str) -> Result<(), net::Error> {
+    let actual = manifest::hash_bytes(data);
+    match actual.eq_ignore_ascii_case(expected) {
+        true => Ok(()),
+        false => Err(net::Error::Checksum {
+            url: url.to_owned(),
+            expected: expected.to_ascii_lowercase(),
+            actual,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Limits, Response};
+    use std::cell::RefCell;
+
+    const URL: &str = "https://example.com/theme.css";
+
+    /// A server that always responds the same way, recording the headers of every request for the theme
+    struct Mock {
+        res: Response,
+        /// The contents of the checksum file, or `None` to respond with 404 Not Found
+        checksum: Option<String>,
+        requests: RefCell<Vec<Vec<(String, String)>>>,
+    }
+
+    impl Mock {
+        fn new(status: u16, body: &str) -> Self {
+            Self {
+                res: Response {
+                    status,
+                    etag: Some("\"v2\"".into()),
+                    last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".into()),
+                    content_type: None,
+                    body: body.as_bytes().to_vec(),
+                },
+                checksum: None,
+                requests: RefCell::new(Vec::new()),
+            }
+        }
+
+        fn with_checksum(self, checksum: &str) -> Self {
+            Self {
+                checksum: Some(checksum.into()),
+                ..self
+            }
+        }
+    }
+
+    impl Transport for Mock {
+        fn get(&self, url: &str, headers: &[(&str, &str)]) -> Result<Response, net::Error> {
+            if url.ends_with(CHECKSUM_EXT) {
+                return match &self.checksum {
+                    Some(checksum) => Ok(Response {
+                        status: 200,
+                        etag: None,
+                        last_modified: None,
+                        content_type: None,
+                        body: checksum.as_bytes().to_vec(),
+                    }),
+                    None => Err(net::Error::Status {
+                        url: url.to_owned(),
+                        code: 404,
+                    }),
+                };
+            }
+            self.requests
+                .borrow_mut()
+                .push(headers.iter().map(|(name, val)| (name.to_string(), val.to_string())).collect());
+            Ok(self.res.clone())
+        }
+    }
+
+    fn store(dir: &std::path::Path) -> Store {
+        Store::new(dir, Limits { max_age: None, max_bytes: None })
+    }
+
+    /// Store `text` as an older copy of the theme
+    fn cached(store: &Store, text: &str) {
+        let res = Response {
+            status: 200,
+            etag: Some("\"v1\"".into()),
+            last_modified: Some("Tue, 20 Oct 2015 07:28:00 GMT".into()),
+            content_type: None,
+            body: text.as_bytes().to_vec(),
+        };
+        store.put(URL, &res).unwrap();
+    }
+
+    #[test]
+    pub fn conditional_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = store(dir.path());
+
+        //Nothing is cached, so the request is unconditional
+        let server = Mock::new(200, "new");
+        let download = fetch(&server, &cache, URL, Policy::PreferFresh, None, false).unwrap();
+        assert!(matches!(&download, Download::Changed { text, unstored: None, .. } if text == "new"));
+        assert_eq!(cache.entry(URL).unwrap().etag.as_deref(), Some("\"v2\""));
+        assert!(server.requests.borrow()[0].is_empty());
+
+        //Send the cached headers and use the cached copy when the server says it is unchanged
+        cached(&cache, "old");
+        let server = Mock::new(304, "");
+        assert_eq!(fetch(&server, &cache, URL, Policy::PreferFresh, None, false).unwrap(), Download::Unchanged("old".into()));
+        assert_eq!(
+            server.requests.borrow()[0],
+            [
+                ("If-None-Match".to_owned(), "\"v1\"".to_owned()),
+                ("If-Modified-Since".to_owned(), "Tue, 20 Oct 2015 07:28:00 GMT".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    pub fn refresh_bypasses_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = store(dir.path());
+        cached(&cache, "old");
+
+        let server = Mock::new(200, "new");
+        assert_eq!(fetch(&server, &cache, URL, Policy::Refresh, None, false).unwrap().into_text(), "new");
+        assert!(server.requests.borrow()[0].is_empty());
+
+        //An unrequested 304 is an error instead of an empty theme
+        assert!(matches!(fetch(&Mock::new(304, ""), &cache, URL, Policy::Refresh, None, false), Err(net::Error::Status { code: 304, .. })));
+    }
+
+    #[test]
+    pub fn checksum_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = store(dir.path());
+        let hash = manifest::hash_bytes(b"new");
+
+        let server = Mock::new(200, "new").with_checksum(&format!("{}  theme.css\n", hash));
+        assert!(matches!(fetch(&server, &cache, URL, Policy::PreferFresh, None, false), Ok(Download::Changed { verified: Verified::Published, .. })));
+
+        //A pinned hash is used instead of the published one
+        let server = Mock::new(200, "new").with_checksum(&manifest::hash_bytes(b"other"));
+        let pinned = hash.to_ascii_uppercase();
+        assert!(matches!(fetch(&server, &cache, URL, Policy::PreferFresh, Some(&pinned), false), Ok(Download::Changed { verified: Verified::Pinned, .. })));
+    }
+
+    #[test]
+    pub fn checksum_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = store(dir.path());
+
+        let server = Mock::new(200, "tampered").with_checksum(&manifest::hash_bytes(b"new"));
+        assert!(matches!(fetch(&server, &cache, URL, Policy::PreferFresh, None, false), Err(net::Error::Checksum { .. })));
+        let pinned = manifest::hash_bytes(b"new");
+        assert!(matches!(fetch(&Mock::new(200, "tampered"), &cache, URL, Policy::PreferFresh, Some(&pinned), false), Err(net::Error::Checksum { .. })));
+        assert!(matches!(
+            fetch(&Mock::new(200, "new").with_checksum("not a hash"), &cache, URL, Policy::PreferFresh, None, false),
+            Err(net::Error::InvalidChecksum { .. })
+        ));
+
+        //A cached copy that doesn't match a newly pinned hash is refused too
+        cached(&cache, "old");
+        assert!(matches!(fetch(&Mock::new(304, ""), &cache, URL, Policy::PreferFresh, Some(&pinned), false), Err(net::Error::Checksum { .. })));
+    }
+
+    #[test]
+    pub fn missing_checksum() {
+        let dir = tempfile::tempdir().unwrap();
+        let download = fetch(&Mock::new(200, "new"), &store(dir.path()), URL, Policy::PreferFresh, None, false).unwrap();
+        assert!(matches!(download, Download::Changed { verified: Verified::Unpublished, .. }));
+
+        //The default theme must have a checksum, so it is refused without one and isn't stored
+        let dir = tempfile::tempdir().unwrap();
+        let cache = store(dir.path());
+        assert!(matches!(
+            fetch(&Mock::new(200, "new"), &cache, URL, Policy::PreferFresh, None, true),
+            Err(net::Error::MissingChecksum { url }) if url == format!("{}{}", URL, CHECKSUM_EXT)
+        ));
+        assert!(cache.get(URL).is_none());
+        let hash = manifest::hash_bytes(b"new");
+        assert!(matches!(
+            fetch(&Mock::new(200, "new").with_checksum(&hash), &cache, URL, Policy::PreferFresh, None, true),
+            Ok(Download::Changed { verified: Verified::Published, .. })
+        ));
+    }
+
+    #[test]
+    pub fn published_checksum_is_current() {
+        let published = parse_checksum(include_str!("../assets/old-compressed.css.sha256"));
+        assert_eq!(published, Some(manifest::hash_bytes(include_bytes!("../assets/old-compressed.css"))));
+        let published = parse_checksum(include_str!("../assets/tweaks.json.sha256"));
+        assert_eq!(published, Some(manifest::hash_bytes(include_bytes!("../assets/tweaks.json"))));
+    }
+}
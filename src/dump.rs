@@ -0,0 +1,133 @@
+//! The `dump` module gathers the files that are needed to debug a broken patch, like Discord's archive and the file that creates its
+//! window, into a folder or `.zip` file that can be attached to an issue. Custom javascript is redacted and the user's name is scrubbed
+//! from paths before anything is written, because the dump is meant to be shared
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use zip::{result::ZipResult, write::FileOptions, ZipWriter};
+
+use crate::{config, manifest::Manifest, patch};
+
+/// The text that redacted values are replaced with
+pub const REDACTED: &str = "<redacted>";
+
+/// The text that the user's name is replaced with in paths
+pub const USER: &str = "<user>";
+
+/// The options whose values point at custom javascript, which can be a URL with a token in it
+const REDACTED_KEYS: &[&str] = &["custom-js", "custom-js-preload"];
+
+/// A file in the dump as its name and contents
+pub type File = (String, Vec<u8>);
+
+/// Replace the user's home folder with `~` and their name with [USER] wherever it is a part of a path in `text`
+pub fn scrub(text: &str, home: Option<&str>, user: Option<&str>) -> String {
+    let mut text = match home.filter(|home| home.len() > 1) {
+        Some(home) => text.replace(home.trim_end_matches(['/', '\\']), "~"),
+        None => text.to_owned(),
+    };
+    if let Some(user) = user.filter(|user| !user.is_empty()) {
+        for sep in ["/", "\\", "\\\\"] {
+            text = text.replace(&format!("{}{}{}", sep, user, sep), &format!("{}{}{}", sep, USER, sep));
+        }
+    }
+    text
+}
+
+/// Replace the value of every option that points at custom javascript in the text of a TOML config file with [REDACTED], including
+/// the ones in `[flavors.<name>]` tables and arrays of inline scripts split across lines. Commented out options are left alone
+pub fn redact_config(toml: &str) -> String {
+    let lines = toml.lines().map(str::to_owned).collect::<Vec<_>>();
+    let mut redacted = String::new();
+    let mut i = 0;
+    while i < lines.len() {
+        match lines[i].split_once('=') {
+            Some((key, _)) if REDACTED_KEYS.contains(&key.trim()) => {
+                redacted += &format!("{}= \"{}\"\n", key, REDACTED);
+                i += config::toml_option_lines(&lines, i);
+            }
+            _ => {
+                redacted += &lines[i];
+                redacted.push('\n');
+                i += 1;
+            }
+        }
+    }
+    redacted
+}
+
+/// Replace the custom javascript and preload javascript that the manifest's history remembers with [REDACTED], keeping their hashes
+pub fn redact_manifest(manifest: &mut Manifest) {
+    for applied in manifest.history.iter_mut() {
+        for js in applied.payload.js.iter_mut().chain(applied.payload.preload.iter_mut()) {
+            *js = REDACTED.to_owned();
+        }
+    }
+}
+
+/// Replace the custom javascript and preload javascript in the contents of a patched file with [REDACTED], leaving the CSS theme and
+/// the rest of the injection so that it can still be debugged
+pub fn redact_injection(js: &str) -> String {
+    let mut redacted = js.to_owned();
+    for found in [patch::injected_js(js), patch::injected_preload(js)].iter().flatten() {
+        if !found.is_empty() {
+            redacted = redacted.replacen(*found, REDACTED, 1);
+        }
+    }
+    redacted
+}
+
+/// Write the files to `dest`, which is a `.zip` file if it ends in `.zip` and a folder otherwise. Returns
+/// the paths of the written files, or the path of the zip file
+pub fn write(dest: &Path, files: &[File]) -> ZipResult<Vec<PathBuf>> {
+    if dest.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("zip")) {
+        let mut zip = ZipWriter::new(fs::File::create(dest)?);
+        for (name, contents) in files {
+            zip.start_file(name.as_str(), FileOptions::default())?;
+            zip.write_all(contents)?;
+        }
+        zip.finish()?;
+        return Ok(vec![dest.to_owned()]);
+    }
+    fs::create_dir_all(dest)?;
+    files
+        .iter()
+        .map(|(name, contents)| {
+            let path = dest.join(name);
+            fs::write(&path, contents).map(|_| path)
+        })
+        .collect::<io::Result<_>>()
+        .map_err(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{patch, schedule::Css, testutil::MAIN_SCREEN_JS};
+
+    #[test]
+    pub fn redaction() {
+        let config = "# custom-js = \"example.js\"\ncustom-js = \"https://example.com/a.js?token=secret\"\n\n[flavors.canary]\ncustom-js-preload = \"/home/ada/early.js\"\nbackup-dir = \"/home/ada/backups\"\n";
+        let redacted = redact_config(config);
+        assert!(!redacted.contains("secret") && !redacted.contains("early.js"));
+        assert!(redacted.starts_with("# custom-js = \"example.js\"\ncustom-js = \"<redacted>\"\n"));
+        assert!(redacted.contains("custom-js-preload = \"<redacted>\"\nbackup-dir = \"/home/ada/backups\"\n"));
+        let inline = "custom-js = [\n  \"a.js\",\n  { inline = \"sendToken('secret');\" },\n]\nmake-backup = true\n";
+        assert_eq!(redact_config(inline), "custom-js = \"<redacted>\"\nmake-backup = true\n");
+
+        let scrubbed = scrub(&redacted, Some("/home/ada/"), Some("ada"));
+        assert!(scrubbed.contains("backup-dir = \"~/backups\"") && !scrubbed.contains("ada"));
+        assert_eq!(scrub("C:\\\\Users\\\\ada\\\\AppData and adam", None, Some("ada")), "C:\\\\Users\\\\<user>\\\\AppData and adam");
+        assert_eq!(scrub("/srv/ada/discord", None, Some("ada")), "/srv/<user>/discord");
+
+        let patched = patch::inject(MAIN_SCREEN_JS, &Css::from("a {}"), Some("sendToken();"), Some("early();"), None, patch::Strategy::ExecuteJs).unwrap();
+        let redacted = redact_injection(&patched);
+        assert!(!redacted.contains("sendToken") && !redacted.contains("early();"));
+        assert_eq!(patch::extract(&redacted).map(|found| (found.css, found.js, found.preload)),
+            Some(("a {}".to_owned(), Some(REDACTED.to_owned()), Some(REDACTED.to_owned()))));
+    }
+}
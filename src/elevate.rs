@@ -0,0 +1,185 @@
+//! The `elevate` module runs this program again as an administrator when Discord is installed in a folder that only root can write
+//! to, like `/usr/share/discord` from a Linux distribution's package. The elevated run is given the user's folders so that it finds the
+//! same config file, backups, and Discord installation, and the files it creates in them are given back to the user afterwards so
+//! that running with `sudo` doesn't leave root-owned files behind
+
+use std::{
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::OnceLock,
+};
+
+use crate::userdata;
+
+/// The hidden flag that marks the elevated run, followed by the [Owner] to give created files to
+pub const FLAG: &str = "--elevated-from";
+
+/// The programs that can run this program as an administrator, in the order they are tried. `pkexec` asks for the password in a
+/// window, which also works when this program wasn't started from a terminal
+const TOOLS: &[&str] = &["pkexec", "sudo"];
+
+/// The environment variables that locate the user's folders, which `pkexec` and `sudo` would otherwise replace with root's
+const USER_ENV: &[&str] = &["HOME", "XDG_CONFIG_HOME", "XDG_DATA_HOME", "XDG_CACHE_HOME", userdata::CONFIG_DIR_ENV, userdata::DATA_DIR_ENV];
+
+/// The owner and folders of the user that started the elevated run, set by [init]
+static HAND_BACK: OnceLock<(Owner, Vec<PathBuf>)> = OnceLock::new();
+
+/// The `Owner` struct is the user and group that files are given back to after an elevated run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Owner {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Owner {
+    /// Get the owner of a file, or `None` on platforms without file owners
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    pub fn of(path: &Path) -> io::Result<Option<Self>> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let meta = fs::metadata(path)?;
+            Ok(Some(Self { uid: meta.uid(), gid: meta.gid() }))
+        }
+        #[cfg(not(unix))]
+        Ok(None)
+    }
+
+    /// Parse an owner written like `1000:1000` after [FLAG]
+    pub fn parse(text: &str) -> Option<Self> {
+        let (uid, gid) = text.split_once(':')?;
+        Some(Self { uid: uid.parse().ok()?, gid: gid.parse().ok()? })
+    }
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.uid, self.gid)
+    }
+}
+
+/// Find the first of [TOOLS] that is installed, or `None` if there is none or this platform has no `sudo`
+pub fn tool() -> Option<&'static str> {
+    if !cfg!(unix) {
+        return None;
+    }
+    let path = env::var_os("PATH")?;
+    TOOLS.iter().copied().find(|tool| env::split_paths(&path).any(|dir| dir.join(tool).is_file()))
+}
+
+/// Make the command that runs `exe` with `args` through `tool`, passing on the user's folders and marking the run as elevated from
+/// `owner`
+pub fn command(tool: &str, exe: &Path, args: &[&str], owner: Owner) -> Command {
+    let mut command = Command::new(tool);
+    command.arg("env");
+    for name in USER_ENV {
+        if let Some(val) = env::var_os(name) {
+            let mut assignment = std::ffi::OsString::from(format!("{}=", name));
+            assignment.push(val);
+            command.arg(assignment);
+        }
+    }
+    command.arg(exe).args(args).arg(FLAG).arg(owner.to_string());
+    command
+}
+
+/// Remember that this is an elevated run for `owner`, so that [hand_back] gives the files created under `roots` back to them
+pub fn init(owner: Owner, roots: Vec<PathBuf>) {
+    let _ = HAND_BACK.set((owner, roots));
+}
+
+/// Check if this is an elevated run started by [command]
+pub fn is_elevated() -> bool {
+    HAND_BACK.get().is_some()
+}
+
+/// Get the user that started this run with more rights than they have, which is the owner passed to [init] or the user that ran
+/// `sudo`. Returns `None` if this run wasn't elevated
+pub fn invoking_owner() -> Option<Owner> {
+    if let Some((owner, _)) = HAND_BACK.get() {
+        return Some(*owner);
+    }
+    let (uid, gid) = (env::var("SUDO_UID").ok()?, env::var("SUDO_GID").ok()?);
+    Some(Owner { uid: uid.parse().ok()?, gid: gid.parse().ok()? })
+}
+
+/// Check if a Discord installation owned by `install` belongs to a different user than `user`, who started the elevated run. Discord
+/// installed by an administrator for everyone is owned by root and belongs to no other user
+pub fn is_other_user(install: Owner, user: Owner) -> bool {
+    install.uid != 0 && install.uid != user.uid
+}
+
+/// Get the owner of the Discord installation at `dir` if this run is elevated and the installation belongs to another user than the
+/// one who started it, so that patching it would change someone else's Discord
+pub fn other_user_install(dir: &Path) -> Option<Owner> {
+    let user = invoking_owner()?;
+    Owner::of(dir).ok().flatten().filter(|install| is_other_user(*install, user))
+}
+
+/// Give every file and folder under the roots passed to [init] that root owns back to the user that started the elevated run,
+/// returning how many were changed. Does nothing if this isn't an elevated run
+pub fn hand_back() -> io::Result<usize> {
+    match HAND_BACK.get() {
+        Some((owner, roots)) => roots.iter().filter(|root| root.exists()).try_fold(0, |changed, root| Ok(changed + chown_tree(root, *owner)?)),
+        None => Ok(0),
+    }
+}
+
+/// Give `path` and everything in it that root owns to `owner`, without following symbolic links
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn chown_tree(path: &Path, owner: Owner) -> io::Result<usize> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let meta = fs::symlink_metadata(path)?;
+        let mut changed = 0;
+        if meta.uid() == 0 && owner.uid != 0 {
+            std::os::unix::fs::lchown(path, Some(owner.uid), Some(owner.gid))?;
+            changed += 1;
+        }
+        if meta.is_dir() {
+            for entry in fs::read_dir(path)? {
+                changed += chown_tree(&entry?.path(), owner)?;
+            }
+        }
+        Ok(changed)
+    }
+    #[cfg(not(unix))]
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn elevated_command() {
+        assert_eq!(Owner::parse("1000:100"), Some(Owner { uid: 1000, gid: 100 }));
+        assert_eq!(Owner::parse("1000"), None);
+        assert_eq!(Owner::parse(&Owner { uid: 5, gid: 6 }.to_string()), Some(Owner { uid: 5, gid: 6 }));
+
+        let command = command("sudo", Path::new("/bin/discord-theme"), &["restore", "--flavor", "ptb"], Owner { uid: 1000, gid: 1000 });
+        let args = command.get_args().map(|arg| arg.to_string_lossy().into_owned()).collect::<Vec<_>>();
+        assert_eq!(command.get_program(), "sudo");
+        assert_eq!(args[0], "env");
+        assert!(args.iter().any(|arg| arg.starts_with("HOME=")) || env::var_os("HOME").is_none());
+        assert!(args.ends_with(&[
+            "/bin/discord-theme".to_owned(),
+            "restore".to_owned(),
+            "--flavor".to_owned(),
+            "ptb".to_owned(),
+            FLAG.to_owned(),
+            "1000:1000".to_owned()
+        ]));
+    }
+
+    #[test]
+    pub fn other_user() {
+        let (user, other, root) = (Owner { uid: 1000, gid: 1000 }, Owner { uid: 1001, gid: 1001 }, Owner { uid: 0, gid: 0 });
+        assert!(is_other_user(other, user));
+        assert!(!is_other_user(user, user));
+        assert!(!is_other_user(Owner { uid: 1000, gid: 100 }, user));
+        assert!(!is_other_user(root, user));
+    }
+}
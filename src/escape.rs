@@ -0,0 +1,102 @@
+//! The `escape` module escapes text that is inserted into the javascript template literals that Discord's files are patched with,
+//! which is used for both the CSS theme and the custom javascript. The injection is a template literal that Discord's main process
+//! runs in its window, so a `${` that isn't escaped would run code with full access to the computer before the theme is ever shown
+
+/// Text that is common in javascript but never needed in CSS, which a theme that was made to run code would contain
+const SUSPICIOUS: &[&str] = &["${", "`", "require(", "child_process", "process.", "eval(", "Function(", "<script", "javascript:"];
+
+/// Escape text so that it can be put inside a javascript template literal without ending the literal early or running code with a
+/// `${}` substitution. Backslashes are escaped first so that the backslashes added in front of backticks and `${` aren't escaped again
+pub fn template_literal(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('`', "\\`").replace("${", "\\${")
+}
+
+/// Escape a CSS theme as a javascript string literal inside of the injection's template literal. The theme used to be put in a
+/// `String.raw` template literal inside of the outer one, which ran any `${}` in it a second time in Discord's window
+pub fn css_string(css: &str) -> String {
+    template_literal(&js_string(css))
+}
+
+/// Escape text as a javascript string literal, for a CSS theme that is inserted into Discord's main process outside of a template
+/// literal
+pub fn js_string(text: &str) -> String {
+    serde_json::Value::from(text).to_string()
+}
+
+/// Get the text that a template literal without substitutions evaluates to, for text escaped by [template_literal],
+/// where every backslash escapes the character after it
+pub fn cook(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// The `Suspicious` struct is javascript-looking text found in a CSS theme by [suspicious_js]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suspicious {
+    /// The line that the text is on, starting at 1
+    pub line: usize,
+    pub text: &'static str,
+}
+
+/// Find text in a CSS theme that looks like javascript, which can't run after the theme is escaped but means that the file is
+/// probably not the theme it claims to be. Only the first suspicious text on each line is returned
+pub fn suspicious_js(css: &str) -> Vec<Suspicious> {
+    css.lines()
+        .enumerate()
+        .filter_map(|(idx, line)| {
+            SUSPICIOUS
+                .iter()
+                .filter_map(|text| line.find(text).map(|col| (col, *text)))
+                .min()
+                .map(|(_, text)| Suspicious { line: idx + 1, text })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::unescaped;
+
+    #[test]
+    pub fn backticks_and_backslashes() {
+        assert_eq!(template_literal("a { content: '\\2014'; }"), "a { content: '\\\\2014'; }");
+        assert_eq!(template_literal("console.log(`hi`)"), "console.log(\\`hi\\`)");
+        //A backslash before a backtick can't be used to unescape the backtick
+        assert_eq!(template_literal("\\`"), "\\\\\\`");
+        assert_eq!(template_literal("`${a}` $b \\${c}"), "\\`\\${a}\\` $b \\\\\\${c}");
+    }
+
+    #[test]
+    pub fn hostile_css_is_inert() {
+        for css in [
+            "a { content: \"${require('child_process').exec('calc')}\"; }",
+            "a::after { content: '`); require(\"fs\"); (`'; }",
+            "\\${process.exit()} \\\\${x} \\`${y}\\",
+            "a { content: \"\\f101\"; } /* \" */ b { color: red }\n",
+        ] {
+            let escaped = css_string(css);
+            assert!(!unescaped(&escaped, "${") && !unescaped(&escaped, "`"), "{} can end or run code in the template literal", css);
+            //The template literal turns the escaped text back into a string literal that is exactly the theme
+            let literal = cook(&escaped);
+            assert_eq!(serde_json::from_str::<String>(&literal).unwrap(), css);
+        }
+    }
+
+    #[test]
+    pub fn find_suspicious() {
+        let css = "a { color: red; }\nb { content: \"${require('x')}\"; }\n/* process.exit */\n@import url(https://example.com/a.css);";
+        assert_eq!(
+            suspicious_js(css),
+            vec![Suspicious { line: 2, text: "${" }, Suspicious { line: 3, text: "process." }]
+        );
+        assert!(suspicious_js("a { content: '$'; background: url(data:image/png;base64,AAAA) }").is_empty());
+    }
+}
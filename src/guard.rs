@@ -0,0 +1,169 @@
+//! The `guard` module makes sure that a patch that is interrupted never leaves Discord broken. Before the patched archive is written,
+//! a [GUARD_NAME] file is written to the backup folder of the Discord version that is patched, recording the archive and its hash, and
+//! it is deleted when the patch finishes. If the program panics in between, the panic hook rolls the patch back with [rollback], and if
+//! the program was killed, the next run finds the file and does the same. The patched archive is written to a temporary file that only
+//! replaces Discord's archive once it is complete, so the archive is usually still the original one and rolling back only deletes the
+//! temporary files. If it was already replaced, it is restored from the backup
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use indicatif::ProgressBar;
+use serde::{Deserialize, Serialize};
+
+use crate::{asar, backup, manifest};
+
+/// The name of the file in a version's backup folder that marks a patch that is in progress
+pub const GUARD_NAME: &str = "patch-in-progress.json";
+
+/// The backup folder of the patch that this run has in progress, which the panic hook rolls back
+static ACTIVE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// The `State` struct is what the guard file records about the patch in progress
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct State {
+    /// The archive that is being patched
+    pub archive: PathBuf,
+    /// The javascript file in the archive that is being patched, which a backup of only that file is restored to
+    pub file: String,
+    /// The SHA-256 hash of the archive before it was patched
+    pub original_hash: String,
+}
+
+/// The `Rollback` enum is what [rollback] did to the archive of an interrupted patch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rollback {
+    /// The archive was never replaced, so only the temporary files were deleted
+    Intact,
+    /// The archive was replaced, so it was restored from the given backup
+    Restored(PathBuf),
+    /// The archive was replaced and there is no backup to restore it from
+    NoBackup,
+}
+
+/// The `Guard` struct is a patch in progress, which is rolled back if the program stops before [Guard::finish] is called
+#[derive(Debug)]
+pub struct Guard {
+    /// The backup folder that the guard file is in
+    dir: PathBuf,
+}
+
+impl Guard {
+    /// Write the guard file for a patch of the javascript file `file` in the archive at `archive` with the hash `original_hash` to the
+    /// backup folder `dir`, and remember it for the panic hook
+    pub fn begin(dir: &Path, archive: &Path, file: &str, original_hash: &str) -> io::Result<Self> {
+        let state = State { archive: archive.to_owned(), file: file.to_owned(), original_hash: original_hash.to_owned() };
+        fs::write(path(dir), serde_json::to_vec_pretty(&state)?)?;
+        *ACTIVE.lock().unwrap_or_else(|e| e.into_inner()) = Some(dir.to_owned());
+        Ok(Self { dir: dir.to_owned() })
+    }
+
+    /// Delete the guard file because the patch finished
+    pub fn finish(self) -> io::Result<()> {
+        ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).take();
+        fs::remove_file(path(&self.dir))
+    }
+}
+
+/// Get the path of the guard file in a version's backup folder
+pub fn path(dir: &Path) -> PathBuf {
+    dir.join(GUARD_NAME)
+}
+
+/// Get the backup folder of the patch that this run has in progress, if there is one
+pub fn active() -> Option<PathBuf> {
+    ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Load the state of an interrupted patch from a version's backup folder, returning `None` if no patch was interrupted there
+pub fn interrupted(dir: &Path) -> Option<State> {
+    serde_json::from_slice(&fs::read(path(dir)).ok()?).ok()
+}
+
+/// Roll back the interrupted patch recorded in a version's backup folder, deleting the temporary files that it left next to the
+/// archive and restoring the archive from its backup if it was already replaced. Returns `None` if no patch was interrupted there
+pub fn rollback(dir: &Path) -> Option<Result<Rollback, asar::Error>> {
+    let state = interrupted(dir)?;
+    let result = rollback_state(dir, &state);
+    if result.is_ok() {
+        let _ = fs::remove_file(path(dir));
+        let mut active = ACTIVE.lock().unwrap_or_else(|e| e.into_inner());
+        if active.as_deref() == Some(dir) {
+            active.take();
+        }
+    }
+    Some(result)
+}
+
+/// Roll back one interrupted patch, see [rollback]
+fn rollback_state(dir: &Path, state: &State) -> Result<Rollback, asar::Error> {
+    let parent = state.archive.parent().unwrap_or_else(|| Path::new("."));
+    let mut dirs = vec![parent.to_owned(), backup::work_dir(&state.archive)];
+    dirs.dedup();
+    for entry in dirs.iter().flat_map(fs::read_dir).flatten().filter_map(Result::ok) {
+        if entry.file_name().to_string_lossy().starts_with(backup::TEMP_PREFIX) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+
+    if manifest::hash_file(&state.archive).ok().as_deref() == Some(state.original_hash.as_str()) {
+        return Ok(Rollback::Intact);
+    }
+    match backup::find(dir).or_else(|| backup::find_file(dir)) {
+        Some(found) => {
+            backup::restore_asar(&found, &state.archive, &state.file, &ProgressBar::hidden())?;
+            Ok(Rollback::Restored(found))
+        }
+        None => Ok(Rollback::NoBackup),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, FakeDiscord, MAIN_SCREEN_JS};
+    use crate::MAIN_SCREEN;
+    use std::io::Write;
+
+    #[test]
+    pub fn panic_mid_pack() {
+        let fake = FakeDiscord::create();
+        let dir = fake.dir("backups");
+        fs::create_dir_all(&dir).unwrap();
+        let pristine = fs::read(fake.asar()).unwrap();
+        Guard::begin(&dir, &fake.asar(), MAIN_SCREEN, &manifest::hash_bytes(&pristine)).unwrap();
+
+        //A panic halfway through writing the patched archive leaves the guard behind, and the archive is the pristine one
+        let panicked = std::panic::catch_unwind(|| {
+            let _ = backup::replace_archive(
+                &fake.asar(),
+                |temp| {
+                    temp.write_all(&pristine[..pristine.len() / 2])?;
+                    panic!("interrupted while packing");
+                },
+                |_| Ok(()),
+            );
+        });
+        assert!(panicked.is_err());
+        assert_eq!(interrupted(&dir).map(|state| state.archive), Some(fake.asar()));
+        assert_eq!(fs::read(fake.asar()).unwrap(), pristine);
+
+        //Rolling back a temporary file that a killed run left behind deletes it and the guard
+        let left = fake.asar().with_file_name(format!("{}leftover", backup::TEMP_PREFIX));
+        fs::write(&left, "half an archive").unwrap();
+        assert_eq!(rollback(&dir).unwrap().unwrap(), Rollback::Intact);
+        assert!(!left.exists() && !path(&dir).exists());
+        assert!(rollback(&dir).is_none());
+
+        //An archive that was already replaced is restored from the backup
+        fs::write(dir.join(backup::BACKUP_NAME), &pristine).unwrap();
+        Guard::begin(&dir, &fake.asar(), MAIN_SCREEN, &manifest::hash_bytes(&pristine)).unwrap();
+        fs::write(fake.asar(), testutil::archive("patched")).unwrap();
+        assert_eq!(rollback(&dir).unwrap().unwrap(), Rollback::Restored(dir.join(backup::BACKUP_NAME)));
+        assert_eq!(fake.main_screen(), MAIN_SCREEN_JS);
+    }
+}
@@ -0,0 +1,225 @@
+//! The `history` module keeps a short record of past runs in a `history.jsonl` file in the data folder, so that when something that
+//! used to work stops working, the `history` command can show what changed between the runs, like the Discord version or the size
+//! of the theme. It is never sent anywhere, and it holds no paths, theme names, or error messages, only the command, whether it
+//! succeeded, the Discord version, how long each step took, and how many warnings there were
+
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use console::style;
+use indicatif::HumanBytes;
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+use crate::{messages::msg, output::RunReport};
+
+/// The name of the file in the data folder that the history is kept in
+pub const HISTORY_NAME: &str = "history.jsonl";
+
+/// The `Entry` struct is one run in the history
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Entry {
+    /// When the run finished, in RFC 3339 format
+    pub timestamp: String,
+    /// The command that was run, like `apply` or `restore`
+    pub action: String,
+    /// Wether the run exited without an error
+    pub success: bool,
+    /// The name of Discord's version folder, like `app-1.0.9001`
+    pub discord_version: Option<String>,
+    /// How many bytes of CSS and javascript were injected
+    pub bytes_injected: Option<u64>,
+    /// How long reading Discord's archive took, in milliseconds
+    pub read_ms: Option<u64>,
+    /// How long writing the patched archive took, in milliseconds
+    pub write_ms: Option<u64>,
+    /// How long the whole run took, in milliseconds
+    pub elapsed_ms: Option<u64>,
+    /// How many warnings were printed
+    pub warnings: usize,
+}
+
+impl Entry {
+    /// Take what is kept in the history out of a run's report, or `None` if the run had no command worth recording
+    pub fn from_report(report: &RunReport) -> Option<Self> {
+        let action = report.action.clone().filter(|action| action != "history")?;
+        Some(Self {
+            timestamp: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            action,
+            success: report.success,
+            discord_version: report.discord_version.clone(),
+            bytes_injected: report.bytes_injected,
+            read_ms: report.read_ms,
+            write_ms: report.write_ms,
+            elapsed_ms: report.elapsed_ms,
+            warnings: report.warnings.len(),
+        })
+    }
+}
+
+/// Get the path of the history file in the given data folder
+pub fn path(dir: &Path) -> PathBuf {
+    dir.join(HISTORY_NAME)
+}
+
+/// Add a run to the end of the history file at `path`, forgetting the oldest runs so that at most `limit` are kept
+pub fn append(path: &Path, entry: &Entry, limit: usize) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let line = serde_json::to_string(entry).map_err(io::Error::from)?;
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)?;
+    drop(file);
+    prune(path, limit)
+}
+
+/// Rewrite the history file at `path` with only its last `limit` lines, if it has more than that
+fn prune(path: &Path, limit: usize) -> io::Result<()> {
+    let lines = BufReader::new(fs::File::open(path)?).lines().collect::<io::Result<Vec<_>>>()?;
+    if lines.len() <= limit {
+        return Ok(());
+    }
+    //A run that is killed while the file is rewritten must not lose the whole history
+    let mut tmp = NamedTempFile::new_in(path.parent().unwrap_or_else(|| Path::new(".")))?;
+    for line in &lines[lines.len() - limit..] {
+        writeln!(tmp, "{}", line)?;
+    }
+    tmp.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+/// Read every run in the history file at `path`, oldest first. Lines that can't be read, like ones written by a newer version, are
+/// skipped, and a missing file is an empty history
+pub fn load(path: &Path) -> io::Result<Vec<Entry>> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        if let Ok(entry) = serde_json::from_str(&line?) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Format runs as a table with a header and one aligned row for each run
+pub fn table(entries: &[Entry]) -> String {
+    let secs = |ms: Option<u64>| ms.map(|ms| format!("{:.2}s", ms as f64 / 1000.0)).unwrap_or_else(|| "-".to_owned());
+    let header = [
+        msg!("history-time"),
+        msg!("history-action"),
+        msg!("history-result"),
+        msg!("history-discord"),
+        msg!("history-size"),
+        msg!("history-read"),
+        msg!("history-write"),
+        msg!("history-total"),
+        msg!("history-warnings"),
+    ];
+    let rows = entries
+        .iter()
+        .map(|entry| {
+            [
+                entry.timestamp.clone(),
+                entry.action.clone(),
+                match entry.success {
+                    true => msg!("history-ok"),
+                    false => msg!("history-failed"),
+                },
+                entry.discord_version.clone().unwrap_or_else(|| "-".to_owned()),
+                entry.bytes_injected.map(|bytes| HumanBytes(bytes).to_string()).unwrap_or_else(|| "-".to_owned()),
+                secs(entry.read_ms),
+                secs(entry.write_ms),
+                secs(entry.elapsed_ms),
+                entry.warnings.to_string(),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let widths = (0..header.len())
+        .map(|col| rows.iter().map(|row| &row[col]).chain(Some(&header[col])).map(|cell| console::measure_text_width(cell)).max().unwrap_or(0))
+        .collect::<Vec<_>>();
+    let line = |cells: &[String]| {
+        let padded = cells.iter().zip(&widths).map(|(cell, width)| console::pad_str(cell, *width, console::Alignment::Left, None));
+        padded.collect::<Vec<_>>().join("  ").trim_end().to_owned()
+    };
+    rows.iter().fold(style(line(&header)).bold().to_string(), |table, row| format!("{}\n{}", table, line(row)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(action: &str) -> Entry {
+        Entry {
+            timestamp: "2026-10-16T12:00:00Z".into(),
+            action: action.into(),
+            success: true,
+            discord_version: Some("app-1.0.9001".into()),
+            bytes_injected: Some(2048),
+            read_ms: Some(120),
+            write_ms: Some(1500),
+            elapsed_ms: Some(2000),
+            warnings: 1,
+        }
+    }
+
+    #[test]
+    pub fn from_report() {
+        let mut report = RunReport { action: Some("apply".into()), success: true, read_ms: Some(5), ..RunReport::default() };
+        report.paths = Some(Default::default());
+        let recorded = Entry::from_report(&report).unwrap();
+        assert_eq!((recorded.action.as_str(), recorded.read_ms, recorded.warnings), ("apply", Some(5), 0));
+        //Paths aren't kept in the history
+        assert!(!serde_json::to_string(&recorded).unwrap().contains("paths"));
+
+        report.action = Some("history".into());
+        assert_eq!(Entry::from_report(&report), None);
+        assert_eq!(Entry::from_report(&RunReport::default()), None);
+    }
+
+    #[test]
+    pub fn append_and_prune() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = path(&dir.path().join("data"));
+        assert_eq!(load(&path).unwrap(), vec![]);
+
+        for action in ["apply", "restore", "check", "apply"] {
+            append(&path, &entry(action), 3).unwrap();
+        }
+        let actions = load(&path).unwrap().into_iter().map(|entry| entry.action).collect::<Vec<_>>();
+        assert_eq!(actions, ["restore", "check", "apply"]);
+
+        //A line that can't be read doesn't hide the others
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        writeln!(file, "not json").unwrap();
+        drop(file);
+        assert_eq!(load(&path).unwrap().len(), 3);
+        append(&path, &entry("status"), 2).unwrap();
+        assert_eq!(load(&path).unwrap(), vec![entry("status")]);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    pub fn table_rows() {
+        let mut failed = entry("restore");
+        failed.success = false;
+        failed.bytes_injected = None;
+        let table = console::strip_ansi_codes(&table(&[entry("apply"), failed])).into_owned();
+        let lines = table.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].contains("apply") && lines[1].contains(&HumanBytes(2048).to_string()) && lines[1].contains("1.50s"));
+        assert!(lines[2].contains("restore") && lines[2].contains(&msg!("history-failed")));
+        //Every column starts at the same place in each row
+        assert_eq!(lines[1].find("app-1.0.9001"), lines[2].find("app-1.0.9001"));
+    }
+}
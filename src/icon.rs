@@ -0,0 +1,525 @@
+//! The `icon` module replaces Discord's icon file with the old blurple clyde icon or a custom icon, and checks that custom icons
+//! are in the format that the current platform expects before they overwrite anything
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use image::{imageops::FilterType, ImageOutputFormat};
+
+/// The icon file that we will swap with Discord's new one, used for Discord's `.ico` icons on Windows
+pub const OLD_ICO: &[u8] = include_bytes!("../assets/old.ico");
+
+/// The old icon file in png format because linux uses normal images for icons, and Windows has some `.png` icons too
+pub const OLD_PNG: &[u8] = include_bytes!("../assets/old.png");
+
+/// The name of Discord's icon file name
+#[cfg(target_os = "windows")]
+pub const ICON_NAME: &str = "app.ico";
+
+/// The non-windows discord icon file name
+#[cfg(not(target_os = "windows"))]
+pub const ICON_NAME: &str = "discord.png";
+
+/// Other icon files that may be in Discord's version folder, relative to it
+#[cfg(target_os = "windows")]
+const VERSION_ICONS: &[&str] = &["app.ico", "app.png"];
+
+/// Other icon files that may be in Discord's version folder, relative to it
+#[cfg(not(target_os = "windows"))]
+const VERSION_ICONS: &[&str] = &["discord.png"];
+
+/// Icon files that may be in the `discord_desktop_core` folder, relative to it
+const CORE_ICONS: &[&str] = &["app.png"];
+
+/// The format that Discord's icon file is in on this platform
+#[cfg(target_os = "windows")]
+pub const PLATFORM_FORMAT: Format = Format::Ico;
+
+/// The format that Discord's icon file is in on this platform
+#[cfg(not(target_os = "windows"))]
+pub const PLATFORM_FORMAT: Format = Format::Png;
+
+/// The magic bytes that every PNG file starts with
+const PNG_MAGIC: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+/// The reserved and type fields at the start of every ICO file
+const ICO_MAGIC: [u8; 4] = [0, 0, 1, 0];
+
+/// The sizes of the square images that are put in an ICO file converted from a PNG
+const ICO_SIZES: [u32; 6] = [16, 32, 48, 64, 128, 256];
+
+/// The default location of Discord's app bundle on macOS
+pub const MAC_BUNDLE: &str = "/Applications/Discord.app";
+
+/// The path of the dock icon inside of Discord's macOS app bundle
+pub const ICNS_PATH: &str = "Contents/Resources/electron.icns";
+
+/// The PNG-compressed image types that are put in an ICNS file converted from a PNG, with the pixel size of each one
+const ICNS_TYPES: [(&[u8; 4], u32); 8] = [
+    (b"ic11", 32),
+    (b"ic12", 64),
+    (b"ic07", 128),
+    (b"ic13", 256),
+    (b"ic08", 256),
+    (b"ic14", 512),
+    (b"ic09", 512),
+    (b"ic10", 1024),
+];
+
+/// The biggest icon that will be downloaded from a URL, in bytes
+#[cfg(feature = "autoupdate")]
+const MAX_DOWNLOAD_SIZE: u64 = 8 * 1024 * 1024;
+
+/// The `Format` enum is every image format that an icon can be in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// A Windows icon file containing one or more images
+    Ico,
+
+    /// A PNG image
+    Png,
+}
+
+impl Format {
+    /// Detect the format of an image from its magic bytes
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&PNG_MAGIC) {
+            Some(Self::Png)
+        } else if data.starts_with(&ICO_MAGIC) {
+            Some(Self::Ico)
+        } else {
+            None
+        }
+    }
+
+    /// Get the format that an icon file should be in from its extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "ico" => Some(Self::Ico),
+            "png" => Some(Self::Png),
+            _ => None,
+        }
+    }
+}
+
+/// The `Icon` struct holds an icon in every format that Discord's icon files may need
+#[derive(Debug, Clone)]
+pub struct Icon {
+    /// The icon as a PNG image, `None` if a custom .ico file was given
+    pub png: Option<Vec<u8>>,
+
+    /// The icon as an ICO file, `None` if a custom .png file was given on a platform that doesn't use ICO icons
+    pub ico: Option<Vec<u8>>,
+}
+
+impl Icon {
+    /// Get the old icon that is embedded in this executable
+    pub fn embedded() -> Self {
+        Self {
+            png: Some(OLD_PNG.to_vec()),
+            ico: Some(OLD_ICO.to_vec()),
+        }
+    }
+
+    /// Read a custom icon from the given path and check that it is in the format that Discord uses on this platform,
+    /// converting PNG icons to ICO files if this platform needs them
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Self::from_bytes(fs::read(path)?)
+    }
+
+    /// Get a custom icon from the given URL from the [cache](crate::cache) as `policy` says, refusing icons bigger than
+    /// [MAX_DOWNLOAD_SIZE] and showing the download's progress on `bar`, and check it like [Icon::load]
+    #[cfg(feature = "autoupdate")]
+    pub fn download(url: &str, policy: crate::cache::Policy, bar: &indicatif::ProgressBar) -> Result<Self, Error> {
+        use crate::cache;
+        let http = cache::Http { bar, limit: MAX_DOWNLOAD_SIZE };
+        let data = cache::fetch(&http, &cache::Store::open(), url, policy)
+            .map_err(|e| Error::Download {
+                url: url.to_owned(),
+                err: e.to_string(),
+            })?;
+        Self::from_bytes(data)
+    }
+
+    /// Check that an icon is in the format that Discord uses on this platform, converting PNG icons to ICO files if this platform needs them
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, Error> {
+        match (Format::detect(&data), PLATFORM_FORMAT) {
+            (Some(Format::Png), Format::Ico) => Ok(Self {
+                ico: Some(png_to_ico(&data)?),
+                png: Some(data),
+            }),
+            (_, format) => {
+                validate(&data, format)?;
+                Ok(match format {
+                    Format::Png => Self {
+                        png: Some(data),
+                        ico: None,
+                    },
+                    Format::Ico => Self {
+                        png: None,
+                        ico: Some(data),
+                    },
+                })
+            }
+        }
+    }
+
+    /// Get the icon in the given format, if we have it in that format
+    pub fn data(&self, format: Format) -> Option<&[u8]> {
+        match format {
+            Format::Png => self.png.as_deref(),
+            Format::Ico => self.ico.as_deref(),
+        }
+    }
+}
+
+/// The `Variants` struct is every icon file that [variants] looked for in Discord's installation
+#[derive(Debug, Default)]
+pub struct Variants {
+    /// The icon files that exist
+    pub found: Vec<PathBuf>,
+
+    /// Icon files that some Discord builds have but this one doesn't
+    pub missing: Vec<PathBuf>,
+}
+
+/// Find the icon files other than [ICON_NAME] in the root folder that Discord uses for the taskbar and tray, looking for a known
+/// list of names and any other `.ico` or `app*.png` files in the version and `discord_desktop_core` folders because the set of
+/// icons changes between Discord builds
+pub fn variants(root: &Path, version: &Path, core: &Path) -> Variants {
+    let main = root.join(ICON_NAME);
+    let mut variants = Variants::default();
+    for (dir, names) in [(version, VERSION_ICONS), (core, CORE_ICONS)].iter() {
+        for name in names.iter() {
+            let path = dir.join(name);
+            if path == main || variants.found.contains(&path) {
+                continue;
+            }
+            match path.is_file() {
+                true => variants.found.push(path),
+                false => variants.missing.push(path),
+            }
+        }
+
+        //Also pick up icons that aren't in the known list
+        if let Ok(entries) = fs::read_dir(dir) {
+            for path in entries.filter_map(Result::ok).map(|entry| entry.path()) {
+                if is_icon(&path) && path.is_file() && path != main && !variants.found.contains(&path) {
+                    variants.found.push(path);
+                }
+            }
+        }
+    }
+    variants
+}
+
+/// Check if a file name looks like one of Discord's icons
+fn is_icon(path: &Path) -> bool {
+    match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name.ends_with(".ico") || (name.starts_with("app") && name.ends_with(".png")),
+        None => false,
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ico => write!(f, ".ico"),
+            Self::Png => write!(f, ".png"),
+        }
+    }
+}
+
+/// Check that `data` is an image in the `expected` format, making sure that ICO files contain at least one image
+pub fn validate(data: &[u8], expected: Format) -> Result<(), Error> {
+    match Format::detect(data) {
+        Some(found) if found != expected => Err(Error::WrongFormat { expected, found }),
+        Some(Format::Ico) => {
+            //The number of images is stored after the magic bytes, and each one has a 16 byte directory entry after the 6 byte header
+            let count = match data.get(4..6) {
+                Some(count) => u16::from_le_bytes([count[0], count[1]]) as usize,
+                None => 0,
+            };
+            match count > 0 && data.len() >= 6 + count * 16 {
+                true => Ok(()),
+                false => Err(Error::NoImages),
+            }
+        }
+        Some(Format::Png) => Ok(()),
+        None => Err(Error::UnknownFormat),
+    }
+}
+
+/// Convert a PNG image to an ICO file containing resized copies of it in every size in [ICO_SIZES]. Every image is
+/// stored PNG-compressed, which the ICO format requires for 256px images and Windows supports for all sizes
+pub fn png_to_ico(png: &[u8]) -> Result<Vec<u8>, Error> {
+    let images = resize_png(png, &ICO_SIZES)?;
+
+    //Write the header, then one directory entry for every image, then the images themselves
+    let mut ico = Vec::new();
+    ico.extend_from_slice(&ICO_MAGIC);
+    ico.extend_from_slice(&(images.len() as u16).to_le_bytes());
+    let mut offset = 6 + images.len() * 16;
+    for (size, buf) in images.iter() {
+        let dim = if *size >= 256 { 0 } else { *size as u8 }; //A size of 0 means 256 pixels
+        ico.extend_from_slice(&[dim, dim, 0, 0]); //Width, height, palette size, and reserved byte
+        ico.extend_from_slice(&1u16.to_le_bytes()); //Color planes
+        ico.extend_from_slice(&32u16.to_le_bytes()); //Bits per pixel
+        ico.extend_from_slice(&(buf.len() as u32).to_le_bytes());
+        ico.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += buf.len();
+    }
+    for (_, buf) in images {
+        ico.extend_from_slice(&buf);
+    }
+    Ok(ico)
+}
+
+/// Convert a PNG image to a macOS ICNS file containing resized copies of it for every type in [ICNS_TYPES]
+pub fn png_to_icns(png: &[u8]) -> Result<Vec<u8>, Error> {
+    let sizes = ICNS_TYPES.iter().map(|(_, size)| *size).collect::<Vec<_>>();
+    let images = resize_png(png, &sizes)?;
+
+    //Every ICNS file and every image in it has a four character type and a big endian length that includes the 8 byte header
+    let len = 8 + images.iter().map(|(_, buf)| 8 + buf.len()).sum::<usize>();
+    let mut icns = Vec::with_capacity(len);
+    icns.extend_from_slice(b"icns");
+    icns.extend_from_slice(&(len as u32).to_be_bytes());
+    for ((ty, _), (_, buf)) in ICNS_TYPES.iter().zip(images) {
+        icns.extend_from_slice(*ty);
+        icns.extend_from_slice(&(8 + buf.len() as u32).to_be_bytes());
+        icns.extend_from_slice(&buf);
+    }
+    Ok(icns)
+}
+
+/// Decode a PNG image and encode a square copy of it for every size in `sizes`. Every size is encoded before returning
+/// so that a failed resize never leaves a partial icon written
+fn resize_png(png: &[u8], sizes: &[u32]) -> Result<Vec<(u32, Vec<u8>)>, Error> {
+    let img = image::load_from_memory(png)?;
+    let mut images = Vec::with_capacity(sizes.len());
+    for size in sizes.iter().copied() {
+        let mut buf = Vec::new();
+        img.resize_exact(size, size, FilterType::Lanczos3)
+            .write_to(&mut buf, ImageOutputFormat::Png)?;
+        images.push((size, buf));
+    }
+    Ok(images)
+}
+
+/// Convert a PNG icon to an ICNS file and replace the dock icon in Discord's macOS app bundle with it, then touch the bundle
+/// so that Finder notices the change and refreshes its icon cache
+pub fn replace_icns(bundle: &Path, png: &[u8]) -> Result<(), Error> {
+    let icns = png_to_icns(png)?;
+    fs::write(bundle.join(ICNS_PATH), icns)?;
+    match Command::new("touch").arg(bundle).status()?.success() {
+        true => Ok(()),
+        false => Err(Error::IOErr(io::Error::other(
+            "Failed to touch Discord's app bundle, the dock icon may not change until Finder is restarted",
+        ))),
+    }
+}
+
+/// The `Replaced` enum is what [replace] did to an icon file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replaced {
+    /// The icon file was overwritten with our icon
+    Written,
+
+    /// The icon file already contained our icon, so it wasn't written again
+    Unchanged,
+
+    /// We don't have the icon in the format that the file needs, so it wasn't changed
+    WrongFormat,
+}
+
+/// Replace an icon file with the given icon in the format that the file's extension needs. The file is left alone if it already contains
+/// our icon, so that its timestamp doesn't change and Windows doesn't refresh its icon cache for nothing
+pub fn replace(path: &Path, icon: &Icon) -> io::Result<Replaced> {
+    let data = match Format::from_path(path).and_then(|format| icon.data(format)) {
+        Some(data) => data,
+        None => return Ok(Replaced::WrongFormat),
+    };
+    match fs::read(path) {
+        Ok(current) if current == data => Ok(Replaced::Unchanged),
+        _ => fs::write(path, data).map(|_| Replaced::Written),
+    }
+}
+
+/// The `Error` enum represents all errors that can happen when loading a custom icon
+#[derive(Debug)]
+pub enum Error {
+    /// The icon is a valid image but not in the format that this platform needs
+    WrongFormat {
+        /// The format that this platform needs
+        expected: Format,
+        /// The format that the icon is in
+        found: Format,
+    },
+
+    /// The icon is not in any format that we recognize
+    UnknownFormat,
+
+    /// The icon is an ICO file with no images in it
+    NoImages,
+
+    /// A PNG icon could not be decoded or resized while converting it to an ICO file
+    Convert(image::ImageError),
+
+    /// An icon couldn't be downloaded from a URL
+    Download {
+        /// The URL that the icon was downloaded from
+        url: String,
+        /// Why the download failed
+        err: String,
+    },
+
+    /// Read or write error
+    IOErr(io::Error),
+}
+
+impl From<image::ImageError> for Error {
+    fn from(err: image::ImageError) -> Self {
+        Self::Convert(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::IOErr(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongFormat { expected, found } => write!(
+                f,
+                "The icon is a {} file but Discord needs a {} file on this platform",
+                found, expected
+            ),
+            Self::UnknownFormat => write!(f, "The icon is not a .png or .ico file"),
+            Self::NoImages => write!(f, "The .ico file does not contain any images"),
+            Self::Convert(err) => write!(f, "Failed to convert the .png icon to a .ico file: {}", err),
+            Self::Download { url, err } => write!(f, "Failed to download the icon from {}: {}", url, err),
+            Self::IOErr(err) => write!(f, "IO Error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn embedded_icon() {
+        validate(OLD_ICO, Format::Ico).unwrap();
+        validate(OLD_PNG, Format::Png).unwrap();
+        assert!(Icon::embedded().data(PLATFORM_FORMAT).is_some());
+    }
+
+    #[test]
+    pub fn find_variants() {
+        let root = tempfile::tempdir().unwrap();
+        let version = root.path().join("app-1.0.9001");
+        let core = version.join("modules/discord_desktop_core");
+        fs::create_dir_all(&core).unwrap();
+        fs::write(root.path().join(ICON_NAME), OLD_PNG).unwrap();
+        fs::write(version.join(VERSION_ICONS[0]), OLD_PNG).unwrap();
+        fs::write(version.join("tray.ico"), OLD_ICO).unwrap();
+        fs::write(version.join("app-tray.png"), OLD_PNG).unwrap();
+        fs::write(version.join("readme.txt"), b"").unwrap();
+
+        let mut variants = variants(root.path(), &version, &core);
+        variants.found.sort();
+        let mut expected = vec![version.join(VERSION_ICONS[0]), version.join("tray.ico"), version.join("app-tray.png")];
+        expected.sort();
+        assert_eq!(variants.found, expected);
+        assert!(variants.missing.contains(&core.join("app.png")));
+
+        //Icons are only replaced with data in the format that their extension needs
+        let png = root.path().join("icon.png");
+        let icon = Icon {
+            png: None,
+            ico: Some(OLD_ICO.to_vec()),
+        };
+        assert_eq!(replace(&png, &icon).unwrap(), Replaced::WrongFormat);
+        assert_eq!(replace(&png, &Icon::embedded()).unwrap(), Replaced::Written);
+        assert_eq!(fs::read(&png).unwrap(), OLD_PNG);
+        assert_eq!(replace(&png, &Icon::embedded()).unwrap(), Replaced::Unchanged);
+    }
+
+    #[test]
+    pub fn icon_from_bytes() {
+        let icon = Icon::from_bytes(OLD_PNG.to_vec()).unwrap();
+        assert_eq!(icon.data(Format::Png), Some(OLD_PNG));
+        assert!(icon.data(PLATFORM_FORMAT).is_some());
+        assert!(matches!(Icon::from_bytes(b"<html>".to_vec()), Err(Error::UnknownFormat)));
+    }
+
+    #[test]
+    pub fn invalid_icons() {
+        assert!(matches!(
+            validate(include_bytes!("../assets/old.png"), Format::Ico),
+            Err(Error::WrongFormat {
+                expected: Format::Ico,
+                found: Format::Png
+            })
+        ));
+        assert!(matches!(
+            validate(b"GIF89a", Format::Png),
+            Err(Error::UnknownFormat)
+        ));
+        assert!(matches!(
+            validate(&[0, 0, 1, 0, 0, 0], Format::Ico),
+            Err(Error::NoImages)
+        ));
+        assert!(matches!(
+            validate(&[0, 0, 1, 0, 1, 0], Format::Ico),
+            Err(Error::NoImages)
+        ));
+    }
+
+    #[test]
+    pub fn png_to_ico_roundtrip() {
+        let ico = png_to_ico(include_bytes!("../assets/old.png")).unwrap();
+        validate(&ico, Format::Ico).unwrap();
+        assert_eq!(u16::from_le_bytes([ico[4], ico[5]]) as usize, ICO_SIZES.len());
+
+        for (i, size) in ICO_SIZES.iter().enumerate() {
+            let entry = &ico[6 + i * 16..6 + (i + 1) * 16];
+            assert_eq!(entry[0], if *size == 256 { 0 } else { *size as u8 });
+            assert_eq!(entry[0], entry[1]);
+            let len = u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]) as usize;
+            let offset = u32::from_le_bytes([entry[12], entry[13], entry[14], entry[15]]) as usize;
+            assert!(offset + len <= ico.len());
+            if *size == 256 {
+                assert_eq!(Format::detect(&ico[offset..offset + len]), Some(Format::Png));
+            }
+        }
+
+        assert!(matches!(png_to_ico(b"not a png"), Err(Error::Convert(_))));
+    }
+
+    #[test]
+    pub fn png_to_icns_types() {
+        let icns = png_to_icns(include_bytes!("../assets/old.png")).unwrap();
+        assert_eq!(&icns[..4], b"icns");
+        assert_eq!(u32::from_be_bytes([icns[4], icns[5], icns[6], icns[7]]) as usize, icns.len());
+
+        //Walk every image in the file and check that it has the expected type and is a PNG
+        let mut pos = 8;
+        for (ty, _) in ICNS_TYPES.iter() {
+            assert_eq!(&icns[pos..pos + 4], *ty);
+            let len = u32::from_be_bytes([icns[pos + 4], icns[pos + 5], icns[pos + 6], icns[pos + 7]]) as usize;
+            assert_eq!(Format::detect(&icns[pos + 8..pos + len]), Some(Format::Png));
+            pos += len;
+        }
+        assert_eq!(pos, icns.len());
+    }
+}
@@ -0,0 +1,109 @@
+//! The `iconcache` module makes Windows Explorer show Discord's replaced icon. Explorer caches icons, so after `app.ico` is replaced the
+//! taskbar and shortcuts keep showing the new logo until the cache is refreshed
+
+use std::{
+    ffi::OsStr,
+    fs, io,
+    os::windows::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use windows::Win32::UI::Shell::{
+    SHChangeNotify, SHCNE_ASSOCCHANGED, SHCNE_UPDATEITEM, SHCNF_IDLIST, SHCNF_PATHW,
+};
+
+/// Folders relative to the roaming app data folder that Discord's shortcuts are stored in
+const SHORTCUT_DIRS: [&str; 3] = [
+    "Microsoft/Windows/Start Menu/Programs/Discord Inc",
+    "Microsoft/Windows/Start Menu/Programs",
+    "Microsoft/Internet Explorer/Quick Launch/User Pinned/TaskBar",
+];
+
+/// Refresh Explorer's icon cache and every shortcut to Discord, returning the files that couldn't be refreshed and why. Everything here is
+/// best-effort, so nothing stops at the first error
+pub fn flush() -> Vec<(PathBuf, io::Error)> {
+    let mut failed = Vec::new();
+
+    //Touch every shortcut and tell Explorer that it changed so that it reads the icon again
+    for shortcut in shortcuts() {
+        match touch(&shortcut) {
+            Ok(()) => notify_item(&shortcut),
+            Err(e) => failed.push((shortcut, e)),
+        }
+    }
+
+    //Delete the per-user icon cache databases, Explorer keeps the ones that are in use locked so those are skipped
+    for db in cache_databases() {
+        match fs::remove_file(&db) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => (),
+            Err(e) => failed.push((db, e)),
+        }
+    }
+
+    //Tell Explorer that file associations changed, which makes it throw away every cached icon
+    unsafe { SHChangeNotify(SHCNE_ASSOCCHANGED, SHCNF_IDLIST, None, None) };
+    failed
+}
+
+/// Find every shortcut to Discord in the Start Menu, taskbar, and desktop
+fn shortcuts() -> Vec<PathBuf> {
+    let dirs = dirs::data_dir()
+        .into_iter()
+        .flat_map(|appdata| SHORTCUT_DIRS.iter().map(move |dir| appdata.join(dir)))
+        .chain(dirs::desktop_dir());
+    dirs.filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            let name = path.file_name().and_then(OsStr::to_str).unwrap_or_default();
+            name.starts_with("Discord") && name.ends_with(".lnk")
+        })
+        .collect()
+}
+
+/// Find the per-user icon cache database files
+fn cache_databases() -> Vec<PathBuf> {
+    let local = match dirs::data_local_dir() {
+        Some(local) => local,
+        None => return Vec::new(),
+    };
+    let explorer = fs::read_dir(local.join("Microsoft/Windows/Explorer"))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            let name = path.file_name().and_then(OsStr::to_str).unwrap_or_default();
+            name.starts_with("iconcache_") && name.ends_with(".db")
+        });
+    std::iter::once(local.join("IconCache.db"))
+        .filter(|path| path.exists())
+        .chain(explorer)
+        .collect()
+}
+
+/// Update a file's modification time without changing its contents
+fn touch(path: &Path) -> io::Result<()> {
+    fs::OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_modified(SystemTime::now())
+}
+
+/// Tell Explorer that the file at the given path changed
+fn notify_item(path: &Path) {
+    let wide = path
+        .as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect::<Vec<u16>>();
+    unsafe {
+        SHChangeNotify(
+            SHCNE_UPDATEITEM,
+            SHCNF_PATHW,
+            Some(wide.as_ptr() as *const _),
+            None,
+        )
+    };
+}
@@ -0,0 +1,332 @@
+//! The `inline` module copies the stylesheets and files that a CSS theme loads from the internet into the theme itself. Discord's
+//! content security policy blocks most hosts, so a theme that uses `@import url("https://...")` or remote fonts is silently missing
+//! parts inside of Discord. Imported stylesheets are inserted as CSS text and other files become `data:` URLs. References are found
+//! by [references], which skips comments and strings so that only real `@import` rules and `url()` values are rewritten
+
+use std::ops::Range;
+
+/// The biggest file that is inlined as a `data:` URL
+pub const MAX_ASSET_SIZE: u64 = 256 * 1024;
+
+/// The most bytes that are inlined into one theme, counting imported stylesheets and files
+pub const MAX_TOTAL_SIZE: u64 = 4 * 1024 * 1024;
+
+/// How many levels of imports inside of imported stylesheets are followed
+const MAX_DEPTH: usize = 4;
+
+/// The `Asset` struct is a downloaded stylesheet or file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Asset {
+    pub data: Vec<u8>,
+    /// The `Content-Type` header that the file was served with, if there was one
+    pub content_type: Option<String>,
+}
+
+/// The `Kind` enum is how a [Reference] loads its URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kind {
+    /// A top level `@import` rule, with the media query list that follows the URL if there is one
+    Import { media: String },
+    /// A `url()` value, like a font or an image
+    Url,
+}
+
+/// The `Reference` struct is one place in a stylesheet that loads a URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    /// The bytes of the whole `@import` rule or `url()` value in the stylesheet
+    pub range: Range<usize>,
+    pub url: String,
+    pub kind: Kind,
+}
+
+/// The `Inlined` struct is a stylesheet with its remote references inlined by [inline]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inlined {
+    pub css: String,
+    /// The URLs that were inlined
+    pub inlined: Vec<String>,
+    /// The URLs that couldn't be inlined and were left as they are, with the reason why
+    pub failed: Vec<(String, String)>,
+}
+
+/// Check if the bytes at `idx` start with `pat`, ignoring case
+fn starts_with(bytes: &[u8], idx: usize, pat: &str) -> bool {
+    bytes.get(idx..idx + pat.len()).is_some_and(|found| found.eq_ignore_ascii_case(pat.as_bytes()))
+}
+
+/// Get the index just past the end of the string that starts with a quote at `start`. Strings that aren't closed end at the end of
+/// their line, like they do in CSS
+fn skip_string(bytes: &[u8], start: usize) -> usize {
+    let quote = bytes[start];
+    let mut idx = start + 1;
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'\\' => idx += 2,
+            b'\n' => return idx,
+            byte if byte == quote => return idx + 1,
+            _ => idx += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// Skip whitespace starting at `idx`
+fn skip_space(bytes: &[u8], mut idx: usize) -> usize {
+    while bytes.get(idx).is_some_and(u8::is_ascii_whitespace) {
+        idx += 1;
+    }
+    idx
+}
+
+/// Parse the `url()` value that starts at `start`, returning the index just past its closing parenthesis and the URL in it
+fn parse_url(css: &str, start: usize) -> Option<(usize, String)> {
+    let bytes = css.as_bytes();
+    let open = skip_space(bytes, start + "url(".len());
+    let (url, after) = match bytes.get(open)? {
+        b'"' | b'\'' => {
+            let end = skip_string(bytes, open);
+            (css.get(open + 1..end.checked_sub(1)?)?.to_owned(), end)
+        }
+        _ => {
+            let close = open + css[open..].find(')')?;
+            (css[open..close].trim_end().to_owned(), close)
+        }
+    };
+    let close = skip_space(bytes, after);
+    match bytes.get(close) {
+        Some(b')') => Some((close + 1, url)),
+        _ => None,
+    }
+}
+
+/// Parse the `@import` rule that starts at `start`, returning the index just past its semicolon, the URL, and the media query list
+fn parse_import(css: &str, start: usize) -> Option<(usize, String, String)> {
+    let bytes = css.as_bytes();
+    let at = skip_space(bytes, start + "@import".len());
+    let (after, url) = match bytes.get(at)? {
+        b'"' | b'\'' => {
+            let end = skip_string(bytes, at);
+            (end, css.get(at + 1..end.checked_sub(1)?)?.to_owned())
+        }
+        _ if starts_with(bytes, at, "url(") => parse_url(css, at)?,
+        _ => return None,
+    };
+    let semicolon = after + css[after..].find(';')?;
+    Some((semicolon + 1, url, css[after..semicolon].trim().to_owned()))
+}
+
+/// Find every top level `@import` rule and every `url()` value in a stylesheet, skipping the ones inside of comments and strings
+pub fn references(css: &str) -> Vec<Reference> {
+    let bytes = css.as_bytes();
+    let mut found = Vec::new();
+    let (mut idx, mut depth) = (0, 0usize);
+    while idx < bytes.len() {
+        idx = match bytes[idx] {
+            b'/' if bytes.get(idx + 1) == Some(&b'*') => css[idx + 2..].find("*/").map_or(bytes.len(), |end| idx + 2 + end + 2),
+            b'"' | b'\'' => skip_string(bytes, idx),
+            b'\\' => idx + 2,
+            b'{' => {
+                depth += 1;
+                idx + 1
+            }
+            b'}' => {
+                depth = depth.saturating_sub(1);
+                idx + 1
+            }
+            b'@' if depth == 0 && starts_with(bytes, idx, "@import") => match parse_import(css, idx) {
+                Some((end, url, media)) => {
+                    found.push(Reference {
+                        range: idx..end,
+                        url,
+                        kind: Kind::Import { media },
+                    });
+                    end
+                }
+                None => idx + 1,
+            },
+            //`url(` is only a function if it isn't the end of a longer name like `myurl(`
+            b'u' | b'U'
+                if starts_with(bytes, idx, "url(")
+                    && !(idx > 0 && (bytes[idx - 1].is_ascii_alphanumeric() || bytes[idx - 1] == b'-' || bytes[idx - 1] == b'_')) =>
+            {
+                match parse_url(css, idx) {
+                    Some((end, url)) => {
+                        found.push(Reference {
+                            range: idx..end,
+                            url,
+                            kind: Kind::Url,
+                        });
+                        end
+                    }
+                    None => idx + 1,
+                }
+            }
+            _ => idx + 1,
+        };
+    }
+    found
+}
+
+/// Inline every `https://` stylesheet that a theme imports and every `https://` file that it references with `url()`, downloading them
+/// with `fetch`, which is given the most bytes that can be downloaded. Files bigger than [MAX_ASSET_SIZE] and anything that would make
+/// the inlined files bigger than [MAX_TOTAL_SIZE] are left as they are
+pub fn inline(css: &str, fetch: &mut dyn FnMut(&str, u64) -> Result<Asset, String>) -> Inlined {
+    let mut inlined = Inlined {
+        css: String::new(),
+        inlined: Vec::new(),
+        failed: Vec::new(),
+    };
+    let mut total = 0;
+    inlined.css = rewrite(css, fetch, &mut inlined, &mut total, 0);
+    inlined
+}
+
+/// Inline the references in one stylesheet, recording what was inlined in `out`
+fn rewrite(css: &str, fetch: &mut dyn FnMut(&str, u64) -> Result<Asset, String>, out: &mut Inlined, total: &mut u64, depth: usize) -> String {
+    let mut text = String::with_capacity(css.len());
+    let mut last = 0;
+    for reference in references(css).into_iter().filter(|reference| reference.url.starts_with("https://")) {
+        let remaining = MAX_TOTAL_SIZE.saturating_sub(*total);
+        let replacement = match &reference.kind {
+            _ if remaining == 0 => Err("the inlined files already reached their size limit".to_owned()),
+            Kind::Import { .. } if depth >= MAX_DEPTH => Err("it is imported by too many nested stylesheets".to_owned()),
+            Kind::Import { media } => fetch(&reference.url, remaining).and_then(|asset| {
+                *total += asset.data.len() as u64;
+                let imported = String::from_utf8(asset.data).map_err(|_| "the stylesheet is not UTF-8 text".to_owned())?;
+                let imported = rewrite(&imported, fetch, out, total, depth + 1);
+                Ok(match media.is_empty() {
+                    true => imported,
+                    false => format!("@media {} {{\n{}\n}}", media, imported),
+                })
+            }),
+            Kind::Url => fetch(&reference.url, MAX_ASSET_SIZE.min(remaining)).map(|asset| {
+                *total += asset.data.len() as u64;
+                let mime = asset
+                    .content_type
+                    .as_deref()
+                    .and_then(|content_type| content_type.split(';').next())
+                    .map(str::trim)
+                    .filter(|mime| !mime.is_empty())
+                    .unwrap_or_else(|| guess_mime(&reference.url));
+                format!("url(data:{};base64,{})", mime, base64(&asset.data))
+            }),
+        };
+
+        match replacement {
+            Ok(replacement) => {
+                text.push_str(&css[last..reference.range.start]);
+                text.push_str(&replacement);
+                last = reference.range.end;
+                out.inlined.push(reference.url);
+            }
+            Err(e) => out.failed.push((reference.url, e)),
+        }
+    }
+    text.push_str(&css[last..]);
+    text
+}
+
+/// Guess the MIME type of a file that was served without a `Content-Type` header from the extension in its URL
+fn guess_mime(url: &str) -> &'static str {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    match path.rsplit('.').next().unwrap_or_default().to_ascii_lowercase().as_str() {
+        "woff2" => "font/woff2",
+        "woff" => "font/woff",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "css" => "text/css",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Encode bytes as base64 for a `data:` URL
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], chunk.get(1).copied().unwrap_or(0), chunk.get(2).copied().unwrap_or(0)];
+        let n = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for idx in 0..4 {
+            match idx <= chunk.len() {
+                true => out.push(ALPHABET[(n >> (18 - 6 * idx) & 63) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const THEME: &str = r#"/* @import url(https://example.com/commented.css); url(https://example.com/commented.png) */
+@import url("https://example.com/fonts.css") screen;
+@import 'http://example.com/insecure.css';
+.a { content: "url(https://example.com/string.png)"; background: URL( 'https://example.com/image.png' ) }
+.b { --myurl(x): 1; src: url(https://example.com/font.woff2) }
+"#;
+
+    #[test]
+    pub fn find_references() {
+        let found = references(THEME);
+        let urls = found.iter().map(|reference| reference.url.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            urls,
+            vec![
+                "https://example.com/fonts.css",
+                "http://example.com/insecure.css",
+                "https://example.com/image.png",
+                "https://example.com/font.woff2"
+            ]
+        );
+        assert_eq!(found[0].kind, Kind::Import { media: "screen".to_owned() });
+        assert_eq!(&THEME[found[2].range.clone()], "URL( 'https://example.com/image.png' )");
+        assert!(references(".a { background: url(").is_empty());
+    }
+
+    #[test]
+    pub fn inline_theme() {
+        let files = [
+            ("https://example.com/fonts.css", "@font-face { src: url(https://example.com/font.woff2) }"),
+            ("https://example.com/font.woff2", "Man"),
+        ]
+        .iter()
+        .copied()
+        .collect::<HashMap<_, _>>();
+        let mut fetch = |url: &str, limit: u64| match files.get(url) {
+            Some(data) if data.len() as u64 <= limit => Ok(Asset {
+                data: data.as_bytes().to_vec(),
+                content_type: None,
+            }),
+            Some(_) => Err("too big".to_owned()),
+            None => Err("404 Not Found".to_owned()),
+        };
+
+        let inlined = inline(THEME, &mut fetch);
+        assert!(inlined.css.contains("@media screen {\n@font-face { src: url(data:font/woff2;base64,TWFu) }\n}"));
+        assert!(inlined.css.contains("src: url(data:font/woff2;base64,TWFu) }"));
+        //Comments, strings, plain http URLs, and files that failed to download are left alone
+        assert!(inlined.css.contains("/* @import url(https://example.com/commented.css);"));
+        assert!(inlined.css.contains("\"url(https://example.com/string.png)\""));
+        assert!(inlined.css.contains("@import 'http://example.com/insecure.css';"));
+        assert!(inlined.css.contains("URL( 'https://example.com/image.png' )"));
+        assert_eq!(inlined.failed, vec![("https://example.com/image.png".to_owned(), "404 Not Found".to_owned())]);
+        assert_eq!(inlined.inlined.len(), 3);
+    }
+
+    #[test]
+    pub fn encode_base64() {
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b"M"), "TQ==");
+        assert_eq!(base64(b""), "");
+    }
+}
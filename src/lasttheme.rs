@@ -0,0 +1,211 @@
+//! The `lasttheme` module remembers the theme that was last applied to each Discord flavor, with the exact CSS and javascript that were
+//! inserted, so that it can be applied again without asking anything after a Discord update replaces the patched archive. It is kept
+//! in the flavor's backup folder because it outlives every version folder of Discord
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+use crate::{
+    patch::Strategy,
+    schedule::{Css, Schedule},
+};
+
+/// The name of the file in a flavor's backup folder that the [LastTheme] is saved in
+pub const LAST_THEME_NAME: &str = "last-theme.json";
+
+/// The `Precedence` enum is what to do when the program starts, decided by [precedence] from the theme given on the command line and
+/// the last theme that was applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precedence {
+    /// Apply the theme given on the command line, which becomes the last theme
+    Argument,
+    /// Ask before switching from the last theme to the different one given on the command line
+    ConfirmSwitch,
+    /// Show the menu with re-applying the last theme as its default entry
+    OfferLast,
+    /// Show the menu without a last theme to re-apply
+    Menu,
+}
+
+/// Decide what to do with the theme named `argument` that was given on the command line and the `last` theme that was applied. A theme
+/// given on the command line always wins, but switching away from a different last theme is confirmed first when someone can answer.
+/// Themes are the same if they have the same name, which is the path or URL that they were read from
+pub fn precedence(argument: Option<&str>, last: Option<&LastTheme>, interactive: bool) -> Precedence {
+    match (argument, last) {
+        (Some(name), Some(last)) if name != last.name && interactive => Precedence::ConfirmSwitch,
+        (Some(_), _) => Precedence::Argument,
+        (None, Some(_)) => Precedence::OfferLast,
+        (None, None) => Precedence::Menu,
+    }
+}
+
+/// Find the flavor backup folder in `backup_dir` that has a last theme, which is the one named `folder` if it is given. Without a
+/// folder name, like on Linux where Discord's folder is only known after asking for it, the folder whose last theme was saved most
+/// recently is used
+pub fn find(backup_dir: &Path, folder: Option<&str>) -> Option<PathBuf> {
+    if let Some(folder) = folder {
+        return Some(backup_dir.join(folder)).filter(|dir| LastTheme::path(dir).is_file());
+    }
+    fs::read_dir(backup_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter_map(|dir| Some((fs::metadata(LastTheme::path(&dir)).ok()?.modified().ok()?, dir)))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, dir)| dir)
+}
+
+/// The `LastTheme` struct is everything that was inserted into Discord by the last apply
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LastTheme {
+    /// The name of the theme, as it is recorded in the manifest
+    pub name: String,
+    /// The CSS theme, or the light theme if a light and dark theme were applied together
+    pub css: String,
+    /// The dark theme if a light and dark theme were applied together
+    pub dark: Option<String>,
+    /// The `dark-hours` that picked between the light and dark theme, `None` to follow the system
+    pub dark_hours: Option<String>,
+    /// The custom javascript, already escaped to be inserted, or `None` if the block that runs it was left out
+    pub js: Option<String>,
+    /// The javascript that runs when Discord loads the patched file, if there was any
+    pub preload: Option<String>,
+    /// The `window-title` that Discord's window was kept at, if there was one
+    #[serde(default)]
+    pub title: Option<String>,
+    /// The `inject-strategy` that the theme was added with
+    #[serde(default)]
+    pub strategy: Strategy,
+}
+
+impl LastTheme {
+    /// Remember the CSS and javascript that were inserted into Discord with the default strategy and without a window title, which
+    /// are set on the result when they were used
+    pub fn new(name: &str, css: &Css, js: Option<&str>, preload: Option<&str>) -> Self {
+        let (css, dark, dark_hours) = match css {
+            Css::Single(css) => (css.clone(), None, None),
+            Css::Scheduled { light, dark, schedule } => (light.clone(), Some(dark.clone()), schedule.hours()),
+            Css::None => (String::new(), None, None),
+        };
+        Self {
+            name: name.to_owned(),
+            css,
+            dark,
+            dark_hours,
+            js: js.map(str::to_owned),
+            preload: preload.map(str::to_owned),
+            title: None,
+            strategy: Strategy::default(),
+        }
+    }
+
+    /// Get the CSS to insert again. No CSS with custom javascript was an injection that only ran the javascript
+    pub fn css(&self) -> Result<Css, String> {
+        Ok(match &self.dark {
+            Some(dark) => Css::Scheduled {
+                light: self.css.clone(),
+                dark: dark.clone(),
+                schedule: Schedule::parse(self.dark_hours.as_deref())?,
+            },
+            None if self.css.is_empty() && self.js.is_some() => Css::None,
+            None => Css::Single(self.css.clone()),
+        })
+    }
+
+    /// Get the path of the file in a flavor's backup folder
+    pub fn path(flavor_dir: &Path) -> PathBuf {
+        flavor_dir.join(LAST_THEME_NAME)
+    }
+
+    /// Load the last theme that was applied to a flavor, or `None` if there is none or it can't be read
+    pub fn load(flavor_dir: &Path) -> Option<Self> {
+        serde_json::from_slice(&fs::read(Self::path(flavor_dir)).ok()?).ok()
+    }
+
+    /// Save the theme to a flavor's backup folder, replacing the old file only once the new one is written completely
+    pub fn save(&self, flavor_dir: &Path) -> io::Result<()> {
+        let mut temp = NamedTempFile::new_in(flavor_dir)?;
+        temp.write_all(&serde_json::to_vec_pretty(self)?)?;
+        temp.persist(Self::path(flavor_dir)).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    /// Delete the last theme of a flavor, returning `false` if there was none
+    pub fn forget(flavor_dir: &Path) -> io::Result<bool> {
+        match fs::remove_file(Self::path(flavor_dir)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get how many bytes of CSS the theme has
+    pub fn bytes(&self) -> usize {
+        self.css.len() + self.dark.as_ref().map(String::len).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(LastTheme::load(dir.path()), None);
+
+        let scheduled = Css::Scheduled { light: "l".into(), dark: "d".into(), schedule: Schedule::Hours { dark: 19, light: 7 } };
+        let last = LastTheme::new("light + dark", &scheduled, Some(""), Some("early();"));
+        last.save(dir.path()).unwrap();
+        let loaded = LastTheme::load(dir.path()).unwrap();
+        assert_eq!(loaded, last);
+        assert_eq!(loaded.css(), Ok(scheduled));
+
+        //The window title and strategy are remembered too, and files saved before they were are read with the defaults
+        let titled = LastTheme { title: Some("Discord".into()), strategy: Strategy::InsertCss, ..LastTheme::new("a", &Css::from("a {}"), None, None) };
+        titled.save(dir.path()).unwrap();
+        assert_eq!(LastTheme::load(dir.path()), Some(titled));
+        fs::write(LastTheme::path(dir.path()), r#"{"name": "a", "css": "a {}", "dark": null, "dark-hours": null, "js": null, "preload": null}"#).unwrap();
+        assert_eq!(LastTheme::load(dir.path()), Some(LastTheme::new("a", &Css::from("a {}"), None, None)));
+        assert_eq!(LastTheme::new("a", &Css::from("a {}"), None, None).css(), Ok(Css::from("a {}")));
+        assert_eq!(LastTheme::new("reconnect.js", &Css::None, Some("reconnect();"), None).css(), Ok(Css::None));
+
+        assert!(LastTheme::forget(dir.path()).unwrap());
+        assert_eq!((LastTheme::load(dir.path()), LastTheme::forget(dir.path()).unwrap()), (None, false));
+    }
+
+    #[test]
+    pub fn precedence_matrix() {
+        let last = LastTheme::new("/themes/old.css", &Css::from("a {}"), None, None);
+        for interactive in [true, false] {
+            assert_eq!(precedence(None, None, interactive), Precedence::Menu);
+            assert_eq!(precedence(None, Some(&last), interactive), Precedence::OfferLast);
+            assert_eq!(precedence(Some("/themes/new.css"), None, interactive), Precedence::Argument);
+            //Giving the last theme again needs no confirmation
+            assert_eq!(precedence(Some("/themes/old.css"), Some(&last), interactive), Precedence::Argument);
+        }
+        assert_eq!(precedence(Some("/themes/new.css"), Some(&last), true), Precedence::ConfirmSwitch);
+        //Nobody can answer when it runs from a script, so the theme that was given is applied
+        assert_eq!(precedence(Some("/themes/new.css"), Some(&last), false), Precedence::Argument);
+    }
+
+    #[test]
+    pub fn find_flavor_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(find(dir.path(), None), None);
+        for flavor in ["Discord", "DiscordCanary"] {
+            fs::create_dir(dir.path().join(flavor)).unwrap();
+        }
+        fs::create_dir(dir.path().join("discord")).unwrap();
+        LastTheme::new("a", &Css::from("a {}"), None, None).save(&dir.path().join("Discord")).unwrap();
+        assert_eq!(find(dir.path(), Some("Discord")), Some(dir.path().join("Discord")));
+        assert_eq!(find(dir.path(), Some("DiscordCanary")), None);
+        assert_eq!(find(dir.path(), None), Some(dir.path().join("Discord")));
+    }
+}
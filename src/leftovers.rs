@@ -0,0 +1,85 @@
+//! The `leftovers` module finds the folders that old versions of this program left behind. They extracted `core.asar` with `rasar`
+//! into a `coreasar` folder next to the executable, patched `app/mainScreen.js` there, and packed the folder back into Discord. A
+//! run that was interrupted left the folder behind, and its `mainScreen.js` may not be what Discord is running, which is confusing
+//! when the folder is found later
+
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use crate::{asar, MAIN_SCREEN};
+
+/// The name of the folder that old versions extracted `core.asar` into
+pub const LEFTOVER_DIR: &str = "coreasar";
+
+/// The `Leftover` enum is how the `mainScreen.js` in a leftover folder compares to the one in Discord's current `core.asar`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Leftover {
+    /// The leftover file is the same as the one in Discord, so the run that left it finished
+    Matching,
+    /// The leftover file is different from the one in Discord, so the run that left it may not have finished
+    Different,
+    /// The folder has no `mainScreen.js` or Discord's archive doesn't, so there is nothing to compare
+    Unknown,
+}
+
+/// Find the leftover `coreasar` folders in the given folders, like the current folder and the executable's folder, without listing
+/// a folder twice if the same folder is given twice
+pub fn find(dirs: impl IntoIterator<Item = PathBuf>) -> Vec<PathBuf> {
+    let mut found: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+        let leftover = dir.join(LEFTOVER_DIR);
+        let canonical = leftover.canonicalize().unwrap_or_else(|_| leftover.clone());
+        if leftover.is_dir() && !found.iter().any(|other| other.canonicalize().ok().as_ref() == Some(&canonical)) {
+            found.push(leftover);
+        }
+    }
+    found
+}
+
+/// Compare the `mainScreen.js` in the leftover folder with the one in the archive at `core_asar`
+pub fn compare(leftover: &Path, core_asar: &Path) -> Leftover {
+    let extracted = match fs::read(leftover.join(MAIN_SCREEN)) {
+        Ok(extracted) => extracted,
+        Err(_) => return Leftover::Unknown,
+    };
+    let installed = fs::File::open(core_asar)
+        .map_err(asar::Error::from)
+        .and_then(|mut file| asar::Archive::read(&mut file))
+        .ok()
+        .and_then(|mut archive| {
+            let mut buf = Vec::new();
+            archive.get_file_mut(MAIN_SCREEN)?.read_to_end(&mut buf).ok()?;
+            Some(buf)
+        });
+    match installed {
+        Some(installed) if installed == extracted => Leftover::Matching,
+        Some(_) => Leftover::Different,
+        None => Leftover::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{FakeDiscord, MAIN_SCREEN_JS};
+
+    #[test]
+    pub fn leftover_folders() {
+        let fake = FakeDiscord::create();
+        let (cwd, exe) = (fake.dir("cwd"), fake.dir("exe"));
+        let leftover = cwd.join(LEFTOVER_DIR);
+        fs::create_dir_all(leftover.join("app")).unwrap();
+        fs::create_dir_all(&exe).unwrap();
+        assert_eq!(find([cwd.clone(), exe.clone(), cwd.clone()]), vec![leftover.clone()]);
+        assert!(find([exe]).is_empty());
+
+        assert_eq!(compare(&leftover, &fake.asar()), Leftover::Unknown);
+        fs::write(leftover.join(MAIN_SCREEN), MAIN_SCREEN_JS).unwrap();
+        assert_eq!(compare(&leftover, &fake.asar()), Leftover::Matching);
+        fs::write(leftover.join(MAIN_SCREEN), "half patched").unwrap();
+        assert_eq!(compare(&leftover, &fake.asar()), Leftover::Different);
+    }
+}
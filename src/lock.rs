@@ -0,0 +1,256 @@
+//! The `lock` module stops two runs of this program from changing Discord at the same time, like a scheduled `check` that starts while
+//! a theme is being applied from the menu, which could both write `core.asar` at once and leave it corrupted. A run that changes
+//! anything holds the [LOCK_NAME] file in the data folder, which records its process ID and when it started, until it exits. A lock
+//! file whose process is no longer running was left by a run that was killed, and is taken over
+
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// The name of the lock file in the data folder
+pub const LOCK_NAME: &str = "discord-theme.lock";
+
+/// How old a lock file that can't be read must be before it is taken over, because a run on a file system without hard links may still
+/// be writing it
+const UNREADABLE_GRACE: Duration = Duration::from_secs(5);
+
+/// The lock that this run holds, which [release] gives up before the program exits
+static HELD: Mutex<Option<Lock>> = Mutex::new(None);
+
+/// The `Holder` struct is what the lock file records about the run that holds it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Holder {
+    /// The process ID of the run
+    pub pid: u32,
+    /// When the run started, in RFC 3339 format
+    pub started: String,
+}
+
+/// The `Lock` struct is a lock file that this run created, which is deleted when it is dropped
+#[derive(Debug)]
+pub struct Lock {
+    path: PathBuf,
+}
+
+impl Lock {
+    /// Create the lock file in `dir` for this process, taking over a lock file that was left by a process that isn't running anymore
+    pub fn acquire(dir: &Path) -> Result<Self, Error> {
+        fs::create_dir_all(dir)?;
+        let path = path(dir);
+        let holder = Holder {
+            pid: std::process::id(),
+            started: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        };
+        //The holder is written to a temporary file first, so that other runs never see the lock file empty or half written
+        let mut temp = tempfile::NamedTempFile::new_in(dir)?;
+        temp.write_all(&serde_json::to_vec_pretty(&holder)?)?;
+        //The second try is for a stale lock file that was just deleted
+        for _ in 0..2 {
+            match create(temp.path(), &path) {
+                Ok(()) => return Ok(Self { path }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => match holder_of(&path) {
+                    Some(other) if is_running(other.pid) => return Err(Error::Held(other)),
+                    None if !is_stale(&path) => return Err(Error::Starting),
+                    //A lock file that can't be read and is old was cut off when its run was killed while writing it
+                    _ => match fs::remove_file(&path) {
+                        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                        _ => (),
+                    },
+                },
+                Err(e) => return Err(e.into()),
+            }
+        }
+        match holder_of(&path) {
+            Some(other) => Err(Error::Held(other)),
+            None => Err(io::Error::new(io::ErrorKind::AlreadyExists, "the lock file keeps being created by another process").into()),
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Get the path of the lock file in the data folder
+pub fn path(dir: &Path) -> PathBuf {
+    dir.join(LOCK_NAME)
+}
+
+/// Create the lock file at `path` with the contents of the finished file at `temp`, never replacing a lock file that exists. A hard link
+/// is created at once with all of its contents, and file systems without hard links fall back to creating the file and then writing it
+fn create(temp: &Path, path: &Path) -> io::Result<()> {
+    match fs::hard_link(temp, path) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => {
+            let mut file = fs::OpenOptions::new().write(true).create_new(true).open(path)?;
+            file.write_all(&fs::read(temp)?)
+        }
+        linked => linked,
+    }
+}
+
+/// Check if a lock file that can't be read is old enough that it isn't being written by a run that just created it, see [UNREADABLE_GRACE]
+fn is_stale(path: &Path) -> bool {
+    match fs::metadata(path).and_then(|meta| meta.modified()) {
+        Ok(modified) => modified.elapsed().map(|age| age >= UNREADABLE_GRACE).unwrap_or(false),
+        Err(_) => true,
+    }
+}
+
+/// Read who holds the lock file at `path`
+fn holder_of(path: &Path) -> Option<Holder> {
+    serde_json::from_slice(&fs::read(path).ok()?).ok()
+}
+
+/// Acquire the lock in `dir` for the rest of this run, doing nothing if this run already holds it
+pub fn hold(dir: &Path) -> Result<(), Error> {
+    let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+    if held.is_none() {
+        *held = Some(Lock::acquire(dir)?);
+    }
+    Ok(())
+}
+
+/// Check if this run holds the lock
+pub fn is_held() -> bool {
+    HELD.lock().unwrap_or_else(|e| e.into_inner()).is_some()
+}
+
+/// Get the other run that holds the lock in `dir` and is still running, if there is one
+pub fn other_holder(dir: &Path) -> Option<Holder> {
+    holder_of(&path(dir)).filter(|holder| holder.pid != std::process::id() && is_running(holder.pid))
+}
+
+/// Give up the lock that this run holds so that other runs can start, which is done before exiting because exiting skips destructors
+pub fn release() {
+    HELD.lock().unwrap_or_else(|e| e.into_inner()).take();
+}
+
+/// Check if a process with the given ID is running
+#[cfg(target_os = "linux")]
+fn is_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// Check if a process with the given ID is running
+#[cfg(all(unix, not(target_os = "linux")))]
+fn is_running(pid: u32) -> bool {
+    use std::process::{Command, Stdio};
+    Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(true)
+}
+
+/// Check if a process with the given ID is running
+#[cfg(windows)]
+fn is_running(pid: u32) -> bool {
+    use std::process::Command;
+    Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+        .output()
+        .map(|out| String::from_utf8_lossy(&out.stdout).split_whitespace().any(|word| word == pid.to_string()))
+        .unwrap_or(true)
+}
+
+/// The `Error` enum is why the lock couldn't be acquired
+#[derive(Debug)]
+pub enum Error {
+    /// Another run that is still running holds the lock
+    Held(Holder),
+
+    /// Another run just created the lock file and hasn't finished writing who it is
+    Starting,
+
+    /// The lock file couldn't be read or written
+    IOErr(io::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::IOErr(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::IOErr(err.into())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Held(holder) => write!(
+                f,
+                "another discord-theme instance is running (process {}, started at {}). Wait for it to finish, or close it if it is waiting for input",
+                holder.pid, holder.started
+            ),
+            Self::Starting => write!(f, "another discord-theme instance is starting. Wait a few seconds and try again"),
+            Self::IOErr(err) => write!(f, "failed to use the lock file: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn lock_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = Lock::acquire(dir.path()).unwrap();
+        assert_eq!(holder_of(&path(dir.path())).map(|holder| holder.pid), Some(std::process::id()));
+
+        //This process is running, so a second lock is refused until the first is dropped
+        assert!(matches!(Lock::acquire(dir.path()), Err(Error::Held(Holder { pid, .. })) if pid == std::process::id()));
+        drop(lock);
+        assert!(!path(dir.path()).exists());
+        drop(Lock::acquire(dir.path()).unwrap());
+
+        //A lock file that another run just created and hasn't written yet is held, not taken over
+        fs::write(path(dir.path()), "").unwrap();
+        assert!(matches!(Lock::acquire(dir.path()), Err(Error::Starting)));
+        assert_eq!(fs::read(path(dir.path())).unwrap(), b"");
+
+        //Lock files from processes that aren't running and old cut off lock files are taken over
+        for stale in [r#"{"pid": 4294967295, "started": "2020-01-01T00:00:00Z"}"#, "{\"pid\": 12", ""] {
+            fs::write(path(dir.path()), stale).unwrap();
+            let old = SystemTime::now() - UNREADABLE_GRACE * 2;
+            fs::File::options().write(true).open(path(dir.path())).unwrap().set_modified(old).unwrap();
+            assert_eq!(other_holder(dir.path()), None);
+            let lock = Lock::acquire(dir.path()).unwrap();
+            assert_eq!(holder_of(&path(dir.path())).map(|holder| holder.pid), Some(std::process::id()));
+            drop(lock);
+        }
+    }
+
+    #[test]
+    pub fn contention() {
+        let dir = tempfile::tempdir().unwrap();
+        let start = std::sync::Barrier::new(8);
+        //Runs that start at the same time never see each other's lock file empty, so exactly one of them gets it
+        let results = std::thread::scope(|scope| {
+            let runs = (0..8).map(|_| scope.spawn(|| {
+                start.wait();
+                Lock::acquire(dir.path())
+            }));
+            runs.collect::<Vec<_>>().into_iter().map(|run| run.join().unwrap()).collect::<Vec<_>>()
+        });
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+        assert!(results.iter().all(|result| matches!(result, Ok(_) | Err(Error::Held(_)))));
+    }
+}
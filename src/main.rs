@@ -1,73 +1,118 @@
 pub mod asar;
+pub mod backup;
+#[cfg(feature = "autoupdate")]
+pub mod cache;
+#[cfg(feature = "autoupdate")]
+pub mod catalog;
+pub mod components;
 pub mod config;
-use config::Config;
+#[cfg(test)]
+mod conformance;
+pub mod desktop;
+#[cfg(feature = "autoupdate")]
+pub mod download;
+pub mod dump;
+pub mod elevate;
+pub mod escape;
+pub mod guard;
+pub mod history;
+pub mod icon;
+#[cfg(target_os = "windows")]
+pub mod iconcache;
+pub mod inline;
+pub mod lasttheme;
+pub mod leftovers;
+pub mod lock;
+pub mod manifest;
+pub mod messages;
+pub mod mods;
+#[cfg(feature = "autoupdate")]
+pub mod net;
+#[cfg(feature = "notify")]
+pub mod notify;
+pub mod output;
+pub mod pack;
+pub mod patch;
+pub mod pending;
+pub mod preflight;
+#[cfg(feature = "preview")]
+pub mod preview;
+pub mod schedule;
+pub mod site;
+pub mod symlink;
+pub mod task;
+#[cfg(test)]
+pub mod testutil;
+pub mod text;
+pub mod tweaks;
+pub mod ui;
+pub mod uninstall;
+pub mod userdata;
+pub mod variables;
+pub mod version;
+pub mod work;
+use backup::BackupMode;
+use config::{Config, CustomJs};
+use manifest::{AsarState, Manifest, RestoreCheck};
+use messages::msg;
+use output::Category;
+use schedule::{Css, Schedule};
 
 use console::style;
 use console::Color;
 use console::Style;
 use dialoguer::theme::ColorfulTheme;
-use dialoguer::Select;
+use dialoguer::{Confirm, Input, MultiSelect, Select};
 #[cfg(target_os = "linux")]
-use dialoguer::{Attribute, Input};
+use console::Attribute;
 use indicatif::ProgressBar;
-use indicatif::ProgressStyle;
 use std::env;
 use std::fs;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-/// The old CSS theme to insert if no input is given to the exe
-#[cfg(not(feature = "autoupdate"))]
+/// The old CSS theme to insert if no input is given to the exe, or if downloading the newest theme fails
 const OLD_THEME: &str = include_str!("../assets/old-compressed.css");
 
-/// The icon file that we will swap with Discord's new one, this is Windows-specific
-#[cfg(target_os = "windows")]
-const OLD_ICON: &[u8] = include_bytes!("../assets/old.ico");
-
-/// The old icon file in png format because linux uses normal images for icons
-#[cfg(not(target_os = "windows"))]
-const OLD_ICON: &[u8] = include_bytes!("../assets/old.png");
+/// The name that [OLD_THEME] is recorded as in the manifest
+const EMBEDDED_THEME_NAME: &str = "Old Discord (compiled in)";
 
-/// The name of Discord's icon file name
-#[cfg(target_os = "windows")]
-const ICON_NAME: &str = "app.ico";
+/// The path of the javascript file inside of Discord's archive that the CSS is injected into
+const MAIN_SCREEN: &str = "app/mainScreen.js";
 
-/// The non-windows discord icon file name
-#[cfg(not(target_os = "windows"))]
-const ICON_NAME: &str = "discord.png";
+/// The folder next to an archive given with `--asar` that its backups and manifest are kept in
+const EXPLICIT_BACKUP_DIR: &str = "discord-theme-backups";
 
-/// The old URL to download the most recent compressed old.css file from
-#[cfg(feature = "autoupdate")]
-const OLD_URL: &str =
-    "https://raw.githubusercontent.com/Bendi11/discord-theme/master/assets/old-compressed.css";
+/// How many runs the `history` command shows when it isn't given a number
+const HISTORY_SHOWN: usize = 10;
 
 /// I use so many progress bars here that I need a function dedicated to making them with a consistent style
 fn spinner<D: Into<std::borrow::Cow<'static, str>>>(msg: D) -> ProgressBar {
-    let spin = ProgressBar::new_spinner()
-        .with_style(
-            ProgressStyle::default_spinner()
-                .tick_strings(&[
-                    "[>---]", "[=>--]", "[==>-]", "[===>]", "[-===]", "[--==]", "[---=]", "[----]",
-                ])
-                .template("{spinner} - {msg}"),
-        )
-        .with_message(msg);
+    let spin = ui::bar(ui::Kind::Spinner, 0);
     spin.enable_steady_tick(100); //Tick the progress bar every 10th of a second
-    spin
+    output::progress(spin, msg)
 }
 
 /// Get the highest-level discord installation directory, not into a specific version folder, but to the root folder containing all of the
-/// versioned folders. This is kept separate from the [get_discord_dir] function because we need the root folder when replacing the Discord icon
-fn get_discord_root() -> PathBuf {
+/// versioned folders. This is kept separate from the [get_discord_dir] function because we need the root folder when replacing the Discord icon.
+/// The folder given with `--root` is used instead if there is one, which lets tests patch a fake installation
+#[cfg_attr(target_os = "linux", allow(unused_variables))]
+fn get_discord_root(cfg: &Config) -> PathBuf {
+    if let Some(root) = &cfg.root {
+        return root.clone();
+    }
+    let flavor = cfg.flavor;
+
     #[cfg(all(target_os = "windows"))]
     let path = PathBuf::from(format!(
-        "{}\\Discord",
+        "{}\\{}",
         env::var("LOCALAPPDATA")
-            .expect("LOCALAPPDATA environment variable is not present but you are running on a windows system. If you are using wine or similar on linux, please download the linux version of this program")
+            .unwrap_or_else(|e| panic!("{}: {:?}", msg!("localappdata-environment-variable-not-present"), e)),
+        flavor.folder_name()
     )); //Get the path to discord's modules directory
 
     #[cfg(target_os = "macos")]
-    let path = PathBuf::from("/Library/Application Support/Discord"); //We already know the path to the discord install directory
+    let path = Path::new("/Library/Application Support").join(flavor.folder_name()); //We already know the path to the discord install directory
 
     //Make a prompt to request Discord's intstallation path if on linux, because it could be installed in many locations
     #[cfg(target_os = "linux")]
@@ -76,479 +121,3210 @@ fn get_discord_root() -> PathBuf {
             prompt_style: Style::default().attr(Attribute::Italic).fg(Color::Yellow),
             error_style: Style::default().attr(Attribute::Bold).fg(Color::Red),
             ..Default::default()
-        }).with_prompt("Please enter the directory that Discord is installed to (where the 'Discord') binary is located)...").validate_with(|val: &String| {
+        }).with_prompt(msg!("please-enter-directory-discord-installed")).validate_with(|val: &String| {
             let entered = PathBuf::from(val); //Create a path from the string
             match entered.exists() {
                 true => match entered.is_dir() {
                     true => Ok(()),
-                    false => Err("The entered path exists but is not a directory: try removing the file name from the path"),
+                    false => Err(msg!("entered-path-exists-not-directory")),
                 },
-                false => Err("The entered directory does not exist or the application is unable to access it")
+                false => Err(msg!("entered-directory-does-not-exist"))
             }
-        }).interact().unwrap_or_else(|e| panic!("Unable to read input from a query: {}", e))
+        }).interact().unwrap_or_else(|e| panic!("{}", msg!("unable-read-input-query", e)))
     );
 
     path
 }
 
-/// Get the location that Discord was installed to based on the current compilation target and navigate to the highest discord version installed
-fn get_discord_dir(mut root: PathBuf) -> PathBuf {
+/// Get the location that Discord was installed to based on the current compilation target and navigate to the highest discord version installed.
+/// Portable builds without any `app-x.y.z` folders are patched in place if the theme can be injected into something in the root folder,
+/// see [site::portable_core]
+fn get_discord_dir(mut root: PathBuf) -> (PathBuf, site::Layout) {
     //Read all directories in discord's module dir and get the latest version
     let dirs = fs::read_dir(&root).unwrap_or_else(|_| {
-        panic!(
-            "Failed to read Discord's installation directory from {}, does it exist?",
-            root.display()
-        )
+        panic!("{}", msg!("failed-read-discords-installation-directory", root.display()))
     });
 
     //Get the path to the highest version folder of discord and add it to our path
-    root.push(
-        dirs.filter(|entry| entry.as_ref().unwrap().metadata().unwrap().is_dir()) //Filter for only directories in the iterator
-            //Take the maximum semver from the directory
-            .max_by(|entry: &std::io::Result<fs::DirEntry>, next| {
-                //Trim the prefix to the folder to get at the nice semver string
-                match (
-                    entry
-                        .as_ref()
-                        .unwrap()
-                        .file_name()
-                        .to_str()
-                        .unwrap()
-                        .strip_prefix("app-"),
-                    next.as_ref()
-                        .unwrap()
-                        .file_name()
-                        .to_str()
-                        .unwrap()
-                        .strip_prefix("app-"),
-                ) {
-                    (Some(version), Some(next_version)) => {
-                        //Compare the two semantic versioned folders and determine which is a bigger semver number
-                        semver::Version::parse(version)
-                            .unwrap()
-                            .cmp(&semver::Version::parse(next_version).unwrap())
-                    }
-                    (Some(_), None) => std::cmp::Ordering::Greater, //If the next dir doesn't start with the prefix, we are automatically higher semver
-                    (None, Some(_)) => std::cmp::Ordering::Less,    //Same but in reverse
-                    (None, None) => std::cmp::Ordering::Equal,
-                }
-            })
-            .ok_or_else(|| "No directories found in Discord data directory".to_owned())
-            .unwrap()
-            .unwrap()
-            .path(),
-    );
+    let names = dirs
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.metadata().map(|meta| meta.is_dir()).unwrap_or(false)) //Filter for only directories in the iterator
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    //Portable builds have `modules` and `resources` where the version folders would be
+    if !names.iter().any(|name| name.starts_with(version::APP_PREFIX)) {
+        if let Some(core) = site::portable_core(&root) {
+            output::info(msg!("using-portable-discord", style(root.display()).cyan()));
+            return (core, site::Layout::Portable);
+        }
+    }
+    let newest = version::newest(names.iter().map(String::as_str)).unwrap_or_else(|| panic!("{}", msg!("no-directories-found-discord-data")));
+    root.push(newest);
 
-    println!(
-        "Got path to Discords highest version folder: {}",
-        style(root.display()).cyan()
-    );
+    output::info(msg!("got-path-discords-highest-version", style(root.display()).cyan()));
 
-    root.push("modules"); //Go to the modules directory
-
-    //Because of the new update, both discord_desktop_core-1 and discord_desktop_core-2 are possible, so search for the highest number
-    let (_, mut max_path) = match fs::read_dir(root) {
-        //Filter the directories so that we only get the discord_desktop_core ones
-        Ok(dir) => dir
-            .filter_map(|entry| match entry {
-                Ok(entry) => {
-                    let name = entry.file_name();
-                    let name = name.to_str()?; //Get the directory name as a string
-                    if name.starts_with("discord_desktop_core-") {
-                        return Some((
-                            name.strip_prefix("discord_desktop_core-")?
-                                .parse::<u8>()
-                                .ok()?,
-                            entry.path(),
-                        )); //Return the directory number as an integer
-                    }
-                    None
-                }
-                Err(_) => None,
-            })
-            .max_by(|(prev_num, _), (this_num, _)| prev_num.cmp(this_num))
-            .unwrap_or_else(|| panic!("Failed to find an appropriate discord_desktop_core folder")),
-        Err(e) => panic!(
-            "Failed to read an appropriate discord_desktop_core folder: {}",
-            e
-        ),
+    match site::desktop_core(&root.join("modules")) {
+        Ok(Some(core)) => (core, site::Layout::Versioned),
+        Ok(None) => panic!("{}", msg!("failed-find-appropriate-discord-desktop")),
+        Err(e) => panic!("{}", msg!("failed-read-appropriate-discord-desktop", e)),
+    }
+}
+
+/// Roll back a patch of the Discord version whose backups are in `dir` that didn't finish, because this run panicked in the middle of it
+/// or an earlier run was killed, see [guard]
+fn roll_back_interrupted(dir: &Path) {
+    //A patch that another run is writing right now has a guard file too, but it wasn't interrupted
+    let other = match lock::is_held() {
+        true => None,
+        false => lock::other_holder(&userdata::data_dir()),
     };
-    max_path.push("discord_desktop_core");
-    max_path
+    if let Some(holder) = other {
+        if guard::interrupted(dir).is_some() {
+            output::info(style(msg!("patch-in-progress-elsewhere", holder.pid)).yellow());
+        }
+        return;
+    }
+    match guard::rollback(dir) {
+        None => (),
+        Some(Ok(guard::Rollback::Intact)) => output::info(style(msg!("interrupted-patch-archive-intact")).yellow()),
+        Some(Ok(guard::Rollback::Restored(backup))) => {
+            output::info(style(msg!("interrupted-patch-restored", backup.display())).yellow())
+        }
+        Some(Ok(guard::Rollback::NoBackup)) => {
+            output::warning(Category::Backup, style(msg!("interrupted-patch-no-backup")).fg(Color::Color256(172)))
+        }
+        Some(Err(e)) => output::warning(Category::Backup, style(msg!("failed-roll-back-interrupted-patch", e)).fg(Color::Color256(172))),
+    }
 }
 
-/// Replace the `app.ico` on windows or `app.png` on linux / mac with the old blurple clyde icon that is embedded in this executable
-#[inline]
-fn replace_icon(root: &std::path::Path) -> Result<(), std::io::Error> {
-    //Overwrite the icon file
-    std::fs::write(root.join(ICON_NAME), OLD_ICON)
+/// Look for the `coreasar` folders that old versions left in the current folder and next to the executable, warn if one holds a
+/// `mainScreen.js` that isn't the one in Discord because the run that left it may not have finished, and offer to delete them
+fn check_leftovers(paths: &Paths) {
+    let exe_dir = env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_owned));
+    let core_asar = site::Kind::Core.path(&paths.core, &paths.resources());
+    for leftover in leftovers::find(env::current_dir().ok().into_iter().chain(exe_dir)) {
+        output::warning(Category::Discord, style(msg!("found-leftover-coreasar", leftover.display())).fg(Color::Color256(172)));
+        if leftovers::compare(&leftover, &core_asar) == leftovers::Leftover::Different {
+            output::warning(Category::Discord, style(msg!("leftover-coreasar-differs")).fg(Color::Color256(172)));
+        }
+        let delete = console::user_attended()
+            && !output::is_json()
+            && Confirm::new()
+                .with_prompt(msg!("delete-leftover-coreasar", leftover.display()))
+                .default(true)
+                .interact()
+                .unwrap_or(false);
+        if delete {
+            match fs::remove_dir_all(&leftover) {
+                Ok(()) => output::info(style(msg!("deleted-leftover-coreasar", leftover.display())).green()),
+                Err(e) => output::warning(Category::Discord, style(msg!("failed-delete-leftover-coreasar", leftover.display(), e)).fg(Color::Color256(172))),
+            }
+        }
+    }
+}
+
+/// Refuse custom javascript that changed since its hash was pinned in `custom-js-sha256`, and save the new hash when the change is
+/// accepted or the user agrees to pin the script for the first time, see [Config::check_js_pin]
+fn check_js_pin(cfg: &mut Config, accept_change: bool) {
+    match cfg.check_js_pin(accept_change).unwrap_or_else(|e| panic!("{}", e)) {
+        config::JsPin::Unchanged => return,
+        config::JsPin::Accepted(hash) => {
+            output::info(style(msg!("accepted-custom-js-change", hash)).yellow());
+            cfg.custom_js_sha256 = Some(hash);
+        }
+        //Declining is remembered so that the offer isn't made every run, but nobody answered if the output isn't a terminal
+        config::JsPin::Offer(hash) => {
+            if !console::user_attended() || output::is_json() {
+                return;
+            }
+            let pin = Confirm::new().with_prompt(msg!("pin-custom-js-hash", hash)).default(true).interact().unwrap_or(false);
+            match pin {
+                true => cfg.custom_js_sha256 = Some(hash),
+                false => cfg.pin_custom_js = false,
+            }
+        }
+    }
+    if let Err(e) = cfg.save() {
+        output::warning(Category::Config, style(msg!("failed-save-js-pin", cfg.path.display(), e)).fg(Color::Color256(172)));
+    }
 }
 
 /// Prompt the user to quit the application by entering any character, used to make sure that the program doesn't immediately exit
 /// on error
 fn prompt_quit(errcode: i32) -> ! {
     //Render a dialog based on the error code (non-zero means error)
-    println!(
-        "{}",
-        match errcode != 0 {
-            true => style("Enter any character to exit...").red().bold(),
-            false => style("Enter any character to exit...").bold().bright(),
-        }
-    );
-    if console::user_attended() {
+    if !output::is_json() {
+        output::info(match errcode != 0 {
+            true => style(msg!("enter-character-exit")).red().bold(),
+            false => style(msg!("enter-character-exit")).bold().bright(),
+        });
+    }
+    //Files that an elevated run created in the user's folders are given back to the user
+    if let Err(e) = elevate::hand_back() {
+        output::warning(Category::Permissions, style(msg!("failed-hand-back-files", e)).fg(Color::Color256(172)));
+    }
+    output::finish(errcode);
+    lock::release();
+    //Scripts reading the JSON output can't press a key
+    if console::user_attended() && !output::is_json() {
         let _ = console::Term::stdout().read_key();
     }
     std::process::exit(errcode);
 }
 
-/// Create a backup of Discord's data core.asar file and return any errors that occurred. Because making a backup is deemed important,
-/// this function will `panic` instead of returning a `Result`. This is the default behavior, but if the user wants they can edit the config file and turn
-/// backups off.
-fn make_backup(root: PathBuf, dir: PathBuf) {
-    let mut backup_path = dir.clone();
-    backup_path.push("core.asar.backup"); //Add the backup file name to the discord dir
+/// The `Paths` struct holds every location that is read or written for one Discord installation
+struct Paths {
+    /// The root Discord folder containing all of the version folders, see [get_discord_root]
+    root: PathBuf,
+
+    /// The `discord_desktop_core` folder containing `core.asar`, see [get_discord_dir]
+    core: PathBuf,
+
+    /// The `app-x.y.z` folder of the installed Discord version, or the root folder if Discord isn't installed in version folders
+    version: PathBuf,
+
+    /// If Discord is installed in version folders or is a portable build, see [get_discord_dir]
+    layout: site::Layout,
+
+    /// The folder that backups and the manifest for the installed Discord version are stored in
+    backups: PathBuf,
+
+    /// The archive and the javascript file in it that the theme is injected into, see [site::probe]
+    site: site::Site,
+
+    /// Discord's app bundle that the dock icon is stored in on macOS
+    #[cfg(target_os = "macos")]
+    bundle: PathBuf,
+
+    /// The archive given with `--asar`, which is patched instead of the one in Discord's installation, see [Paths::explicit]
+    explicit: Option<PathBuf>,
+
+    /// The symbolic link that the archive is reached through, if it is, see [Paths::find_link]
+    link: Option<symlink::Link>,
+}
 
-    //If the path already exists, then don't overwrite the backup. The reason that we do this instead of overwriting is because we want to keep the original Discord data
-    //intact, with no changes from our program.
-    if backup_path.exists() {
-        println!("Discord backup file {} already exists, not creating a new backup that overrides the old one", backup_path.display());
+impl Paths {
+    /// Find Discord's installation and the backup folder for the installed version, creating the backup folder and moving any
+    /// backups that older versions of this program left inside of Discord's files into it
+    fn resolve(cfg: &Config) -> Self {
+        let mut paths = Self::locate(cfg);
+        fs::create_dir_all(&paths.backups).unwrap_or_else(|e| {
+            panic!("{}", msg!("failed-create-backup-directory", paths.backups.display(), e))
+        });
+
+        if paths.explicit.is_none() {
+            match backup::migrate_legacy(&paths.core, &paths.backups, &paths.root) {
+                Ok(moved) if !moved.is_empty() => {
+                    moved.iter().for_each(|path| {
+                        output::info(msg!("moved-backup-discords-installation", style(path.display()).cyan()))
+                    });
+                    //A manifest that was moved may say which archive was patched
+                    paths.site = paths.find_site();
+                    paths.link = paths.find_link();
+                    paths.report(cfg);
+                }
+                Ok(_) => (),
+                Err(e) => output::warning(Category::Backup, style(msg!("failed-move-old-backups-out", e)).fg(Color::Color256(172))),
+            }
+            check_leftovers(&paths);
+        }
+        roll_back_interrupted(&paths.backups);
+        paths
     }
-    // Otherwise create a backup file
-    else {
-        let mut original = fs::File::open(format!("{}/core.asar", dir.display())).unwrap_or_else(|e| panic!("Failed to open Discord's original core.asar file when creating a backup! Error: {}", e)); //Open the Discord archive file
-        let backup = fs::File::create(&backup_path).unwrap_or_else(|e| {
-            panic!(
-                "Failed to create a backup file for Discord's data! Error: {}",
-                e
-            )
-        }); //Create the backup file
-
-        //Create a progress bar that shows the backup file copying progress
-        let copyprog = ProgressBar::new(match original.metadata() {
-            Ok(meta) => meta.len(),
-            Err(_) => 100,
-        }); //Create a progress bar to show backup copy progress
-        copyprog.set_style(
-            ProgressStyle::default_bar()
-                .template("{bar} {bytes}/{total_bytes} - {binary_bytes_per_sec}"),
-        );
-        copyprog.println("Creating a backup of Discord's files...");
 
-        std::io::copy(&mut original, &mut copyprog.wrap_write(backup)).unwrap_or_else(|e| {
-            panic!(
-                "Failed to copy Discord's core.asar file to a backup file! Error: {}",
-                e
-            )
-        }); //Wrap the writer in a progress bar and copy the file
-
-        //Copy the file and write an error message on error
-        if let Err(e) = fs::copy(format!("{}/core.asar", dir.display()), &backup_path) {
-            eprintln!(
-                "Failed to make a backup of file {}! Reason {:?}",
-                backup_path.display(),
-                style(e).red()
-            );
-            prompt_quit(-1);
+    /// Find Discord's installation and the backup folder for the installed version like [Paths::resolve], without creating, moving, or
+    /// deleting anything
+    fn locate(cfg: &Config) -> Self {
+        if let Some(asar) = &cfg.asar {
+            return Self::explicit(cfg, asar);
+        }
+        let root = get_discord_root(cfg); //Get the Discord root folder by automatic searching or querying on Linux
+        let (core, layout) = get_discord_dir(root.clone()); //Get the path to the highest version Discord installation
+
+        //Backups are stored per Discord flavor like DiscordCanary, which is the name of the root folder
+        let flavor = root
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("Discord")
+            .to_owned();
+        let backups = backup::version_dir(&cfg.backup_dir(), &flavor, &discord_version(&core));
+
+        let version = core
+            .ancestors()
+            .find(|dir| dir.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with("app-")))
+            .unwrap_or(&root)
+            .to_owned();
+
+        let mut paths = Self {
+            root,
+            core,
+            version,
+            layout,
+            backups,
+            site: site::Site::default(),
+            #[cfg(target_os = "macos")]
+            bundle: cfg.mac_bundle(),
+            explicit: None,
+            link: None,
+        };
+        paths.site = paths.find_site();
+        paths.link = paths.find_link();
+        paths.report(cfg);
+        paths
+    }
+
+    /// Follow the archive that the manifest says was patched so that restoring changes the same one, or look for the one that creates
+    /// Discord's window if this version was never patched
+    fn find_site(&self) -> site::Site {
+        let installed = discord_version(&self.core);
+        match Manifest::load(&self.backups).ok().flatten() {
+            Some(manifest) if manifest.discord_version == installed && manifest.layout == self.layout && manifest.patched_hash.is_some() => {
+                manifest.site.unwrap_or_default()
+            }
+            _ => site::probe(&self.core, &self.resources()).ok().flatten().unwrap_or_default(),
         }
     }
 
-    //Create a backup icon file now
+    /// Use an archive that was copied out of a Discord installation without looking for Discord, like with `--asar`. Every folder is
+    /// the one that the archive is in, backups and the manifest are kept in a folder next to it, and icons are never changed
+    fn explicit(cfg: &Config, asar: &Path) -> Self {
+        if !asar.is_file() {
+            panic!("{}", msg!("asar-not-found", asar.display()));
+        }
+        let dir = asar.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or_else(|| Path::new(".")).to_owned();
+        let name = asar.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let backups = dir.join(EXPLICIT_BACKUP_DIR).join(&name);
+
+        //The file to patch is looked for in the given archive, unless the manifest says which one was patched
+        let archive = match name == site::Kind::App.file_name() {
+            true => site::Kind::App,
+            false => site::Kind::Core,
+        };
+        let file = match Manifest::load(&backups).ok().flatten() {
+            Some(Manifest { site: Some(site), patched_hash: Some(_), .. }) => Some(site.file),
+            _ => fs::File::open(asar)
+                .map_err(asar::Error::from)
+                .and_then(|mut file| asar::Archive::read(&mut file))
+                .ok()
+                .and_then(|archive| site::find_file(&archive)),
+        };
+        let mut paths = Self {
+            root: dir.clone(),
+            core: dir.clone(),
+            version: dir,
+            layout: site::Layout::default(),
+            backups,
+            site: site::Site { archive, file: file.unwrap_or_else(|| site::Site::default().file) },
+            #[cfg(target_os = "macos")]
+            bundle: cfg.mac_bundle(),
+            explicit: Some(asar.to_owned()),
+            link: None,
+        };
+        paths.link = paths.find_link();
+        output::info(msg!("using-explicit-asar", style(asar.display()).cyan()));
+        paths.report(cfg);
+        paths
+    }
 
-    let icon = root.join(ICON_NAME); //Get the discord icon name
+    /// Record the locations in the report
+    fn report(&self, cfg: &Config) {
+        output::report(|report| {
+            report.paths = Some(output::ReportPaths {
+                root: self.root.clone(),
+                core: self.core.clone(),
+                asar: self.asar(),
+                backups: self.backups.clone(),
+                version: self.version.clone(),
+                config: cfg.path.clone(),
+                backup_dir: cfg.backup_dir(),
+                #[cfg(feature = "autoupdate")]
+                cache: Some(cache::Store::open().dir().to_owned()),
+                #[cfg(not(feature = "autoupdate"))]
+                cache: None,
+            });
+            report.flavor = Some(cfg.flavor.name().to_owned());
+            report.discord_version = Some(discord_version(&self.core));
+        });
+    }
 
-    let icon_backup = root.join("icon-backup"); //We store the backup without extension because it doesn't really matter and it allows me to write non platform-specific code
-                                                //Only create a backup if there is not a backup there already, this is so that we don't overwrite the old icon backup
-    if !icon_backup.exists() {
-        //Copy the file to a backup
-        match std::fs::copy(icon, icon_backup) {
-            Ok(_) => (),
-            Err(e) => println!(
-                "{}",
-                style(format!("Failed to make a backup of Discord's icon: {}", e))
-                    .fg(Color::Color256(172))
-            ), //Print a warning but don't panic if we couldn't make an icon backup
+    /// Check if the archive is reached through a symbolic link in Discord's installation, printing a warning and treating it as a
+    /// normal file if the link can't be followed
+    fn find_link(&self) -> Option<symlink::Link> {
+        let path = self.found_asar();
+        symlink::find(&path, &self.root).unwrap_or_else(|e| {
+            output::warning(Category::Discord, style(msg!("failed-follow-archive-symlink", path.display(), e)).fg(Color::Color256(172)));
+            None
+        })
+    }
+
+    /// Get the path to the archive that the theme is injected into, which is Discord's `core.asar` file unless its window is created in
+    /// `app.asar`. An archive that is reached through a symbolic link is changed where it really is, so that the link is kept
+    #[inline]
+    fn asar(&self) -> PathBuf {
+        let path = self.found_asar();
+        match &self.link {
+            Some(link) if link.path == path => link.target.clone(),
+            _ => path,
+        }
+    }
+
+    /// Get the path to the archive like [Paths::asar] without following a symbolic link
+    fn found_asar(&self) -> PathBuf {
+        match &self.explicit {
+            Some(asar) => asar.clone(),
+            None => self.site.path(&self.core, &self.resources()),
         }
     }
+
+    /// Get the path to Discord's `resources` folder that `app.asar` is in
+    fn resources(&self) -> PathBuf {
+        #[cfg(target_os = "macos")]
+        return self.bundle.join("Contents/Resources");
+        #[cfg(not(target_os = "macos"))]
+        self.version.join("resources")
+    }
 }
 
-/// Run the discord theme setter application
-fn run() -> Result<(), Box<dyn std::error::Error>> {
-    //Set a panic handler for printing error messages cleanly
-    std::panic::set_hook(Box::new(|pinfo: &std::panic::PanicInfo| {
-        if let Some(loc) = pinfo.location() {
-            eprintln!("In {}", loc);
+/// Create a backup of Discord's data core.asar file and return any errors that occurred. Because making a backup is deemed important,
+/// this function will `panic` instead of returning a `Result`. This is the default behavior, but if the user wants they can edit the config file and turn
+/// backups off. If `compress` is true then new backups are compressed with gzip, and in [BackupMode::File] only the patched file is backed up
+fn make_backup(paths: &Paths, mode: BackupMode, compress: bool) {
+    let version = discord_version(&paths.core);
+    match backup::plan(&paths.backups) {
+        //If a backup already exists in either format, then don't overwrite the backup. The reason that we do this instead of overwriting is because we want to keep the original Discord data
+        //intact, with no changes from our program.
+        backup::Plan::Existing(backup_path) => {
+            output::info(msg!("using-existing-backup-file-discord", style(backup_path.display()).cyan(), style(&version).cyan()));
+            output::report(|report| report.backup = Some(output::ReportBackup { created: false, path: backup_path }));
         }
-        if let Some(s) = pinfo.payload().downcast_ref::<String>() {
-            eprintln!(
-                "A fatal error occurred when executing program: {}",
-                style(s).red()
-            );
-        } else if let Some(s) = pinfo.payload().downcast_ref::<&str>() {
-            eprintln!(
-                "A fatal error occurred when executing program: {}",
-                style(s).red()
-            );
-        } else {
-            eprintln!(
-                "{}",
-                style("An unknown error occurred when executing").red()
-            );
+        //Backups of older versions don't apply to the installed version, so a new one is always made after Discord updates
+        backup::Plan::Create { previous } => {
+            if let Some(previous) = previous {
+                output::info(msg!("discord-updated-since-last-backup", style(previous.file_name().unwrap_or_default().to_string_lossy()).cyan(), style(&version).cyan(), style(&version).cyan()));
+            }
+            let path = create_backup(paths, mode, compress);
+            output::report(|report| report.backup = Some(output::ReportBackup { created: true, path }));
         }
-        prompt_quit(-1);
-    }));
+    }
 
-    //Get the input file path from the arguments or let the user select an option
-    let theme = match env::args().nth(1) {
-        //Read the user CSS theme to a string and escape any '`' characters to not mess up CSS insertion
-        Some(p) => std::fs::read_to_string(&p).unwrap_or_else(|e| panic!("Failed to read custom theme CSS file: {:?}", e)),
-        //No input path given, ask for either a theme download, backup restoration, or exit
-        None => {
-            #[cfg(feature = "autoupdate")]
-            let patch_text = "Download the latest old theme from Github and apply it do Discord";
+    //Icons aren't changed when patching an archive that was given with --asar
+    if paths.explicit.is_some() {
+        return;
+    }
 
-            #[cfg(not(feature = "autoupdate"))]
-            let patch_text = format!("Apply the default old theme that the program was compiled with (last updated {})", env!("COMPILEDATE", "build.rs did not run properly, no compile date set"));
-            
-            //Make a menu for selecting what the user wants to do
-            let selection = Select::with_theme(&ColorfulTheme {
-                prompt_style: Style::default().fg(Color::Blue).bold(),
-                active_item_style: Style::default().fg(Color::Green),
-                active_item_prefix: style(">>".to_owned()).blink(),
-                hint_style: Style::default().fg(Color::Color256(252)),
+    //Create a backup icon file now, we store the backup without extension because it doesn't really matter and it allows me to write non platform-specific code
+    backup_icon(&paths.root.join(icon::ICON_NAME), &backup::icon_backup_path(&paths.backups));
 
-                ..Default::default()
-            }).with_prompt("No input given! Drag and drop a .css theme file onto the executable or pass a path as an argument on the command line if you would like to apply a custom css theme, or select an option")
-            
-            .item(patch_text)
-            .item("Reset Discord's theme to factory defaults from a backup file")
-            .item("Exit the program")
-            .default(0)
-            .interact()
-            .expect("Failed to take a selection from the menu!");
+    //On macOS the dock icon comes from Discord's app bundle instead of the root folder
+    #[cfg(target_os = "macos")]
+    backup_icon(&paths.bundle.join(icon::ICNS_PATH), &backup::icns_backup_path(&paths.backups));
+}
 
-            match selection {
-                //Restore a backup of Discord's asar
-                1 => {
-                    let root = get_discord_root(); //Get the root folder of Discord by searching or querying
-                    let dir = get_discord_dir(root.clone()); //Get the path to Discord
-                                                 //Get the path to both the backup and archive files
-                    let (backup, real) = (dir.join("core.asar.backup"), dir.join("core.asar"));
-                    //If the file doesn't exist then print an error and prompt the user to quit
-                    if !backup.exists() {
-                        panic!("Discord backup file {} doesn't exist, if you want to revert Discord to factory defaults uninstall and then reinstall it", backup.display());
-                    }
+/// Copy one of Discord's icon files to a backup file, printing a warning if it can't be copied. An existing backup is kept so that the
+/// original icon isn't overwritten
+fn backup_icon(icon: &Path, icon_backup: &Path) {
+    if let Err(e) = backup::backup_icon(icon, icon_backup) {
+        output::warning(Category::Icon, style(msg!("failed-make-backup-discords-icon", e)).fg(Color::Color256(172))); //Print a warning but don't panic if we couldn't make an icon backup
+    }
+}
+
+/// Replace Discord's main icon, its other icons, and the macOS dock icon with the given icon, recording the other icons that were
+/// replaced in the manifest
+fn apply_icons(paths: &Paths, icon: &icon::Icon, manifest: &mut Manifest) {
+    let main_icon = paths.root.join(icon::ICON_NAME);
+    let replaced = match icon::replace(&main_icon, icon) {
+        Ok(icon::Replaced::Unchanged) => {
+            output::info(msg!("icon-already-replaced"));
+            icon::Replaced::Unchanged
+        }
+        Ok(replaced) => replaced,
+        Err(e) => {
+            output::warning(Category::Icon, style(msg!("failed-replace-icon", e)).fg(Color::Color256(172))); //Print a warning but don't fail if the icon couldn't be swapped
+            icon::Replaced::WrongFormat
+        }
+    };
+    output::report(|report| report.icon_replaced = Some(replaced != icon::Replaced::WrongFormat));
+    manifest.replaced_icon_hash = icon.data(icon::Format::from_path(&main_icon).unwrap_or(icon::PLATFORM_FORMAT)).map(manifest::hash_bytes);
+    manifest.icons = replace_icon_variants(paths, icon);
 
-                    //Get a progress bar showing how far we are in copying the backup over
-                    let rest_prog = ProgressBar::new(match real.metadata() {
-                        Ok(m) => m.len(),
-                        Err(_) => 100,
-                    }).with_style(ProgressStyle::default_bar().template("{bar} {bytes}/{total_bytes} - {binary_bytes_per_sec}: {msg}")).with_message("Restoring backup file...");
+    //The dock icon on macOS is in the app bundle, which may be protected by SIP or owned by another user
+    #[cfg(target_os = "macos")]
+    if let Err(e) = icon::replace_icns(&paths.bundle, icon.data(icon::Format::Png).unwrap_or(icon::OLD_PNG)) {
+        output::warning(Category::Icon, style(msg!("failed-replace-discords-dock-icon", paths.bundle.display(), e)).fg(Color::Color256(172)));
+    }
 
-                    let _ = fs::remove_file(&real); //Remove the original asar file if it exists
+    //Explorer caches icons, so it keeps showing the old one until it is told to refresh
+    #[cfg(target_os = "windows")]
+    if replaced == icon::Replaced::Written {
+        for (path, e) in iconcache::flush() {
+            output::warning(Category::Icon, style(msg!("failed-refresh-explorers-cached-icon", path.display(), e)).fg(Color::Color256(172)));
+        }
+    }
 
-                    //Open the backup file so that we can wrap it in a progress bar
-                    let mut backup_file = std::fs::File::open(&backup).unwrap_or_else(|e| panic!("Failed to open Discord backup file at {}: {}", backup.display(), e));
+    //Linux launchers usually show an icon from the icon theme instead of the one in Discord's folder
+    #[cfg(target_os = "linux")]
+    match apply_desktop_icon(paths, icon) {
+        Ok(Some(change)) => manifest.desktop_icon = Some(change),
+        Ok(None) => (),
+        Err(e) => output::warning(Category::Icon, style(msg!("failed-update-icon-discords-launcher", e)).fg(Color::Color256(172))),
+    }
+}
 
-                    let real_file = std::fs::File::create(&real).unwrap_or_else(|e| panic!("Failed to open the file that backup is restoring: {}", e)); //Open the real file that we will be copying the backed-up data to
+/// Change the icon of Discord's Linux launcher entry by replacing the icon file that it points to, or by writing a launcher entry for
+/// only this user that points at our icon if that file can't be written. Returns how the icon was changed, or `None` if there is no
+/// launcher entry
+#[cfg(target_os = "linux")]
+fn apply_desktop_icon(paths: &Paths, icon: &icon::Icon) -> std::io::Result<Option<manifest::DesktopIcon>> {
+    let desktop = match desktop::find(desktop::DESKTOP_NAME) {
+        Some(desktop) => desktop,
+        None => {
+            output::info(style(msg!("no-launcher-entry-found-not", desktop::DESKTOP_NAME)).dim());
+            return Ok(None);
+        }
+    };
+    let contents = fs::read_to_string(&desktop)?;
+    let ours = paths.root.join(icon::ICON_NAME);
+    let user_dir = desktop::user_dir().unwrap_or_else(|| desktop.parent().unwrap_or(&desktop).to_owned());
+
+    let change = match desktop::icon(&contents).and_then(desktop::resolve_icon) {
+        //The launcher already shows the icon in Discord's folder, which was just replaced
+        Some(target) if target == ours => match desktop.starts_with(&user_dir) {
+            true => Some(manifest::DesktopIcon::Override { desktop: desktop.clone() }),
+            false => None,
+        },
+        Some(target) if replace_desktop_target(&target, &paths.backups, icon).is_ok() => {
+            output::info(msg!("replaced-launcher-icon", style(target.display()).cyan()));
+            Some(manifest::DesktopIcon::Replaced { icon: target })
+        }
+        //Write our own launcher entry if the icon file is in a system folder that we can't write to
+        _ => {
+            let over = user_dir.join(desktop::DESKTOP_NAME);
+            let backup = backup::desktop_backup_path(&paths.backups);
+            if over.exists() && !backup.exists() {
+                fs::copy(&over, &backup)?;
+            }
+            fs::create_dir_all(&user_dir)?;
+            fs::write(&over, desktop::with_icon(&contents, &ours))?;
+            output::info(msg!("wrote-launcher-entry-using-replaced", style(over.display()).cyan()));
+            Some(manifest::DesktopIcon::Override { desktop: over })
+        }
+    };
+    desktop::refresh(&user_dir)?;
+    Ok(change)
+}
 
-                    //Copy the backup file to the real file, we copy here instead of moving the file to keep a backup just in case the copy operation fails somehow
-                    if let Err(e) = std::io::copy(&mut backup_file, &mut rest_prog.wrap_write(real_file)) {
-                        panic!("Failed to restore backup file {} with error {}, reinstall Discord to restore factory default settings", backup.display(), e);
-                    }
+/// Back up the icon file that the Linux launcher entry points to if it wasn't backed up already, then replace it with our icon
+#[cfg(target_os = "linux")]
+fn replace_desktop_target(target: &Path, backups: &Path, icon: &icon::Icon) -> std::io::Result<()> {
+    let backup = backup::desktop_icon_backup_path(backups);
+    if !backup.exists() {
+        fs::copy(target, &backup)?;
+    }
+    icon::replace(target, icon).map(|_| ())
+}
 
-                    rest_prog.finish_with_message(style("Restored backup file!").green().to_string()); //Finish the progress bar
+/// Undo the change that [apply_desktop_icon] made to the Linux launcher entry's icon
+#[cfg(target_os = "linux")]
+fn restore_desktop_icon(backups: &Path, change: &manifest::DesktopIcon) -> std::io::Result<()> {
+    let dir = match change {
+        manifest::DesktopIcon::Replaced { icon } => {
+            fs::copy(backup::desktop_icon_backup_path(backups), icon)?;
+            icon.parent().map(Path::to_owned)
+        }
+        //Put back the user's own launcher entry if we overwrote one, or remove ours so that the system wide one is used again
+        manifest::DesktopIcon::Override { desktop } => {
+            let backup = backup::desktop_backup_path(backups);
+            match backup.exists() {
+                true => drop(fs::copy(&backup, desktop)?),
+                false => fs::remove_file(desktop)?,
+            }
+            desktop.parent().map(Path::to_owned)
+        }
+    };
+    desktop::refresh(&desktop::user_dir().or(dir).unwrap_or_default())
+}
 
-                    let (iconb, iconr) = (root.join("icon-backup"), root.join(ICON_NAME)); //Get a path to Discord's icon file and backup file
-                    if let Err(e) = fs::copy(iconb, iconr) {
-                        eprintln!("{}", style(format!("Failed to restore Discord's icon from a backup file at {}: {}", root.join("icon-backup").display(), e)).fg(Color::Color256(172)) ); //Print a warning if the backup was not restored
-                    }
+/// Back up and replace every icon file that Discord uses besides the main icon, printing which icons were and weren't found and returning
+/// the replaced icons' paths relative to Discord's root folder so that they can be recorded in the manifest
+fn replace_icon_variants(paths: &Paths, icon: &icon::Icon) -> Vec<String> {
+    let variants = icon::variants(&paths.root, &paths.version, &paths.core);
+    let mut replaced = Vec::new();
+    for path in variants.found {
+        let relative = path.strip_prefix(&paths.root).unwrap_or(&path).to_string_lossy().into_owned();
+        match replace_icon_variant(&path, &backup::icon_variant_path(&paths.backups, &relative), icon) {
+            Ok(icon::Replaced::Written) => {
+                output::info(msg!("replaced-icon-variant", style(&relative).cyan()));
+                replaced.push(relative);
+            }
+            Ok(icon::Replaced::Unchanged) => {
+                output::info(msg!("icon-variant-already-replaced", style(&relative).cyan()));
+                replaced.push(relative);
+            }
+            Ok(icon::Replaced::WrongFormat) => output::info(msg!("skipped-icon-variant", style(&relative).cyan())),
+            Err(e) => output::warning(Category::Icon, style(msg!("failed-replace-icon-variant", relative, e)).fg(Color::Color256(172))),
+        }
+    }
+    for path in variants.missing {
+        output::info(style(msg!("icon-variant-missing", path.display())).dim());
+    }
+    replaced
+}
 
-                    //Print that the operation was good and the backup was restored
-                    println!("{}", style("Restored backup file successfully").green());
-                    prompt_quit(0);
-                },
-                #[cfg(feature = "autoupdate")]
-                //Download the most recent version of the theme from github
-                0 => {
-                    let dlprog = spinner(
-                        console::truncate_str("Downloading most recent theme file from https://raw.githubusercontent.com/Bendi11/discord-theme/master/assets/old-compressed.css", console::Term::stdout().size().0 as usize, "...")
-                    ); //Create a spinner to show download progress
-
-                    //Download the newest version of the theme from github
-                    let text = ureq::get(OLD_URL)
-                        .call()
-                        .unwrap_or_else(|e| panic!("Failed to download newest old theme from {} with error: {}", OLD_URL, e))
-                        .into_string()
-                        .unwrap_or_else(|e| panic!("Failed to get text response from {} when downloading newest theme: {}", OLD_URL, e));
-
-                    dlprog.finish_with_message(style("Downloaded most updated theme file!").green().to_string());
-
-                    //Return the text that was returned based on conditional compilation
-                    text
-                } ,
-                #[cfg(not(feature = "autoupdate"))]
-                0 => OLD_THEME.to_owned(),
-                //Return the default old theme CSS string
-                _ => std::process::exit(0), //Exit the program if the user doesn't want to roll back changes or set the old theme
-            }
-        }
-    }
-    .replace("\\", "\\\\") //Escape characters in CSS will mess up Javascript, so escape the escape sequences
-    .replace("`", "\\`"); //In ES6 template literals, the only character needing escaping is the backtick. I don't know if CSS will ever have this character but just in case
-
-    let cfg = Config::load(); //Load the configuration toml file or create a default one
-
-    //Make a css injection javascript
-    let css = format!(
-        "
-    mainWindow.webContents.on('dom-ready', () => {{
-        mainWindow.webContents.executeJavaScript(`
-            let CSS_INJECTION_USER_CSS = String.raw \\`{css}\\`;  
-            const style = document.createElement('style');  
-            style.innerHTML = CSS_INJECTION_USER_CSS;  
-            document.head.appendChild(style);  
-              
-            //JS_SCRIPT_BEGIN 
-            {js} 
-            //JS_SCRIPT_END 
-        `);
-    }});mainWindow.webContents.
-    ",
-        css = theme,
-        js = cfg.customjs
-    );
+/// Back up an icon file if it wasn't already backed up, then replace it
+fn replace_icon_variant(path: &Path, backup: &Path, icon: &icon::Icon) -> std::io::Result<icon::Replaced> {
+    if !backup.exists() {
+        if let Some(parent) = backup.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(path, backup)?;
+    }
+    icon::replace(path, icon)
+}
 
-    let root = get_discord_root(); //Get the Discord root folder by automatic searching or querying on Linux
+/// Create a new backup of Discord's `core.asar` file in the backup folder, or of only the patched file inside of it in [BackupMode::File],
+/// returning the path of the backup file
+fn create_backup(paths: &Paths, mode: BackupMode, compress: bool) -> PathBuf {
+    //Only save the file that we patch from inside the archive
+    if mode == BackupMode::File {
+        let backup_path = paths.backups.join(backup::FILE_BACKUP_NAME);
+        let backup_prog = spinner(msg!("creating-backup", paths.site.file));
+        if let Err(e) = backup::backup_file(&paths.asar(), &paths.site.file, &backup_path) {
+            panic!("{}", msg!("failed-make-backup-discords-core", paths.site.file, e));
+        }
 
-    let mut path = get_discord_dir(root.clone()); //Get the path to the highest version Discord installation
+        //A file that already contains our injection isn't a backup of Discord's original files
+        if fs::read_to_string(&backup_path).map(|js| js.contains(site::INJECTION_MARKER)).unwrap_or(false) {
+            let _ = fs::remove_file(&backup_path);
+            backup_prog.finish_and_clear();
+            panic!("{}", msg!("discords-file-has-already-been", paths.site.file));
+        }
+        backup_prog.finish_with_message(style(msg!("created-backup-file", backup_path.display())).green().to_string());
+        backup_path
+    }
+    // Otherwise create a backup file
+    else {
+        let backup_path = paths.backups.join(backup::backup_name(compress)); //Add the backup file name to the backup dir
+        let source = paths.asar();
+
+        //An archive whose window file already contains our injection isn't a backup of Discord's original files either
+        let patched = fs::File::open(&source)
+            .map_err(asar::Error::from)
+            .and_then(|mut file| asar::Archive::read(&mut file))
+            .map(|archive| archive.get_file(&paths.site.file).is_some_and(|file| String::from_utf8_lossy(file.as_ref()).contains(site::INJECTION_MARKER)))
+            .unwrap_or(false);
+        if patched {
+            panic!("{}", msg!("discords-file-has-already-been", paths.site.file));
+        }
 
-    //Replace the icon file if the option is specified
-    if cfg.replace_icon {
-        if let Err(e) = replace_icon(&root) {
-            eprintln!(
-                "{}",
-                style(format!("Failed to replace Discord's icon file: {}", e))
-                    .fg(Color::Color256(172))
-            ); //Print a warning but don't fail if the icon couldn't be swapped
+        //Create a progress bar that shows the backup file copying progress, when compressing this shows the compressed bytes written
+        //so it will finish before reaching the original file's size
+        let copyprog = output::progress(
+            ui::bar(ui::Kind::Copy, source.metadata().map(|meta| meta.len()).unwrap_or(100)),
+            msg!("creating-backup-discords-files"),
+        ); //Create a progress bar to show backup copy progress
+
+        //The backup is checked against the original and removed if it doesn't match, so a bad backup is never trusted
+        match backup::backup_asar(&source, &backup_path, compress, &copyprog) {
+            Ok(_) => copyprog.finish_and_clear(),
+            Err(e) => panic!("{}", backup_error(e)),
         }
+        output::info(style(msg!("created-verified-backup-file", backup_path.display())).green());
+        backup_path
     }
+}
 
-    //If make_backup is on then make a backup asar file
-    if cfg.make_backup {
-        make_backup(root, path.clone());
+/// Get the message to show when [backup::backup_asar] fails
+fn backup_error(e: backup::Error) -> String {
+    match e {
+        backup::Error::Open(e) => msg!("failed-open-discords-original-core", e),
+        backup::Error::Corrupt(e) => msg!("refusing-backup-broken-archive", e),
+        backup::Error::Create(e) => msg!("failed-create-backup-file-discords", e),
+        backup::Error::Copy(e) => msg!("failed-copy-discords-core-asar", e),
+        backup::Error::Length { original, backup } => msg!("failed-verify-backup-discords-core", msg!("original-bytes-backup-bytes", original, backup)),
+        backup::Error::Contents => msg!("failed-verify-backup-discords-core", msg!("contents-backup-differ-original")),
     }
+}
 
-    path.push("core.asar"); //Push the core archive file name to the path
+/// Find the backup to restore for the installed Discord version, returning the directory it is in and the path to the backup file.
+/// If there is no backup for the installed Discord version, the most recent backup of another version is used
+fn select_backup(paths: &Paths) -> (PathBuf, PathBuf) {
+    let backups = match backup::find(&paths.backups).or_else(|| backup::find_file(&paths.backups)) {
+        Some(_) => paths.backups.clone(),
+        None => backup::versions(paths.backups.parent().unwrap_or(&paths.backups))
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| paths.backups.clone()),
+    };
 
-    //Create a spinner to show that we are reading Discord's files
-    let js_prog = spinner("Unpacking Discord's archive files...");
+    //Offer whichever kinds of backup exist, asking which one to use if both a whole archive and a single file were backed up
+    let backup = match (backup::find(&backups), backup::find_file(&backups)) {
+        (Some(archive), Some(file)) => {
+            let selection = Select::new()
+                .with_prompt(msg!("both-backup-discords-whole-archive"))
+                .item(msg!("restore-whole-archive", archive.display()))
+                .item(msg!("restore-only-file", paths.site.file, file.display()))
+                .default(0)
+                .interact()
+                .unwrap_or_else(|e| panic!("{}: {:?}", msg!("failed-take-selection-menu"), e));
+            match selection {
+                0 => archive,
+                _ => file,
+            }
+        }
+        (Some(backup), None) | (None, Some(backup)) => backup,
+        //If the file doesn't exist then print an error and prompt the user to quit
+        (None, None) => panic!("{}", msg!("discord-backup-file-doesnt-exist", backups.join(backup::BACKUP_NAME).display())),
+    };
 
-    let mut archive_file = std::fs::OpenOptions::new().read(true).open(&path)?;
-    let mut archive = asar::Archive::read(&mut archive_file)?; //Open the asar archive and parse it
-    drop(archive_file);
+    (backups, backup)
+}
 
-    //Open the javascript file
-    let js_file = archive
-        .get_file_mut("app/mainScreen.js")
-        .ok_or_else(|| "Did not find file \"app/mainScreen.js\" in asar archive".to_owned())?;
+/// Back up Discord's archive and icon and save the manifest without patching anything, returning the path of the archive backup and
+/// the hash of what it contains. An existing backup of the installed version is kept unless `force` is set, and a patched archive is
+/// never backed up because the backup must be Discord's original files
+fn backup_only(paths: &Paths, cfg: &Config, force: bool) -> Result<(PathBuf, String), Box<dyn std::error::Error>> {
+    let existing = [backup::find(&paths.backups), backup::find_file(&paths.backups)];
+    if force || existing.iter().all(Option::is_none) {
+        let archive = asar::Archive::read(&mut fs::File::open(paths.asar())?)?;
+        let patched = archive.get_file(&paths.site.file).is_some_and(|file| String::from_utf8_lossy(file.as_ref()).contains(site::INJECTION_MARKER));
+        if patched {
+            return Err(msg!("refusing-backup-patched-discord", paths.site.file).into());
+        }
+    }
+    if force {
+        for old in existing.iter().flatten() {
+            fs::remove_file(old)?;
+        }
+    }
 
-    let mut jsstr = String::new();
-    js_file.read_to_string(&mut jsstr)?; //Read the javascript file to a string
+    make_backup(paths, cfg.backup_mode, cfg.compress_backups);
+    let mut manifest = current_manifest(paths)?;
+    if force {
+        manifest.original_hash = manifest::hash_file(&paths.asar())?;
+    }
+    manifest.symlink = paths.link.clone();
+    manifest.save(&paths.backups)?;
+
+    let path = backup::find(&paths.backups)
+        .or_else(|| backup::find_file(&paths.backups))
+        .ok_or_else(|| msg!("discord-backup-file-doesnt-exist", paths.backups.join(backup::BACKUP_NAME).display()))?;
+    let hash = manifest::hash_reader(backup::open(&path)?)?;
+    Ok((path, hash))
+}
 
-    //Finish the first progress bar
-    js_prog.finish_with_message(
-        style("Unpacked Discord's archive")
-            .fg(Color::Green)
-            .to_string(),
-    );
+/// Back up Discord's files without patching them, like right after installing Discord, and print where the backup is
+fn backup_now(paths: &Paths, cfg: &Config, force: bool) -> ! {
+    output::action("backup");
+    match backup_only(paths, cfg, force) {
+        Ok((path, hash)) => output::info(style(msg!("backed-up-discord", path.display(), hash)).green()),
+        Err(e) => panic!("{}", e),
+    }
+    prompt_quit(0);
+}
 
-    //Create a spinner to show that we are doing the search and replace for the custom CSS theme
-    let ins_prog = spinner("Inserting CSS theme into Discord's archive...");
-
-    //If the injection string is already in the asar archive then don't replace anything but the user CSS
-    match jsstr.find("CSS_INJECTION_USER_CSS") {
-        //The CSS string is already present, replace the CSS
-        Some(mut idx) => {
-            //println!("{}", style("CSS injection string already present, replacing contents with new CSS theme...").yellow()); //Print that we already did this once
-
-            //Get to the index of the first string quote
-            let begin = loop {
-                //If we reached the ES6 raw string literal return the idx
-                if jsstr
-                    .get(idx..idx + 1)
-                    .ok_or_else(|| panic!("Failed to get the first opening backtick"))
-                    .unwrap()
-                    == "`"
-                {
-                    idx += 1;
-                    break idx;
-                }
-                idx += 1;
-            };
-            let end = loop {
-                //If we reached the ES6 raw string literal return the idx
-                if jsstr
-                    .get(idx..idx + 1)
-                    .ok_or_else(|| panic!("Failed to get the closing backtick"))
-                    .unwrap()
-                    == "`"
-                {
-                    idx += 1;
-                    break idx;
-                }
-                idx += 1;
-            };
+/// Restore Discord's `core.asar` and icon from the backup files made by [make_backup], asking for confirmation first if the
+/// manifest says that the backup was made for a different Discord version
+fn restore_backup(paths: &Paths) -> ! {
+    output::action("restore");
+    check_other_user(paths, false);
+    check_symlink(paths, false);
+    //Get the path to both the backup and archive files
+    let (backups, backup) = select_backup(paths);
+
+    //Make sure that the backup belongs to the Discord version that is installed
+    let manifest = load_manifest(&backups);
+    if let Some(manifest) = &manifest {
+        if let RestoreCheck::DifferentVersion { backup: from, current } = manifest.check_restore(&discord_version(&paths.core)) {
+            let confirmed = Confirm::new()
+                .with_prompt(msg!("backup-file-made-discord-version", from, current))
+                .default(false)
+                .interact()
+                .unwrap_or(false);
+            if !confirmed {
+                output::info(style(msg!("not-restoring-backup-file")).yellow());
+                prompt_quit(0);
+            }
+        }
+    }
+    let real = restore_target(paths, manifest.as_ref());
 
-            jsstr.replace_range((begin)..(end - 2), &theme); //Replace the user CSS with the new user CSS
+    //Distribution packages install Discord where only root can restore it
+    let read_only = real.parent().map(|dir| matches!(tempfile::NamedTempFile::new_in(dir), Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied));
+    if read_only == Some(true) {
+        offer_elevation();
+    }
 
-            let mut idx = jsstr.find("//JS_SCRIPT_BEGIN").expect(
-                "Failed to get JS injection string, please reset Discord and re-apply theme",
-            );
-            idx += "//JS_SCRIPT_BEGIN\n".len(); //Increment the index to go past the end of the JS_SCRIPT_BEGIN string
-                                                //Get to the index of the first string quote
-            let begin = idx;
-            let end = jsstr
-                .find("//JS_SCRIPT_END")
-                .expect("Failed to find JS injection terminator, please reset and re-apply theme");
+    //A single backed up file is re-inserted into the current archive instead of replacing the whole archive
+    restore_archive(&backup, &real, &paths.site.file);
 
-            jsstr.replace_range((begin)..(end), &cfg.customjs); //Replace the JS script path with the new custom JS
-        }
-        //If there is no injection string then replace the strings with an injection string
-        None => {
-            //Replace the string with the CSS injection string inserted
-            jsstr = jsstr.replacen("mainWindow.webContents.", &css, 1);
-        }
+    output::report(|report| report.backup = Some(output::ReportBackup { created: false, path: backup }));
+    if paths.explicit.is_none() {
+        restore_icons(paths, &backups, manifest.as_ref());
     }
 
-    ins_prog.finish_with_message(
-        style("Inserted user CSS into discord's archive")
-            .green()
-            .to_string(),
-    );
+    //Print that the operation was good and the backup was restored
+    output::info(style(msg!("restored-backup-file-successfully")).green());
+    prompt_quit(0);
+}
 
-    //Create a spinner to show that we are re-packing discord's asar file
-    let pack_prog = ProgressBar::new(jsstr.len() as u64)
-        .with_style(
-            ProgressStyle::default_bar()
-                .template("[{bar}] {bytes}/{total_bytes} - {binary_bytes_per_sec}: {msg}")
-                .progress_chars("=>."),
-        )
-        .with_message("Re-packing modified Discord archive files...");
+/// Get the archive that a backup is restored to, which is the one that the manifest says was patched through a symbolic link if the
+/// link now points somewhere else, so that the archive that was changed is the one that is put back
+fn restore_target(paths: &Paths, manifest: Option<&Manifest>) -> PathBuf {
+    let current = paths.asar();
+    match manifest.and_then(|manifest| manifest.symlink.as_ref()) {
+        Some(link) if link.path == paths.found_asar() && link.target != current && link.target.is_file() => {
+            output::warning(
+                Category::Discord,
+                style(msg!("archive-symlink-moved", link.link.display(), link.target.display(), current.display())).fg(Color::Color256(172)),
+            );
+            link.target.clone()
+        }
+        _ => current,
+    }
+}
 
-    //Replace the contents of the file with the new string with CSS and JS inserted
-    js_file.replace_contents(jsstr.into_bytes().as_mut())?;
+/// Copy Discord's main icon, the other icons listed in the manifest, and the macOS dock icon back from the backups in the given folder,
+/// printing a warning for any icon that couldn't be restored and returning `false` if there were any
+fn restore_icons(paths: &Paths, backups: &Path, manifest: Option<&Manifest>) -> bool {
+    let mut restored = true;
+    let (iconb, iconr) = (backup::icon_backup_path(backups), paths.root.join(icon::ICON_NAME)); //Get a path to Discord's icon file and backup file
+    if let Err(e) = fs::copy(&iconb, iconr) {
+        output::warning(Category::Icon, style(msg!("failed-restore-discords-icon-backup", iconb.display(), e)).fg(Color::Color256(172))); //Print a warning if the backup was not restored
+        restored = false;
+    }
+    for relative in manifest.iter().flat_map(|manifest| manifest.icons.iter()) {
+        let variant = backup::icon_variant_path(backups, relative);
+        if let Err(e) = fs::copy(&variant, paths.root.join(relative)) {
+            output::warning(Category::Icon, style(msg!("failed-restore-icon-variant", relative, variant.display(), e)).fg(Color::Color256(172)));
+            restored = false;
+        }
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let icnsb = backup::icns_backup_path(backups);
+        if let Err(e) = fs::copy(&icnsb, paths.bundle.join(icon::ICNS_PATH)) {
+            output::warning(Category::Icon, style(msg!("failed-restore-discords-dock-icon", icnsb.display(), e)).fg(Color::Color256(172)));
+            restored = false;
+        }
+    }
+    #[cfg(target_os = "windows")]
+    for (path, e) in iconcache::flush() {
+        output::warning(Category::Icon, style(msg!("failed-refresh-explorers-cached-icon", path.display(), e)).fg(Color::Color256(172)));
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(change) = manifest.and_then(|manifest| manifest.desktop_icon.as_ref()) {
+        if let Err(e) = restore_desktop_icon(backups, change) {
+            output::warning(Category::Icon, style(msg!("failed-restore-icon-discords-launcher", e)).fg(Color::Color256(172)));
+            restored = false;
+        }
+    }
+    restored
+}
 
-    let archive_file = std::fs::OpenOptions::new().write(true).open(path)?;
-    archive.pack(&mut pack_prog.wrap_write(archive_file), true)?; //Re-pack the Discord asar file
+/// Restore only Discord's icons from their backups without changing the theme. If the main icon's backup is missing, an unchanged copy
+/// of it from the backups of Discord's other icons is offered instead
+fn restore_icons_only(paths: &Paths) -> ! {
+    output::action("icon restore");
+    let icon_backup = backup::icon_backup_path(&paths.backups);
+    if !icon_backup.exists() {
+        let stock = backup::stock_icon(&paths.backups, icon::ICON_NAME).filter(|stock| {
+            Confirm::new()
+                .with_prompt(msg!("no-backup-discords-icon-found", stock.display()))
+                .default(true)
+                .interact()
+                .unwrap_or(false)
+        });
+        match stock {
+            Some(stock) => {
+                fs::copy(&stock, &icon_backup).unwrap_or_else(|e| panic!("{}", msg!("failed-copy-icon-backup", stock.display(), e)));
+            }
+            None => panic!("{}", msg!("no-icon-backup", icon_backup.display())),
+        }
+    }
 
-    pack_prog.finish_with_message(
-        style("Re-packed modified Discord archive, restart Discord for the changes to take effect")
-            .fg(Color::Green)
-            .to_string(),
-    );
+    restore_icons(paths, &paths.backups, load_manifest(&paths.backups).as_ref());
+    output::info(style(msg!("restored-discords-icons-successfully")).green());
+    prompt_quit(0);
+}
 
-    drop(pack_prog);
+/// Replace only Discord's icons without patching `core.asar`, backing up the original icons first
+fn apply_icons_only(paths: &Paths, icon: &icon::Icon) -> ! {
+    output::action("icon apply");
+    backup_icon(&paths.root.join(icon::ICON_NAME), &backup::icon_backup_path(&paths.backups));
+    #[cfg(target_os = "macos")]
+    backup_icon(&paths.bundle.join(icon::ICNS_PATH), &backup::icns_backup_path(&paths.backups));
 
+    let mut manifest = current_manifest(paths).unwrap_or_else(|e| panic!("{}", msg!("failed-read-discords-files", e)));
+    apply_icons(paths, icon, &mut manifest);
+    if let Err(e) = manifest.save(&paths.backups) {
+        output::warning(Category::Backup, style(msg!("failed-save-manifest-restoring-may", e)).fg(Color::Color256(172)));
+    }
+    output::info(style(msg!("replaced-discords-icons-successfully")).green());
+    prompt_quit(0);
+}
+
+/// Restore a backup of the whole `core.asar` archive with a progress bar, or of only the patched file at `file` inside of it
+fn restore_archive(backup: &Path, real: &Path, file: &str) {
+    let single = backup.ends_with(backup::FILE_BACKUP_NAME);
+    //Get a progress bar showing how far we are in copying the backup over
+    let rest_prog = match single {
+        true => ProgressBar::hidden(),
+        false => output::progress(ui::bar(ui::Kind::Copy, real.metadata().map(|meta| meta.len()).unwrap_or(100)), msg!("restoring-backup-file")),
+    };
+
+    //The backup is copied next to the real file and checked before replacing it, so a corrupt backup never leaves Discord without a core.asar
+    match backup::restore_asar(backup, real, file, &rest_prog) {
+        Ok(()) if single => output::info(style(msg!("restored-backup-file", file)).green()),
+        Ok(()) => rest_prog.finish_with_message(style(msg!("restored-backup-archive")).green().to_string()),
+        Err(e) if single => panic!("{}", msg!("failed-restore-backup-file-error", file, backup.display(), e)),
+        Err(e) => panic!("{}", msg!("failed-restore-backup-archive", backup.display(), e)),
+    }
+}
+
+/// Print every file that differs between Discord's `core.asar` and the backup that would be restored without changing anything
+fn diff_backup(paths: &Paths) -> ! {
+    output::action("restore --diff");
+    let (_, backup) = select_backup(paths);
+    let read_archive = |mut read: Box<dyn Read>| -> Result<asar::Archive, asar::Error> {
+        let mut buf = Vec::new();
+        read.read_to_end(&mut buf)?;
+        asar::Archive::read(&mut std::io::Cursor::new(buf))
+    };
+
+    let live = fs::File::open(paths.asar()).map_err(asar::Error::from).and_then(|mut file| asar::Archive::read(&mut file)).unwrap_or_else(|e| panic!("{}", msg!("failed-read-discords-core-asar", e)));
+    let changes = match backup.ends_with(backup::FILE_BACKUP_NAME) {
+        //A backup of only the patched file can only differ in that file
+        true => {
+            let original = fs::read(&backup).unwrap_or_else(|e| panic!("{}", msg!("failed-read-backup-file", backup.display(), e)));
+            match live.get_file(&paths.site.file) {
+                Some(file) if file.as_ref() == original.as_slice() => vec![],
+                Some(file) => vec![(paths.site.file.clone(), asar::Change::Modified { old_size: file.size(), new_size: original.len() })],
+                None => vec![(paths.site.file.clone(), asar::Change::Added { size: original.len() })],
+            }
+        }
+        false => live.diff(&backup::open(&backup).map_err(asar::Error::from).and_then(read_archive).unwrap_or_else(|e| panic!("{}", msg!("failed-read-backup-file", backup.display(), e)))),
+    };
+
+    output::info(msg!("comparing-backup", style(paths.asar().display()).cyan(), style(backup.display()).cyan()));
+    for (path, change) in changes.iter() {
+        let kind = match change {
+            asar::Change::Added { .. } => style(msg!("only-backup")).green(),
+            asar::Change::Removed { .. } => style(msg!("not-backup")).red(),
+            asar::Change::Modified { .. } => style(msg!("modified")).yellow(),
+        };
+        output::info(format!("{:<60} {:<16} {}", path, kind, msg!("size-delta", format!("{:+}", change.size_delta()))));
+    }
+
+    match changes.as_slice() {
+        [] => output::info(style(msg!("discords-core-asar-identical-backup")).green()),
+        [(path, _)] if *path == paths.site.file => output::info(style(msg!("only-differs-restoring-backup-will", paths.site.file)).green()),
+        _ => output::info(style(msg!("files-differ-discord-probably-updated", changes.len())).yellow()),
+    }
+    prompt_quit(0);
+}
+
+/// Copy every backup and the config file into a directory so that they can be moved somewhere safe
+fn export_backups(cfg: &Config, dest: &Path) -> ! {
+    output::action("backup export");
+    let copied = backup::export(&cfg.backup_dir(), dest).unwrap_or_else(|e| panic!("{}", msg!("failed-export-backups", dest.display(), e)));
+    copied.iter().for_each(|path| output::info(msg!("exported", style(path.display()).cyan())));
+
+    if cfg.path.exists() {
+        let config_dest = dest.join(cfg.path.file_name().unwrap_or_default());
+        if let Err(e) = fs::copy(&cfg.path, &config_dest) {
+            panic!("{}", msg!("failed-export-config-file", dest.display(), e));
+        }
+        output::info(msg!("exported", style(config_dest.display()).cyan()));
+    }
+
+    output::info(style(msg!("exported-backup-files", copied.len(), dest.display())).green());
+    prompt_quit(0);
+}
+
+/// Convert the JSON config file to TOML, asking before overwriting an existing TOML config file unless `force` is set
+fn migrate_config(cfg: &Config, force: bool) -> ! {
+    output::action("config migrate");
+    let json = match cfg.path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => cfg.path.clone(),
+        _ => cfg.path.with_file_name(config::LEGACY_CONFIG_NAME),
+    };
+    if !json.exists() {
+        panic!("{}", msg!("there-no-file-migrate", json.display()));
+    }
+    let toml = json.with_file_name(config::CONFIG_NAME);
+    if toml.exists()
+        && !force
+        && !Confirm::new()
+            .with_prompt(msg!("already-exists-overwrite-options", toml.display(), json.display()))
+            .default(false)
+            .interact()
+            .unwrap_or(false)
+    {
+        prompt_quit(0);
+    }
+
+    let path = Config::migrate(&json).unwrap_or_else(|e| panic!("{}", msg!("failed-migrate", json.display(), e)));
+    output::info(style(msg!("wrote-options-no-longer-read", json.display(), path.display(), json.display())).green());
+    prompt_quit(0);
+}
+
+/// Import backups and the config file that were exported with [export_backups], asking before overwriting anything that already
+/// exists unless `force` is set
+fn import_backups(cfg: &Config, overrides: &config::Overrides, src: &Path, force: bool) -> ! {
+    output::action("backup import");
+    //Ask before replacing a backup or config that is already here, unless --force was passed
+    let overwrite = |path: &Path| {
+        force
+            || Confirm::new()
+                .with_prompt(msg!("already-exists-overwrite-imported-one", path.display()))
+                .default(false)
+                .interact()
+                .unwrap_or(false)
+    };
+
+    let imported = backup::import(src, &cfg.backup_dir(), overwrite).unwrap_or_else(|e| panic!("{}", msg!("failed-import-backups", src.display(), e)));
+    imported.iter().for_each(|path| output::info(msg!("imported", style(path.display()).cyan())));
+
+    let config_dir = cfg.path.parent().unwrap_or_else(|| Path::new("."));
+    for name in [config::CONFIG_NAME, config::LEGACY_CONFIG_NAME].iter() {
+        let config_src = src.join(name);
+        let config_dest = config_dir.join(name);
+        if config_src.exists() && (!config_dest.exists() || overwrite(&config_dest)) {
+            if let Err(e) = fs::copy(&config_src, &config_dest) {
+                panic!("{}", msg!("failed-import-config-file", config_src.display(), e));
+            }
+            output::info(msg!("imported", style(config_dest.display()).cyan()));
+        }
+    }
+
+    //Find Discord on this machine to tell the user if the imported backups can be restored to it
+    let paths = Paths::resolve(&Config {
+        root: cfg.root.clone(),
+        ..Config::load(&cfg.path, overrides, cfg.flavor)
+    });
+    match backup::plan(&paths.backups) {
+        backup::Plan::Existing(path) => output::info(style(msg!("backup-applies-installed-discord-version", path.display(), discord_version(&paths.core))).green()),
+        backup::Plan::Create { .. } => output::info(style(msg!("none-imported-backups-were-made", discord_version(&paths.core))).yellow()),
+    }
+    prompt_quit(0);
+}
+
+/// Put Discord back the way it was before this program ever ran and remove the backups, manifests, downloads, and config file that
+/// it created, printing a checklist of what was done. Backups are kept if `keep_backups` is set or if Discord couldn't be restored
+fn uninstall(cfg: &Config, keep_config: bool, keep_backups: bool, force: bool) -> ! {
+    output::action("uninstall");
+    let paths = Paths::resolve(cfg);
+    let confirmed = force
+        || Confirm::new()
+            .with_prompt(msg!("uninstall-confirm"))
+            .default(false)
+            .interact()
+            .unwrap_or(false);
+    if !confirmed {
+        output::info(style(msg!("uninstall-cancelled")).yellow());
+        prompt_quit(0);
+    }
+
+    let mut items = Vec::new();
+    let rest_prog = spinner(msg!("restoring-backup-file"));
+    items.push(uninstall::Item {
+        path: paths.asar(),
+        status: match uninstall::revert_asar(&paths.asar(), &paths.backups, &paths.site.file, &rest_prog) {
+            Ok(uninstall::Reverted::Unpatched) => uninstall::Status::Unchanged,
+            Ok(_) => uninstall::Status::Restored,
+            Err(e) => uninstall::Status::Failed(e.to_string()),
+        },
+    });
+    rest_prog.finish_and_clear();
+
+    //An archive given with --asar only has its own backups, so nothing else that belongs to this program is touched, including the
+    //backups of other archives next to it
+    if paths.explicit.is_some() {
+        let reverted = !items.iter().any(|item| matches!(item.status, uninstall::Status::Failed(_)));
+        if !reverted && !keep_backups {
+            output::warning(Category::Backup, style(msg!("uninstall-keeping-backups")).fg(Color::Color256(172)));
+        }
+        items.extend(uninstall::remove_archive_backups(&paths.backups, keep_backups || !reverted));
+        print_uninstall(items);
+    }
+
+    //The icon backups are shared by every version, so they are restored even if the installed version was never patched
+    let icon_backup = backup::icon_backup_path(&paths.backups);
+    items.push(uninstall::Item {
+        path: paths.root.join(icon::ICON_NAME),
+        status: match icon_backup.exists() {
+            true if restore_icons(&paths, &paths.backups, load_manifest(&paths.backups).as_ref()) => uninstall::Status::Restored,
+            true => uninstall::Status::Failed(msg!("uninstall-icons-failed")),
+            false => uninstall::Status::Missing,
+        },
+    });
+
+    //Never delete the only copy of Discord's original files
+    let reverted = items.iter().all(|item| !matches!(item.status, uninstall::Status::Failed(_)));
+    if !reverted && !keep_backups {
+        output::warning(Category::Backup, style(msg!("uninstall-keeping-backups")).fg(Color::Color256(172)));
+    }
+    let backup_dir = cfg.backup_dir();
+    let flavor_dir = paths.backups.parent().unwrap_or(&paths.backups);
+    items.extend(uninstall::remove_backups(flavor_dir, keep_backups || !reverted));
+
+    #[cfg(feature = "autoupdate")]
+    items.extend(uninstall::remove_existing(std::iter::once(cache::Store::open().dir().to_owned()).chain(cache::legacy_paths())));
+
+    match keep_config {
+        true => items.push(uninstall::Item {
+            path: cfg.path.clone(),
+            status: uninstall::Status::Kept,
+        }),
+        false => {
+            items.push(uninstall::remove(&cfg.path));
+            items.extend(uninstall::remove_existing((0..config::CONFIG_VERSION).map(|version| config::migration_backup_path(&cfg.path, version))));
+        }
+    }
+
+    //Only the folders that are ours by default are deleted once they're empty, not a folder that the user picked
+    uninstall::prune(std::iter::once(backup_dir.as_path()).filter(|dir| *dir == backup::default_dir()));
+    uninstall::prune(Some(config::default_dir().as_path()));
+    uninstall::prune(backup::default_dir().parent());
+    print_uninstall(items);
+}
+
+/// Print what happened to every file that [uninstall] went through and exit, with an error code if any of them failed
+fn print_uninstall(items: Vec<uninstall::Item>) -> ! {
+    let mut failed = false;
+    for item in items {
+        let path = item.path.display();
+        output::info(match item.status {
+            uninstall::Status::Restored => format!("{} {}", style("[ OK ]").green().bold(), msg!("uninstall-restored", path)),
+            uninstall::Status::Removed => format!("{} {}", style("[ OK ]").green().bold(), msg!("uninstall-removed", path)),
+            uninstall::Status::Kept => format!("{} {}", style("[KEEP]").cyan().bold(), msg!("uninstall-kept", path)),
+            uninstall::Status::Missing => format!("{} {}", style("[ -- ]").bold(), msg!("uninstall-missing", path)),
+            uninstall::Status::Unchanged => format!("{} {}", style("[ -- ]").bold(), msg!("uninstall-unchanged", path)),
+            uninstall::Status::Failed(e) => {
+                failed = true;
+                format!("{} {}", style("[FAIL]").red().bold(), style(msg!("uninstall-failed", path, e)).red())
+            }
+        });
+    }
+    prompt_quit(failed as i32);
+}
+
+/// Get the name of the Discord version folder like `app-1.0.9001` that the given path is contained in
+fn discord_version(dir: &Path) -> String {
+    dir.ancestors()
+        .filter_map(|p| p.file_name()?.to_str())
+        .find(|name| name.starts_with("app-"))
+        .unwrap_or("unknown")
+        .to_owned()
+}
+
+/// Keep the manifest from the last time the installed Discord version was patched, or start a new one from the pristine archive
+fn current_manifest(paths: &Paths) -> Result<Manifest, Box<dyn std::error::Error>> {
+    let version = discord_version(&paths.core);
+    let mut manifest = match load_manifest(&paths.backups) {
+        Some(manifest) if manifest.discord_version == version => manifest,
+        _ => {
+            let original = match backup::find(&paths.backups) {
+                Some(backup) => manifest::hash_reader(backup::open(&backup)?)?,
+                None => manifest::hash_file(&paths.asar())?,
+            };
+            Manifest { history: previous_history(paths), ..Manifest::new(version, original) }
+        }
+    };
+    //The icon is shared by every version and is already ours after the first apply, so the original is the one in its backup
+    if manifest.icon_hash.is_none() {
+        let icon_backup = backup::icon_backup_path(&paths.backups);
+        let original = match icon_backup.exists() {
+            true => icon_backup,
+            false => paths.root.join(icon::ICON_NAME),
+        };
+        manifest.icon_hash = manifest::hash_file(&original).ok();
+    }
+    Ok(manifest)
+}
+
+/// Get the history of the manifest that was written last in any version folder of this flavor, so that the history outlives Discord
+/// updates
+fn previous_history(paths: &Paths) -> Vec<manifest::Applied> {
+    let flavor_dir = paths.backups.parent().unwrap_or(&paths.backups);
+    fs::read_dir(flavor_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| Manifest::load(&entry.ok()?.path()).ok().flatten())
+        .max_by(|a, b| a.timestamp.cmp(&b.timestamp))
+        .map(|manifest| manifest.history)
+        .unwrap_or_default()
+}
+
+/// Load the manifest from the given directory, printing a warning and returning `None` if it couldn't be read
+fn load_manifest(dir: &Path) -> Option<Manifest> {
+    match Manifest::load(dir) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            output::warning(Category::Backup, style(msg!("failed-read-backup-manifest-file", e)).fg(Color::Color256(172)));
+            None
+        }
+    }
+}
+
+/// Print every location that is read or written, like Discord's archive, the config file, and the backup folder, without changing
+/// anything
+fn print_paths(cfg: &Config) -> ! {
+    output::action("paths");
+    let paths = Paths::locate(cfg);
+    output::info(msg!("paths-root", style(paths.root.display()).cyan()));
+    output::info(msg!("paths-version", style(paths.version.display()).cyan()));
+    output::info(msg!("paths-core", style(paths.core.display()).cyan()));
+    output::info(msg!("paths-asar", style(paths.found_asar().display()).cyan()));
+    if let Some(link) = &paths.link {
+        output::info(msg!("paths-symlink", style(link.link.display()).cyan(), style(link.target.display()).cyan()));
+    }
+    output::info(msg!("paths-config", style(cfg.path.display()).cyan()));
+    output::info(msg!("paths-backup-dir", style(cfg.backup_dir().display()).cyan()));
+    output::info(msg!("paths-backups", style(paths.backups.display()).cyan()));
+    #[cfg(feature = "autoupdate")]
+    output::info(msg!("paths-cache", style(cache::Store::open().dir().display()).cyan()));
+    prompt_quit(0);
+}
+
+/// Print the last `count` runs in the [history] file as a table, oldest first
+fn print_history(cfg: &Config, count: usize) -> ! {
+    output::action("history");
+    let path = history::path(&userdata::data_dir());
+    let entries = history::load(&path).unwrap_or_else(|e| panic!("{}", msg!("failed-read-history", path.display(), e)));
+    match entries.is_empty() {
+        true if cfg.run_history == 0 => output::info(style(msg!("history-disabled")).yellow()),
+        true => output::info(msg!("history-empty", style(path.display()).cyan())),
+        false => output::info(history::table(&entries[entries.len().saturating_sub(count)..])),
+    }
+    prompt_quit(0);
+}
+
+/// Print how many downloads the [cache] holds, how much space they take up, and the limits that they are evicted at
+#[cfg(feature = "autoupdate")]
+fn cache_stats(cfg: &Config) -> ! {
+    output::action("cache");
+    let store = cache::Store::open();
+    let stats = store.stats();
+    output::info(msg!("cache-dir", style(store.dir().display()).cyan()));
+    output::info(msg!("cache-stats", stats.urls, stats.objects, style(format!("{:.1} MiB", stats.bytes as f64 / (1024.0 * 1024.0))).cyan()));
+    if let Some(oldest) = stats.oldest {
+        let when = std::time::UNIX_EPOCH + std::time::Duration::from_secs(oldest);
+        output::info(msg!("cache-oldest", humantime::format_rfc3339_seconds(when)));
+    }
+    output::info(msg!("cache-limits", cfg.cache_max_age_days, cfg.cache_max_mib));
+    prompt_quit(0);
+}
+
+/// Delete every download in the [cache], along with the downloads that older versions of this program kept elsewhere
+#[cfg(feature = "autoupdate")]
+fn cache_clear() -> ! {
+    output::action("cache");
+    let stats = cache::Store::open().clear().unwrap_or_else(|e| panic!("{}", msg!("failed-clear-cache", e)));
+    for item in uninstall::remove_existing(cache::legacy_paths()) {
+        if let uninstall::Status::Failed(e) = item.status {
+            output::warning(Category::Network, style(msg!("failed-clear-cache", e)).fg(Color::Color256(172)));
+        }
+    }
+    output::info(style(msg!("cleared-cache", stats)).green());
+    prompt_quit(0);
+}
+
+/// Print what the manifest recorded about the last patch and what state the current `core.asar` file is in
+fn status(paths: &Paths) -> ! {
+    output::action("status");
+    output::info(msg!("discord-version", style(discord_version(&paths.core)).cyan()));
+    output::info(msg!("discord-layout", style(paths.layout.name()).cyan()));
+    output::info(msg!("backup-file",
+        match backup::find(&paths.backups).or_else(|| backup::find_file(&paths.backups)) {
+            Some(path) => style(path.display().to_string()).green(),
+            None => style(msg!("missing")).red(),
+        }
+    ));
+    output::info(msg!("patched-archive", style(paths.asar().display()).cyan(), paths.site.file));
+    let detected = client_mods(paths);
+    output::info(msg!("other-client-mods",
+        match detected.is_empty() {
+            true => style(msg!("none")).green(),
+            false => style(detected.iter().map(|found| found.name).collect::<Vec<_>>().join(", ")).yellow(),
+        }
+    ));
+
+    //Read the live patched file, because the manifest doesn't know if the file was patched by another program since
+    let patched = fs::File::open(paths.asar())
+        .map_err(asar::Error::from)
+        .and_then(|mut file| asar::Archive::read(&mut file))
+        .map(|archive| archive.get_file(&paths.site.file).map(|file| String::from_utf8_lossy(file.as_ref()).into_owned()).unwrap_or_default());
+    output::info(msg!("status-injections",
+        match patched.as_deref().map(patch::count) {
+            Ok(0) => style(msg!("none")).green(),
+            Ok(1) => style("1".to_owned()).green(),
+            Ok(count) => style(msg!("duplicated-injections", count)).yellow(),
+            Err(e) => style(e.to_string()).red(),
+        }
+    ));
+    output::info(msg!("status-js-injection",
+        match patched.as_deref().map(patch::injected_js) {
+            Ok(None) => style(msg!("none")).green(),
+            Ok(Some("")) => style(msg!("js-injection-empty")).cyan(),
+            Ok(Some(js)) => style(msg!("js-injection-bytes", js.len())).yellow(),
+            Err(e) => style(e.to_string()).red(),
+        }
+    ));
+    output::info(msg!("status-js-preload",
+        match patched.as_deref().map(patch::injected_preload) {
+            Ok(None) => style(msg!("none")).green(),
+            Ok(Some(js)) => style(msg!("js-preload-bytes", js.len())).yellow(),
+            Err(e) => style(e.to_string()).red(),
+        }
+    ));
+    output::info(msg!("status-window-title",
+        match patched.as_deref().map(|js| patch::extract(js).and_then(|found| found.title)) {
+            Ok(None) => style(msg!("none")).green(),
+            Ok(Some(title)) => style(title).cyan(),
+            Err(e) => style(e.to_string()).red(),
+        }
+    ));
+
+    let pending = pending::detect(&paths.root, &paths.version, &paths.asar());
+    output::info(msg!("pending-updates",
+        match pending.is_empty() {
+            true => style(msg!("none")).green(),
+            false => style(pending.iter().map(describe_pending).collect::<Vec<_>>().join(", ")).yellow(),
+        }
+    ));
+
+    let manifest = match load_manifest(&paths.backups) {
+        Some(manifest) => manifest,
+        None => {
+            output::info(style(msg!("no-manifest-found-discord-has")).yellow());
+            prompt_quit(0);
+        }
+    };
+
+    output::info(msg!("backup-made-discord-version", style(&manifest.discord_version).cyan()));
+    output::info(msg!("last-patched", style(&manifest.timestamp).cyan()));
+    output::info(msg!("status-theme", style(manifest.theme.as_deref().unwrap_or("unknown")).cyan()));
+    output::info(msg!("original-core-asar-hash", manifest.original_hash));
+    output::info(msg!("patched-core-asar-hash", manifest.patched_hash.clone().unwrap_or_else(|| msg!("none"))));
+    output::info(msg!("original-icon-hash", manifest.icon_hash.clone().unwrap_or_else(|| msg!("none"))));
+    match manifest::hash_file(&paths.root.join(icon::ICON_NAME)) {
+        Ok(hash) => output::info(msg!("current-icon", style(manifest.icon_state(&hash)).bold())),
+        Err(e) => output::info(style(msg!("failed-read-discords-icon", e)).red()),
+    }
+
+    match manifest::hash_file(&paths.asar()) {
+        Ok(hash) => output::info(msg!("current-core-asar", style(manifest.asar_state(&hash)).bold())),
+        Err(e) => output::info(style(msg!("failed-read-core-asar", e)).red()),
+    }
+    prompt_quit(0);
+}
+
+/// Run the [preflight] checks for an installation
+fn preflight_checks(paths: &Paths) -> Vec<preflight::Check> {
+    let (temp, asar) = (env::temp_dir(), paths.asar());
+    let work = backup::work_dir(&asar);
+    let mut writable = vec![paths.version.as_path(), paths.core.as_path(), paths.backups.as_path(), temp.as_path()];
+    //The archive is replaced through a temporary file in the work folder that is renamed over it, which is in the resources folder if
+    //app.asar is patched
+    for dir in asar.parent().into_iter().chain([work.as_path()]) {
+        if !writable.contains(&dir) {
+            writable.push(dir);
+        }
+    }
+    writable.dedup();
+    preflight::run(&asar, &writable, &work)
+}
+
+/// Check that patching can finish before anything is changed, exiting with a message for every check that failed
+fn preflight(paths: &Paths) {
+    let failed = preflight_checks(paths).into_iter().filter_map(|check| check.failure).collect::<Vec<_>>();
+    if !failed.is_empty() {
+        for failure in failed.iter() {
+            output::error(style(failure).red());
+        }
+        if failed.iter().any(preflight::Failure::broken_archive) && restore_broken_archive(paths) {
+            return preflight(paths);
+        }
+        if failed.iter().any(preflight::Failure::read_only) {
+            offer_elevation();
+        }
+        output::error(style(msg!("nothing-changed-because-patching-discord")).red().bold());
+        prompt_quit(1);
+    }
+}
+
+/// Explain that Discord is installed in a folder that only an administrator can change and offer to run this program again with
+/// `pkexec` or `sudo`, exiting with the elevated run's exit code if the user agrees. Returns if they don't, if no tool to do that is
+/// installed, or if this already is the elevated run
+fn offer_elevation() {
+    if elevate::is_elevated() {
+        return;
+    }
+    output::warning(Category::Permissions, style(msg!("discord-installed-read-only")).fg(Color::Color256(172)));
+    let tool = match elevate::tool() {
+        Some(tool) => tool,
+        None => return output::info(style(msg!("run-again-as-administrator")).yellow()),
+    };
+    let confirmed = console::user_attended()
+        && !output::is_json()
+        && Confirm::new()
+            .with_prompt(msg!("rerun-elevated", tool))
+            .default(false)
+            .interact()
+            .unwrap_or(false);
+    if !confirmed {
+        return output::info(style(msg!("not-rerunning-elevated", tool)).yellow());
+    }
+
+    //The config file belongs to the user that started this program, so the files the elevated run creates are given to its owner
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let config_path = config::resolve_path(flag_value(&args, "--config").map(Path::new));
+    let owner = elevate::Owner::of(&config_path)
+        .ok()
+        .flatten()
+        .unwrap_or_else(|| panic!("{}", msg!("failed-find-file-owner", config_path.display())));
+    let exe = env::current_exe().unwrap_or_else(|e| panic!("{}", msg!("failed-rerun-elevated", tool, e)));
+    let mut command = elevate::command(tool, &exe, &args, owner);
+    output::info(msg!("rerunning-elevated", style(format!("{:?}", command)).cyan()));
+    let status = command.status().unwrap_or_else(|e| panic!("{}", msg!("failed-rerun-elevated", tool, e)));
+    lock::release();
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+/// Offer to restore the backup of the installed Discord version's archive when the archive is empty or can't be read, returning `true`
+/// if it was restored. Backups of only the patched file can't fix a broken archive, so only whole archive backups are offered
+fn restore_broken_archive(paths: &Paths) -> bool {
+    let backup = match backup::find(&paths.backups) {
+        Some(backup) => backup,
+        None => {
+            output::info(style(msg!("broken-archive-no-backup")).yellow());
+            return false;
+        }
+    };
+    let confirmed = Confirm::new()
+        .with_prompt(msg!("restore-broken-archive", backup.display()))
+        .default(true)
+        .interact()
+        .unwrap_or(false);
+    if confirmed {
+        restore_archive(&backup, &paths.asar(), &paths.site.file);
+        output::info(style(msg!("restored-broken-archive")).green());
+    }
+    confirmed
+}
+
+/// Find the client mods that are installed into Discord, printing a warning if Discord's files can't be read
+fn client_mods(paths: &Paths) -> Vec<mods::Detected> {
+    mods::detect(&paths.core, &paths.resources()).unwrap_or_else(|e| {
+        output::warning(Category::Discord, style(msg!("failed-check-discords-files-other", e)).fg(Color::Color256(172)));
+        Vec::new()
+    })
+}
+
+/// Warn about every client mod that is installed and ask before patching on top of them, unless `force` is set
+fn check_client_mods(paths: &Paths, force: bool) {
+    let detected = client_mods(paths);
+    for found in detected.iter() {
+        output::warning(
+            Category::Discord,
+            style(msg!("installed-patching-top-can-break", found.name, found.path.display(), found.name))
+                .fg(Color::Color256(172)),
+        );
+    }
+    if !detected.is_empty()
+        && !force
+        && !Confirm::new()
+            .with_prompt(msg!("patch-discord-anyway-pass-force"))
+            .default(false)
+            .interact()
+            .unwrap_or(false)
+    {
+        output::info(style(msg!("not-patching-discord")).yellow());
+        prompt_quit(1);
+    }
+}
+
+/// Warn when this run is elevated and Discord belongs to another account than the one that started it, like `sudo` on a shared computer
+/// finding someone else's installation, and ask before changing it unless `force` is set
+fn check_other_user(paths: &Paths, force: bool) {
+    let (install, user) = match (elevate::other_user_install(&paths.root), elevate::invoking_owner()) {
+        (Some(install), Some(user)) => (install, user),
+        _ => return,
+    };
+    output::warning(
+        Category::Permissions,
+        style(msg!("other-users-discord", paths.root.display(), install.uid, user.uid)).fg(Color::Color256(172)),
+    );
+    if !force
+        && !Confirm::new()
+            .with_prompt(msg!("patch-other-users-discord"))
+            .default(false)
+            .interact()
+            .unwrap_or(false)
+    {
+        output::info(style(msg!("not-patching-other-users-discord")).yellow());
+        prompt_quit(1);
+    }
+}
+
+/// Show where Discord's archive really is when it is reached through a symbolic link. Changing an archive outside of Discord's
+/// installation changes files that a package or mod manager probably owns, so ask first unless `force` is set
+fn check_symlink(paths: &Paths, force: bool) {
+    let link = match &paths.link {
+        Some(link) => link,
+        None => return,
+    };
+    if !link.outside {
+        output::info(msg!("archive-symlink", style(link.link.display()).cyan(), style(link.target.display()).cyan()));
+        return;
+    }
+    output::warning(
+        Category::Discord,
+        style(msg!("archive-symlink-outside", link.link.display(), link.target.display(), paths.root.display())).fg(Color::Color256(172)),
+    );
+    if !force
+        && !Confirm::new()
+            .with_prompt(msg!("change-symlinked-archive", link.target.display()))
+            .default(false)
+            .interact()
+            .unwrap_or(false)
+    {
+        output::info(style(msg!("not-changing-symlinked-archive")).yellow());
+        prompt_quit(1);
+    }
+}
+
+/// Describe an update that Discord will install when it restarts
+fn describe_pending(pending: &pending::Pending) -> String {
+    match &pending.marker {
+        pending::Marker::PendingDir => msg!("pending-modules", pending.path.display()),
+        pending::Marker::UpdaterDb => msg!("pending-updater", pending.path.display()),
+        pending::Marker::NewVersion(version) => msg!("pending-version", version, pending.path.display()),
+    }
+}
+
+/// Warn if Discord downloaded an update that will replace the patched archive when it restarts, and ask whether to stop so that the
+/// update can be installed first, unless `force` is set
+fn check_pending_updates(paths: &Paths, force: bool) {
+    let found = pending::detect(&paths.root, &paths.version, &paths.asar());
+    for pending in found.iter() {
+        output::warning(Category::Discord, style(msg!("pending-update-will-undo-theme", describe_pending(pending))).fg(Color::Color256(172)));
+    }
+    if found.is_empty() || force {
+        return;
+    }
+    let choice = Select::new()
+        .with_prompt(msg!("pending-update-prompt"))
+        .item(msg!("pending-update-stop"))
+        .item(msg!("pending-update-patch-anyway"))
+        .default(0)
+        .interact()
+        .unwrap_or(0);
+    if choice == 0 {
+        output::info(style(msg!("pending-update-restart-first")).yellow());
+        prompt_quit(1);
+    }
+}
+
+/// Set the variables that a theme declares to their values in the config file, asking for the missing ones and saving the answers.
+/// When nobody can answer, the declared defaults are used and the variables that got them are listed
+fn theme_variables(cfg: &mut Config, theme: String) -> String {
+    let declarations = match variables::declarations(&theme) {
+        Ok(declarations) => declarations,
+        Err(e) => {
+            output::warning(Category::Theme, style(msg!("invalid-theme-variables", e)).fg(Color::Color256(172)));
+            return theme;
+        }
+    };
+    let prompt_theme = ColorfulTheme::default();
+    let mut prompt = |declaration: &variables::Declaration| {
+        let kind = declaration.kind;
+        Input::<String>::with_theme(&prompt_theme)
+            .with_prompt(msg!("ask-theme-variable", declaration.label, declaration.name))
+            .with_initial_text(declaration.default.clone())
+            .validate_with(move |val: &String| kind.validate(val))
+            .interact_text()
+            .unwrap_or_else(|e| panic!("{}", msg!("unable-read-input-query", e)))
+            .trim()
+            .to_owned()
+    };
+    let ask: Option<&mut dyn FnMut(&variables::Declaration) -> String> = match console::user_attended() && !output::is_json() {
+        true => Some(&mut prompt),
+        false => None,
+    };
+    let resolved = variables::resolve(&declarations, &cfg.variables, ask);
+
+    if !resolved.defaulted.is_empty() {
+        output::info(style(msg!("defaulted-theme-variables", resolved.defaulted.join(", "))).yellow());
+    }
+    if !resolved.asked.is_empty() {
+        for name in resolved.asked.iter() {
+            cfg.variables.insert(name.clone(), resolved.values[name].clone());
+        }
+        //Reload the file so that only the variables are saved, and not options that were changed for this run
+        let saved = Config {
+            variables: cfg.variables.clone(),
+            ..Config::load(&cfg.path, &config::Overrides::default(), cfg.flavor)
+        };
+        match saved.save() {
+            Ok(()) => output::info(style(msg!("saved-theme-variables", cfg.path.display())).green()),
+            Err(e) => output::warning(Category::Config, style(msg!("failed-save-settings", cfg.path.display(), e)).fg(Color::Color256(172))),
+        }
+    }
+    variables::apply(&theme, &declarations, &resolved.values)
+}
+
+/// Warn about text in a CSS theme that looks like javascript and ask before applying it unless `force` is set. The theme can't run
+/// code after it is escaped, but a file like that was probably made to attack whoever applies it
+fn check_suspicious_theme(theme: &str, force: bool) {
+    let found = escape::suspicious_js(theme);
+    for suspicious in found.iter() {
+        output::warning(Category::Theme, style(msg!("theme-looks-like-javascript", suspicious.text, suspicious.line)).fg(Color::Color256(172)));
+    }
+    if !found.is_empty()
+        && !force
+        && !Confirm::new()
+            .with_prompt(msg!("apply-suspicious-theme-anyway"))
+            .default(false)
+            .interact()
+            .unwrap_or(false)
+    {
+        output::info(style(msg!("not-patching-discord")).yellow());
+        prompt_quit(1);
+    }
+}
+
+/// Show the result of every [preflight] check without changing anything, exiting with a non-zero code if any failed
+fn doctor(paths: &Paths) -> ! {
+    output::action("doctor");
+    output::info(msg!("discord-version", style(discord_version(&paths.core)).cyan()));
+    let mut failed = false;
+    for check in preflight_checks(paths) {
+        match check.failure {
+            Some(failure) => {
+                failed = true;
+                output::info(format!("{} {}\n    {}", style("[FAIL]").red().bold(), check.name, style(failure).red()));
+            }
+            None => output::info(format!("{} {}", style("[ OK ]").green().bold(), check.name)),
+        }
+    }
+    for found in client_mods(paths) {
+        output::info(msg!("doctor-mod-installed", style("[WARN]").yellow().bold(), found.name, found.path.display()));
+    }
+    prompt_quit(failed as i32);
+}
+
+/// Check that the current `core.asar` file is the one that we patched, exiting with a non-zero code if it isn't
+fn verify(paths: &Paths) -> ! {
+    output::action("verify");
+    let manifest = load_manifest(&paths.backups).unwrap_or_else(|| panic!("{}", msg!("no-manifest-file-found-apply", paths.backups.display())));
+    let hash = manifest::hash_file(&paths.asar()).unwrap_or_else(|e| panic!("{}", msg!("failed-read-discords-core-asar", e)));
+    //An archive whose header declares the wrong size for its JSON can still be read and patched, but it isn't what we wrote
+    let header = fs::File::open(paths.asar()).map_err(asar::Error::from).and_then(|mut file| asar::Archive::read(&mut file));
+    if let Err(e) = header.and_then(|archive| archive.check_header()) {
+        output::error(style(msg!("core-asar-broken-header", e)).red());
+        prompt_quit(1);
+    }
+
+    match manifest.asar_state(&hash) {
+        AsarState::Patched => {
+            output::info(style(msg!("core-asar-patched")).green());
+            prompt_quit(0);
+        }
+        AsarState::Original => {
+            output::info(style(msg!("core-asar-unpatched")).yellow());
+            prompt_quit(1);
+        }
+        AsarState::Updated => {
+            output::info(style(msg!("core-asar-updated")).red());
+            prompt_quit(1);
+        }
+    }
+}
+
+/// Load the custom icon from the `--icon` flag or the config file, which can be a path or a URL to download it from, or the old icon
+/// embedded in this executable if there is no custom icon
+fn load_icon(cfg: &Config, icon_arg: Option<&str>) -> icon::Icon {
+    match icon_arg.or(cfg.custom_icon.as_deref()) {
+        //The icon is written into Discord's installation, so it isn't downloaded over an unencrypted connection
+        Some(url) if url.get(..7).is_some_and(|s| s.eq_ignore_ascii_case("http://")) => panic!("{}", msg!("icon-url-not-https", url)),
+        Some(url) if url.get(..8).is_some_and(|s| s.eq_ignore_ascii_case("https://")) => {
+            #[cfg(feature = "autoupdate")]
+            {
+                let dlprog = spinner(msg!("downloading-icon", url));
+                let icon = icon::Icon::download(url, cache::Policy::fresh(false, cfg.offline), &dlprog).unwrap_or_else(|e| panic!("{}", e));
+                dlprog.finish_with_message(style(msg!("downloaded-icon")).green().to_string());
+                icon
+            }
+            #[cfg(not(feature = "autoupdate"))]
+            panic!("{}", msg!("cant-download-icon-because-program", url))
+        }
+        Some(path) => icon::Icon::load(Path::new(path)).unwrap_or_else(|e| panic!("{}", msg!("failed-use-custom-icon-file", path, e))),
+        None => icon::Icon::embedded(),
+    }
+}
+
+/// Download a theme or script, described by `what` in messages, retrying with [net::RETRY_BACKOFF] if the download fails and using
+/// the copy last stored in the [cache] if every attempt fails. The stored copy is also used without downloading it again if the
+/// server says it hasn't changed, unless `refresh` is set, and without asking the server in offline mode. The file must have the
+/// `pinned` hash, or the hash published next to it if no hash is pinned. With `require_checksum`, a file that has no hash published
+/// next to it is refused instead of being used unverified
+#[cfg(feature = "autoupdate")]
+fn fetch_text(what: &str, url: &str, pinned: Option<&str>, require_checksum: bool, attempts: u32, refresh: bool) -> Result<String, net::Error> {
+    let width = console::Term::stdout().size().0 as usize;
+    let dlprog = spinner(
+        console::truncate_str(&msg!("downloading", what, url), width, "...").into_owned()
+    ); //Create a spinner to show download progress
+
+    let attempts = attempts.max(1);
+    let store = cache::Store::open();
+    let policy = cache::Policy::fresh(refresh, net::is_offline());
+    let fetched = net::fetch_or_cached(
+        attempts,
+        net::RETRY_BACKOFF,
+        std::thread::sleep,
+        |attempt| {
+            if attempt > 1 {
+                dlprog.set_message(console::truncate_str(&msg!("downloading-attempt", what, url, attempt, attempts), width, "...").into_owned());
+            }
+            download::fetch(&cache::Http { bar: &dlprog, limit: net::MAX_TEXT_SIZE }, &store, url, policy, pinned, require_checksum)
+        },
+        || store.get(url).and_then(|(_, data)| text::load(url, data).ok()),
+    );
+
+    match fetched {
+        Ok(net::Fetched::Fresh(download::Download::Changed { text, verified, unstored })) => {
+            dlprog.finish_with_message(style(msg!("downloaded-most-updated", what, url)).green().to_string());
+            if verified == download::Verified::Unpublished {
+                output::warning(Category::Network, style(msg!("no-checksum-published-next-downloaded", url, what)).fg(Color::Color256(172)));
+            }
+            //Keep the file to fall back on if a later download fails, and to skip downloading it again if it doesn't change
+            if let Some(e) = unstored {
+                output::warning(Category::Network, style(msg!("failed-save-downloaded", what, store.dir().display(), e)).fg(Color::Color256(172)));
+            }
+            Ok(text)
+        }
+        Ok(net::Fetched::Fresh(download::Download::Unchanged(text))) if policy == cache::Policy::OfflineOnly => {
+            dlprog.finish_with_message(style(msg!("offline-using-stored-copy", what)).yellow().to_string());
+            output::warning(Category::Network, style(msg!("using-last-downloaded-which-may", what, store.dir().display())).yellow());
+            Ok(text)
+        }
+        Ok(net::Fetched::Fresh(download::Download::Unchanged(text))) => {
+            dlprog.finish_with_message(style(msg!("unchanged-using-cached-copy", what)).green().to_string());
+            Ok(text)
+        }
+        Ok(net::Fetched::Stale { text, err }) => {
+            //Not downloading in offline mode is expected, so it isn't shown as an error
+            let msg = style(msg!("failed-download", what, err));
+            dlprog.finish_with_message(match err {
+                net::Error::Offline { .. } => msg.yellow().to_string(),
+                _ => msg.red().to_string(),
+            });
+            output::warning(Category::Network, style(msg!("using-last-downloaded-which-may", what, store.dir().display())).yellow());
+            Ok(text)
+        }
+        Err(err) => {
+            dlprog.finish_with_message(style(msg!("failed-download", what, err)).red().to_string());
+            Err(err)
+        }
+    }
+}
+
+/// Get the old theme, downloading the newest version if this program was compiled with network support. Returns the theme and the
+/// name to record it as in the manifest
+#[cfg_attr(not(feature = "autoupdate"), allow(unused_variables))]
+fn classic_theme(cfg: &Config, refresh: bool) -> (String, String) {
+    #[cfg(feature = "autoupdate")]
+    return download_theme(cfg, refresh);
+    #[cfg(not(feature = "autoupdate"))]
+    (OLD_THEME.to_owned(), EMBEDDED_THEME_NAME.to_owned())
+}
+
+/// Get the tweaks that can be added to the old theme, downloading their index if this program was compiled with network support and
+/// using the ones it was compiled with if that fails
+#[cfg_attr(not(feature = "autoupdate"), allow(unused_variables))]
+fn available_tweaks(cfg: &Config, refresh: bool) -> Vec<tweaks::Tweak> {
+    #[cfg(feature = "autoupdate")]
+    if let Some(url) = cfg.tweaks_url().filter(|_| !cfg.offline) {
+        match fetch_text(&msg!("what-tweaks"), url, None, true, cfg.download_attempts, refresh).map(|text| tweaks::parse_index(&text)) {
+            Ok(Ok(available)) => return available,
+            Ok(Err(e)) => output::warning(Category::Network, style(msg!("invalid-tweaks-index", url, e)).fg(Color::Color256(172))),
+            Err(_) => (),
+        }
+    }
+    tweaks::bundled()
+}
+
+/// Let the user pick which tweaks to add to the old theme, starting from the ones picked last time and saving the new picks. When
+/// nobody can answer, the saved picks are used
+fn add_tweaks(cfg: &mut Config, theme: String, refresh: bool) -> String {
+    let available = available_tweaks(cfg, refresh);
+    if !console::user_attended() || output::is_json() || available.is_empty() {
+        return tweaks::apply(&theme, &available, cfg.tweaks.as_deref().unwrap_or_default());
+    }
+
+    let saved = cfg.tweaks.clone().unwrap_or_default();
+    let picked = MultiSelect::with_theme(&ColorfulTheme::default())
+        .with_prompt(msg!("select-tweaks"))
+        .items_checked(
+            &available
+                .iter()
+                .map(|tweak| (tweak.description.as_str(), saved.contains(&tweak.name)))
+                .collect::<Vec<_>>(),
+        )
+        .interact()
+        .unwrap_or_else(|e| panic!("{}", msg!("unable-read-input-query", e)))
+        .into_iter()
+        .map(|idx| available[idx].name.clone())
+        .collect::<Vec<_>>();
+
+    if cfg.tweaks.as_ref() != Some(&picked) {
+        cfg.tweaks = Some(picked.clone());
+        //Reload the file so that only the tweaks are saved, and not options that were changed for this run
+        let saved = Config {
+            tweaks: cfg.tweaks.clone(),
+            ..Config::load(&cfg.path, &config::Overrides::default(), cfg.flavor)
+        };
+        if let Err(e) = saved.save() {
+            output::warning(Category::Config, style(msg!("failed-save-settings", cfg.path.display(), e)).fg(Color::Color256(172)));
+        }
+    }
+    tweaks::apply(&theme, &available, &picked)
+}
+
+/// Let the user pick which parts of the old look to apply, starting from the ones picked last time and saving the new picks. When
+/// nobody can answer, the saved picks are used. The picks are recorded in the run report
+fn pick_components(cfg: &mut Config) -> Vec<components::Component> {
+    let saved = components::picked(cfg);
+    let picked = match console::user_attended() && !output::is_json() {
+        true => MultiSelect::with_theme(&ColorfulTheme::default())
+            .with_prompt(msg!("select-components"))
+            .items_checked(
+                &components::Component::ALL
+                    .iter()
+                    .map(|component| {
+                        let label = match component {
+                            components::Component::Css => msg!("component-css"),
+                            components::Component::Icon => msg!("component-icon"),
+                            components::Component::Title => msg!("component-title"),
+                        };
+                        (label, saved.contains(component))
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .interact()
+            .unwrap_or_else(|e| panic!("{}", msg!("unable-read-input-query", e)))
+            .into_iter()
+            .map(|idx| components::Component::ALL[idx])
+            .collect::<Vec<_>>(),
+        false => saved.clone(),
+    };
+
+    if picked != saved {
+        //Reload the file so that only the picks are saved, and not options that were changed for this run
+        let mut file = Config::load(&cfg.path, &config::Overrides::default(), cfg.flavor);
+        for component in components::Component::ALL.iter() {
+            component.set(cfg, picked.contains(component));
+            component.set(&mut file, picked.contains(component));
+        }
+        if let Err(e) = file.save() {
+            output::warning(Category::Config, style(msg!("failed-save-settings", cfg.path.display(), e)).fg(Color::Color256(172)));
+        }
+    }
+    //Icons are never changed in an archive given with --asar
+    let applied = picked.iter().filter(|component| **component != components::Component::Icon || cfg.asar.is_none());
+    let names = applied.map(|component| component.name().to_owned()).collect();
+    output::report(|report| report.components = Some(names));
+    picked
+}
+
+/// Read a custom CSS theme file, returning the theme and the name to record it as in the manifest
+fn read_theme(path: &str) -> (String, String) {
+    (
+        fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| text::load(path, data).map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| panic!("{}", msg!("failed-read-custom-theme-css", e))),
+        path.to_owned(),
+    )
+}
+
+/// Read a `.zip` theme pack, printing which of its CSS theme, custom javascript, and icon were found. Returns the pack and its path
+fn read_pack(path: &str) -> (pack::Pack, String) {
+    let file = fs::File::open(path).unwrap_or_else(|e| panic!("{}", msg!("failed-read-theme-pack", path, e)));
+    let pack = pack::read(std::io::BufReader::new(file)).unwrap_or_else(|e| panic!("{}", msg!("failed-read-theme-pack", path, e)));
+    output::info(msg!("pack-found-css", style(&pack.members.css).cyan()));
+    output::info(match &pack.members.js {
+        Some(name) => msg!("pack-found-js", style(name).cyan()),
+        None => msg!("pack-no-js"),
+    });
+    output::info(match &pack.members.icon {
+        Some(name) => msg!("pack-found-icon", style(name).cyan()),
+        None => msg!("pack-no-icon"),
+    });
+    (pack, path.to_owned())
+}
+
+/// Download the newest old theme with [fetch_text], using the theme embedded in this executable if it can't be downloaded and
+/// wasn't downloaded before. Returns the theme and the name to record it as in the manifest
+#[cfg(feature = "autoupdate")]
+fn download_theme(cfg: &Config, refresh: bool) -> (String, String) {
+    let url = cfg.theme_url();
+    //Only a theme URL that the user set may be used without a checksum, the default one always publishes it
+    match fetch_text(&msg!("what-theme"), url, cfg.theme_sha256.as_deref(), cfg.theme_url.is_none(), cfg.download_attempts, refresh) {
+        Ok(text) => (text, url.to_owned()),
+        Err(_) => {
+            output::warning(Category::Network, style(msg!("using-theme-program-compiled-last", env!("COMPILEDATE", "build.rs did not run properly, no compile date set"))).yellow());
+            (OLD_THEME.to_owned(), EMBEDDED_THEME_NAME.to_owned())
+        }
+    }
+}
+
+/// Download the catalog of hosted themes and let the user pick one to download. Returns the theme and its name
+#[cfg(feature = "autoupdate")]
+fn browse_catalog(cfg: &Config, refresh: bool) -> (String, String) {
+    let url = cfg.catalog_url();
+    let dlprog = spinner(msg!("downloading-theme-catalog", url));
+    let catalog = match catalog::Catalog::download(url, cache::Policy::fresh(refresh, cfg.offline), &dlprog) {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            dlprog.finish_with_message(style(msg!("failed-read-theme-catalog", e)).red().to_string());
+            prompt_quit(1);
+        }
+    };
+    dlprog.finish_with_message(style(msg!("downloaded-theme-catalog-themes", catalog.themes.len())).green().to_string());
+    for skipped in catalog.skipped.iter() {
+        output::warning(Category::Network, style(msg!("skipped-invalid-catalog-entry", skipped)).fg(Color::Color256(172)));
+    }
+    if catalog.themes.is_empty() {
+        output::info(style(msg!("theme-catalog-doesnt-have-themes")).yellow());
+        prompt_quit(0);
+    }
+
+    let selection = Select::new()
+        .with_prompt(msg!("select-theme-apply"))
+        .items(
+            &catalog
+                .themes
+                .iter()
+                .map(|entry| msg!("catalog-entry", entry.name, entry.author, entry.description))
+                .collect::<Vec<_>>(),
+        )
+        .default(0)
+        .interact()
+        .unwrap_or_else(|e| panic!("{}", msg!("failed-take-catalog-selection", e)));
+    let entry = &catalog.themes[selection];
+    if let Some(preview) = &entry.preview_url {
+        output::info(msg!("preview", entry.name, style(preview).cyan()));
+    }
+
+    let text = fetch_text(&msg!("what-theme"), &entry.css_url, Some(&entry.sha256), false, cfg.download_attempts, refresh).unwrap_or_else(|e| panic!("{}", msg!("failed-download-catalog-theme", entry.name, e)));
+    (text, entry.name.clone())
+}
+
+/// Show a menu for changing the options in the config file, saving them when the user is done
+fn settings(cfg: &mut Config) -> ! {
+    output::action("settings");
+    let yes_no = |val: bool| if val { msg!("yes") } else { msg!("no") };
+    //Ask for a path, returning `None` if the user entered nothing
+    let ask_path = |prompt: &str, current: Option<String>| -> Option<String> {
+        Some(
+            Input::<String>::new()
+                .with_prompt(prompt)
+                .with_initial_text(current.unwrap_or_default())
+                .allow_empty(true)
+                .interact_text()
+                .unwrap_or_else(|e| panic!("{}", msg!("unable-read-input-query", e))),
+        )
+        .filter(|path| !path.trim().is_empty())
+    };
+
+    loop {
+        let selection = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt(msg!("settings-prompt", cfg.path.display()))
+            .item(msg!("back-up-discords-files-before", yes_no(cfg.make_backup)))
+            .item(msg!("replace-discords-icon", yes_no(cfg.replace_icon)))
+            .item(msg!("custom-javascript-file", cfg.customjs_path.as_ref().map(ToString::to_string).unwrap_or_else(|| msg!("none"))))
+            .item(msg!("backup-folder", cfg.backup_dir().display()))
+            .item(msg!("save-exit"))
+            .default(0)
+            .interact()
+            .unwrap_or_else(|e| panic!("{}: {:?}", msg!("failed-take-selection-menu"), e));
+
+        let key = match selection {
+            0 => {
+                cfg.make_backup = !cfg.make_backup;
+                "make-backup"
+            }
+            1 => {
+                cfg.replace_icon = !cfg.replace_icon;
+                "replace-icon"
+            }
+            //Inline scripts and arrays can't be typed into a single line, so they are only changed in the file
+            2 if cfg.customjs_path.as_ref().is_some_and(|js| js.path().is_none()) => {
+                output::info(style(msg!("custom-js-edit-in-file", cfg.path.display())).yellow());
+                continue;
+            }
+            2 => {
+                let current = cfg.customjs_path.as_ref().and_then(CustomJs::path).map(str::to_owned);
+                cfg.customjs_path = ask_path(&msg!("ask-custom-js-path"), current).map(CustomJs::from);
+                "custom-js"
+            }
+            3 => {
+                cfg.backup_dir = ask_path(
+                    &msg!("ask-backup-dir"),
+                    cfg.backup_dir.as_ref().map(|dir| dir.display().to_string()),
+                )
+                .map(PathBuf::from);
+                "backup-dir"
+            }
+            _ => break,
+        };
+        //Options changed here are saved even if they were overridden by an environment variable or command line flag
+        cfg.mark_edited(key);
+    }
+
+    cfg.save().unwrap_or_else(|e| panic!("{}", msg!("failed-save-settings", cfg.path.display(), e)));
+    output::info(style(msg!("saved-settings", cfg.path.display())).green());
+    prompt_quit(0);
+}
+
+/// Guide a first-time user through finding Discord, picking a theme, and the backup and icon options, saving their choices to the
+/// config file. Pressing escape skips a step, keeping what the menu and config file would have used. Returns the theme to apply and
+/// the installation that was found
+fn wizard(cfg: &mut Config, refresh: bool) -> ((String, String), Paths) {
+    output::action("wizard");
+    output::info(style(msg!("wizard-welcome")).bold());
+    let prompt_theme = ColorfulTheme::default();
+
+    //Show which installation will be patched before asking anything
+    let paths = Paths::resolve(cfg);
+    output::info(msg!("wizard-found-discord", style(discord_version(&paths.core)).cyan(), style(paths.root.display()).cyan()));
+
+    let custom = Select::with_theme(&prompt_theme)
+        .with_prompt(msg!("wizard-theme-prompt"))
+        .item(msg!("wizard-theme-classic"))
+        .item(msg!("wizard-theme-custom"))
+        .default(0)
+        .interact_opt()
+        .unwrap_or_else(|e| panic!("{}: {:?}", msg!("failed-take-selection-menu"), e))
+        == Some(1);
+    let path = match custom {
+        true => Input::<String>::with_theme(&prompt_theme)
+            .with_prompt(msg!("wizard-theme-path"))
+            .allow_empty(true)
+            .validate_with(|val: &String| match wizard_path(val) {
+                "" => Ok(()),
+                path => match fs::metadata(path) {
+                    Ok(meta) if meta.is_file() => Ok(()),
+                    Ok(_) => Err(msg!("wizard-theme-not-file")),
+                    Err(e) => Err(msg!("wizard-theme-unreadable", e)),
+                },
+            })
+            .interact_text()
+            .unwrap_or_else(|e| panic!("{}", msg!("unable-read-input-query", e))),
+        false => String::new(),
+    };
+    let theme = match wizard_path(&path) {
+        "" => {
+            let (theme, name) = classic_theme(cfg, refresh);
+            (add_tweaks(cfg, theme, refresh), name)
+        }
+        path => read_theme(path),
+    };
+
+    //Explain each option before asking, keeping the configured value if the question is skipped
+    output::info(msg!("wizard-backup-explain", style(cfg.backup_dir().display()).cyan()));
+    if let Some(make_backup) = wizard_confirm(&prompt_theme, msg!("wizard-backup-prompt"), cfg.make_backup) {
+        cfg.make_backup = make_backup;
+    }
+    output::info(msg!("wizard-icon-explain"));
+    if let Some(replace_icon) = wizard_confirm(&prompt_theme, msg!("wizard-icon-prompt"), cfg.replace_icon) {
+        cfg.replace_icon = replace_icon;
+    }
+
+    //Answers for options that the flavor's table sets are saved there, and ones overridden for this run only aren't saved
+    for key in ["make-backup", "replace-icon"].iter() {
+        if cfg.source(key) <= config::Source::Flavor {
+            cfg.mark_edited(key);
+        }
+    }
+    match cfg.save() {
+        Ok(()) => output::info(style(msg!("saved-settings", cfg.path.display())).green()),
+        Err(e) => output::warning(Category::Config, style(msg!("failed-save-settings", cfg.path.display(), e)).fg(Color::Color256(172))),
+    }
+    (theme, paths)
+}
+
+/// Remove the whitespace and quotes that terminals add around paths that are dragged onto them
+fn wizard_path(val: &str) -> &str {
+    val.trim().trim_matches(|c| c == '"' || c == '\'')
+}
+
+/// Ask a yes or no question in the [wizard], returning `None` if it was skipped
+fn wizard_confirm(theme: &ColorfulTheme, prompt: String, default: bool) -> Option<bool> {
+    Confirm::with_theme(theme)
+        .with_prompt(prompt)
+        .default(default)
+        .interact_opt()
+        .unwrap_or_else(|e| panic!("{}", msg!("unable-read-input-query", e)))
+}
+
+/// Offer to restart Discord so that a theme that was just applied shows up
+fn offer_relaunch(paths: &Paths, flavor: config::Flavor) {
+    if wizard_confirm(&ColorfulTheme::default(), msg!("wizard-relaunch-prompt"), true) != Some(true) {
+        return;
+    }
+    match relaunch(paths, flavor) {
+        Ok(()) => output::info(style(msg!("wizard-relaunched")).green()),
+        Err(e) => output::warning(Category::General, style(msg!("wizard-relaunch-failed", e)).fg(Color::Color256(172))),
+    }
+}
+
+/// Close Discord if it is running and start it again
+#[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+fn relaunch(paths: &Paths, flavor: config::Flavor) -> std::io::Result<()> {
+    use std::process::{Command, Stdio};
+    let name = flavor.folder_name();
+    #[cfg(target_os = "windows")]
+    let exe = format!("{}.exe", name);
+
+    //Discord isn't always running, so failing to close it is fine
+    #[cfg(target_os = "windows")]
+    let _ = Command::new("taskkill").args(["/IM", exe.as_str(), "/F"]).stdout(Stdio::null()).stderr(Stdio::null()).status();
+    #[cfg(not(target_os = "windows"))]
+    let _ = Command::new("pkill").args(["-x", name]).stdout(Stdio::null()).stderr(Stdio::null()).status();
+    std::thread::sleep(std::time::Duration::from_secs(1)); //Give Discord time to exit before starting it again
+
+    //Discord's updater starts the newest installed version
+    #[cfg(target_os = "windows")]
+    let mut launch = {
+        let mut cmd = Command::new(paths.root.join("Update.exe"));
+        cmd.args(["--processStart", exe.as_str()]);
+        cmd
+    };
+    #[cfg(target_os = "macos")]
+    let mut launch = {
+        let mut cmd = Command::new("open");
+        cmd.arg(&paths.bundle);
+        cmd
+    };
+    //The binary is in the installation folder, or on the PATH in lower case for packaged installs
+    #[cfg(target_os = "linux")]
+    let mut launch = match paths.version.join(name) {
+        binary if binary.is_file() => Command::new(binary),
+        _ => Command::new(name.to_lowercase()),
+    };
+    launch.stdout(Stdio::null()).stderr(Stdio::null()).spawn().map(drop)
+}
+
+/// Command line flags that are followed by a value, like `--icon <path>`
+const VALUE_FLAGS: &[&str] = &[
+    "--asar",
+    "--icon",
+    "--config",
+    "--custom-js",
+    "--js",
+    "--backup-dir",
+    "--work-dir",
+    "--theme-url",
+    "--flavor",
+    "--output",
+    "--root",
+    "--emit-injection",
+    "--port",
+    "--from-manifest",
+    elevate::FLAG,
+];
+
+/// Get the config options that are overridden by environment variables and command line flags
+fn config_overrides(args: &[&str]) -> config::Overrides {
+    let mut overrides = config::Overrides::from_env();
+    if args.contains(&"--no-backup") {
+        overrides.cli.push(("make-backup", false.into()));
+    }
+    if args.contains(&"--no-icon") {
+        overrides.cli.push(("replace-icon", false.into()));
+    }
+    if args.contains(&"--no-js") {
+        overrides.cli.push(("inject-js", false.into()));
+    }
+    if let Some(path) = flag_value(args, "--custom-js") {
+        overrides.cli.push(("custom-js", path.into()));
+    }
+    //--js inserts only custom javascript, so it is inserted even if the config file turns it off
+    if let Some(path) = flag_value(args, "--js") {
+        overrides.cli.push(("custom-js", path.into()));
+        overrides.cli.push(("inject-js", true.into()));
+    }
+    if let Some(dir) = flag_value(args, "--backup-dir") {
+        overrides.cli.push(("backup-dir", dir.into()));
+    }
+    if let Some(dir) = flag_value(args, "--work-dir") {
+        overrides.cli.push(("work-dir", dir.into()));
+    }
+    if let Some(url) = flag_value(args, "--theme-url") {
+        overrides.cli.push(("theme-url", url.into()));
+    }
+    if args.contains(&"--offline") {
+        overrides.cli.push(("offline", true.into()));
+    }
+    overrides
+}
+
+/// Get the value given after a flag like `--icon <path>` in the command line arguments
+fn flag_value<'a>(args: &[&'a str], flag: &str) -> Option<&'a str> {
+    let pos = args.iter().position(|arg| *arg == flag)?;
+    match args.get(pos + 1) {
+        Some(val) => Some(val),
+        None => panic!("{}", msg!("expected-value-after-flag", flag)),
+    }
+}
+
+/// Get the command line arguments that are not flags or the values of flags
+fn positional<'a>(args: &[&'a str]) -> Vec<&'a str> {
+    let mut positional = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if VALUE_FLAGS.contains(arg) {
+            iter.next(); //Skip the flag's value
+        } else if !arg.starts_with("--") {
+            positional.push(*arg);
+        }
+    }
+    positional
+}
+
+/// Apply the last theme again if Discord updated and replaced the patched archive since it was applied, without asking anything so that
+/// it can run from a scheduled task. Restarts Discord afterwards if `relaunch_after` is set
+fn check(cfg: &mut Config, relaunch_after: bool) -> ! {
+    output::action("check");
+    #[cfg(feature = "notify")]
+    notify::enable(cfg.notify, true);
+    let paths = Paths::resolve(cfg);
+    let hash = manifest::hash_file(&paths.asar()).unwrap_or_else(|e| panic!("{}", msg!("failed-read-archive", paths.asar().display(), e)));
+    let injected = fs::File::open(paths.asar())
+        .map_err(asar::Error::from)
+        .and_then(|mut file| asar::Archive::read(&mut file))
+        .map(|archive| archive.get_file(&paths.site.file).is_some_and(|file| patch::extract(&String::from_utf8_lossy(file.as_ref())).is_some()))
+        .unwrap_or_else(|e| panic!("{}", msg!("failed-read-archive", paths.asar().display(), e)));
+
+    match task::verdict(load_manifest(&paths.backups).as_ref(), &hash, injected) {
+        task::Verdict::Patched => {
+            output::info(style(msg!("check-still-patched")).green());
+            prompt_quit(0);
+        }
+        //Restoring is how the theme is removed on purpose, so it isn't put back
+        task::Verdict::Restored => {
+            output::info(msg!("check-restored"));
+            prompt_quit(0);
+        }
+        task::Verdict::Updated => output::info(style(msg!("check-discord-updated")).yellow()),
+    }
+
+    let flavor_dir = paths.backups.parent().unwrap_or(&paths.backups).to_owned();
+    let last = match lasttheme::LastTheme::load(&flavor_dir) {
+        Some(last) => last,
+        None => {
+            output::info(msg!("check-no-last-theme"));
+            prompt_quit(0);
+        }
+    };
+    let css = use_last_theme(cfg, &last, &flavor_dir);
+
+    preflight(&paths);
+    apply(&paths, cfg, &css, last.name.clone(), None, false).unwrap_or_else(|e| panic!("{}", e));
+    output::info(style(msg!("check-reapplied", last.name)).green());
+    #[cfg(feature = "notify")]
+    notify::send(&notify::Notification::new(
+        msg!("notify-discord-updated", discord_version(&paths.core).trim_start_matches("app-")),
+        msg!("notify-reapplied", last.name),
+    ));
+    if relaunch_after {
+        if let Err(e) = relaunch(&paths, cfg.flavor) {
+            output::warning(Category::General, style(msg!("wizard-relaunch-failed", e)).fg(Color::Color256(172)));
+        }
+    }
+    prompt_quit(0);
+}
+
+/// Set the custom javascript, window title, and strategy in `cfg` to what was inserted along with the `last` theme from `flavor_dir`,
+/// returning its CSS
+fn use_last_theme(cfg: &mut Config, last: &lasttheme::LastTheme, flavor_dir: &Path) -> Css {
+    cfg.window_title = last.title.clone();
+    cfg.inject_strategy = last.strategy;
+    cfg.inject_js = last.js.is_some();
+    cfg.customjs = last.js.clone().unwrap_or_default();
+    cfg.preload_js = last.preload.clone().unwrap_or_default();
+    if last.preload.is_none() {
+        cfg.preload_path = None;
+    } else if cfg.preload_path.is_none() {
+        cfg.preload_path = Some(lasttheme::LastTheme::path(flavor_dir).display().to_string());
+    }
+    last.css().unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Apply exactly what was inserted `ago` applies ago again, from the history in the manifest of the installed Discord version. With
+/// `emit`, the injection is written to that file first like with `--emit-injection`
+fn apply_from_manifest(cfg: &mut Config, ago: usize, emit: Option<&str>, yes: bool, force: bool) -> ! {
+    output::action("apply");
+    let paths = Paths::resolve(cfg);
+    let (name, css) = from_manifest(cfg, &paths, ago);
+    if let Some(dest) = emit {
+        emit_injection(cfg, &css, Path::new(dest), yes);
+    }
+    preflight(&paths);
+    apply(&paths, cfg, &css, name.clone(), None, force).unwrap_or_else(|e| panic!("{}", e));
+    output::info(style(msg!("reapplied-from-manifest", name, ago)).green());
+    prompt_quit(0);
+}
+
+/// Set the custom javascript in `cfg` to what was inserted `ago` applies ago according to the manifest's history, returning the name
+/// and CSS of the theme that was inserted with it
+fn from_manifest(cfg: &mut Config, paths: &Paths, ago: usize) -> (String, Css) {
+    let manifest = load_manifest(&paths.backups);
+    let recorded = manifest.as_ref().map(|manifest| manifest.history.len()).unwrap_or(0);
+    let applied = manifest
+        .as_ref()
+        .and_then(|manifest| manifest.applied(ago))
+        .unwrap_or_else(|| panic!("{}", msg!("no-manifest-history", ago, recorded)));
+    applied.verify().unwrap_or_else(|e| panic!("{}", e));
+    let flavor_dir = paths.backups.parent().unwrap_or(&paths.backups);
+    (applied.payload.name.clone(), use_last_theme(cfg, &applied.payload, flavor_dir))
+}
+
+/// Find the flavor backup folder that the last theme for `cfg` is saved in without looking for Discord, which would ask for its folder
+/// on Linux. Returns `None` if no theme was applied yet
+fn last_theme_dir(cfg: &Config) -> Option<PathBuf> {
+    let backup_dir = cfg.backup_dir();
+    match (&cfg.asar, &cfg.root) {
+        (Some(asar), _) => {
+            let dir = asar.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+            Some(dir.join(EXPLICIT_BACKUP_DIR)).filter(|dir| lasttheme::LastTheme::path(dir).is_file())
+        }
+        (None, Some(root)) => lasttheme::find(&backup_dir, root.file_name().and_then(|name| name.to_str())),
+        (None, None) if cfg!(target_os = "linux") => lasttheme::find(&backup_dir, None),
+        (None, None) => lasttheme::find(&backup_dir, Some(cfg.flavor.folder_name())),
+    }
+}
+
+/// Delete the last theme so that starting without a theme shows the menu without offering to re-apply it
+fn forget_last_theme(cfg: &Config) -> ! {
+    output::action("forget");
+    let dir = match last_theme_dir(cfg) {
+        Some(dir) => dir,
+        None => {
+            output::info(msg!("no-last-theme-to-forget"));
+            prompt_quit(0);
+        }
+    };
+    match lasttheme::LastTheme::forget(&dir) {
+        Ok(true) => output::info(style(msg!("forgot-last-theme", lasttheme::LastTheme::path(&dir).display())).green()),
+        Ok(false) => output::info(msg!("no-last-theme-to-forget")),
+        Err(e) => panic!("{}", msg!("failed-forget-last-theme", lasttheme::LastTheme::path(&dir).display(), e)),
+    }
+    prompt_quit(0);
+}
+
+/// Apply the `last` theme from `flavor_dir` again, which is picked from the menu
+fn reapply_last_theme(cfg: &mut Config, last: &lasttheme::LastTheme, flavor_dir: &Path) -> ! {
+    let css = use_last_theme(cfg, last, flavor_dir);
+    let paths = Paths::resolve(cfg);
+    preflight(&paths);
+    apply(&paths, cfg, &css, last.name.clone(), None, false).unwrap_or_else(|e| panic!("{}", e));
+    output::info(style(msg!("check-reapplied", last.name)).green());
+    prompt_quit(0);
+}
+
+/// Ask before replacing the `last` theme with the theme named `name` that has `bytes` bytes of CSS, quitting if the answer is no
+fn confirm_switch(last: &lasttheme::LastTheme, name: &str, bytes: usize) {
+    let switch = Confirm::new()
+        .with_prompt(msg!("confirm-switch-theme", last.name, indicatif::HumanBytes(last.bytes() as u64), name, indicatif::HumanBytes(bytes as u64)))
+        .default(true)
+        .interact()
+        .unwrap_or(false);
+    if !switch {
+        output::info(msg!("kept-last-theme", last.name));
+        output::finish(0);
+        lock::release();
+        std::process::exit(0);
+    }
+}
+
+/// Register the scheduled task that runs `check` after logging in, or remove it if `remove` is set
+fn schedule_check(cfg: &Config, remove: bool, relaunch_after: bool) -> ! {
+    let name = task::name(cfg.flavor);
+    if remove {
+        output::action("uninstall-task");
+        match task::uninstall(&name) {
+            Ok(Some(removed)) => output::info(style(msg!("removed-task", style(removed).cyan())).green()),
+            Ok(None) => output::info(msg!("no-task-registered", name)),
+            Err(e) => panic!("{}", msg!("failed-remove-task", name, e)),
+        }
+        prompt_quit(0);
+    }
+    output::action("install-task");
+    let exe = env::current_exe().unwrap_or_else(|e| panic!("{}", msg!("failed-install-task", name, e)));
+    match task::install(&name, &exe, &task::args(cfg.flavor, &cfg.path, relaunch_after)) {
+        Ok(installed) => output::info(style(msg!("installed-task", style(installed).cyan())).green()),
+        Err(e) => panic!("{}", msg!("failed-install-task", name, e)),
+    }
+    prompt_quit(0);
+}
+
+/// Gather the files that are needed to debug a broken patch into the folder or `.zip` file at `dest`, see [dump]
+fn debug_dump(paths: &Paths, cfg: &Config, dest: &Path) -> ! {
+    output::action("debug dump");
+    let written = dump_files(paths, cfg)
+        .and_then(|files| Ok(dump::write(dest, &files)?))
+        .unwrap_or_else(|e| panic!("{}", msg!("failed-debug-dump", dest.display(), e)));
+    written.iter().for_each(|path| output::info(msg!("exported", style(path.display()).cyan())));
+    output::info(style(msg!("wrote-debug-dump", dest.display())).green());
+    prompt_quit(0);
+}
+
+/// Read the files that [debug_dump] writes, with the custom javascript redacted and the user's name scrubbed
+/// from the paths in them
+fn dump_files(paths: &Paths, cfg: &Config) -> Result<Vec<dump::File>, Box<dyn std::error::Error>> {
+    let home = env::var("HOME").or_else(|_| env::var("USERPROFILE")).ok();
+    let user = env::var("USER").or_else(|_| env::var("USERNAME")).ok();
+    let scrub = |text: &str| dump::scrub(text, home.as_deref(), user.as_deref()).into_bytes();
+    let name = |path: &Path| path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+
+    //The archive is packed again with the patched file redacted, so that the custom javascript isn't in the copy of it either
+    let mut archive = asar::Archive::read(&mut fs::File::open(paths.asar())?)?;
+    let file = archive
+        .get_file_mut(&paths.site.file)
+        .ok_or_else(|| msg!("did-not-find-file-asar", paths.site.file))?;
+    let js = dump::redact_injection(&String::from_utf8_lossy(file.as_ref()));
+    file.replace_contents(js.as_bytes())?;
+    let mut packed = std::io::Cursor::new(Vec::new());
+    archive.pack(&mut packed, false)?;
+
+    let mut files = vec![
+        (name(&paths.asar()), packed.into_inner()),
+        (name(Path::new(&paths.site.file)), js.into_bytes()),
+        (
+            "info.txt".to_owned(),
+            scrub(&format!(
+                "discord-theme {}\nDiscord {} ({})\nPatched file: {}\nPlatform: {}\n",
+                env!("CARGO_PKG_VERSION"),
+                discord_version(&paths.core),
+                paths.core.display(),
+                paths.site.file,
+                env::consts::OS
+            )),
+        ),
+    ];
+    if let Some(mut manifest) = Manifest::load(&paths.backups)? {
+        dump::redact_manifest(&mut manifest);
+        files.push((manifest::MANIFEST_NAME.to_owned(), scrub(&String::from_utf8(serde_json::to_vec_pretty(&manifest)?)?)));
+    }
+    if cfg.path.exists() {
+        files.push((name(&cfg.path), scrub(&dump::redact_config(&fs::read_to_string(&cfg.path)?))));
+    }
+    Ok(files)
+}
+
+/// The `ReadTimer` struct shows the progress of reading Discord's archive on `bar` and records how long it took in the report
+struct ReadTimer<'a> {
+    bar: ProgressBar,
+    /// The archive's file name, for the bar's message
+    name: &'a str,
+    started: Option<std::time::Instant>,
+}
+
+impl asar::ProgressSink for ReadTimer<'_> {
+    fn event(&mut self, event: asar::ReadEvent<'_>) {
+        match &event {
+            asar::ReadEvent::Phase(asar::Phase::Header) => self.started = Some(std::time::Instant::now()),
+            asar::ReadEvent::Phase(asar::Phase::Files) => self.bar.set_message(msg!("reading-archive", self.name)),
+            asar::ReadEvent::Done => {
+                if let Some(started) = self.started {
+                    output::report(|report| report.read_ms = Some(started.elapsed().as_millis() as u64));
+                }
+            }
+            _ => (),
+        }
+        self.bar.event(event);
+    }
+}
+
+/// Patch Discord's `core.asar` with the CSS `theme` and the custom javascript from `cfg`, backing it up first if `make_backup` is on and
+/// replacing Discord's icons with `icon` if it is given, then record the patch in the manifest. The archive isn't written if it is
+/// already patched with the same theme and javascript, unless `force` is set
+fn apply(paths: &Paths, cfg: &Config, theme: &Css, theme_name: String, icon: Option<&icon::Icon>, force: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let css_bytes = theme.bytes() as u64;
+    let js = cfg.inject_js.then_some(cfg.customjs.as_str());
+    let preload = cfg.preload();
+    let title = cfg.window_title.as_deref();
+
+    //If make_backup is on then make a backup asar file, this happens before the icon is replaced so that the original icon is backed up
+    if cfg.make_backup {
+        make_backup(paths, cfg.backup_mode, cfg.compress_backups);
+    }
+
+    let mut manifest = current_manifest(paths)?;
+
+    //Replace the icon file if the option is specified
+    if let Some(icon) = icon {
+        apply_icons(paths, icon, &mut manifest);
+    }
+
+    let path = paths.asar(); //Get the path to the core archive file
+
+    //Create a spinner to show that we are reading Discord's files
+    let js_prog = spinner(msg!("unpacking-discords-archive-files"));
+
+    //The archive is read once to both hash and parse it, and the hash finds the state that the last apply left for this archive
+    let archive_bytes = fs::read(&path)?;
+    let archive_hash = manifest::hash_bytes(&archive_bytes);
+    let cached = work::Work::load(&paths.backups, &archive_hash).filter(|work| work.file == paths.site.file);
+    let archive_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let mut reading = ReadTimer { bar: js_prog.clone(), name: &archive_name, started: None };
+    let mut archive = asar::Archive::read_with_progress(&mut std::io::Cursor::new(&archive_bytes), &mut reading)?; //Parse the asar archive
+    drop(archive_bytes);
+    if let Err(asar::Error::HeaderSizeMismatch { declared, actual }) = archive.check_header() {
+        output::warning(Category::Discord, style(msg!("archive-header-size-mismatch", declared, actual)).fg(Color::Color256(172)).bold());
+    }
+
+    //Open the javascript file
+    let js_file = archive
+        .get_file_mut(&paths.site.file)
+        .ok_or_else(|| msg!("did-not-find-file-asar", paths.site.file))?;
+
+    let mut jsstr = String::new();
+    js_file.read_to_string(&mut jsstr)?; //Read the javascript file to a string
+
+    //Finish the first progress bar
+    js_prog.finish_with_message(
+        style(msg!("unpacked-discords-archive"))
+            .fg(Color::Green)
+            .to_string(),
+    );
+
+    //Writing the same injection again would only change the archive's timestamps and throw away the work that was saved for it
+    if !force && patch::up_to_date(&jsstr, theme, js, preload, title, cfg.inject_strategy) {
+        output::info(style(msg!("already-up-to-date", paths.site.file)).green());
+        manifest.theme = Some(theme_name);
+        manifest.config = cfg.to_json();
+        manifest.save(&paths.backups)?;
+        return Ok(());
+    }
+
+    //Create a spinner to show that we are doing the search and replace for the custom CSS theme
+    let ins_prog = spinner(msg!("inserting-css-theme-into-discords"));
+
+    //If the file was already patched then replace the whole injection, which also updates injections made by older versions. When
+    //this archive is the one that the last apply made, the injection is where it was left and doesn't need to be searched for
+    let searching = std::time::Instant::now();
+    let (patched, damage) = match cached.as_ref().and_then(|work| work.unpatched(&jsstr)) {
+        Some(unpatched) => patch::inject(&unpatched, theme, js, preload, title, cfg.inject_strategy).map(|patched| (patched, Vec::new())),
+        None => patch::update(&jsstr, theme, js, preload, title, cfg.inject_strategy),
+    }
+    .unwrap_or_else(|e| match e {
+        patch::Error::Unrecognized => panic!("{}", msg!("unrecognized-injection", paths.site.file)),
+        e => panic!("{}", msg!("failed-patch-file", paths.site.file, e)),
+    });
+    let searched = searching.elapsed();
+    if let Some(work) = &cached {
+        output::info(style(msg!("reused-work", format!("{:.1}", work.search_time().saturating_sub(searched).as_micros() as f64 / 1000.0))).dim());
+    }
+    //A run that was interrupted can leave a broken injection behind, which is removed along with the rest of it
+    for damage in damage.iter() {
+        output::warning(Category::Discord, style(msg!("repaired-injection", paths.site.file, damage)).fg(Color::Color256(172)));
+    }
+    jsstr = patched;
+
+    ins_prog.finish_with_message(
+        style(msg!("inserted-user-css-into-discords"))
+            .green()
+            .to_string(),
+    );
+
+    //Replace the contents of the file with the new string with CSS and JS inserted
+    let expected = jsstr.len();
+    let found = work::Work::find(&paths.site.file, &jsstr);
+    js_file.replace_contents(jsstr.into_bytes().as_mut())?;
+    let js_bytes = js.map(str::len).unwrap_or(0) + preload.map(str::len).unwrap_or(0);
+    output::report(|report| report.bytes_injected = Some((theme.expression().len() + js_bytes) as u64));
+    output::report(|report| {
+        report.css = Some(output::ReportSource { source: theme_name.clone(), bytes: css_bytes });
+        report.js = cfg.customjs_path.as_ref().filter(|_| cfg.inject_js).map(|source| output::ReportSource { source: source.to_string(), bytes: js_bytes as u64 });
+    });
+
+    //Writing the archive is the slowest part, so it gets its own bar that follows every byte written
+    let pack_prog = output::progress(ui::bar(ui::Kind::Transfer, archive.packed_size_estimate()), msg!("writing-archive", archive_name));
+
+    //From here until the manifest is saved, a patch that is interrupted is rolled back by the panic hook or the next run
+    let guard = guard::Guard::begin(&paths.backups, &path, &paths.site.file, &archive_hash)?;
+
+    //Re-pack the Discord asar file next to the real one, which is only replaced if the patched file was written completely
+    let writing = std::time::Instant::now();
+    //Only an archive that was written but is broken can be recovered from, failing to write or rename it is reported as it is
+    backup::replace_checked(
+        &path,
+        |temp| {
+            archive.pack_with_progress(temp, &pack_prog)?;
+            after_pack_for_tests(temp, &path)
+        },
+        |written| match written.get_file(&paths.site.file).map(asar::FileEntry::size) {
+            Some(found) if found == expected => Ok(()),
+            found => Err(asar::Error::Truncated { path: paths.site.file.clone(), expected, found: found.unwrap_or(0) }),
+        },
+    )
+    .map_err(|e| -> Box<dyn std::error::Error> {
+        match e {
+            backup::ReplaceError::Check(e) => Box::new(VerifyFailed(e)),
+            backup::ReplaceError::Write(e) => e.into(),
+        }
+    })?;
+    output::report(|report| report.write_ms = Some(writing.elapsed().as_millis() as u64));
+
+    //Record the patched archive in the manifest so that verify and status can check it later
+    manifest.patched_hash = Some(manifest::hash_file(&path)?);
+    //Remember where the injection is so that the next apply to this archive can skip searching for it
+    if let (Some(mut work), Some(hash)) = (found, manifest.patched_hash.clone()) {
+        work.archive_hash = hash;
+        work.search_micros = cached.map(|cached| cached.search_micros).unwrap_or(searched.as_micros() as u64);
+        if let Err(e) = work.save(&paths.backups) {
+            output::warning(Category::Backup, style(msg!("failed-save-work", work::Work::path(&paths.backups).display(), e)).fg(Color::Color256(172)));
+        }
+    }
+    //Remember exactly what was inserted so that `check` can insert it again after Discord updates
+    let flavor_dir = paths.backups.parent().unwrap_or(&paths.backups);
+    let last = lasttheme::LastTheme {
+        title: title.map(str::to_owned),
+        strategy: cfg.inject_strategy,
+        ..lasttheme::LastTheme::new(&theme_name, theme, js, preload)
+    };
+    if let Err(e) = last.save(flavor_dir) {
+        output::warning(Category::Backup, style(msg!("failed-save-last-theme", lasttheme::LastTheme::path(flavor_dir).display(), e)).fg(Color::Color256(172)));
+    }
+    manifest.record(manifest::Applied::new(last), cfg.manifest_history);
+    manifest.config = cfg.to_json();
+    manifest.theme = Some(theme_name);
+    manifest.site = Some(paths.site.clone());
+    manifest.layout = paths.layout;
+    manifest.symlink = paths.link.clone();
+    manifest.save(&paths.backups)?;
+    guard.finish()?;
+
+    pack_prog.finish_with_message(
+        style(msg!("re-packed-modified-discord-archive"))
+            .fg(Color::Green)
+            .to_string(),
+    );
+
+    drop(pack_prog);
+    Ok(())
+}
+
+/// The `VerifyFailed` struct is the error that [apply] returns when the patched archive was written but failed the check after writing.
+/// Discord's archive isn't replaced then, so [recover] can offer to restore it, patch it again, or leave it
+#[derive(Debug)]
+struct VerifyFailed(asar::Error);
+
+impl std::fmt::Display for VerifyFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", msg!("patched-archive-failed-check", self.0))
+    }
+}
+
+impl std::error::Error for VerifyFailed {}
+
+/// A hook that tests run on an archive that [apply] wrote and the path of the archive that it replaces, see [AFTER_PACK]
+#[cfg(test)]
+type AfterPack = fn(&mut fs::File, &Path) -> std::io::Result<()>;
+
+#[cfg(test)]
+thread_local! {
+    /// What to do to the next archive that [apply] writes on this thread and to the archive that it replaces, so that tests can make
+    /// the check after writing or the rename after it fail
+    static AFTER_PACK: std::cell::Cell<Option<AfterPack>> = const { std::cell::Cell::new(None) };
+}
+
+/// Run the hook that a test set with [AFTER_PACK] on the archive that [apply] just wrote to `temp` to replace `dest`
+#[cfg_attr(not(test), allow(unused_variables))]
+fn after_pack_for_tests(temp: &mut fs::File, dest: &Path) -> Result<(), asar::Error> {
+    #[cfg(test)]
+    if let Some(hook) = AFTER_PACK.with(std::cell::Cell::take) {
+        hook(temp, dest)?;
+    }
+    Ok(())
+}
+
+/// The `Recovery` enum is what can be done after the patched archive failed the check after writing, see [recover]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Recovery {
+    /// Put Discord's archive and icons back from the backups
+    Restore,
+    /// Patch again without the work saved by the last apply, so the whole file is searched and the whole archive is packed again
+    Retry,
+    /// Leave Discord as it is and write a debug dump to the backup folder
+    Leave,
+}
+
+/// The name of the debug dump in the backup folder that [Recovery::Leave] writes
+const RECOVERY_DUMP_NAME: &str = "failed-patch-dump.zip";
+
+/// Ask what to do after [apply] failed with `failed`, do it, and exit. Nobody can be asked when the output is JSON or no one is
+/// watching, so Discord is left as it is and a debug dump is written then. Patching again can fail the same way, which asks again
+fn recover(paths: &Paths, cfg: &Config, theme: &Css, theme_name: &str, icon: Option<&icon::Icon>, failed: &VerifyFailed) -> ! {
+    output::error(style(failed).red());
+    loop {
+        let choice = match console::user_attended() && !output::is_json() {
+            true => match Select::with_theme(&ColorfulTheme::default())
+                .with_prompt(msg!("recovery-prompt"))
+                .item(msg!("recovery-restore"))
+                .item(msg!("recovery-retry"))
+                .item(msg!("recovery-leave"))
+                .default(0)
+                .interact()
+            {
+                Ok(0) => Recovery::Restore,
+                Ok(1) => Recovery::Retry,
+                _ => Recovery::Leave,
+            },
+            false => Recovery::Leave,
+        };
+        match recover_with(choice, paths, cfg, theme, theme_name, icon) {
+            Ok(()) => {
+                match choice {
+                    Recovery::Restore => output::info(style(msg!("recovery-restored")).yellow()),
+                    Recovery::Retry => output::info(style(msg!("recovery-retried")).green()),
+                    Recovery::Leave => output::info(style(msg!("recovery-left", paths.backups.join(RECOVERY_DUMP_NAME).display())).yellow()),
+                }
+                prompt_quit(if choice == Recovery::Retry { 0 } else { 1 });
+            }
+            Err(e) if e.is::<VerifyFailed>() => output::error(style(e).red()),
+            Err(e) => panic!("{}", msg!("failed-recover", e)),
+        }
+    }
+}
+
+/// Do what was picked in [recover]. Every choice first rolls back what is left of the failed patch, and doing the same one twice
+/// leaves Discord the same as doing it once
+fn recover_with(choice: Recovery, paths: &Paths, cfg: &Config, theme: &Css, theme_name: &str, icon: Option<&icon::Icon>) -> Result<(), Box<dyn std::error::Error>> {
+    //The archive wasn't replaced, so this only deletes the temporary files and the guard file
+    if let Some(rolled) = guard::rollback(&paths.backups) {
+        rolled?;
+    }
+    match choice {
+        Recovery::Restore => {
+            let found = backup::find(&paths.backups)
+                .or_else(|| backup::find_file(&paths.backups))
+                .ok_or_else(|| msg!("recovery-no-backup", paths.backups.display()))?;
+            backup::restore_asar(&found, &paths.asar(), &paths.site.file, &ProgressBar::hidden())?;
+            if icon.is_some() && paths.explicit.is_none() {
+                restore_icons(paths, &paths.backups, Manifest::load(&paths.backups)?.as_ref());
+            }
+        }
+        Recovery::Retry => {
+            //Without the saved work, apply searches the whole file for the injection instead of trusting where it was left
+            match fs::remove_file(work::Work::path(&paths.backups)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => (),
+            }
+            apply(paths, cfg, theme, theme_name.to_owned(), icon, true)?;
+        }
+        Recovery::Leave => {
+            dump::write(&paths.backups.join(RECOVERY_DUMP_NAME), &dump_files(paths, cfg)?)?;
+        }
+    }
+    Ok(())
+}
+
+/// Get a stylesheet ready to be applied by inlining what it imports if `inline` is set, setting the variables it declares, and
+/// checking that it doesn't have javascript in it
+fn prepare_theme(cfg: &mut Config, mut theme: String, inline: bool, force: bool) -> String {
+    if inline {
+        theme = inline_imports(theme); //Discord blocks most remote stylesheets and fonts, so copy them into the theme
+    }
+    theme = theme_variables(cfg, theme); //Themes can declare colors and sizes that each user picks once
+    check_suspicious_theme(&theme, force); //A theme with javascript in it was probably made to attack whoever applies it
+    theme
+}
+
+/// Serve the theme at `path` on a local page with markup like Discord's main window until the program is stopped with Ctrl-C, without
+/// touching Discord. The theme goes through [prepare_theme] like when it is applied, again every time the file is saved
+#[cfg(feature = "preview")]
+fn preview(cfg: &mut Config, path: &str, port: Option<&str>, inline: bool) -> ! {
+    output::action("preview");
+    let port = match port {
+        Some(port) => port.parse().unwrap_or_else(|_| panic!("{}", msg!("invalid-preview-port", port))),
+        None => preview::DEFAULT_PORT,
+    };
+    //Nothing is inserted into Discord, so a theme that looks like javascript is only warned about
+    let server = preview::Server::bind(port, prepare_theme(cfg, read_theme(path).0, inline, true))
+        .unwrap_or_else(|e| panic!("{}", msg!("failed-start-preview", port, e)));
+    server.spawn().unwrap_or_else(|e| panic!("{}", msg!("failed-start-preview", port, e)));
+    output::info(msg!("preview-serving", style(path).cyan(), style(server.url()).cyan().underlined()));
+    output::info(style(msg!("preview-stop")).yellow());
+
+    let mut watch = preview::Watch::new(Path::new(path));
+    loop {
+        std::thread::sleep(preview::POLL);
+        if !watch.changed() {
+            continue;
+        }
+        //Editors can save a file in more than one step, so a file that can't be read is only warned about until the next save
+        match fs::read(path).map_err(|e| e.to_string()).and_then(|data| text::load(path, data).map_err(|e| e.to_string())) {
+            Ok(theme) => {
+                server.update(prepare_theme(cfg, theme, inline, true));
+                output::info(style(msg!("preview-reloaded", path)).green());
+            }
+            Err(e) => output::warning(Category::Theme, style(msg!("failed-read-preview-theme", path, e)).fg(Color::Color256(172))),
+        }
+    }
+}
+
+/// Write the injection that is about to be inserted into Discord to `dest` so that it can be read first, then ask before going on
+/// unless `yes` is set. The injection is made the same way as by [apply], so the file matches what is inserted byte for byte, with the
+/// preload block that goes at the top of the file before it
+fn emit_injection(cfg: &Config, css: &Css, dest: &Path, yes: bool) {
+    let preload = cfg.preload().map(patch::preload_block).unwrap_or_default();
+    let block = patch::block(css, cfg.inject_js.then_some(cfg.customjs.as_str()), cfg.inject_strategy);
+    let injection = preload + &patch::titled(block, cfg.window_title.as_deref());
+    fs::write(dest, &injection).unwrap_or_else(|e| panic!("{}", msg!("failed-write-injection", dest.display(), e)));
+    output::report(|report| {
+        report.injection = Some(output::ReportInjection {
+            path: dest.to_owned(),
+            bytes: injection.len() as u64,
+            sha256: manifest::hash_bytes(injection.as_bytes()),
+        })
+    });
+    output::info(msg!("wrote-injection", style(dest.display()).cyan()));
+
+    let confirmed = yes
+        || (console::user_attended()
+            && !output::is_json()
+            && Confirm::new()
+                .with_prompt(msg!("confirm-apply-emitted-injection"))
+                .default(false)
+                .interact()
+                .unwrap_or(false));
+    if !confirmed {
+        output::info(style(msg!("stopped-after-emit-injection")).yellow());
+        output::finish(0);
+        lock::release();
+        std::process::exit(0);
+    }
+}
+
+/// Warn if the escaped theme and custom javascript are big enough to slow down Discord's startup, which is usually because of images
+/// embedded as `data:` URLs, and refuse to inject more than the `theme-size-limit-kib` option unless `force` is set
+fn check_theme_size(cfg: &Config, theme: &Css, force: bool) {
+    let size = (theme.expression().len() + cfg.customjs.len() + cfg.preload().map(str::len).unwrap_or(0)) as u64;
+    let data_urls = indicatif::HumanBytes(theme.sheets().into_iter().map(data_url_bytes).sum::<usize>() as u64);
+    if size > cfg.theme_size_limit_kib * 1024 {
+        let message = msg!("theme-too-large", indicatif::HumanBytes(size), indicatif::HumanBytes(cfg.theme_size_limit_kib * 1024), data_urls);
+        match force {
+            true => output::warning(Category::Theme, style(message).fg(Color::Color256(172))),
+            false => panic!("{}", message),
+        }
+    } else if size > cfg.theme_size_warn_kib * 1024 {
+        output::warning(Category::Theme, style(msg!("theme-large", indicatif::HumanBytes(size), data_urls)).yellow());
+    }
+}
+
+/// Count how many bytes of a CSS theme are in `data:` URLs, which embed whole images in the theme
+fn data_url_bytes(css: &str) -> usize {
+    css.match_indices("data:")
+        .map(|(idx, _)| {
+            css[idx..]
+                .find(|c: char| c == ')' || c == '"' || c == '\'' || c.is_whitespace())
+                .unwrap_or(css.len() - idx)
+        })
+        .sum()
+}
+
+/// Inline the stylesheets that a theme imports and the fonts and images that it loads from `https://` URLs, which Discord's content
+/// security policy would block. Anything that can't be downloaded is left as it is with a warning
+fn inline_imports(theme: String) -> String {
+    #[cfg(feature = "autoupdate")]
+    let mut fetch = |url: &str, limit: u64| {
+        //Imported fonts and stylesheets rarely change, so the stored copy is used without asking the server
+        let http = cache::Http { bar: &ProgressBar::hidden(), limit };
+        let fetched = cache::fetch_checked(&http, &cache::Store::open(), url, cache::Policy::PreferCache, |_| Ok(())).map_err(|e| e.to_string())?;
+        Ok(inline::Asset {
+            data: fetched.data,
+            content_type: fetched.content_type,
+        })
+    };
+    #[cfg(not(feature = "autoupdate"))]
+    let mut fetch = |_: &str, _: u64| Err(msg!("program-compiled-without-network-support"));
+
+    let spin = spinner(msg!("inlining-imports"));
+    let inlined = inline::inline(&theme, &mut fetch);
+    spin.finish_with_message(style(msg!("inlined-imports", inlined.inlined.len())).green().to_string());
+    for (url, err) in inlined.failed.iter() {
+        output::warning(Category::Network, style(msg!("failed-inline", url, err)).fg(Color::Color256(172)));
+    }
+    inlined.css
+}
+
+/// Run the discord theme setter application
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    //Set a panic handler for printing error messages cleanly
+    std::panic::set_hook(Box::new(|pinfo: &std::panic::PanicInfo| {
+        //The location goes into the same message so that it is part of the error in the JSON report
+        let loc = pinfo.location().map(|loc| msg!("panic-location", loc)).unwrap_or_default();
+        if let Some(s) = pinfo.payload().downcast_ref::<String>() {
+            output::error(format!("{}{}", loc, msg!("fatal-error", style(s).red())));
+        } else if let Some(s) = pinfo.payload().downcast_ref::<&str>() {
+            output::error(format!("{}{}", loc, msg!("fatal-error", style(s).red())));
+        } else {
+            output::error(format!("{}{}", loc, style(msg!("unknown-error")).red()));
+        }
+        #[cfg(feature = "notify")]
+        {
+            let err = pinfo.payload().downcast_ref::<String>().cloned().or_else(|| pinfo.payload().downcast_ref::<&str>().map(|s| s.to_string()));
+            notify::send(&notify::Notification::critical(msg!("notify-failed"), msg!("notify-failed-run-doctor", err.unwrap_or_else(|| msg!("unknown-error")))));
+        }
+        //A panic in the middle of a patch would leave Discord half patched
+        if let Some(dir) = guard::active() {
+            roll_back_interrupted(&dir);
+        }
+        prompt_quit(-1);
+    }));
+
+    output::start();
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    ui::set_quiet(args.contains(&"--quiet"));
+    if let Some(name) = flag_value(&args, "--output") {
+        output::init(output::Mode::from_name(name).unwrap_or_else(|| panic!("{}", msg!("unknown-output-format-expected-human", name))));
+    }
+    let config_path = config::resolve_path(flag_value(&args, "--config").map(Path::new));
+    let first_run = !config_path.exists(); //Loading the config creates it, so check if this is the first run before that
+    let overrides = config_overrides(&args);
+    let flavor = match flag_value(&args, "--flavor") {
+        Some(name) => config::Flavor::from_name(name).unwrap_or_else(|| panic!("{}", msg!("unknown-discord-flavor-expected-stable", name))),
+        None => config::Flavor::Stable,
+    };
+    let mut cfg = Config::load(&config_path, &overrides, flavor);
+    cfg.root = flag_value(&args, "--root").map(PathBuf::from);
+    cfg.asar = flag_value(&args, "--asar").map(PathBuf::from);
+    backup::set_work_dir(cfg.work_dir.clone());
+    if let Some(owner) = flag_value(&args, elevate::FLAG) {
+        let owner = elevate::Owner::parse(owner).unwrap_or_else(|| panic!("{}", msg!("invalid-elevated-owner", owner)));
+        #[cfg_attr(not(feature = "autoupdate"), allow(unused_mut))]
+        let mut roots = vec![cfg.backup_dir(), cfg.path.clone()];
+        #[cfg(feature = "autoupdate")]
+        roots.push(cache::Store::open().dir().to_owned());
+        elevate::init(owner, roots);
+        output::info(style(msg!("running-elevated", owner)).yellow());
+    } else {
+        //The run that started an elevated one records it, so it isn't recorded twice
+        output::keep_history(history::path(&userdata::data_dir()), cfg.run_history);
+    }
+    if let Err(e) = messages::init(cfg.language.as_deref(), config_path.parent().unwrap_or_else(|| Path::new("."))) {
+        output::warning(Category::Config, style(msg!("failed-load-language", e)).fg(Color::Color256(172)));
+    }
+    #[cfg(feature = "autoupdate")]
+    net::init(&cfg).unwrap_or_else(|e| panic!("{}", e)); //Load the configuration file or create a default one
+    #[cfg(feature = "autoupdate")]
+    cache::init(&cfg);
+    #[cfg(feature = "notify")]
+    notify::enable(cfg.notify, false);
+    if args.contains(&"--verbose") {
+        output::info(msg!("using-config-file", style(config_path.display()).cyan()));
+        if let serde_json::Value::Object(options) = cfg.to_json() {
+            for (key, val) in options.iter() {
+                output::info(format!("  {} = {} ({})", key, val, cfg.source(key)));
+            }
+        }
+    }
+
+    //Run one of the commands that don't apply a theme if one was given
+    let force = args.contains(&"--force");
+    let icon_arg = flag_value(&args, "--icon");
+    let positional = positional(&args);
+    if cfg.asar.is_some() && (icon_arg.is_some() || positional.first() == Some(&"icon")) {
+        panic!("{}", msg!("asar-no-icons"));
+    }
+    //Runs that change Discord or this program's files take turns, while the commands that only read can run alongside them. An
+    //elevated run was started by a run that already holds the lock
+    let read_only = match positional.as_slice() {
+        ["status", ..] | ["paths", ..] | ["doctor", ..] | ["verify", ..] | ["debug", ..] | ["preview", ..] | ["backup", "export", ..] | ["cache", "stats", ..] | ["history", ..] => true,
+        ["restore", ..] => args.contains(&"--diff"),
+        _ => false,
+    };
+    if !read_only && flag_value(&args, elevate::FLAG).is_none() {
+        lock::hold(&userdata::data_dir()).unwrap_or_else(|e| panic!("{}", e));
+    }
+    let relaunch_after = args.contains(&"--relaunch");
+    if args.contains(&"--install-task") || args.contains(&"--uninstall-task") {
+        schedule_check(&cfg, args.contains(&"--uninstall-task"), relaunch_after);
+    }
+    if args.contains(&"--forget") {
+        forget_last_theme(&cfg);
+    }
+    match positional.as_slice() {
+        ["check", ..] => check(&mut cfg, relaunch_after),
+        ["status", ..] => status(&Paths::resolve(&cfg)),
+        ["paths", ..] => print_paths(&cfg),
+        ["history", count] => print_history(&cfg, count.parse().unwrap_or_else(|_| panic!("{}", msg!("invalid-history-count", count)))),
+        ["history", ..] => print_history(&cfg, HISTORY_SHOWN),
+        ["doctor", ..] => doctor(&Paths::resolve(&cfg)),
+        ["verify", ..] => verify(&Paths::resolve(&cfg)),
+        ["restore", ..] if args.contains(&"--diff") => diff_backup(&Paths::resolve(&cfg)),
+        ["restore", ..] => restore_backup(&Paths::resolve(&cfg)),
+        ["backup", "export", dir, ..] => export_backups(&cfg, Path::new(dir)),
+        ["backup", "import", dir, ..] => import_backups(&cfg, &overrides, Path::new(dir), force),
+        ["backup"] => backup_now(&Paths::resolve(&cfg), &cfg, force),
+        ["uninstall", ..] => uninstall(&cfg, args.contains(&"--keep-config"), args.contains(&"--keep-backups"), force),
+        ["icon", "restore", ..] => restore_icons_only(&Paths::resolve(&cfg)),
+        ["icon", "apply", ..] => apply_icons_only(&Paths::resolve(&cfg), &load_icon(&cfg, icon_arg)),
+        ["icon", ..] => panic!("{}", msg!("expected-icon-restore-icon-apply")),
+        ["config", "migrate", ..] => migrate_config(&cfg, force),
+        #[cfg(feature = "autoupdate")]
+        ["cache", "stats", ..] => cache_stats(&cfg),
+        #[cfg(feature = "autoupdate")]
+        ["cache", "clear", ..] => cache_clear(),
+        #[cfg(feature = "autoupdate")]
+        ["cache", ..] => panic!("{}", msg!("expected-cache-stats-clear")),
+        #[cfg(not(feature = "autoupdate"))]
+        ["cache", ..] => panic!("{}", msg!("program-compiled-without-network-support")),
+        #[cfg(feature = "preview")]
+        ["preview", path, ..] => preview(&mut cfg, path, flag_value(&args, "--port"), args.contains(&"--inline-imports")),
+        #[cfg(feature = "preview")]
+        ["preview"] => panic!("{}", msg!("expected-preview-css")),
+        #[cfg(not(feature = "preview"))]
+        ["preview", ..] => panic!("{}", msg!("program-compiled-without-preview")),
+        ["debug", "dump", dest, ..] => debug_dump(&Paths::resolve(&cfg), &cfg, Path::new(dest)),
+        ["debug", ..] => panic!("{}", msg!("expected-debug-dump-dir")),
+        ["backup", ..] => panic!("{}", msg!("expected-backup-export-dir-backup")),
+        ["config", ..] => panic!("{}", msg!("expected-config-migrate-force")),
+        _ => (),
+    }
+
+    //An earlier apply from the manifest's history is inserted exactly as it was, whatever the config file says now
+    if let Some(ago) = flag_value(&args, "--from-manifest") {
+        let ago = ago.parse().unwrap_or_else(|_| panic!("{}", msg!("invalid-from-manifest", ago)));
+        apply_from_manifest(&mut cfg, ago, flag_value(&args, "--emit-injection"), args.contains(&"--yes"), force);
+    }
+
+    let refresh = args.contains(&"--refresh");
+
+    //With --js and no theme only the custom javascript is inserted, without any CSS
+    let js_only = flag_value(&args, "--js").filter(|_| positional.is_empty());
+    if js_only.is_some() && args.contains(&"--no-js") {
+        panic!("{}", msg!("js-only-with-no-js"));
+    }
+
+    //Guide first-time users through the choices that would otherwise need the menu and the config file
+    let guided = positional.is_empty() && js_only.is_none() && (args.contains(&"--wizard") || first_run && console::user_attended() && !output::is_json());
+
+    //A theme given on the command line replaces the last theme, while starting without one offers to apply the last theme again
+    let last = last_theme_dir(&cfg).and_then(|dir| lasttheme::LastTheme::load(&dir).map(|last| (dir, last)));
+    output::report(|report| report.last_theme = last.as_ref().map(|(_, last)| last.name.clone()));
+    let (wizard_theme, wizard_paths) = match guided {
+        true => {
+            let (theme, paths) = wizard(&mut cfg, refresh);
+            (Some(theme), Some(paths))
+        }
+        false => (None, None),
+    };
+
+    //Theme packs bring their own javascript and icon along with the CSS
+    let pack = match (guided, positional.first()) {
+        (false, Some(path)) if path.to_ascii_lowercase().ends_with(".zip") => Some(read_pack(path)),
+        _ => None,
+    };
+
+    let pack_icon = pack.as_ref().and_then(|(pack, _)| pack.icon.clone()).filter(|_| icon_arg.is_none());
+    #[cfg(feature = "autoupdate")]
+    let download_js = {
+        let attempts = cfg.download_attempts;
+        move |url: &str, pinned: Option<&str>| {
+            fetch_text(&msg!("what-custom-js"), url, pinned, false, attempts, refresh).map_err(|e| e.to_string())
+        }
+    };
+    #[cfg(not(feature = "autoupdate"))]
+    let download_js = |_: &str, _: Option<&str>| Err(msg!("program-compiled-without-network-support"));
+    //With --no-js the custom javascript isn't inserted, so it isn't read or downloaded either
+    match cfg.inject_js {
+        true => {
+            cfg.resolve_custom_js(download_js).unwrap_or_else(|e| panic!("{}", e));
+            check_js_pin(&mut cfg, args.contains(&"--accept-js-change"));
+        }
+        false => {
+            let pack_js = pack.as_ref().and_then(|(pack, _)| pack.members.js.clone());
+            if let Some(source) = cfg.customjs_path.as_ref().map(ToString::to_string).or(pack_js) {
+                output::info(style(msg!("skipping-custom-js", source)).yellow());
+            }
+        }
+    }
+    if let (Some((pack::Pack { js: Some(js), members, .. }, path)), true) = (&pack, cfg.inject_js) {
+        cfg.customjs = escape::template_literal(js);
+        cfg.customjs_path = Some(format!("{}/{}", path, members.js.as_deref().unwrap_or_default()).into());
+    }
+
+    //Get the input file path from the arguments or let the user select an option
+    let mut dark = None; //The dark theme when the light and dark themes from the config file are applied together
+    let (theme, theme_name) = match (wizard_theme, positional.first(), js_only) {
+        (Some(theme), _, _) => theme,
+        //Read the user CSS theme to a string and escape any '`' characters to not mess up CSS insertion
+        (None, Some(p), _) => match pack {
+            Some((pack, path)) => (pack.css, pack.name.unwrap_or(path)),
+            None => read_theme(p),
+        },
+        (None, None, Some(js)) => (String::new(), js.to_owned()),
+        //No input path given, ask for either a theme download, backup restoration, or exit
+        (None, None, None) => {
+            #[cfg(feature = "autoupdate")]
+            let patch_text = msg!("download-latest-old-theme-apply", cfg.theme_url());
+
+            #[cfg(not(feature = "autoupdate"))]
+            let patch_text = msg!("apply-default-old-theme-program", env!("COMPILEDATE", "build.rs did not run properly, no compile date set"));
+
+            //Themes set in the config file are applied instead of the old theme
+            let patch_text = match (&cfg.theme_light, &cfg.theme_dark) {
+                (Some(light), Some(dark)) => msg!("apply-scheduled-themes", light, dark),
+                (Some(path), None) | (None, Some(path)) => msg!("apply-configured-theme", path),
+                (None, None) => patch_text,
+            };
+
+            //Make a menu for selecting what the user wants to do
+            let menu_theme = ColorfulTheme {
+                prompt_style: Style::default().fg(Color::Blue).bold(),
+                active_item_style: Style::default().fg(Color::Green),
+                active_item_prefix: style(">>".to_owned()).blink(),
+                hint_style: Style::default().fg(Color::Color256(252)),
+
+                ..Default::default()
+            };
+            let mut menu = Select::with_theme(&menu_theme);
+            menu.with_prompt(msg!("no-input-given-drag-drop"));
+            let offer = match &last {
+                Some((_, last)) => {
+                    menu.item(msg!("reapply-last-theme", last.name, indicatif::HumanBytes(last.bytes() as u64)));
+                    1
+                }
+                _ => 0,
+            };
+            menu
+            .item(patch_text)
+            .item(msg!("reset-discords-theme-factory-defaults"))
+            .item(msg!("restore-discords-original-icon-without"))
+            .item(msg!("back-up-discord-without-patching"))
+            .item(msg!("change-settings"));
+            #[cfg(feature = "autoupdate")]
+            menu.item(msg!("browse-online-themes"));
+            let selection = menu
+                .item(msg!("exit-program"))
+                .default(0)
+                .interact()
+                .unwrap_or_else(|e| panic!("{}: {:?}", msg!("failed-take-selection-menu"), e));
+
+            //The last theme comes before the rest of the entries when it is offered
+            if let (Some((dir, last)), 0, 1) = (&last, selection, offer) {
+                reapply_last_theme(&mut cfg, last, dir);
+            }
+            match selection - offer {
+                //Restore a backup of Discord's asar
+                1 => restore_backup(&Paths::resolve(&cfg)),
+                //Restore only the icons from their backups
+                2 => restore_icons_only(&Paths::resolve(&cfg)),
+                //Back up Discord without patching it
+                3 => backup_now(&Paths::resolve(&cfg), &cfg, force),
+                //Change and save the config file
+                4 => settings(&mut cfg),
+                //Download the most recent version of the theme from github, or use the compiled in one
+                0 => match (&cfg.theme_light, &cfg.theme_dark) {
+                    (Some(light), Some(dark_path)) => {
+                        let (light, dark_theme) = (read_theme(light), read_theme(dark_path));
+                        dark = Some(dark_theme.0);
+                        (light.0, format!("{} + {}", light.1, dark_theme.1))
+                    }
+                    //With only one of them set there is nothing to switch between, so it is applied like any other theme
+                    (Some(path), None) | (None, Some(path)) => read_theme(path),
+                    (None, None) => {
+                        let picked = pick_components(&mut cfg);
+                        let theme = match picked.contains(&components::Component::Css) {
+                            true => {
+                                let (theme, name) = classic_theme(&cfg, refresh);
+                                (add_tweaks(&mut cfg, theme, refresh), name)
+                            }
+                            false => (String::new(), String::new()),
+                        };
+                        match components::css(&picked, theme) {
+                            Some(theme) => theme,
+                            None if picked.contains(&components::Component::Icon) && cfg.asar.is_none() => {
+                                apply_icons_only(&Paths::resolve(&cfg), &load_icon(&cfg, icon_arg))
+                            }
+                            None => {
+                                output::info(style(msg!("no-components-picked")).yellow());
+                                prompt_quit(0)
+                            }
+                        }
+                    }
+                },
+                //Pick a theme from the catalog
+                #[cfg(feature = "autoupdate")]
+                5 => browse_catalog(&cfg, refresh),
+                //Return the default old theme CSS string
+                //Exit the program if the user doesn't want to roll back changes or set the old theme
+                _ => {
+                    output::finish(0);
+                    lock::release();
+                    std::process::exit(0)
+                }
+            }
+        }
+    };
+    //Read and check the custom icon before anything is changed so that a bad icon file doesn't leave Discord half patched. This comes
+    //after the menu because the icon can be picked there
+    let replace_icon = cfg.asar.is_none() && (cfg.replace_icon || icon_arg.is_some() || pack_icon.is_some());
+    let icon = match (pack_icon, replace_icon) {
+        (Some(data), _) => icon::Icon::from_bytes(data).unwrap_or_else(|e| panic!("{}", msg!("failed-use-pack-icon", e))),
+        (None, true) => load_icon(&cfg, icon_arg),
+        (None, false) => icon::Icon::embedded(),
+    };
+    if let (Some((_, last)), Some(_)) = (&last, positional.first().filter(|_| !guided)) {
+        let interactive = console::user_attended() && !output::is_json() && !force;
+        if lasttheme::precedence(Some(&theme_name), Some(last), interactive) == lasttheme::Precedence::ConfirmSwitch {
+            confirm_switch(last, &theme_name, theme.len());
+        }
+    }
+    output::action("apply");
+    let paths = wizard_paths.unwrap_or_else(|| Paths::resolve(&cfg)); //Find Discord's installation and the backup folder for it
+    preflight(&paths); //Stop before changing anything if patching can't finish
+    check_other_user(&paths, force); //An elevated run can find another account's Discord on a shared computer
+    check_symlink(&paths, force); //A linked archive is changed where it really is, which can be outside of Discord
+    check_client_mods(&paths, force); //Other client mods patch the same files, so ask before stacking on top of them
+    check_pending_updates(&paths, force); //A downloaded update would replace the patched archive as soon as Discord restarts
+    let inline = args.contains(&"--inline-imports");
+    let css = match dark {
+        _ if js_only.is_some() => Css::None,
+        Some(dark) => Css::Scheduled {
+            light: prepare_theme(&mut cfg, theme, inline, force),
+            dark: prepare_theme(&mut cfg, dark, inline, force),
+            schedule: Schedule::parse(cfg.dark_hours.as_deref()).unwrap_or_else(|e| panic!("{}", e)),
+        },
+        None => Css::Single(prepare_theme(&mut cfg, theme, inline, force)),
+    };
+    check_theme_size(&cfg, &css, force); //Huge themes make Discord start slowly or not at all
+    if let Some(dest) = flag_value(&args, "--emit-injection") {
+        emit_injection(&cfg, &css, Path::new(dest), args.contains(&"--yes"));
+    }
+
+    if let Err(e) = apply(&paths, &cfg, &css, theme_name.clone(), replace_icon.then_some(&icon), force) {
+        match e.downcast::<VerifyFailed>() {
+            Ok(failed) => recover(&paths, &cfg, &css, &theme_name, replace_icon.then_some(&icon), &failed),
+            Err(e) => return Err(e),
+        }
+    }
+    #[cfg(feature = "notify")]
+    notify::send(&notify::Notification::new(msg!("notify-applied"), msg!("notify-applied-to", theme_name, discord_version(&paths.core).trim_start_matches("app-"))));
+
+    if guided {
+        offer_relaunch(&paths, cfg.flavor);
+    }
     prompt_quit(0);
 }
 
@@ -558,3 +3334,575 @@ fn main() {
         Err(e) => panic!("{}", e),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutil::{FakeDiscord, MAIN_SCREEN_JS};
+
+    #[test]
+    pub fn refuse_patched_backup() {
+        let fake = FakeDiscord::create();
+        let paths = Paths::resolve(&fake.config());
+        apply(&paths, &fake.config(), &Css::from("body { color: red; }"), "first".to_owned(), None, false).unwrap();
+        fs::remove_dir_all(&paths.backups).unwrap();
+        fs::create_dir_all(&paths.backups).unwrap();
+
+        //Neither backup mode keeps a copy of Discord's files that is already patched
+        for mode in [BackupMode::File, BackupMode::Archive] {
+            assert!(std::panic::catch_unwind(|| create_backup(&paths, mode, false)).is_err());
+            assert!(!paths.backups.join(backup::FILE_BACKUP_NAME).exists() && !paths.backups.join(backup::backup_name(false)).exists());
+        }
+    }
+
+    #[test]
+    pub fn apply_and_reapply() {
+        let fake = FakeDiscord::create();
+        let mut cfg = fake.config();
+        cfg.customjs = "console.log('themed');".to_owned();
+        let paths = Paths::resolve(&cfg);
+        assert_eq!(paths.core, fake.core());
+
+        apply(&paths, &cfg, &Css::from("body { color: red; }"), "first".to_owned(), None, false).unwrap();
+        let patched = fake.main_screen();
+        assert_eq!(patched.matches("CSS_INJECTION_USER_CSS").count(), 2);
+        assert!(patched.contains("body { color: red; }") && patched.contains("console.log('themed');"));
+
+        //Applying again replaces the theme instead of inserting a second one
+        apply(&paths, &cfg, &Css::from("body { color: blue; }"), "second".to_owned(), None, false).unwrap();
+        let reapplied = fake.main_screen();
+        assert_eq!(reapplied.matches("CSS_INJECTION_USER_CSS").count(), 2);
+        assert!(reapplied.contains("body { color: blue; }") && !reapplied.contains("color: red"));
+
+        let manifest = Manifest::load(&paths.backups).unwrap().unwrap();
+        assert_eq!(manifest.theme.as_deref(), Some("second"));
+        //What was inserted is remembered so that `check` can insert it again after Discord updates
+        let last = lasttheme::LastTheme::load(paths.backups.parent().unwrap()).unwrap();
+        assert_eq!((last.name.as_str(), last.css(), last.js.as_deref()), ("second", Ok(Css::from("body { color: blue; }")), Some("console.log('themed');")));
+        //The second apply found the injection where the first one left it, and left its own for the next
+        let work = work::Work::load(&paths.backups, manifest.patched_hash.as_deref().unwrap()).unwrap();
+        assert_eq!(work.unpatched(&reapplied).as_deref(), Some(MAIN_SCREEN_JS));
+        assert_eq!(manifest.patched_hash, Some(manifest::hash_file(&fake.asar()).unwrap()));
+        assert_eq!(manifest.asar_state(&manifest::hash_file(&fake.asar()).unwrap()), AsarState::Patched);
+
+        //The injection can be removed without a backup
+        assert_eq!(patch::strip_injection(&reapplied).unwrap(), MAIN_SCREEN_JS);
+    }
+
+    #[test]
+    pub fn backup_without_patching() {
+        let fake = FakeDiscord::create();
+        let cfg = fake.config();
+        let paths = Paths::resolve(&cfg);
+        let (path, hash) = backup_only(&paths, &cfg, false).unwrap();
+
+        //The backup is the same as one made before patching, and Discord isn't changed
+        assert_eq!(path, paths.backups.join(backup::BACKUP_NAME));
+        assert_eq!(hash, manifest::hash_file(&fake.asar()).unwrap());
+        assert_eq!(fake.main_screen(), MAIN_SCREEN_JS);
+        let manifest = Manifest::load(&paths.backups).unwrap().unwrap();
+        assert_eq!((manifest.original_hash.as_str(), manifest.patched_hash), (hash.as_str(), None));
+        assert_eq!(backup::plan(&paths.backups), backup::Plan::Existing(path.clone()));
+
+        //After patching, the backup is kept, but making a new one with --force is refused
+        apply(&paths, &cfg, &Css::from("body { color: red; }"), "red".to_owned(), None, false).unwrap();
+        assert_eq!(backup_only(&paths, &cfg, false).unwrap(), (path.clone(), hash.clone()));
+        assert!(backup_only(&paths, &cfg, true).is_err());
+        assert_eq!(manifest::hash_reader(backup::open(&path).unwrap()).unwrap(), hash);
+    }
+
+    #[test]
+    pub fn scheduled_themes() {
+        let fake = FakeDiscord::create();
+        let cfg = fake.config();
+        let paths = Paths::resolve(&cfg);
+        let css = Css::Scheduled {
+            light: "body { color: black; }".to_owned(),
+            dark: "body { color: white; }".to_owned(),
+            schedule: Schedule::Hours { dark: 19, light: 7 },
+        };
+        apply(&paths, &cfg, &css, "light.css + dark.css".to_owned(), None, false).unwrap();
+
+        //Both themes are inserted along with the lines that pick one of them
+        let patched = fake.main_screen();
+        assert!(patched.contains(&format!("let CSS_INJECTION_USER_CSS = {};", css.expression())));
+        assert!(patched.contains("body { color: black; }") && patched.contains("body { color: white; }"));
+        assert_eq!(Manifest::load(&paths.backups).unwrap().unwrap().theme.as_deref(), Some("light.css + dark.css"));
+
+        //Going back to one theme replaces both, and either kind of injection can be removed
+        apply(&paths, &cfg, &Css::from("body { color: red; }"), "red".to_owned(), None, false).unwrap();
+        let single = fake.main_screen();
+        assert!(!single.contains("color: white") && !single.contains("getHours"));
+        assert_eq!(patch::strip_injection(&patched).unwrap(), MAIN_SCREEN_JS);
+        assert_eq!(patch::strip_injection(&single).unwrap(), MAIN_SCREEN_JS);
+    }
+
+    #[test]
+    pub fn explicit_asar() {
+        let fake = FakeDiscord::create();
+        let copy = fake.dir("copied/core.asar");
+        fs::create_dir_all(copy.parent().unwrap()).unwrap();
+        fs::copy(fake.asar(), &copy).unwrap();
+        let mut cfg = fake.config();
+        cfg.root = None;
+        cfg.asar = Some(copy.clone());
+        cfg.make_backup = true;
+
+        //The copy is patched and backed up next to itself without touching the installation
+        let paths = Paths::resolve(&cfg);
+        assert_eq!((paths.asar(), paths.site.file.as_str()), (copy.clone(), MAIN_SCREEN));
+        apply(&paths, &cfg, &Css::from("body { color: red; }"), "copied".to_owned(), None, false).unwrap();
+        assert!(testutil::main_screen(&copy).contains("body { color: red; }"));
+        assert_eq!(fake.main_screen(), MAIN_SCREEN_JS);
+        let backups = fake.dir("copied").join(EXPLICIT_BACKUP_DIR).join("core.asar");
+        assert_eq!(paths.backups, backups);
+        assert!(backup::find(&backups).is_some() && Manifest::path(&backups).exists());
+        assert!(!fake.dir("backups").exists() && !backup::icon_backup_path(&backups).exists());
+
+        //Restoring finds the backup through the same flag
+        let paths = Paths::resolve(&cfg);
+        uninstall::revert_asar(&paths.asar(), &paths.backups, &paths.site.file, &ProgressBar::hidden()).unwrap();
+        assert_eq!(testutil::main_screen(&copy), MAIN_SCREEN_JS);
+    }
+
+    #[test]
+    pub fn new_manifest_hashes_original_icon() {
+        let fake = FakeDiscord::create();
+        let cfg = fake.config();
+        let paths = Paths::resolve(&cfg);
+        let live = paths.root.join(icon::ICON_NAME);
+        assert_eq!(current_manifest(&paths).unwrap().icon_hash, manifest::hash_file(&live).ok());
+
+        //After an earlier version's apply replaced the icon, a manifest for a new version records the icon in the backup
+        let icon_backup = backup::icon_backup_path(&paths.backups);
+        fs::create_dir_all(icon_backup.parent().unwrap()).unwrap();
+        fs::copy(&live, &icon_backup).unwrap();
+        fs::write(&live, b"our icon").unwrap();
+        assert_eq!(current_manifest(&paths).unwrap().icon_hash, manifest::hash_file(&icon_backup).ok());
+        assert_ne!(manifest::hash_file(&icon_backup).ok(), manifest::hash_file(&live).ok());
+    }
+
+    #[test]
+    pub fn uninstall_one_of_two_explicit_archives() {
+        let fake = FakeDiscord::create();
+        let shared = fake.dir("copied").join(EXPLICIT_BACKUP_DIR);
+        let resolved = ["core.asar", "old-core.asar"].map(|name| {
+            let copy = fake.dir("copied").join(name);
+            fs::create_dir_all(copy.parent().unwrap()).unwrap();
+            fs::copy(fake.asar(), &copy).unwrap();
+            let mut cfg = fake.config();
+            cfg.root = None;
+            cfg.asar = Some(copy);
+            cfg.make_backup = true;
+            let paths = Paths::resolve(&cfg);
+            apply(&paths, &cfg, &Css::from("body { color: red; }"), "copied".to_owned(), None, false).unwrap();
+            paths
+        });
+        let [first, second] = &resolved;
+
+        //Keeping the backups keeps them in place and only removes the manifest
+        let kept = uninstall::remove_archive_backups(&first.backups, true);
+        assert!(kept.iter().any(|item| item.status == uninstall::Status::Kept) && backup::find(&first.backups).is_some());
+        assert!(!Manifest::path(&first.backups).exists());
+
+        //Uninstalling the first archive leaves the backups of the other one and the last theme they share alone
+        uninstall::revert_asar(&first.asar(), &first.backups, &first.site.file, &ProgressBar::hidden()).unwrap();
+        uninstall::remove_archive_backups(&first.backups, false);
+        assert!(!first.backups.exists());
+        assert!(backup::find(&second.backups).is_some() && Manifest::path(&second.backups).exists());
+        assert!(lasttheme::LastTheme::path(&shared).exists());
+
+        //The last one takes the shared folder with it
+        uninstall::remove_archive_backups(&second.backups, false);
+        assert!(!shared.exists());
+    }
+
+    #[test]
+    pub fn debug_dump_is_redacted() {
+        let fake = FakeDiscord::create();
+        let mut cfg = fake.config();
+        cfg.customjs = "sendToken('secret');".to_owned();
+        fs::write(&cfg.path, "custom-js = \"https://example.com/a.js?token=secret\"\n").unwrap();
+        let paths = Paths::resolve(&cfg);
+        apply(&paths, &cfg, &Css::from("body { color: red; }"), "dumped".to_owned(), None, false).unwrap();
+
+        let dest = fake.dir("dump.zip");
+        dump::write(&dest, &dump_files(&paths, &cfg).unwrap()).unwrap();
+        let mut zip = zip::ZipArchive::new(fs::File::open(&dest).unwrap()).unwrap();
+        let names = zip.file_names().map(str::to_owned).collect::<Vec<_>>();
+        assert!(names.iter().all(|name| !name.contains('/')) && names.contains(&manifest::MANIFEST_NAME.to_owned()));
+        for name in names.iter() {
+            let mut contents = Vec::new();
+            zip.by_name(name).unwrap().read_to_end(&mut contents).unwrap();
+            assert!(!String::from_utf8_lossy(&contents).contains("secret"), "{}", name);
+        }
+        let mut js = String::new();
+        zip.by_name("mainScreen.js").unwrap().read_to_string(&mut js).unwrap();
+        assert!(js.contains("body { color: red; }") && js.contains(dump::REDACTED));
+    }
+
+    /// Cut the archive that was written short by a byte, which makes reading it back fail
+    fn cut_short(temp: &mut fs::File, _: &Path) -> std::io::Result<()> {
+        temp.set_len(temp.metadata()?.len() - 1)
+    }
+
+    /// Patch a fake Discord with a red theme, then fail to patch it with a blue one because the archive is cut short after it is written
+    fn failed_patch() -> (FakeDiscord, Config, Paths) {
+        let fake = FakeDiscord::create();
+        let mut cfg = fake.config();
+        cfg.make_backup = true;
+        let paths = Paths::resolve(&cfg);
+        apply(&paths, &cfg, &Css::from("body { color: red; }"), "red".to_owned(), None, false).unwrap();
+
+        AFTER_PACK.with(|hook| hook.set(Some(cut_short)));
+        let e = apply(&paths, &cfg, &Css::from("body { color: blue; }"), "blue".to_owned(), None, false).unwrap_err();
+        assert!(e.is::<VerifyFailed>());
+        //The broken archive never replaced Discord's, and the guard is left for the recovery to roll back
+        assert!(fake.main_screen().contains("body { color: red; }"));
+        assert!(guard::interrupted(&paths.backups).is_some());
+        (fake, cfg, paths)
+    }
+
+    /// Check that nothing is left of the failed patch
+    fn assert_rolled_back(paths: &Paths) {
+        assert!(guard::interrupted(&paths.backups).is_none());
+        let temps = fs::read_dir(backup::work_dir(&paths.asar())).unwrap().filter_map(Result::ok);
+        assert!(!temps.into_iter().any(|entry| entry.file_name().to_string_lossy().starts_with(backup::TEMP_PREFIX)));
+    }
+
+    #[test]
+    pub fn failed_rename_is_not_recovered_from() {
+        let fake = FakeDiscord::create();
+        let cfg = fake.config();
+        let paths = Paths::resolve(&cfg);
+        //Something takes the archive's place while it is written, like a folder that it can't be renamed over
+        AFTER_PACK.with(|hook| {
+            hook.set(Some(|_, dest| {
+                fs::remove_file(dest)?;
+                fs::create_dir_all(dest.join("in-use"))
+            }))
+        });
+        let e = apply(&paths, &cfg, &Css::from("body { color: red; }"), "red".to_owned(), None, false).unwrap_err();
+        assert!(!e.is::<VerifyFailed>());
+        assert!(fake.asar().join("in-use").is_dir());
+    }
+
+    #[test]
+    pub fn recover_by_restoring() {
+        let (fake, cfg, paths) = failed_patch();
+        let css = Css::from("body { color: blue; }");
+        for _ in 0..2 {
+            recover_with(Recovery::Restore, &paths, &cfg, &css, "blue", None).unwrap();
+            assert_eq!(fake.main_screen(), MAIN_SCREEN_JS);
+            assert_rolled_back(&paths);
+        }
+    }
+
+    #[test]
+    pub fn recover_by_retrying() {
+        let (fake, cfg, paths) = failed_patch();
+        let css = Css::from("body { color: blue; }");
+
+        //A retry that fails the check too is reported the same way and changes nothing
+        AFTER_PACK.with(|hook| hook.set(Some(cut_short)));
+        assert!(recover_with(Recovery::Retry, &paths, &cfg, &css, "blue", None).unwrap_err().is::<VerifyFailed>());
+        assert!(fake.main_screen().contains("body { color: red; }"));
+
+        for _ in 0..2 {
+            recover_with(Recovery::Retry, &paths, &cfg, &css, "blue", None).unwrap();
+            let patched = fake.main_screen();
+            assert!(patched.contains("body { color: blue; }") && !patched.contains("color: red"));
+            assert_eq!(patched.matches("CSS_INJECTION_USER_CSS").count(), 2);
+            let manifest = Manifest::load(&paths.backups).unwrap().unwrap();
+            assert_eq!(manifest.theme.as_deref(), Some("blue"));
+            assert_eq!(manifest.patched_hash, Some(manifest::hash_file(&fake.asar()).unwrap()));
+            assert_rolled_back(&paths);
+        }
+    }
+
+    #[test]
+    pub fn recover_by_leaving() {
+        let (fake, cfg, paths) = failed_patch();
+        let css = Css::from("body { color: blue; }");
+        let before = manifest::hash_file(&fake.asar()).unwrap();
+        for _ in 0..2 {
+            recover_with(Recovery::Leave, &paths, &cfg, &css, "blue", None).unwrap();
+            assert_eq!(manifest::hash_file(&fake.asar()).unwrap(), before);
+            assert!(paths.backups.join(RECOVERY_DUMP_NAME).is_file());
+            assert_rolled_back(&paths);
+        }
+        assert_eq!(Manifest::load(&paths.backups).unwrap().unwrap().theme.as_deref(), Some("red"));
+    }
+
+    #[test]
+    pub fn emitted_injection_matches() {
+        let fake = FakeDiscord::create();
+        let mut cfg = fake.config();
+        cfg.customjs = escape::template_literal("console.log(`emitted`);");
+        let paths = Paths::resolve(&cfg);
+        let css = Css::from("a { content: \"${x}\"; }");
+        let dest = fake.dir("injection.js");
+        emit_injection(&cfg, &css, &dest, true);
+        apply(&paths, &cfg, &css, "emitted".to_owned(), None, false).unwrap();
+
+        //The emitted file is exactly the text that took the place of the injection point
+        let emitted = fs::read_to_string(&dest).unwrap();
+        let patched = fake.main_screen();
+        assert_eq!(patched.replacen(&emitted, site::INJECTION_POINT, 1), MAIN_SCREEN_JS);
+    }
+
+    #[test]
+    pub fn hostile_theme() {
+        let fake = FakeDiscord::create();
+        let mut cfg = fake.config();
+        cfg.customjs = escape::template_literal("console.log(`${1 + 1}`);");
+        let paths = Paths::resolve(&cfg);
+        let css = "a { content: \"${require('child_process').exec('calc')}\"; } b::after { content: '`); process.exit(); (`' }";
+        apply(&paths, &cfg, &Css::from(css), "hostile".to_owned(), None, false).unwrap();
+
+        //Nothing in the template literal that Discord's main process runs can end it or substitute code into it
+        let patched = fake.main_screen();
+        let start = patched.find("executeJavaScript(`").unwrap() + "executeJavaScript(`".len();
+        let end = start + patched[start..].find("`);\n    });").unwrap();
+        assert!(!testutil::unescaped(&patched[start..end], "${") && !testutil::unescaped(&patched[start..end], "`"));
+        let renderer = escape::cook(&patched[start..end]);
+        assert!(renderer.contains(&format!("let CSS_INJECTION_USER_CSS = {};", serde_json::Value::from(css))));
+        assert!(renderer.contains("console.log(`${1 + 1}`);"));
+        assert_eq!(patch::strip_injection(&patched).unwrap(), MAIN_SCREEN_JS);
+    }
+
+    #[test]
+    pub fn no_js_injection() {
+        let fake = FakeDiscord::create();
+        let mut cfg = fake.config();
+        cfg.customjs = "console.log('themed');".to_owned();
+        cfg.inject_js = false;
+        let paths = Paths::resolve(&cfg);
+        apply(&paths, &cfg, &Css::from("body { color: red; }"), "css only".to_owned(), None, false).unwrap();
+        let patched = fake.main_screen();
+        assert!(patched.contains("body { color: red; }") && !patched.contains("JS_SCRIPT") && !patched.contains("themed"));
+
+        //Re-applying handles an injection without a javascript block, in both directions
+        cfg.inject_js = true;
+        apply(&paths, &cfg, &Css::from("body { color: blue; }"), "with js".to_owned(), None, false).unwrap();
+        assert_eq!(patch::injected_js(&fake.main_screen()), Some("console.log('themed');"));
+        cfg.inject_js = false;
+        apply(&paths, &cfg, &Css::from("body { color: green; }"), "css only".to_owned(), None, false).unwrap();
+        let patched = fake.main_screen();
+        assert_eq!(patched.matches("CSS_INJECTION_USER_CSS").count(), 2);
+        assert!(!patched.contains("JS_SCRIPT") && !patched.contains("color: blue"));
+        assert_eq!(patch::strip_injection(&patched).unwrap(), MAIN_SCREEN_JS);
+    }
+
+    #[test]
+    pub fn repair_damaged_injection() {
+        let fake = FakeDiscord::create();
+        let mut cfg = fake.config();
+        cfg.customjs = "console.log('themed');".to_owned();
+        let paths = Paths::resolve(&cfg);
+        apply(&paths, &cfg, &Css::from("body { color: blue; }"), "clean".to_owned(), None, false).unwrap();
+        let clean = fake.main_screen();
+
+        for damaged in ["unclosed.js", "missing-script-end.js", "script-end-before-begin.js", "duplicate.js", "duplicate-apart.js", "triplicate.js"] {
+            let fixture = fs::read_to_string(Path::new("tests/fixtures/damaged").join(damaged)).unwrap();
+            fs::write(fake.asar(), testutil::archive(&fixture)).unwrap();
+            apply(&paths, &cfg, &Css::from("body { color: blue; }"), "repaired".to_owned(), None, false).unwrap();
+            assert_eq!(fake.main_screen(), clean, "{}", damaged);
+            assert_eq!(patch::count(&clean), 1, "{}", damaged);
+        }
+    }
+
+    #[test]
+    pub fn upgrade_old_injection() {
+        let fake = FakeDiscord::create();
+        let cfg = fake.config();
+        let paths = Paths::resolve(&cfg);
+        //A patched archive can't be backed up, so the backup is the one made before it was patched
+        fs::copy(fake.asar(), paths.backups.join(backup::backup_name(false))).unwrap();
+        //Injections made before the theme was a string literal put it in a String.raw template literal
+        let old = patch::injection("String.raw \\`body { color: red; }\\`", Some(""));
+        fs::write(fake.asar(), testutil::archive(&MAIN_SCREEN_JS.replacen(site::INJECTION_POINT, &old, 1))).unwrap();
+
+        apply(&paths, &cfg, &Css::from("body { color: blue; }"), "new".to_owned(), None, false).unwrap();
+        let patched = fake.main_screen();
+        assert_eq!(patched.matches("CSS_INJECTION_USER_CSS").count(), 2);
+        assert!(patched.contains("let CSS_INJECTION_USER_CSS = \"body { color: blue; }\";"));
+        assert!(!patched.contains("String.raw") && !patched.contains("color: red"));
+    }
+
+    #[test]
+    pub fn skip_identical_theme() {
+        let fake = FakeDiscord::create();
+        let cfg = fake.config();
+        let paths = Paths::resolve(&cfg);
+        apply(&paths, &cfg, &Css::from("body { color: red; }"), "red".to_owned(), None, false).unwrap();
+        let patched = fs::read(fake.asar()).unwrap();
+
+        //The same theme isn't written again, but the manifest still records the name it was applied with
+        fs::write(fake.asar(), &patched[..]).unwrap();
+        let written = fs::metadata(fake.asar()).unwrap().modified().unwrap();
+        apply(&paths, &cfg, &Css::from("body { color: red; }"), "red again".to_owned(), None, false).unwrap();
+        assert_eq!(fs::metadata(fake.asar()).unwrap().modified().unwrap(), written);
+        assert_eq!(Manifest::load(&paths.backups).unwrap().unwrap().theme.as_deref(), Some("red again"));
+
+        //Forcing it writes the archive anyway, with the same contents
+        apply(&paths, &cfg, &Css::from("body { color: red; }"), "forced".to_owned(), None, true).unwrap();
+        assert_eq!(fake.main_screen().matches("CSS_INJECTION_USER_CSS").count(), 2);
+        assert!(fs::metadata(fake.asar()).unwrap().modified().unwrap() >= written);
+    }
+
+    #[test]
+    pub fn backup_and_restore() {
+        let fake = FakeDiscord::create();
+        let cfg = fake.config();
+        let paths = Paths::resolve(&cfg);
+        let original = fs::read(fake.asar()).unwrap();
+
+        apply(&paths, &cfg, &Css::from("body { color: red; }"), "theme".to_owned(), None, false).unwrap();
+        let backup = backup::find(&paths.backups).unwrap();
+        let mut backed_up = Vec::new();
+        backup::open(&backup).unwrap().read_to_end(&mut backed_up).unwrap();
+        assert_eq!(backed_up, original);
+        assert_eq!(fs::read(backup::icon_backup_path(&paths.backups)).unwrap(), testutil::ICON);
+
+        //Patching again keeps the pristine backup instead of backing up the patched archive
+        apply(&paths, &cfg, &Css::from("body { color: blue; }"), "theme".to_owned(), None, false).unwrap();
+        assert_eq!(backup::plan(&paths.backups), backup::Plan::Existing(backup.clone()));
+
+        restore_archive(&backup, &fake.asar(), MAIN_SCREEN);
+        assert_eq!(fs::read(fake.asar()).unwrap(), original);
+        assert_eq!(fake.main_screen(), MAIN_SCREEN_JS);
+    }
+
+    #[test]
+    pub fn file_backup() {
+        let fake = FakeDiscord::create();
+        let cfg = Config {
+            backup_mode: BackupMode::File,
+            compress_backups: true,
+            ..fake.config()
+        };
+        let paths = Paths::resolve(&cfg);
+        apply(&paths, &cfg, &Css::from("body { color: red; }"), "theme".to_owned(), None, false).unwrap();
+        assert!(backup::find(&paths.backups).is_none());
+
+        let backup = backup::find_file(&paths.backups).unwrap();
+        backup::restore_file(&backup, &fake.asar(), MAIN_SCREEN).unwrap();
+        assert_eq!(fake.main_screen(), MAIN_SCREEN_JS);
+    }
+
+    #[test]
+    pub fn apply_app_asar() {
+        let fake = FakeDiscord::create();
+        let shim = testutil::archive("module.exports = require('./app');");
+        fs::write(fake.asar(), &shim).unwrap();
+        let app_asar = testutil::app_asar(&fake);
+        let original = fs::read(&app_asar).unwrap();
+
+        let cfg = fake.config();
+        let paths = Paths::resolve(&cfg);
+        assert_eq!(paths.asar(), app_asar);
+        apply(&paths, &cfg, &Css::from("body { color: red; }"), "theme".to_owned(), None, false).unwrap();
+        assert_eq!(fs::read(fake.asar()).unwrap(), shim);
+        assert!(testutil::read_file(&app_asar, testutil::APP_WINDOW).contains("color: red"));
+
+        //The manifest decides which archive is restored, even though both now contain the injection point
+        let paths = Paths::resolve(&cfg);
+        assert_eq!(Manifest::load(&paths.backups).unwrap().unwrap().site, Some(paths.site.clone()));
+        restore_archive(&backup::find(&paths.backups).unwrap(), &paths.asar(), &paths.site.file);
+        assert_eq!(fs::read(&app_asar).unwrap(), original);
+    }
+
+    #[test]
+    pub fn apply_portable() {
+        for (layout, site) in [
+            (testutil::PORTABLE_MODULES, site::Site::default()),
+            (testutil::PORTABLE_RESOURCES, site::Site { archive: site::Kind::App, file: testutil::APP_WINDOW.to_owned() }),
+        ] {
+            let fake = FakeDiscord::portable(layout);
+            let cfg = fake.config();
+            let paths = Paths::resolve(&cfg);
+            assert_eq!((paths.layout, &paths.site), (site::Layout::Portable, &site));
+            let original = fs::read(paths.asar()).unwrap();
+            apply(&paths, &cfg, &Css::from("body { color: red; }"), "theme".to_owned(), None, false).unwrap();
+            assert!(testutil::read_file(&paths.asar(), &site.file).contains("color: red"));
+
+            //The manifest records the layout so that the archive it names is the one that is restored
+            let paths = Paths::resolve(&cfg);
+            let manifest = Manifest::load(&paths.backups).unwrap().unwrap();
+            assert_eq!((manifest.layout, manifest.site), (site::Layout::Portable, Some(site)));
+            restore_archive(&backup::find(&paths.backups).unwrap(), &paths.asar(), &paths.site.file);
+            assert_eq!(fs::read(paths.asar()).unwrap(), original);
+        }
+    }
+
+    #[test]
+    pub fn reproduce_from_manifest() {
+        let fake = FakeDiscord::create();
+        let mut cfg = fake.config();
+        let paths = Paths::resolve(&cfg);
+        cfg.customjs = "first();".to_owned();
+        cfg.window_title = Some("Discord".to_owned());
+        cfg.inject_strategy = patch::Strategy::InsertCss;
+        apply(&paths, &cfg, &Css::from("a { color: red; }"), "first.css".to_owned(), None, false).unwrap();
+        let first = fake.main_screen();
+        cfg.customjs = "second();".to_owned();
+        cfg.window_title = None;
+        cfg.inject_strategy = patch::Strategy::ExecuteJs;
+        apply(&paths, &cfg, &Css::from("a { color: blue; }"), "second.css".to_owned(), None, false).unwrap();
+        restore_archive(&backup::find(&paths.backups).unwrap(), &paths.asar(), &paths.site.file);
+
+        //The state from two applies ago comes back byte for byte, even though the config file has no javascript
+        let mut cfg = fake.config();
+        let (name, css) = from_manifest(&mut cfg, &paths, 2);
+        assert_eq!((name.as_str(), cfg.customjs.as_str()), ("first.css", "first();"));
+        assert_eq!((cfg.window_title.as_deref(), cfg.inject_strategy), (Some("Discord"), patch::Strategy::InsertCss));
+        apply(&paths, &cfg, &css, name, None, false).unwrap();
+        assert_eq!(fake.main_screen(), first);
+        let history = Manifest::load(&paths.backups).unwrap().unwrap().history;
+        let names = history.iter().map(|applied| applied.payload.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["first.css", "second.css", "first.css"]);
+        assert_eq!(history[0], manifest::Applied { timestamp: history[0].timestamp.clone(), ..history[2].clone() });
+    }
+
+    #[test]
+    pub fn locate_is_read_only() {
+        let fake = FakeDiscord::create();
+        let cfg = fake.config();
+        let count = || fs::read_dir(fake.dir("")).unwrap().count();
+        let before = count();
+
+        //Printing the paths finds the same site as patching, without creating the backup folder or the config
+        let located = Paths::locate(&cfg);
+        assert_eq!((located.asar(), located.version.clone()), (fake.asar(), fake.core().ancestors().nth(3).unwrap().to_owned()));
+        assert!(!located.backups.exists() && !cfg.path.exists());
+        assert_eq!(count(), before);
+
+        let resolved = Paths::resolve(&cfg);
+        assert_eq!((resolved.backups.clone(), resolved.site), (located.backups, located.site));
+        assert!(resolved.backups.is_dir());
+    }
+
+    #[test]
+    pub fn theme_size() {
+        let image = format!("url(data:image/png;base64,{})", "A".repeat(100));
+        let css = format!("a {{ background: {}; }} b {{ src: url('data:font/woff2;base64,AAAA') }}", image);
+        assert_eq!(data_url_bytes(&css), image.len() - "url()".len() + "data:font/woff2;base64,AAAA".len());
+        assert_eq!(data_url_bytes("a { color: red; }"), 0);
+
+        let cfg = Config {
+            theme_size_warn_kib: 0,
+            theme_size_limit_kib: 1,
+            ..Config::default()
+        };
+        check_theme_size(&cfg, &Css::from(css.as_str()), false);
+        check_theme_size(&cfg, &Css::from("a".repeat(2048).as_str()), true);
+        assert!(std::panic::catch_unwind(|| check_theme_size(&cfg, &Css::from("a".repeat(2048).as_str()), false)).is_err());
+
+        //Both stylesheets of a light and dark theme count towards the limit
+        let half = "a".repeat(600);
+        check_theme_size(&cfg, &Css::from(half.as_str()), false);
+        let both = Css::Scheduled { light: half.clone(), dark: half, schedule: Schedule::System };
+        assert!(std::panic::catch_unwind(|| check_theme_size(&cfg, &both, false)).is_err());
+    }
+}
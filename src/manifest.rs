@@ -0,0 +1,513 @@
+//! The `manifest` module records what this program changed in a Discord installation using the [Manifest] struct,
+//! which is saved as a JSON file next to the backup so that later runs can tell if Discord was updated or if a
+//! backup belongs to a different Discord version
+
+use std::{
+    fmt, fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tempfile::NamedTempFile;
+
+use crate::{
+    lasttheme::LastTheme,
+    patch::Strategy,
+    site::{Layout, Site},
+    symlink::Link,
+};
+
+/// The file name of the manifest that is written next to the `core.asar` backup
+pub const MANIFEST_NAME: &str = "discord-theme.manifest.json";
+
+/// The `Manifest` struct holds information about the last time that Discord was patched, like hashes of the
+/// original and patched archive files
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Manifest {
+    /// The name of the Discord version folder that was patched, like `app-1.0.9001`
+    pub discord_version: String,
+
+    /// The archive and the file in it that were patched, `None` for manifests written before `app.asar` could be patched, which always
+    /// patched `mainScreen.js` in `core.asar`
+    pub site: Option<Site>,
+
+    /// How the patched Discord installation's folders are arranged, which is [Layout::Versioned] for manifests written before portable
+    /// builds could be patched
+    #[serde(default)]
+    pub layout: Layout,
+
+    /// The SHA-256 hash of the original, unpatched archive file
+    pub original_hash: String,
+
+    /// The SHA-256 hash of the archive file after it was patched, `None` if it was never patched
+    pub patched_hash: Option<String>,
+
+    /// The SHA-256 hash of Discord's icon file before it was replaced
+    pub icon_hash: Option<String>,
+
+    /// The SHA-256 hash of the icon that we replaced Discord's icon with
+    pub replaced_icon_hash: Option<String>,
+
+    /// The icon files other than the main icon that were replaced, relative to Discord's root folder
+    #[serde(default)]
+    pub icons: Vec<String>,
+
+    /// How the Linux launcher entry's icon was changed, if it was
+    pub desktop_icon: Option<DesktopIcon>,
+
+    /// The symbolic link that the patched archive was reached through and where the archive really was, if it was a link
+    pub symlink: Option<Link>,
+
+    /// The configuration options that were used when patching
+    #[serde(default)]
+    pub config: Value,
+
+    /// The name of the theme that was applied, like the path of a theme file or the name of a theme from the catalog
+    pub theme: Option<String>,
+
+    /// When the manifest was last written, in RFC 3339 format
+    pub timestamp: String,
+
+    /// The last applies with exactly what they inserted, newest first, so that an earlier one can be applied again. It is kept when
+    /// Discord updates and is as long as the `manifest-history` option at most
+    #[serde(default)]
+    pub history: Vec<Applied>,
+}
+
+/// The `Applied` struct is one apply in the manifest's [history](Manifest::history), with the hashes of what it inserted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Applied {
+    /// When the theme was applied, in RFC 3339 format
+    pub timestamp: String,
+    /// The SHA-256 hash of the CSS theme that was inserted, followed by the dark theme if there was one
+    pub css_hash: String,
+    /// The SHA-256 hash of the custom javascript that was inserted, followed by the preload javascript if there was any, or `None` if
+    /// the block that runs it was left out
+    pub js_hash: Option<String>,
+    /// The CSS and javascript that were inserted
+    pub payload: LastTheme,
+}
+
+impl Applied {
+    /// Record an apply that inserted `payload` now
+    pub fn new(payload: LastTheme) -> Self {
+        let (css_hash, js_hash) = payload_hashes(&payload);
+        Self { timestamp: now(), css_hash, js_hash, payload }
+    }
+
+    /// Check that the payload still has the hashes that were recorded with it, which it doesn't if the manifest was edited by hand
+    pub fn verify(&self) -> Result<(), Error> {
+        match payload_hashes(&self.payload) == (self.css_hash.clone(), self.js_hash.clone()) {
+            true => Ok(()),
+            false => Err(Error::InvalidFormat(format!(
+                "The theme applied at {} doesn't match the hashes that were recorded for it",
+                self.timestamp
+            ))),
+        }
+    }
+}
+
+/// Get the hashes of the CSS and javascript in a payload, see [Applied]. The parts are separated by a zero byte so that moving text
+/// from one to the other changes the hash. The strategy and window title are hashed with the CSS when they aren't the defaults, so
+/// that payloads recorded before they were remembered keep their hashes
+fn payload_hashes(payload: &LastTheme) -> (String, Option<String>) {
+    let joined = |first: &str, second: Option<&str>| match second {
+        Some(second) => format!("{}\0{}", first, second),
+        None => first.to_owned(),
+    };
+    let mut css = joined(&payload.css, payload.dark.as_deref());
+    if payload.strategy != Strategy::default() || payload.title.is_some() {
+        css = format!("{}\0{}\0{}", css, payload.strategy.name(), payload.title.as_deref().unwrap_or_default());
+    }
+    (
+        hash_bytes(css.as_bytes()),
+        payload.js.as_deref().map(|js| hash_bytes(joined(js, payload.preload.as_deref()).as_bytes())),
+    )
+}
+
+/// The `DesktopIcon` enum is how the icon of Discord's Linux launcher entry was changed, so that restoring can undo it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "strategy", rename_all = "kebab-case")]
+pub enum DesktopIcon {
+    /// The icon file that the launcher entry points to was backed up and replaced
+    Replaced {
+        /// The icon file that was replaced
+        icon: PathBuf,
+    },
+
+    /// A launcher entry for only this user was written that points at our icon, overriding the system wide entry
+    Override {
+        /// The launcher entry that was written
+        desktop: PathBuf,
+    },
+}
+
+/// The `AsarState` enum is what [Manifest::asar_state] decides the current `core.asar` file is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsarState {
+    /// The archive is the same as the one that was backed up
+    Original,
+
+    /// The archive is the same one that we wrote when patching
+    Patched,
+
+    /// The archive matches neither hash, so Discord was probably updated underneath us
+    Updated,
+}
+
+/// The `IconState` enum is what [Manifest::icon_state] decides Discord's current icon is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IconState {
+    /// The icon is Discord's original icon
+    Stock,
+
+    /// The icon is the one that we replaced it with
+    Replaced,
+
+    /// The icon is neither, for example because Discord updated and put a new version of its own icon back
+    Other,
+}
+
+/// The `RestoreCheck` enum is returned from [Manifest::check_restore] to decide if restoring a backup is safe
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestoreCheck {
+    /// The backup was made from the currently installed Discord version
+    Matching,
+
+    /// The backup was made from a different Discord version than the one installed
+    DifferentVersion {
+        /// The version folder that the backup was made from
+        backup: String,
+        /// The version folder that is currently installed
+        current: String,
+    },
+}
+
+impl Manifest {
+    /// Create a new manifest for the given Discord version folder and original archive hash with no patch recorded yet
+    pub fn new(discord_version: impl Into<String>, original_hash: impl Into<String>) -> Self {
+        Self {
+            discord_version: discord_version.into(),
+            site: None,
+            layout: Layout::default(),
+            original_hash: original_hash.into(),
+            patched_hash: None,
+            icon_hash: None,
+            replaced_icon_hash: None,
+            icons: Vec::new(),
+            desktop_icon: None,
+            symlink: None,
+            config: Value::Null,
+            theme: None,
+            timestamp: now(),
+            history: Vec::new(),
+        }
+    }
+
+    /// Remember an apply at the start of the history, forgetting the oldest ones so that at most `limit` are kept
+    pub fn record(&mut self, applied: Applied, limit: usize) {
+        self.history.insert(0, applied);
+        self.history.truncate(limit);
+    }
+
+    /// Get the apply from `ago` applies ago out of the history, where 1 is the last one
+    pub fn applied(&self, ago: usize) -> Option<&Applied> {
+        self.history.get(ago.checked_sub(1)?)
+    }
+
+    /// Get the path to the manifest file in the given directory
+    #[inline]
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join(MANIFEST_NAME)
+    }
+
+    /// Load the manifest from the given directory, returning `None` if there is no manifest file there
+    pub fn load(dir: &Path) -> Result<Option<Self>, Error> {
+        match fs::read(Self::path(dir)) {
+            Ok(buf) => Ok(Some(Self::from_slice(&buf)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write this manifest to the manifest file in the given directory, updating the timestamp. The old file is only replaced once the
+    /// new one is written completely
+    pub fn save(&mut self, dir: &Path) -> Result<(), Error> {
+        self.timestamp = now();
+        let mut temp = NamedTempFile::new_in(dir)?;
+        temp.write_all(&serde_json::to_vec_pretty(self)?)?;
+        temp.persist(Self::path(dir)).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    /// Parse a manifest from the contents of a manifest file, telling JSON that can't be parsed apart from JSON with a field that is
+    /// missing or the wrong type
+    pub fn from_slice(buf: &[u8]) -> Result<Self, Error> {
+        serde_json::from_slice(buf).map_err(|e| match e.classify() {
+            serde_json::error::Category::Data => Error::InvalidFormat(e.to_string()),
+            _ => Error::InvalidJson(e),
+        })
+    }
+
+    /// Decide what state the `core.asar` file with the given hash is in
+    pub fn asar_state(&self, current_hash: &str) -> AsarState {
+        if self.patched_hash.as_deref() == Some(current_hash) {
+            AsarState::Patched
+        } else if self.original_hash == current_hash {
+            AsarState::Original
+        } else {
+            AsarState::Updated
+        }
+    }
+
+    /// Decide what state Discord's icon file with the given hash is in
+    pub fn icon_state(&self, current_hash: &str) -> IconState {
+        if self.replaced_icon_hash.as_deref() == Some(current_hash) {
+            IconState::Replaced
+        } else if self.icon_hash.as_deref() == Some(current_hash) {
+            IconState::Stock
+        } else {
+            IconState::Other
+        }
+    }
+
+    /// Check if the backup that this manifest describes can be restored to the given Discord version folder
+    pub fn check_restore(&self, current_version: &str) -> RestoreCheck {
+        match self.discord_version == current_version {
+            true => RestoreCheck::Matching,
+            false => RestoreCheck::DifferentVersion {
+                backup: self.discord_version.clone(),
+                current: current_version.to_owned(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for AsarState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Original => write!(f, "unpatched"),
+            Self::Patched => write!(f, "patched"),
+            Self::Updated => write!(f, "changed since last patch (Discord was probably updated)"),
+        }
+    }
+}
+
+impl fmt::Display for IconState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stock => write!(f, "Discord's original icon"),
+            Self::Replaced => write!(f, "replaced"),
+            Self::Other => write!(f, "an unknown icon (Discord was probably updated)"),
+        }
+    }
+}
+
+/// Get the current time as an RFC 3339 string
+fn now() -> String {
+    humantime::format_rfc3339_seconds(SystemTime::now()).to_string()
+}
+
+/// Check if a string is a hex SHA-256 hash in either case
+pub fn is_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Get the lowercase hex SHA-256 hash of a byte slice
+pub fn hash_bytes(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Get the lowercase hex SHA-256 hash of a file's contents without reading the whole file into memory
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    hash_reader(fs::File::open(path)?)
+}
+
+/// Get the lowercase hex SHA-256 hash of everything that can be read from a reader
+pub fn hash_reader(mut read: impl Read) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        match read.read(&mut buf)? {
+            0 => break,
+            n => hasher.update(&buf[..n]),
+        }
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// The `Error` enum represents all errors that can happen when reading or writing a manifest file
+#[derive(Debug)]
+pub enum Error {
+    /// The manifest file is not valid JSON
+    InvalidJson(serde_json::Error),
+
+    /// The JSON is valid, but a field is missing or the wrong type
+    InvalidFormat(String),
+
+    /// Read or write error
+    IOErr(io::Error),
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Self::InvalidJson(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::IOErr(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IOErr(err) => write!(f, "IO Error: {}", err),
+            Self::InvalidJson(err) => write!(f, "Invalid manifest JSON: {}", err),
+            Self::InvalidFormat(err) => write!(f, "Invalid manifest format: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::Css;
+    use serde_json::json;
+
+    /// Write a manifest as JSON and parse it again like [Manifest::save] and [Manifest::load]
+    fn reparse(val: &Value) -> Result<Manifest, Error> {
+        Manifest::from_slice(&serde_json::to_vec(val).unwrap())
+    }
+
+    fn manifest() -> Manifest {
+        let mut manifest = Manifest::new("app-1.0.9001", "aaaa");
+        manifest.site = Some(Site::default());
+        manifest.patched_hash = Some("bbbb".to_owned());
+        manifest.icon_hash = Some("cccc".to_owned());
+        manifest.replaced_icon_hash = Some("eeee".to_owned());
+        manifest.icons = vec!["app-1.0.9001/app.png".to_owned()];
+        manifest.desktop_icon = Some(DesktopIcon::Override {
+            desktop: PathBuf::from("/home/user/.local/share/applications/discord.desktop"),
+        });
+        manifest.symlink = Some(Link {
+            path: PathBuf::from("/opt/discord/resources/app.asar"),
+            link: PathBuf::from("/opt/discord/resources"),
+            target: PathBuf::from("/usr/lib/discord/resources/app.asar"),
+            outside: true,
+        });
+        manifest.config = json!({"make-backup": true});
+        manifest.theme = Some("Old Discord".to_owned());
+        manifest.record(Applied::new(LastTheme::new("Old Discord", &Css::from("a {}"), Some("js();"), None)), 5);
+        manifest
+    }
+
+    #[test]
+    pub fn json_roundtrip() {
+        let manifest = manifest();
+        let val = serde_json::to_value(&manifest).unwrap();
+        assert_eq!(reparse(&val).unwrap(), manifest);
+        assert_eq!(val["desktop-icon"]["strategy"], "override");
+        assert_eq!((&val["site"]["archive"], &val["layout"]), (&json!("core"), &json!("versioned")));
+
+        let mut unpatched = Manifest::new("app-1.0.9001", "aaaa");
+        unpatched.timestamp = manifest.timestamp.clone();
+        assert_eq!(reparse(&serde_json::to_value(&unpatched).unwrap()).unwrap(), unpatched);
+
+        //Manifests written before the layout, icons, and history were recorded still load
+        let old = json!({"discord-version": "app-1.0.9001", "original-hash": "aaaa", "timestamp": manifest.timestamp});
+        assert_eq!(reparse(&old).unwrap(), unpatched);
+    }
+
+    #[test]
+    pub fn invalid_json() {
+        let mut val = serde_json::to_value(manifest()).unwrap();
+        val["original-hash"] = json!(5);
+        assert!(matches!(reparse(&val), Err(Error::InvalidFormat(_))));
+        val.as_object_mut().unwrap().remove("original-hash");
+        assert!(matches!(reparse(&val), Err(Error::InvalidFormat(_))));
+        let mut val = serde_json::to_value(manifest()).unwrap();
+        val["desktop-icon"] = json!({"strategy": "moved"});
+        assert!(matches!(reparse(&val), Err(Error::InvalidFormat(_))));
+        assert!(matches!(Manifest::from_slice(b"{"), Err(Error::InvalidJson(_))));
+    }
+
+    #[test]
+    pub fn bounded_history() {
+        let mut manifest = manifest();
+        for name in ["b", "c", "d"] {
+            manifest.record(Applied::new(LastTheme::new(name, &Css::from(name), None, None)), 3);
+        }
+        let names = manifest.history.iter().map(|applied| applied.payload.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["d", "c", "b"]);
+        assert_eq!(manifest.applied(2).map(|applied| applied.payload.css.as_str()), Some("c"));
+        assert_eq!((manifest.applied(0), manifest.applied(4)), (None, None));
+        manifest.record(Applied::new(LastTheme::new("e", &Css::from("e"), None, None)), 0);
+        assert!(manifest.history.is_empty());
+
+        //A payload that was changed after it was recorded is refused
+        let mut applied = Applied::new(LastTheme::new("a", &Css::from("a {}"), Some("js();"), Some("early();")));
+        assert!(applied.verify().is_ok());
+        applied.payload.js = Some("evil();".to_owned());
+        assert!(matches!(applied.verify(), Err(Error::InvalidFormat(_))));
+        let mut titled = Applied::new(LastTheme { title: Some("Discord".into()), ..LastTheme::new("a", &Css::from("a {}"), None, None) });
+        assert!(titled.verify().is_ok());
+        assert_ne!(titled.css_hash, Applied::new(LastTheme::new("a", &Css::from("a {}"), None, None)).css_hash);
+        titled.payload.strategy = Strategy::InsertCss;
+        assert!(matches!(titled.verify(), Err(Error::InvalidFormat(_))));
+        manifest.record(Applied::new(LastTheme::new("f", &Css::from("f"), None, None)), 1);
+        let mut val = serde_json::to_value(&manifest).unwrap();
+        val["history"][0]["css-hash"] = json!(5);
+        assert!(matches!(reparse(&val), Err(Error::InvalidFormat(_))));
+    }
+
+    #[test]
+    pub fn asar_state() {
+        let manifest = manifest();
+        assert_eq!(manifest.asar_state("aaaa"), AsarState::Original);
+        assert_eq!(manifest.asar_state("bbbb"), AsarState::Patched);
+        assert_eq!(manifest.asar_state("dddd"), AsarState::Updated);
+        assert_eq!(
+            Manifest::new("app-1.0.9001", "aaaa").asar_state("bbbb"),
+            AsarState::Updated
+        );
+    }
+
+    #[test]
+    pub fn icon_state() {
+        let manifest = manifest();
+        assert_eq!(manifest.icon_state("cccc"), IconState::Stock);
+        assert_eq!(manifest.icon_state("eeee"), IconState::Replaced);
+        assert_eq!(manifest.icon_state("ffff"), IconState::Other);
+    }
+
+    #[test]
+    pub fn check_restore() {
+        let manifest = manifest();
+        assert_eq!(manifest.check_restore("app-1.0.9001"), RestoreCheck::Matching);
+        assert_eq!(
+            manifest.check_restore("app-1.0.9002"),
+            RestoreCheck::DifferentVersion {
+                backup: "app-1.0.9001".to_owned(),
+                current: "app-1.0.9002".to_owned()
+            }
+        );
+    }
+
+    #[test]
+    pub fn hashing() {
+        assert_eq!(hash_bytes(b"abc"), hash_bytes(b"abc"));
+        assert_ne!(hash_bytes(b"abc"), hash_bytes(b"abd"));
+        assert_eq!(hash_bytes(b"abc").len(), 64);
+    }
+}
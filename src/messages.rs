@@ -0,0 +1,219 @@
+//! The `messages` module holds every message that is shown to the user, keyed by an identifier. English messages are compiled in,
+//! and other languages are read from a JSON file with the same keys. Messages that a translation is missing are shown in English.
+//! Use the [msg] macro to get a message with its `{}` placeholders filled in
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+/// The English messages, which every other language falls back to
+const ENGLISH: &str = include_str!("../assets/messages/en.json");
+
+/// The name of the folder next to the config file that language files are looked up in
+pub const LANGUAGE_DIR: &str = "languages";
+
+/// The parsed [ENGLISH] messages
+static ENGLISH_CATALOG: OnceLock<Catalog> = OnceLock::new();
+
+/// The messages of the language that was loaded by [init], if it isn't English
+static TRANSLATION: OnceLock<Catalog> = OnceLock::new();
+
+/// Get a message, formatting any arguments into its placeholders, like `msg!("backup-file", path.display())`
+macro_rules! msg {
+    ($key:literal) => {
+        $crate::messages::get($key)
+    };
+    ($key:literal, $($arg:expr),+ $(,)?) => {
+        $crate::messages::format($key, &[$(&$arg as &dyn std::fmt::Display),+])
+    };
+}
+pub(crate) use msg;
+
+/// The `Catalog` struct is every message of one language
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Catalog {
+    messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    /// Parse a catalog from a JSON object of keys to messages
+    pub fn parse(text: &str) -> Result<Self, serde_json::Error> {
+        Ok(Self {
+            messages: serde_json::from_str(text)?,
+        })
+    }
+
+    /// Get the message with the given key, if this catalog has it
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.messages.get(key).map(String::as_str)
+    }
+}
+
+/// The `Error` enum is every error that can happen when loading a language file
+#[derive(Debug)]
+pub enum Error {
+    /// There is no language file for the configured language
+    Missing { language: String, dir: PathBuf },
+    /// The language file couldn't be read
+    IOErr { path: PathBuf, err: io::Error },
+    /// The language file isn't a JSON object of strings
+    Json { path: PathBuf, err: serde_json::Error },
+}
+
+/// Load the messages of `language`, or of the language in the `LANG` environment variable if it is `None`. `language` is either
+/// the path of a JSON file or a language code like `de` that is looked up in the [LANGUAGE_DIR] folder in `dir`, returning the path
+/// of the file that was loaded. Languages from `LANG` that have no file are shown in English without an error
+pub fn init(language: Option<&str>, dir: &Path) -> Result<Option<PathBuf>, Error> {
+    let configured = language.is_some();
+    let language = match language.map(str::to_owned).or_else(|| std::env::var("LANG").ok()) {
+        Some(language) => language,
+        None => return Ok(None),
+    };
+
+    let path = match language.ends_with(".json") {
+        true => Some(dir.join(&language)),
+        false => candidates(&language)
+            .into_iter()
+            .map(|code| dir.join(LANGUAGE_DIR).join(format!("{}.json", code)))
+            .find(|path| path.exists()),
+    };
+    let path = match path {
+        Some(path) => path,
+        None if configured && !is_english(&language) => {
+            return Err(Error::Missing {
+                language,
+                dir: dir.join(LANGUAGE_DIR),
+            })
+        }
+        None => return Ok(None),
+    };
+
+    let text = fs::read_to_string(&path).map_err(|err| Error::IOErr { path: path.clone(), err })?;
+    let catalog = Catalog::parse(&text).map_err(|err| Error::Json { path: path.clone(), err })?;
+    let _ = TRANSLATION.set(catalog);
+    Ok(Some(path))
+}
+
+/// Get the language codes to look for from a locale like `de_DE.UTF-8`, most specific first
+fn candidates(language: &str) -> Vec<String> {
+    let locale = language.split(['.', '@']).next().unwrap_or_default();
+    let mut codes = vec![locale.to_owned()];
+    if let Some((code, _)) = locale.split_once(['_', '-']) {
+        codes.push(code.to_owned());
+    }
+    codes.retain(|code| !code.is_empty());
+    codes
+}
+
+/// Check if a language code means that messages are shown in English
+fn is_english(language: &str) -> bool {
+    candidates(language).iter().any(|code| code == "en" || code == "C" || code == "POSIX")
+}
+
+/// Get the compiled in English messages
+fn english() -> &'static Catalog {
+    ENGLISH_CATALOG.get_or_init(|| Catalog::parse(ENGLISH).expect("The compiled in English messages are not valid JSON"))
+}
+
+/// Get the message with the given key from a translation, falling back to English and then to the key itself
+fn lookup<'a>(translation: Option<&'a Catalog>, key: &'a str) -> &'a str {
+    translation
+        .and_then(|catalog| catalog.get(key))
+        .or_else(|| english().get(key))
+        .unwrap_or(key)
+}
+
+/// Get the message with the given key in the loaded language
+pub fn get(key: &str) -> String {
+    lookup(TRANSLATION.get(), key).to_owned()
+}
+
+/// Get the message with the given key in the loaded language, replacing each `{}` with the next argument and each `{n}` with the
+/// argument at index `n`, so that translations can change the order of the arguments
+pub fn format(key: &str, args: &[&dyn Display]) -> String {
+    render(lookup(TRANSLATION.get(), key), args)
+}
+
+/// Fill in the placeholders of a message
+fn render(template: &str, args: &[&dyn Display]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut next = 0;
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let idx = match &rest[start + 1..end] {
+            "" => {
+                next += 1;
+                Some(next - 1)
+            }
+            num => num.parse::<usize>().ok(),
+        };
+        match idx.and_then(|idx| args.get(idx)) {
+            Some(arg) => out.push_str(&arg.to_string()),
+            None => out.push_str(&rest[start..=end]), //Leave placeholders that don't have an argument as they are
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing { language, dir } => write!(f, "No language file for '{}' was found in {}", language, dir.display()),
+            Self::IOErr { path, err } => write!(f, "Failed to read the language file {}: {}", path.display(), err),
+            Self::Json { path, err } => write!(f, "The language file {} is not a JSON object of messages: {}", path.display(), err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn placeholders() {
+        assert_eq!(render("Copied {} to {}", &[&"a", &"b"]), "Copied a to b");
+        assert_eq!(render("{1} from {0}", &[&"a", &2]), "2 from a");
+        assert_eq!(render("Missing {} and {}", &[&1]), "Missing 1 and {}");
+        assert_eq!(render("No placeholders", &[]), "No placeholders");
+    }
+
+    #[test]
+    pub fn fallback_to_english() {
+        let german = Catalog::parse(r#"{"exported": "{} exportiert"}"#).unwrap();
+        assert_eq!(lookup(Some(&german), "exported"), "{} exportiert");
+        assert_eq!(lookup(Some(&german), "imported"), "Imported {}");
+        assert_eq!(lookup(None, "exported"), "Exported {}");
+        assert_eq!(candidates("de_DE.UTF-8"), vec!["de_DE", "de"]);
+        assert!(is_english("en_US.UTF-8") && is_english("C") && !is_english("fr"));
+    }
+
+    /// Every message key that is used in the source code must be in the English messages
+    #[test]
+    pub fn keys_exist() {
+        let english = english();
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        let mut used = 0;
+        for entry in fs::read_dir(dir).unwrap() {
+            let source = fs::read_to_string(entry.unwrap().path()).unwrap();
+            for (_, rest) in source.match_indices("msg!(\"").map(|(idx, m)| source.split_at(idx + m.len())) {
+                let key = &rest[..rest.find('"').unwrap()];
+                assert!(english.get(key).is_some(), "The message key '{}' is not in assets/messages/en.json", key);
+                used += 1;
+            }
+        }
+        assert!(used > 0);
+    }
+}
@@ -0,0 +1,141 @@
+//! The `mods` module detects other client mods like OpenAsar, BetterDiscord, and Vencord. They replace or inject into the same
+//! files that we patch, so patching on top of them either can't find where to insert the theme or breaks both mods. Every mod is
+//! found by the [SIGNATURES] table, which is easy to extend when a mod changes how it installs itself
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The `Target` enum is the file that a [Signature] is searched for in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// A file relative to the `discord_desktop_core` folder that `core.asar` is in
+    Core(&'static str),
+    /// A file relative to Discord's `resources` folder that `app.asar` is in
+    Resources(&'static str),
+}
+
+/// The `Signature` struct is text that a client mod leaves in one of Discord's files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signature {
+    /// The name of the client mod, shown to the user
+    pub name: &'static str,
+    /// The file that the text is searched for in
+    pub target: Target,
+    /// The text to search for, compared without case. An empty string matches if the file exists at all
+    pub needle: &'static str,
+}
+
+/// Every client mod signature that is checked for before patching
+pub const SIGNATURES: &[Signature] = &[
+    //OpenAsar replaces app.asar with its own, which names itself in its code
+    Signature {
+        name: "OpenAsar",
+        target: Target::Resources("app.asar"),
+        needle: "openasar",
+    },
+    //BetterDiscord adds a require of its own archive to the core module's loader
+    Signature {
+        name: "BetterDiscord",
+        target: Target::Core("index.js"),
+        needle: "betterdiscord.asar",
+    },
+    //Vencord's installer either swaps app.asar for a small archive that loads its patcher, or adds an app folder that does the same
+    Signature {
+        name: "Vencord",
+        target: Target::Resources("app.asar"),
+        needle: "vencord",
+    },
+    Signature {
+        name: "Vencord",
+        target: Target::Resources("app/index.js"),
+        needle: "patcher.js",
+    },
+    //The installer keeps Discord's original app.asar with this name
+    Signature {
+        name: "Vencord",
+        target: Target::Resources("_app.asar"),
+        needle: "",
+    },
+];
+
+/// The `Detected` struct is a client mod that was found and the file that it was found in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Detected {
+    pub name: &'static str,
+    pub path: PathBuf,
+}
+
+impl Signature {
+    /// Check if this signature is in the contents of its target file
+    pub fn matches(&self, contents: &[u8]) -> bool {
+        let needle = self.needle.as_bytes();
+        needle.is_empty() || contents.windows(needle.len()).any(|window| window.eq_ignore_ascii_case(needle))
+    }
+
+    /// Get the path of this signature's target file
+    pub fn path(&self, core: &Path, resources: &Path) -> PathBuf {
+        match self.target {
+            Target::Core(file) => core.join(file),
+            Target::Resources(file) => resources.join(file),
+        }
+    }
+}
+
+/// Find every client mod in the [SIGNATURES] table that is installed, reporting each mod once. Files that don't exist are skipped,
+/// but other errors reading them are returned
+pub fn detect(core: &Path, resources: &Path) -> io::Result<Vec<Detected>> {
+    let mut detected = Vec::<Detected>::new();
+    for sig in SIGNATURES {
+        if detected.iter().any(|found| found.name == sig.name) {
+            continue;
+        }
+        let path = sig.path(core, resources);
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if sig.matches(&contents) {
+            detected.push(Detected { name: sig.name, path });
+        }
+    }
+    Ok(detected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Get the first signature of a client mod
+    fn signature(name: &str) -> &'static Signature {
+        SIGNATURES.iter().find(|sig| sig.name == name).unwrap()
+    }
+
+    #[test]
+    pub fn signatures() {
+        assert!(signature("OpenAsar").matches(br#"const log = (...a) => console.log('[OpenAsar]', ...a);"#));
+        assert!(signature("BetterDiscord").matches(br#"require("C:\\Users\\a\\AppData\\Roaming\\BetterDiscord\\data\\betterdiscord.asar");
+module.exports = require("./core.asar");"#));
+        assert!(signature("Vencord").matches(br#"require("/home/a/.config/Vencord/dist/patcher.js")"#));
+        assert!(!signature("BetterDiscord").matches(b"module.exports = require('./core.asar');"));
+        assert!(!signature("OpenAsar").matches(b"mainWindow.webContents.on('dom-ready', () => {});"));
+    }
+
+    #[test]
+    pub fn detect_installed() {
+        let dir = tempfile::tempdir().unwrap();
+        let (core, resources) = (dir.path().join("core"), dir.path().join("resources"));
+        fs::create_dir_all(&core).unwrap();
+        fs::create_dir_all(resources.join("app")).unwrap();
+        fs::write(core.join("index.js"), b"module.exports = require('./core.asar');").unwrap();
+        assert_eq!(detect(&core, &resources).unwrap(), Vec::new());
+
+        fs::write(resources.join("app").join("index.js"), b"require('C:/Vencord/dist/patcher.js');").unwrap();
+        fs::write(resources.join("_app.asar"), b"").unwrap();
+        fs::write(core.join("index.js"), b"require('betterdiscord.asar');").unwrap();
+        let names = detect(&core, &resources).unwrap().iter().map(|found| found.name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["BetterDiscord", "Vencord"]);
+    }
+}
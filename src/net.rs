@@ -0,0 +1,427 @@
+//! The `net` module builds the HTTP agent that every download is made with, using the proxy, timeout, and TLS options from the
+//! config file
+
+use std::{
+    error::Error as StdError,
+    fmt,
+    io::{self, Read},
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use console::style;
+use indicatif::ProgressBar;
+use rustls::{Certificate, ClientConfig, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError};
+
+use crate::{config::Config, messages::msg, output, text, ui};
+
+/// The environment variables that the proxy is read from when the `proxy` option isn't set, in order of preference
+const PROXY_VARS: [&str; 4] = ["HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy"];
+
+/// How long to wait after the first failed download attempt, which doubles after every failed attempt
+pub const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The biggest theme or catalog that will be downloaded, which stops a broken or malicious server from filling up memory
+pub const MAX_TEXT_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The agent shared by every download, see [init]
+static NET: OnceLock<Net> = OnceLock::new();
+
+/// The `Net` struct is the shared agent and the timeout that it was built with, for error messages
+struct Net {
+    agent: ureq::Agent,
+    timeout: u64,
+    /// Wether every request is refused because the `offline` option is set
+    offline: bool,
+}
+
+/// Build the agent that every download is made with from the network options in the config. Only the first call has any effect
+pub fn init(cfg: &Config) -> Result<(), Error> {
+    let net = build(cfg)?;
+    if cfg.insecure_skip_tls_verify {
+        output::warning(output::Category::Network, style(msg!("warning-insecure-skip-tls-verify")).red().bold());
+    }
+    let _ = NET.set(net);
+    Ok(())
+}
+
+/// Check if the `offline` option is set, so that every request is refused
+pub fn is_offline() -> bool {
+    net().offline
+}
+
+/// Get the shared agent, built with the default network options if [init] wasn't called
+fn net() -> &'static Net {
+    NET.get_or_init(|| Net {
+        agent: ureq::agent(),
+        timeout: Config::default().network_timeout_secs,
+        offline: false,
+    })
+}
+
+/// Build an agent from the network options in the config
+fn build(cfg: &Config) -> Result<Net, Error> {
+    let mut builder = ureq::AgentBuilder::new().timeout(Duration::from_secs(cfg.network_timeout_secs));
+    if let Some(proxy) = proxy(cfg) {
+        builder = builder.proxy(ureq::Proxy::new(&proxy).map_err(|e| Error::Proxy {
+            proxy: proxy.clone(),
+            err: e.to_string(),
+        })?);
+    }
+    if cfg.insecure_skip_tls_verify {
+        let mut tls = ClientConfig::new();
+        tls.dangerous().set_certificate_verifier(Arc::new(NoVerifier));
+        builder = builder.tls_config(Arc::new(tls));
+    }
+    Ok(Net {
+        agent: builder.build(),
+        timeout: cfg.network_timeout_secs,
+        offline: cfg.offline,
+    })
+}
+
+/// Get the proxy to download through from the config or the standard environment variables
+fn proxy(cfg: &Config) -> Option<String> {
+    cfg.proxy.clone().or_else(|| {
+        PROXY_VARS
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|proxy| !proxy.is_empty())
+    })
+}
+
+/// Make a GET request to the given URL with the shared agent
+pub fn get(url: &str) -> Result<ureq::Response, Error> {
+    get_with(url, &[])
+}
+
+/// Make a GET request to the given URL with the shared agent, setting the given request headers
+pub fn get_with(url: &str, headers: &[(&str, &str)]) -> Result<ureq::Response, Error> {
+    let net = net();
+    online(net.offline, url, || {
+        let req = headers.iter().fold(net.agent.get(url), |req, (name, val)| req.set(name, val));
+        req.call().map_err(|e| Error::from_ureq(url, net.timeout, e))
+    })
+}
+
+/// Call `request` only if `offline` isn't set. Every request is made through this, so that offline mode can't be forgotten
+fn online<T>(offline: bool, url: &str, request: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    match offline {
+        true => Err(Error::Offline { url: url.to_owned() }),
+        false => request(),
+    }
+}
+
+/// Read the body of a response, refusing bodies bigger than `limit` bytes without reading more than one byte past it. If the
+/// response has a `Content-Length` header, `bar` shows how much of the body was read, otherwise it is left as a spinner
+pub fn read_body(url: &str, res: ureq::Response, limit: u64, bar: &ProgressBar) -> Result<Vec<u8>, Error> {
+    let len = res.header("Content-Length").and_then(|len| len.trim().parse::<u64>().ok());
+    read_limited(url, res.into_reader(), len, limit, bar)
+}
+
+/// Read the body of a response to a string like [read_body], failing if it isn't UTF-8
+pub fn read_text(url: &str, res: ureq::Response, limit: u64, bar: &ProgressBar) -> Result<String, Error> {
+    into_text(url, read_body(url, res, limit, bar)?)
+}
+
+/// Convert a file downloaded from `url` to a string with [text::load], failing if it isn't text
+pub fn into_text(url: &str, data: Vec<u8>) -> Result<String, Error> {
+    text::load(url, data).map_err(|e| Error::Transport {
+        url: url.to_owned(),
+        err: e.to_string(),
+    })
+}
+
+/// Read a response body with the length from its `Content-Length` header, see [read_body]
+fn read_limited(url: &str, read: impl Read, len: Option<u64>, limit: u64, bar: &ProgressBar) -> Result<Vec<u8>, Error> {
+    let too_large = || Error::TooLarge {
+        url: url.to_owned(),
+        limit,
+    };
+    let mut data = Vec::new();
+    let mut read = read.take(limit + 1);
+    let read_err = |e: io::Error| Error::Transport {
+        url: url.to_owned(),
+        err: e.to_string(),
+    };
+    match len {
+        //Don't start downloading something that the server says is too big
+        Some(len) if len > limit => return Err(too_large()),
+        Some(len) if len > 0 => {
+            bar.set_length(len);
+            bar.set_position(0);
+            bar.set_style(ui::style(ui::Kind::Transfer));
+            bar.wrap_read(read).read_to_end(&mut data).map_err(read_err)?;
+        }
+        _ => {
+            read.read_to_end(&mut data).map_err(read_err)?;
+        }
+    }
+    match data.len() as u64 > limit {
+        true => Err(too_large()),
+        false => Ok(data),
+    }
+}
+
+/// Call `fetch` with the attempt number, starting at 1, until it succeeds, fails with an error that retrying won't fix, or fails
+/// `attempts` times. After every failure `sleep` is called with a wait that starts at `backoff` and doubles every time
+pub fn retry<T>(
+    attempts: u32,
+    backoff: Duration,
+    sleep: impl Fn(Duration),
+    mut fetch: impl FnMut(u32) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let mut wait = backoff;
+    let mut attempt = 1;
+    loop {
+        match fetch(attempt) {
+            Ok(val) => return Ok(val),
+            Err(e) if attempt >= attempts || !e.is_transient() => return Err(e),
+            Err(_) => {
+                sleep(wait);
+                wait *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// The `Fetched` enum is a downloaded file, or an older copy of it that was used because downloading it failed
+#[derive(Debug)]
+pub enum Fetched<T> {
+    /// The file was downloaded
+    Fresh(T),
+    /// Every download attempt failed with `err`, so an older copy was used
+    Stale { text: String, err: Error },
+}
+
+/// Download a file with [retry], using the older copy returned by `cached` if every attempt fails
+pub fn fetch_or_cached<T>(
+    attempts: u32,
+    backoff: Duration,
+    sleep: impl Fn(Duration),
+    fetch: impl FnMut(u32) -> Result<T, Error>,
+    cached: impl FnOnce() -> Option<String>,
+) -> Result<Fetched<T>, Error> {
+    match retry(attempts, backoff, sleep, fetch) {
+        Ok(val) => Ok(Fetched::Fresh(val)),
+        Err(err) => match cached() {
+            Some(text) => Ok(Fetched::Stale { text, err }),
+            None => Err(err),
+        },
+    }
+}
+
+/// The `NoVerifier` struct accepts every TLS certificate, used when `insecure-skip-tls-verify` is set
+struct NoVerifier;
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        _presented_certs: &[Certificate],
+        _dns_name: webpki::DNSNameRef<'_>,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// The `Error` enum is every error that can happen when downloading something
+#[derive(Debug)]
+pub enum Error {
+    /// The proxy URL is invalid
+    Proxy { proxy: String, err: String },
+    /// The server didn't respond before the timeout ran out
+    Timeout { url: String, secs: u64 },
+    /// The server responded with an error status code
+    Status { url: String, code: u16 },
+    /// The connection to the server failed
+    Transport { url: String, err: String },
+    /// The downloaded file's SHA-256 hash isn't the one that was published or pinned for it
+    Checksum { url: String, expected: String, actual: String },
+    /// The checksum file published for a download doesn't contain a SHA-256 hash
+    InvalidChecksum { url: String },
+    /// There is no checksum file published next to a download that must have one
+    MissingChecksum { url: String },
+    /// The `offline` option is set, so nothing can be downloaded
+    Offline { url: String },
+    /// The response is bigger than the most bytes that will be downloaded from the URL
+    TooLarge { url: String, limit: u64 },
+}
+
+impl Error {
+    /// Check if trying again might fix this error, which is true for connection errors and server errors but not for an invalid
+    /// proxy or a missing file
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Timeout { .. } | Self::Transport { .. } => true,
+            Self::Status { code, .. } => *code >= 500 || *code == 429,
+            Self::Proxy { .. } | Self::Checksum { .. } | Self::InvalidChecksum { .. } | Self::MissingChecksum { .. } | Self::TooLarge { .. } | Self::Offline { .. } => false,
+        }
+    }
+
+    /// Convert an error from ureq, telling timeouts apart from other connection errors
+    fn from_ureq(url: &str, secs: u64, err: ureq::Error) -> Self {
+        match err {
+            ureq::Error::Status(code, _) => Self::Status {
+                url: url.to_owned(),
+                code,
+            },
+            ureq::Error::Transport(transport) if is_timeout(&transport) => Self::Timeout {
+                url: url.to_owned(),
+                secs,
+            },
+            ureq::Error::Transport(transport) => Self::Transport {
+                url: url.to_owned(),
+                err: transport.to_string(),
+            },
+        }
+    }
+}
+
+/// Check if a connection error was caused by a timeout, which ureq reports as an I/O error somewhere in the error's sources
+fn is_timeout(err: &(dyn StdError + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(io) = err.downcast_ref::<io::Error>() {
+            if matches!(io.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock) {
+                return true;
+            }
+        }
+        source = err.source();
+    }
+    false
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Proxy { proxy, err } => write!(f, "Invalid proxy URL {}: {}", proxy, err),
+            Self::Timeout { url, secs } => write!(
+                f,
+                "{} didn't respond within {} seconds. Check your internet connection and proxy settings, or raise network-timeout-secs in the config file",
+                url, secs
+            ),
+            Self::Status { url, code } => write!(f, "{} responded with HTTP status {}", url, code),
+            Self::Transport { url, err } => write!(f, "Failed to connect to {}: {}", url, err),
+            Self::Checksum { url, expected, actual } => write!(
+                f,
+                "The file downloaded from {} has the SHA-256 hash {} but {} was expected, so it may have been tampered with",
+                url, actual, expected
+            ),
+            Self::InvalidChecksum { url } => write!(f, "{} doesn't contain a SHA-256 hash", url),
+            Self::MissingChecksum { url } => write!(f, "{} wasn't found, so the download can't be verified and was refused", url),
+            Self::Offline { url } => write!(
+                f,
+                "Can't download {} because offline mode is on, remove --offline or the offline option from the config file to allow downloads",
+                url
+            ),
+            Self::TooLarge { url, limit } => write!(f, "The file at {} is bigger than the {} byte limit", url, limit),
+        }
+    }
+}
+
+impl StdError for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn timeout_errors() {
+        let timeout = io::Error::new(io::ErrorKind::TimedOut, "timed out");
+        assert!(is_timeout(&timeout));
+        assert!(!is_timeout(&io::Error::new(io::ErrorKind::ConnectionRefused, "refused")));
+
+        let cfg = Config {
+            proxy: Some("http://proxy.example.com:8080".into()),
+            ..Config::default()
+        };
+        assert_eq!(proxy(&cfg).as_deref(), Some("http://proxy.example.com:8080"));
+    }
+
+    #[test]
+    pub fn downloaded_text() {
+        assert_eq!(into_text("https://example.com", b"\xEF\xBB\xBFa {\r\n}".to_vec()).unwrap(), "a {\n}");
+        assert!(matches!(into_text("https://example.com", vec![0xC3]), Err(Error::Transport { .. })));
+    }
+
+    fn timeout() -> Error {
+        Error::Timeout {
+            url: "https://example.com".into(),
+            secs: 30,
+        }
+    }
+
+    #[test]
+    pub fn retry_backoff() {
+        let waits = std::cell::RefCell::new(Vec::new());
+        let sleep = |wait| waits.borrow_mut().push(wait);
+
+        //Succeed on the last attempt after waiting longer every time
+        let result = retry(3, RETRY_BACKOFF, sleep, |attempt| match attempt {
+            3 => Ok(attempt),
+            _ => Err(timeout()),
+        });
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(*waits.borrow(), [Duration::from_secs(1), Duration::from_secs(2)]);
+
+        //Give up after every attempt fails
+        let mut tries = 0;
+        assert!(matches!(retry(3, RETRY_BACKOFF, sleep, |_| -> Result<(), _> { tries += 1; Err(timeout()) }), Err(Error::Timeout { .. })));
+        assert_eq!(tries, 3);
+
+        //Don't retry errors that won't go away
+        let mut tries = 0;
+        let not_found = retry(3, RETRY_BACKOFF, sleep, |_| -> Result<(), _> {
+            tries += 1;
+            Err(Error::Status { url: "https://example.com".into(), code: 404 })
+        });
+        assert!(matches!(not_found, Err(Error::Status { code: 404, .. })));
+        assert_eq!(tries, 1);
+    }
+
+    #[test]
+    pub fn fallback_to_cache() {
+        let fresh = fetch_or_cached(3, RETRY_BACKOFF, |_| (), |_| Ok("new".to_owned()), || Some("old".into())).unwrap();
+        assert!(matches!(fresh, Fetched::Fresh(text) if text == "new"));
+
+        let stale = fetch_or_cached(3, RETRY_BACKOFF, |_| (), |_| Err::<String, _>(timeout()), || Some("old".into())).unwrap();
+        assert!(matches!(stale, Fetched::Stale { text, err: Error::Timeout { .. } } if text == "old"));
+
+        assert!(fetch_or_cached(3, RETRY_BACKOFF, |_| (), |_| Err::<String, _>(timeout()), || None).is_err());
+    }
+
+    #[test]
+    pub fn size_limit() {
+        let bar = ProgressBar::hidden();
+        let body = [7u8; 100];
+        assert_eq!(read_limited("https://example.com", &body[..], Some(100), 100, &bar).unwrap().len(), 100);
+        assert_eq!(bar.length(), 100);
+        assert_eq!(read_limited("https://example.com", &body[..], None, 100, &bar).unwrap().len(), 100);
+
+        //Refuse bodies that are too big whether or not the server says how big they are
+        assert!(matches!(read_limited("https://example.com", &body[..], Some(100), 99, &bar), Err(Error::TooLarge { limit: 99, .. })));
+        assert!(matches!(read_limited("https://example.com", &body[..], None, 99, &bar), Err(Error::TooLarge { .. })));
+        assert!(matches!(read_limited("https://example.com", &body[..], Some(10), 99, &bar), Err(Error::TooLarge { .. })));
+    }
+
+    #[test]
+    pub fn offline_makes_no_requests() {
+        let called = std::cell::Cell::new(false);
+        let offline = online(true, "https://example.com", || {
+            called.set(true);
+            Ok(())
+        });
+        assert!(matches!(offline, Err(Error::Offline { .. })));
+        assert!(!called.get());
+
+        assert!(online(false, "https://example.com", || Ok(())).is_ok());
+        let cfg = Config {
+            offline: true,
+            ..Config::default()
+        };
+        assert!(build(&cfg).unwrap().offline);
+    }
+}
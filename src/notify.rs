@@ -0,0 +1,250 @@
+//! The `notify` module shows a desktop notification when a run finishes, so that a scheduled `check` that applied the theme again
+//! after Discord updated, or failed to, doesn't go unnoticed. Notifications are shown with a tool that every platform already has:
+//! `notify-send` on Linux and the BSDs, `osascript` on macOS, and a toast from PowerShell on Windows, falling back to `msg` where
+//! toasts can't be shown. A notification that can't be shown is only a warning and never changes the exit status
+
+use std::{
+    fmt, io,
+    process::{Command, Stdio},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use console::{style, Color};
+
+use crate::{
+    config::Notify,
+    messages::msg,
+    output::{self, Category},
+};
+
+/// The name that notifications are shown under
+pub const APP_NAME: &str = "discord-theme";
+
+/// The app ID that toasts are shown under on Windows, which has to be registered to show them, so PowerShell's own is borrowed
+const WINDOWS_APP_ID: &str = r"{1AC14E77-02E7-4E5D-B744-2EB1AE5198B7}\WindowsPowerShell\v1.0\powershell.exe";
+
+/// Wether this run shows notifications, see [enable]
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Decide if this run shows notifications from the `notify` option, where `background` is set for the scheduled `check`
+pub fn enable(setting: Notify, background: bool) {
+    let enabled = match setting {
+        Notify::Never => false,
+        Notify::Background => background,
+        Notify::Always => true,
+    };
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Check if this run shows notifications
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// The `Urgency` enum is how much a notification asks for attention
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Urgency {
+    /// What was done
+    Normal,
+    /// Something failed and needs to be looked at
+    Critical,
+}
+
+/// The `Notification` struct is a notification's title and text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub summary: String,
+    pub body: String,
+    pub urgency: Urgency,
+}
+
+impl Notification {
+    /// Create a notification of something that was done
+    pub fn new(summary: String, body: String) -> Self {
+        Self {
+            summary,
+            body,
+            urgency: Urgency::Normal,
+        }
+    }
+
+    /// Create a notification of something that failed
+    pub fn critical(summary: String, body: String) -> Self {
+        Self {
+            urgency: Urgency::Critical,
+            ..Self::new(summary, body)
+        }
+    }
+}
+
+/// The `Invocation` struct is a program that shows a notification and its arguments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    pub program: &'static str,
+    pub args: Vec<String>,
+}
+
+/// Get the programs that can show `note` on the platform named `os`, like [std::env::consts::OS], in the order they are tried
+pub fn invocations(note: &Notification, os: &str) -> Vec<Invocation> {
+    match os {
+        "macos" => vec![Invocation {
+            program: "osascript",
+            args: vec![
+                "-e".to_owned(),
+                format!("display notification {} with title {}", applescript_string(&note.body), applescript_string(&note.summary)),
+            ],
+        }],
+        "windows" => vec![
+            Invocation {
+                program: "powershell",
+                args: vec!["-NoProfile".to_owned(), "-NonInteractive".to_owned(), "-Command".to_owned(), toast_script(note)],
+            },
+            Invocation {
+                program: "msg",
+                args: vec!["*".to_owned(), "/TIME:60".to_owned(), format!("{}: {}", note.summary, note.body)],
+            },
+        ],
+        _ => vec![Invocation {
+            program: "notify-send",
+            args: vec![
+                format!("--app-name={}", APP_NAME),
+                format!(
+                    "--urgency={}",
+                    match note.urgency {
+                        Urgency::Normal => "normal",
+                        Urgency::Critical => "critical",
+                    }
+                ),
+                //Without the separator a summary starting with a dash would be read as an option
+                "--".to_owned(),
+                note.summary.clone(),
+                note.body.clone(),
+            ],
+        }],
+    }
+}
+
+/// Quote a string for AppleScript
+fn applescript_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Quote a string for PowerShell, where nothing is special in single quotes except the quote itself
+fn powershell_string(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+/// Get the PowerShell script that shows `note` as a toast through the WinRT notification API
+fn toast_script(note: &Notification) -> String {
+    format!(
+        "$ErrorActionPreference = 'Stop'; \
+         [Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, ContentType = WindowsRuntime] > $null; \
+         $xml = [Windows.UI.Notifications.ToastNotificationManager]::GetTemplateContent([Windows.UI.Notifications.ToastTemplateType]::ToastText02); \
+         $text = $xml.GetElementsByTagName('text'); \
+         $text.Item(0).AppendChild($xml.CreateTextNode({})) > $null; \
+         $text.Item(1).AppendChild($xml.CreateTextNode({})) > $null; \
+         [Windows.UI.Notifications.ToastNotificationManager]::CreateToastNotifier({}).Show([Windows.UI.Notifications.ToastNotification]::new($xml))",
+        powershell_string(&note.summary),
+        powershell_string(&note.body),
+        powershell_string(WINDOWS_APP_ID)
+    )
+}
+
+/// Show `note` with the first program from [invocations] that succeeds
+pub fn show(note: &Notification) -> Result<(), Error> {
+    let mut failures = Vec::new();
+    for invocation in invocations(note, std::env::consts::OS) {
+        let status = Command::new(invocation.program)
+            .args(&invocation.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        match status {
+            Ok(status) if status.success() => return Ok(()),
+            Ok(status) => failures.push(Failure::Status(invocation.program, status.code())),
+            Err(e) => failures.push(Failure::Spawn(invocation.program, e)),
+        }
+    }
+    Err(Error(failures))
+}
+
+/// Show `note` if this run shows notifications, warning instead of failing if it can't be shown
+pub fn send(note: &Notification) {
+    if !is_enabled() {
+        return;
+    }
+    if let Err(e) = show(note) {
+        output::warning(Category::General, style(msg!("failed-show-notification", e)).fg(Color::Color256(172)));
+    }
+}
+
+/// The `Failure` enum is why one program couldn't show a notification
+#[derive(Debug)]
+enum Failure {
+    /// The program isn't installed or couldn't be started
+    Spawn(&'static str, io::Error),
+    /// The program exited with an error code
+    Status(&'static str, Option<i32>),
+}
+
+/// The `Error` struct is why every program that was tried failed to show a notification
+#[derive(Debug)]
+pub struct Error(Vec<Failure>);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, failure) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match failure {
+                Failure::Spawn(program, err) => write!(f, "failed to run {}: {}", program, err)?,
+                Failure::Status(program, Some(code)) => write!(f, "{} exited with code {}", program, code)?,
+                Failure::Status(program, None) => write!(f, "{} was killed", program)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn platform_invocations() {
+        let note = Notification::critical("-Discord \"updated\"".to_owned(), "it's 'quoted' \\ here".to_owned());
+
+        let linux = invocations(&note, "linux");
+        assert_eq!(linux[0].program, "notify-send");
+        assert_eq!(linux[0].args[1..], ["--urgency=critical", "--", "-Discord \"updated\"", "it's 'quoted' \\ here"]);
+        assert_eq!(invocations(&note, "freebsd"), linux);
+
+        let mac = invocations(&note, "macos");
+        assert_eq!(
+            mac[0].args[1],
+            r#"display notification "it's 'quoted' \\ here" with title "-Discord \"updated\"""#
+        );
+
+        //Toasts are tried before the fallback, with quotes doubled so the text can't end the PowerShell string
+        let windows = invocations(&note, "windows");
+        assert_eq!(windows.iter().map(|invocation| invocation.program).collect::<Vec<_>>(), ["powershell", "msg"]);
+        assert!(windows[0].args[3].contains("CreateTextNode('it''s ''quoted'' \\ here')"));
+    }
+
+    #[test]
+    pub fn only_background_by_default() {
+        enable(Notify::Background, false);
+        assert!(!is_enabled());
+        enable(Notify::Background, true);
+        assert!(is_enabled());
+        enable(Notify::Never, true);
+        assert!(!is_enabled());
+        enable(Notify::Always, false);
+        assert!(is_enabled());
+        enable(Notify::Never, false);
+    }
+}
@@ -0,0 +1,512 @@
+//! The `output` module is where every message for the user is printed. Normally messages are printed as styled text, but with
+//! `--output json` they are written to stderr as one JSON event per line instead, and a [RunReport] of what was done is written to stdout
+//! as a single JSON object when the program exits. Commands that change Discord print the same report as a summary table at the end
+
+use std::{
+    borrow::Cow,
+    fmt::Display,
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+    time::Instant,
+};
+
+use console::style;
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget};
+use serde::Serialize;
+
+use crate::{history, messages::msg};
+
+/// How messages are printed, see [init]
+static MODE: OnceLock<Mode> = OnceLock::new();
+
+/// What has been done so far, written when the program exits in [Mode::Json]
+static REPORT: Mutex<Option<RunReport>> = Mutex::new(None);
+
+/// When the program started, see [start]
+static START: OnceLock<Instant> = OnceLock::new();
+
+/// The history file that the run is added to when it finishes and how many runs it keeps, see [keep_history]
+static HISTORY: OnceLock<(PathBuf, usize)> = OnceLock::new();
+
+/// The commands that change Discord, which print a summary of what they did before exiting
+const SUMMARY_ACTIONS: &[&str] = &["apply", "restore", "uninstall", "icon apply", "icon restore"];
+
+/// The `Mode` enum is how messages for the user are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Styled text and progress bars for people
+    Human,
+    /// JSON events on stderr and a JSON report on stdout for scripts
+    Json,
+}
+
+impl Mode {
+    /// Get the mode from the value of the `--output` flag
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "human" | "text" => Some(Self::Human),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+/// The `Level` enum is how important a message is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    /// Normal output, printed to stdout
+    Info,
+    /// Something went wrong but the program can continue, printed to stderr and listed in the report
+    Warning,
+    /// Something went wrong that stops the program, printed to stderr and recorded in the report
+    Error,
+    /// A step started, shown as a spinner
+    Progress,
+}
+
+/// The `Category` enum is what a warning is about, which decides the suggestion that is listed with it in the recap at the end of a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    /// The config file or an option in it
+    Config,
+    /// Backups and the other files kept next to them, like the manifest
+    Backup,
+    /// Replacing or restoring Discord's icon
+    Icon,
+    /// Files that this program isn't allowed to change
+    Permissions,
+    /// The state of Discord's installation, like pending updates and other client mods
+    Discord,
+    /// The contents of the theme
+    Theme,
+    /// Downloading themes, scripts, and indexes
+    Network,
+    /// Anything else, which has no suggestion
+    General,
+}
+
+impl Category {
+    /// Get the name of the category as it is written in the report
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Config => "config",
+            Self::Backup => "backup",
+            Self::Icon => "icon",
+            Self::Permissions => "permissions",
+            Self::Discord => "discord",
+            Self::Theme => "theme",
+            Self::Network => "network",
+            Self::General => "general",
+        }
+    }
+
+    /// Get what the user can do about a warning in this category
+    pub fn suggestion(self) -> Option<String> {
+        match self {
+            Self::Config => Some(msg!("suggest-config")),
+            Self::Backup => Some(msg!("suggest-backup")),
+            Self::Icon => Some(msg!("suggest-icon")),
+            Self::Permissions => Some(msg!("suggest-permissions")),
+            Self::Discord => Some(msg!("suggest-discord")),
+            Self::Theme => Some(msg!("suggest-theme")),
+            Self::Network => Some(msg!("suggest-network")),
+            Self::General => None,
+        }
+    }
+}
+
+/// The `RunReport` struct is what was done by a run of the program, which is written to stdout as JSON in [Mode::Json] and printed
+/// as a summary table otherwise
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RunReport {
+    /// The command that was run, like `apply` or `restore`
+    pub action: Option<String>,
+    /// Wether the program exited without an error
+    pub success: bool,
+    /// The locations of the Discord installation that was used
+    pub paths: Option<ReportPaths>,
+    /// The Discord flavor that was used, like `stable`
+    pub flavor: Option<String>,
+    /// The name of Discord's version folder, like `app-1.0.9001`
+    pub discord_version: Option<String>,
+    /// How many bytes of CSS and javascript were injected into Discord's files
+    pub bytes_injected: Option<u64>,
+    /// Where the CSS theme came from
+    pub css: Option<ReportSource>,
+    /// Where the custom javascript came from
+    pub js: Option<ReportSource>,
+    /// The copy of the injection that was written with `--emit-injection`
+    pub injection: Option<ReportInjection>,
+    /// The backup that was made or already existed
+    pub backup: Option<ReportBackup>,
+    /// Wether Discord's icon was replaced
+    pub icon_replaced: Option<bool>,
+    /// The parts of the old look that were applied from the menu, like `css`, `icon`, and `title`, see [components](crate::components)
+    pub components: Option<Vec<String>>,
+    /// The name of the theme that was remembered from the last apply when the program started, if there was one
+    pub last_theme: Option<String>,
+    /// Every warning that was printed
+    pub warnings: Vec<ReportWarning>,
+    /// The error that stopped the program
+    pub error: Option<String>,
+    /// How long parsing Discord's archive and reading its files took, in milliseconds
+    pub read_ms: Option<u64>,
+    /// How long writing the patched archive took, in milliseconds, which is usually the slowest part of applying a theme
+    pub write_ms: Option<u64>,
+    /// How long the program ran for, in milliseconds
+    pub elapsed_ms: Option<u64>,
+}
+
+/// The `ReportWarning` struct is a warning that was printed during the run, in the [RunReport]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReportWarning {
+    pub category: Category,
+    /// The warning without styling
+    pub message: String,
+    /// What the user can do about it, see [Category::suggestion]
+    pub suggestion: Option<String>,
+}
+
+/// The `ReportSource` struct is where injected CSS or javascript came from in the [RunReport]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReportSource {
+    /// The file, URL, or theme name that the text came from
+    pub source: String,
+    /// How many bytes of text there are
+    pub bytes: u64,
+}
+
+/// The `ReportPaths` struct is the locations of a Discord installation in the [RunReport]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReportPaths {
+    pub root: PathBuf,
+    pub core: PathBuf,
+    pub asar: PathBuf,
+    pub backups: PathBuf,
+    /// The `app-x.y.z` folder of the installed Discord version, or the root folder if there are no version folders
+    pub version: PathBuf,
+    /// The config file
+    pub config: PathBuf,
+    /// The folder that the backups of every Discord flavor and version are kept in
+    pub backup_dir: PathBuf,
+    /// The folder that downloads are cached in, `None` if this program was compiled without network support
+    pub cache: Option<PathBuf>,
+}
+
+/// The `ReportInjection` struct is the copy of the injection written by `--emit-injection` in the [RunReport]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReportInjection {
+    pub path: PathBuf,
+    pub bytes: u64,
+    /// The SHA-256 hash of the injection, which is the same as the hash of the text inserted into Discord's file
+    pub sha256: String,
+}
+
+/// The `ReportBackup` struct is the backup of Discord's files in the [RunReport]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReportBackup {
+    /// Wether the backup was made by this run, instead of already existing
+    pub created: bool,
+    pub path: PathBuf,
+}
+
+/// Set how messages are printed. Only the first call has any effect
+pub fn init(mode: Mode) {
+    let _ = MODE.set(mode);
+}
+
+/// Start timing the run for the [RunReport]. Only the first call has any effect
+pub fn start() {
+    START.get_or_init(Instant::now);
+}
+
+/// Add the run to the [history] file at `path` when it finishes, keeping at most `limit` runs in it. Only the first call has any
+/// effect, and a `limit` of 0 keeps no history
+pub fn keep_history(path: PathBuf, limit: usize) {
+    let _ = HISTORY.set((path, limit));
+}
+
+/// Check if messages are printed as JSON
+pub fn is_json() -> bool {
+    MODE.get() == Some(&Mode::Json)
+}
+
+/// Print a normal message
+pub fn info(msg: impl Display) {
+    print(Level::Info, msg)
+}
+
+/// Print a warning about something in `category`, recording it in the report so that it is listed again when the program exits
+pub fn warning(category: Category, msg: impl Display) {
+    let msg = msg.to_string();
+    report(|report| {
+        report.warnings.push(ReportWarning {
+            category,
+            message: console::strip_ansi_codes(&msg).into_owned(),
+            suggestion: category.suggestion(),
+        })
+    });
+    print(Level::Warning, msg)
+}
+
+/// Print an error
+pub fn error(msg: impl Display) {
+    print(Level::Error, msg)
+}
+
+/// Print a message, or write it as a JSON event in [Mode::Json]
+pub fn print(level: Level, msg: impl Display) {
+    let msg = msg.to_string();
+    match is_json() {
+        true => {
+            let plain = console::strip_ansi_codes(&msg);
+            match level {
+                //Keep every error in case another one happens while handling the first
+                Level::Error => report(|report| match &mut report.error {
+                    Some(error) => *error = format!("{}\n{}", error, plain),
+                    None => report.error = Some(plain.to_string()),
+                }),
+                Level::Info | Level::Warning | Level::Progress => (),
+            }
+            eprintln!("{}", event(level, &plain));
+        }
+        false => match level {
+            Level::Info | Level::Progress => println!("{}", msg),
+            Level::Warning | Level::Error => eprintln!("{}", msg),
+        },
+    }
+}
+
+/// Format one JSON event line
+fn event(level: Level, message: &str) -> String {
+    serde_json::json!({"event": level, "message": message}).to_string()
+}
+
+/// Set the message of a progress bar, or hide it in [Mode::Json] and write the message as a progress event instead
+pub fn progress(bar: ProgressBar, msg: impl Into<Cow<'static, str>>) -> ProgressBar {
+    let msg = msg.into();
+    if is_json() {
+        bar.set_draw_target(ProgressDrawTarget::hidden());
+        print(Level::Progress, &msg);
+    }
+    bar.set_message(msg);
+    bar
+}
+
+/// Change the report of what has been done
+pub fn report(change: impl FnOnce(&mut RunReport)) {
+    let mut report = REPORT.lock().unwrap_or_else(|e| e.into_inner());
+    change(report.get_or_insert_with(RunReport::default));
+}
+
+/// Record which command is being run in the report
+pub fn action(name: &str) {
+    report(|report| report.action = Some(name.to_owned()))
+}
+
+/// Write the report to stdout in [Mode::Json], recording if the program is exiting with an error code, or print it as a summary
+/// table if the command changed Discord
+pub fn finish(errcode: i32) {
+    let mut report = REPORT.lock().unwrap_or_else(|e| e.into_inner()).take().unwrap_or_default();
+    report.success = errcode == 0;
+    report.elapsed_ms = START.get().map(|start| start.elapsed().as_millis() as u64);
+    if let (Some((path, limit)), Some(entry)) = (HISTORY.get().filter(|(_, limit)| *limit > 0), history::Entry::from_report(&report)) {
+        if let Err(e) = history::append(path, &entry, *limit) {
+            print(Level::Warning, style(msg!("failed-save-history", path.display(), e)).fg(console::Color::Color256(172)));
+        }
+    }
+    match is_json() {
+        true => println!("{}", serde_json::to_string(&report).unwrap()),
+        false => {
+            if report.action.as_deref().is_some_and(|action| SUMMARY_ACTIONS.contains(&action)) {
+                println!("\n{}", summary(&report))
+            }
+            //Warnings scroll away long before the end of the run, so they are listed again where they will be read
+            if let Some(recap) = recap(&report) {
+                eprintln!("\n{}", recap)
+            }
+        }
+    }
+}
+
+/// List every warning in a report with its category and suggestion, or `None` if there were no warnings
+pub fn recap(report: &RunReport) -> Option<String> {
+    if report.warnings.is_empty() {
+        return None;
+    }
+    let count = report.warnings.len();
+    let header = match report.success {
+        true => style(msg!("completed-with-warnings", count)).fg(console::Color::Color256(172)).bold(),
+        false => style(msg!("warnings-before-error", count)).fg(console::Color::Color256(172)).bold(),
+    };
+    Some(report.warnings.iter().fold(header.to_string(), |recap, warning| {
+        let recap = format!("{}\n  [{}] {}", recap, style(warning.category.name()).bold(), warning.message);
+        match &warning.suggestion {
+            Some(suggestion) => format!("{}\n      {}", recap, style(suggestion).dim()),
+            None => recap,
+        }
+    }))
+}
+
+/// Format a report as a table with one aligned row for each thing that was done
+pub fn summary(report: &RunReport) -> String {
+    let applied = report.action.as_deref() == Some("apply");
+    let source = |src: &ReportSource| format!("{} ({})", src.source, HumanBytes(src.bytes));
+    let rows = [
+        (msg!("summary-action"), report.action.clone()),
+        (
+            msg!("summary-discord"),
+            match (&report.flavor, &report.discord_version) {
+                (Some(flavor), Some(version)) => Some(format!("{} {}", flavor, version)),
+                (flavor, version) => flavor.clone().or_else(|| version.clone()),
+            },
+        ),
+        (msg!("summary-archive"), report.paths.as_ref().map(|paths| paths.asar.display().to_string())),
+        (msg!("summary-css"), report.css.as_ref().map(source)),
+        (msg!("summary-js"), report.js.as_ref().map(source)),
+        (msg!("summary-injection"), report.injection.as_ref().map(|injection| injection.path.display().to_string())),
+        (
+            msg!("summary-backup"),
+            match &report.backup {
+                Some(ReportBackup { created: true, path }) => Some(msg!("summary-backup-created", path.display())),
+                Some(ReportBackup { created: false, path }) => Some(msg!("summary-backup-existing", path.display())),
+                None if applied => Some(msg!("summary-skipped")),
+                None => None,
+            },
+        ),
+        (
+            msg!("summary-icon"),
+            match report.icon_replaced {
+                Some(true) => Some(msg!("summary-icon-replaced")),
+                Some(false) => Some(msg!("summary-icon-failed")),
+                None if applied => Some(msg!("summary-skipped")),
+                None => None,
+            },
+        ),
+        (msg!("summary-read-time"), report.read_ms.map(|ms| format!("{:.2}s", ms as f64 / 1000.0))),
+        (msg!("summary-write-time"), report.write_ms.map(|ms| format!("{:.2}s", ms as f64 / 1000.0))),
+        (msg!("summary-time"), report.elapsed_ms.map(|ms| format!("{:.2}s", ms as f64 / 1000.0))),
+    ];
+
+    let rows = rows.iter().filter_map(|(name, val)| val.as_ref().map(|val| (name, val))).collect::<Vec<_>>();
+    let width = rows.iter().map(|(name, _)| console::measure_text_width(name)).max().unwrap_or(0);
+    let status = match report.success {
+        true => style(msg!("summary-succeeded")).green().bold(),
+        false => style(msg!("summary-failed")).red().bold(),
+    };
+    rows.iter().fold(status.to_string(), |table, (name, val)| {
+        format!("{}\n  {} {}", table, style(console::pad_str(name, width, console::Alignment::Left, None)).bold(), val)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn report_schema() {
+        let report = RunReport {
+            action: Some("apply".into()),
+            success: true,
+            paths: Some(ReportPaths {
+                root: "Discord".into(),
+                core: "Discord/app-1.0.9001/modules/discord_desktop_core-1/discord_desktop_core".into(),
+                asar: "Discord/app-1.0.9001/modules/discord_desktop_core-1/discord_desktop_core/core.asar".into(),
+                backups: "backups".into(),
+                version: "Discord/app-1.0.9001".into(),
+                config: "config.toml".into(),
+                backup_dir: "backups".into(),
+                cache: None,
+            }),
+            flavor: Some("stable".into()),
+            discord_version: Some("app-1.0.9001".into()),
+            bytes_injected: Some(1024),
+            css: Some(ReportSource {
+                source: "theme.css".into(),
+                bytes: 1000,
+            }),
+            js: None,
+            injection: None,
+            backup: Some(ReportBackup {
+                created: true,
+                path: "backups/core.asar.backup".into(),
+            }),
+            icon_replaced: Some(false),
+            components: Some(vec!["css".into(), "icon".into()]),
+            last_theme: Some("old.css".into()),
+            warnings: vec![ReportWarning { category: Category::Icon, message: "careful".into(), suggestion: Some("retry".into()) }],
+            error: None,
+            read_ms: Some(300),
+            write_ms: Some(800),
+            elapsed_ms: Some(1500),
+        };
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            concat!(
+                r#"{"action":"apply","success":true,"paths":{"root":"Discord","#,
+                r#""core":"Discord/app-1.0.9001/modules/discord_desktop_core-1/discord_desktop_core","#,
+                r#""asar":"Discord/app-1.0.9001/modules/discord_desktop_core-1/discord_desktop_core/core.asar","backups":"backups","#,
+                r#""version":"Discord/app-1.0.9001","config":"config.toml","backup-dir":"backups","cache":null},"#,
+                r#""flavor":"stable","discord-version":"app-1.0.9001","bytes-injected":1024,"css":{"source":"theme.css","bytes":1000},"#,
+                r#""js":null,"injection":null,"backup":{"created":true,"path":"backups/core.asar.backup"},"#,
+                r#""icon-replaced":false,"components":["css","icon"],"last-theme":"old.css","warnings":[{"category":"icon","message":"careful","suggestion":"retry"}],"error":null,"read-ms":300,"write-ms":800,"elapsed-ms":1500}"#
+            )
+        );
+        let table = console::strip_ansi_codes(&summary(&report)).into_owned();
+        let mut lines = table.lines().collect::<Vec<_>>();
+        //How sizes are formatted depends on the indicatif version
+        assert!(lines.remove(4).starts_with("  CSS     theme.css (1000"));
+        assert_eq!(
+            lines,
+            vec![
+                "Succeeded",
+                "  Action  apply",
+                "  Discord stable app-1.0.9001",
+                "  Archive Discord/app-1.0.9001/modules/discord_desktop_core-1/discord_desktop_core/core.asar",
+                "  Backup  created at backups/core.asar.backup",
+                "  Icon    failed",
+                "  Reading 0.30s",
+                "  Writing 0.80s",
+                "  Time    1.50s",
+            ]
+        );
+        assert_eq!(
+            serde_json::to_string(&RunReport::default()).unwrap(),
+            concat!(
+                r#"{"action":null,"success":false,"paths":null,"flavor":null,"discord-version":null,"bytes-injected":null,"css":null,"js":null,"#,
+                r#""injection":null,"backup":null,"icon-replaced":null,"components":null,"last-theme":null,"warnings":[],"error":null,"read-ms":null,"write-ms":null,"elapsed-ms":null}"#
+            )
+        );
+    }
+
+    #[test]
+    pub fn warning_recap() {
+        let mut report = RunReport { success: true, ..RunReport::default() };
+        assert_eq!(recap(&report), None);
+        report.warnings = vec![
+            ReportWarning { category: Category::Icon, message: "Failed to back up the icon".into(), suggestion: Category::Icon.suggestion() },
+            ReportWarning { category: Category::General, message: "Odd".into(), suggestion: None },
+        ];
+        let recap = console::strip_ansi_codes(&recap(&report).unwrap()).into_owned();
+        let lines = recap.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].contains('2'));
+        assert_eq!(lines[1], "  [icon] Failed to back up the icon");
+        assert_eq!(lines[2].trim(), Category::Icon.suggestion().unwrap());
+        assert_eq!(lines[3], "  [general] Odd");
+    }
+
+    #[test]
+    pub fn events() {
+        assert_eq!(event(Level::Warning, "Failed to \"save\""), r#"{"event":"warning","message":"Failed to \"save\""}"#);
+        assert_eq!(event(Level::Progress, "Downloading"), r#"{"event":"progress","message":"Downloading"}"#);
+    }
+}
@@ -0,0 +1,215 @@
+//! The `pack` module reads theme packs, which are `.zip` files that theme authors distribute with a CSS theme, optional custom
+//! javascript, and an optional icon. The files are found by their well-known names, which a `pack.json` file in the zip can change,
+//! and are read into memory without extracting anything to disk
+
+use std::{
+    fmt,
+    io::{self, Read, Seek},
+};
+
+use serde_json::Value;
+use zip::{result::ZipError, ZipArchive};
+
+use crate::text;
+
+/// The file in a pack that can change the names of the other files
+pub const PACK_MANIFEST: &str = "pack.json";
+
+/// The name of the CSS theme in a pack without a [PACK_MANIFEST]
+const DEFAULT_CSS: &str = "theme.css";
+
+/// The name of the custom javascript in a pack without a [PACK_MANIFEST]
+const DEFAULT_JS: &str = "theme.js";
+
+/// The names that an icon in a pack without a [PACK_MANIFEST] is looked for at, in order
+const DEFAULT_ICONS: &[&str] = &["icon.png", "icon.ico", "icon.icns"];
+
+/// The biggest file that is read out of a pack, so that a zip bomb can't use up all memory
+pub const MAX_MEMBER_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The `Members` struct is the names of the files in a pack that were read
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Members {
+    pub css: String,
+    pub js: Option<String>,
+    pub icon: Option<String>,
+}
+
+/// The `Pack` struct is a theme pack read by [read]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pack {
+    /// The name of the theme from the [PACK_MANIFEST], if it has one
+    pub name: Option<String>,
+    pub css: String,
+    pub js: Option<String>,
+    /// The unvalidated contents of the icon file
+    pub icon: Option<Vec<u8>>,
+    pub members: Members,
+}
+
+/// The `Error` enum is every error that can happen when reading a theme pack
+#[derive(Debug)]
+pub enum Error {
+    /// The file isn't a valid zip file
+    Zip(ZipError),
+    IOErr(io::Error),
+    /// The [PACK_MANIFEST] isn't a JSON object of file names
+    Manifest(String),
+    /// There is no CSS theme at the given name
+    MissingCss(String),
+    /// The file at the given name isn't UTF-8 text
+    NotText(String),
+    /// The file at the given name is bigger than [MAX_MEMBER_SIZE]
+    TooLarge(String),
+}
+
+/// Read the file at `name` in a zip file, returning `None` if there isn't one
+fn read_member<R: Read + Seek>(zip: &mut ZipArchive<R>, name: &str) -> Result<Option<Vec<u8>>, Error> {
+    let file = match zip.by_name(name) {
+        Ok(file) => file,
+        Err(ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut data = Vec::new();
+    file.take(MAX_MEMBER_SIZE + 1).read_to_end(&mut data)?;
+    match data.len() as u64 > MAX_MEMBER_SIZE {
+        true => Err(Error::TooLarge(name.to_owned())),
+        false => Ok(Some(data)),
+    }
+}
+
+/// Read the file at `name` in a zip file as text with [text::load], returning `None` if there isn't one
+fn read_text<R: Read + Seek>(zip: &mut ZipArchive<R>, name: &str) -> Result<Option<String>, Error> {
+    read_member(zip, name)?
+        .map(|data| text::load(name, data).map_err(|_| Error::NotText(name.to_owned())))
+        .transpose()
+}
+
+/// Get a file name from the [PACK_MANIFEST], which must be a string if it is there
+fn manifest_name(manifest: &Value, key: &str) -> Result<Option<String>, Error> {
+    match manifest.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(name)) => Ok(Some(name.clone())),
+        Some(_) => Err(Error::Manifest(format!("The '{}' field must be the name of a file in the pack", key))),
+    }
+}
+
+/// Read the CSS theme, custom javascript, and icon out of a theme pack. A pack without a CSS theme is an error, but the javascript
+/// and icon are optional
+pub fn read<R: Read + Seek>(reader: R) -> Result<Pack, Error> {
+    let mut zip = ZipArchive::new(reader)?;
+    let manifest = match read_text(&mut zip, PACK_MANIFEST)? {
+        Some(text) => serde_json::from_str::<Value>(&text).map_err(|e| Error::Manifest(e.to_string()))?,
+        None => Value::Null,
+    };
+    if !manifest.is_null() && !manifest.is_object() {
+        return Err(Error::Manifest("The manifest must be a JSON object".to_owned()));
+    }
+
+    let css_name = manifest_name(&manifest, "css")?.unwrap_or_else(|| DEFAULT_CSS.to_owned());
+    let css = read_text(&mut zip, &css_name)?.ok_or_else(|| Error::MissingCss(css_name.clone()))?;
+    let js_name = manifest_name(&manifest, "js")?.unwrap_or_else(|| DEFAULT_JS.to_owned());
+    let js = read_text(&mut zip, &js_name)?;
+
+    let icon_names = match manifest_name(&manifest, "icon")? {
+        Some(name) => vec![name],
+        None => DEFAULT_ICONS.iter().map(|name| name.to_string()).collect(),
+    };
+    let mut icon = None;
+    for name in icon_names {
+        if let Some(data) = read_member(&mut zip, &name)? {
+            icon = Some((name, data));
+            break;
+        }
+    }
+
+    Ok(Pack {
+        name: manifest_name(&manifest, "name")?,
+        css,
+        members: Members {
+            css: css_name,
+            js: js.as_ref().map(|_| js_name),
+            icon: icon.as_ref().map(|(name, _)| name.clone()),
+        },
+        js,
+        icon: icon.map(|(_, data)| data),
+    })
+}
+
+impl From<ZipError> for Error {
+    fn from(err: ZipError) -> Self {
+        Self::Zip(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::IOErr(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Zip(e) => write!(f, "The theme pack is not a valid zip file: {}", e),
+            Self::IOErr(e) => write!(f, "Failed to read the theme pack: {}", e),
+            Self::Manifest(e) => write!(f, "The theme pack's {} is invalid: {}", PACK_MANIFEST, e),
+            Self::MissingCss(name) => write!(f, "The theme pack has no CSS theme at {}", name),
+            Self::NotText(name) => write!(f, "{} in the theme pack is not UTF-8 text", name),
+            Self::TooLarge(name) => write!(f, "{} in the theme pack is bigger than {} bytes", name, MAX_MEMBER_SIZE),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::{write::FileOptions, ZipWriter};
+
+    /// Build a zip file containing the given files
+    fn zip(files: &[(&str, &[u8])]) -> Cursor<Vec<u8>> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        let mut buf = writer.finish().unwrap();
+        buf.set_position(0);
+        buf
+    }
+
+    #[test]
+    pub fn default_names() {
+        let pack = read(zip(&[("theme.css", b"a { b: c }"), ("theme.js", b"let x;"), ("icon.ico", b"ico")])).unwrap();
+        assert_eq!(pack.css, "a { b: c }");
+        assert_eq!(pack.js.as_deref(), Some("let x;"));
+        assert_eq!(pack.icon.as_deref(), Some(&b"ico"[..]));
+        assert_eq!(pack.members.icon.as_deref(), Some("icon.ico"));
+
+        //Only the CSS is needed
+        let pack = read(zip(&[("theme.css", b"a { b: c }")])).unwrap();
+        assert_eq!((pack.js, pack.icon, pack.members.js), (None, None, None));
+        assert!(matches!(read(zip(&[("theme.js", b"let x;")])), Err(Error::MissingCss(name)) if name == "theme.css"));
+
+        //Files saved by Windows editors are read like any other
+        let pack = read(zip(&[("theme.css", b"\xEF\xBB\xBFa {\r\n}"), ("theme.js", b"let x;\r\n")])).unwrap();
+        assert_eq!((pack.css.as_str(), pack.js.as_deref()), ("a {\n}", Some("let x;\n")));
+    }
+
+    #[test]
+    pub fn manifest_names() {
+        let manifest = br#"{"name": "Dark", "css": "styles/dark.css", "icon": "logo.png"}"#;
+        let pack = read(zip(&[(PACK_MANIFEST, manifest), ("styles/dark.css", b"body {}"), ("theme.css", b"old"), ("logo.png", b"png")])).unwrap();
+        assert_eq!(pack.name.as_deref(), Some("Dark"));
+        assert_eq!(pack.css, "body {}");
+        assert_eq!(pack.members.css, "styles/dark.css");
+        assert_eq!(pack.icon.as_deref(), Some(&b"png"[..]));
+
+        assert!(matches!(read(zip(&[(PACK_MANIFEST, br#"{"css": 1}"#), ("theme.css", b"")])), Err(Error::Manifest(_))));
+        assert!(matches!(read(zip(&[("theme.css", b"\xff\xfe\xff")])), Err(Error::NotText(_))));
+        assert!(matches!(read(Cursor::new(b"not a zip".to_vec())), Err(Error::Zip(_))));
+    }
+}
@@ -0,0 +1,769 @@
+//! The `patch` module changes the contents of the javascript file that creates Discord's window without touching any files, so that
+//! inserting, replacing, removing, and reading the injection can be tested on plain strings. Reading and writing the archive that the
+//! file is in is left to the callers
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    escape,
+    messages::msg,
+    schedule::Css,
+    site::{INJECTION_MARKER, INJECTION_POINT},
+};
+
+/// The text in the injection before the CSS expression
+const CSS_BEGIN: &str = "let CSS_INJECTION_USER_CSS = ";
+
+/// The comment that takes the place of the CSS in an injection that only runs custom javascript, which has the [INJECTION_MARKER] so
+/// that the injection is found and removed like any other
+const JS_ONLY_MARKER: &str = "//CSS_INJECTION_USER_CSS none";
+
+/// The start of the injected javascript, which comes before the [INJECTION_MARKER]
+const INJECTION_START: &str = "mainWindow.webContents.on('dom-ready'";
+
+/// The end of the injected javascript, followed by the text that the injection replaced
+const INJECTION_END: &str = "});mainWindow.webContents.";
+
+/// The comment before the custom javascript in the injection, which is left out with `--no-js`
+const JS_SCRIPT_BEGIN: &str = "//JS_SCRIPT_BEGIN";
+
+/// The comment after the custom javascript in the injection
+const JS_SCRIPT_END: &str = "//JS_SCRIPT_END";
+
+/// The comment at the top of an injection before the name of the strategy that it adds the theme with
+pub const STRATEGY_BEGIN: &str = "//CSS_INJECTION_STRATEGY ";
+
+/// The comment before the preload javascript, which is inserted at the top of the patched file to run when Discord loads it
+pub const PRELOAD_BEGIN: &str = "//JS_PRELOAD_BEGIN\n";
+
+/// The comment after the preload javascript, followed by the rest of the patched file
+pub const PRELOAD_END: &str = "\n//JS_PRELOAD_END\n";
+
+/// The comment before the snippet that keeps Discord's window title, which goes right after the [STRATEGY_BEGIN] comment
+pub const TITLE_BEGIN: &str = "//WINDOW_TITLE_BEGIN";
+
+/// The comment after the snippet that keeps Discord's window title
+pub const TITLE_END: &str = "//WINDOW_TITLE_END";
+
+/// The most characters that the `window-title` option can have, so that a mistake in the config file can't put a huge string in
+/// Discord's title bar
+pub const MAX_TITLE_CHARS: usize = 128;
+
+/// The `Strategy` enum is how the injection adds the theme to Discord's window, selected with the `inject-strategy` config option
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Run javascript in the window that adds a `<style>` element with the theme, which is the default
+    #[default]
+    ExecuteJs,
+    /// Add the theme with `webContents.insertCSS`, which works when Discord's content security policy or sandbox stops
+    /// `executeJavaScript` from running. Javascript is only run in the window if there is custom javascript
+    InsertCss,
+}
+
+impl Strategy {
+    /// Parse a strategy from its name in the config file
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "execute-js" => Some(Self::ExecuteJs),
+            "insert-css" => Some(Self::InsertCss),
+            _ => None,
+        }
+    }
+
+    /// Get the name of this strategy as it is written in the config file and in the injection
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::ExecuteJs => "execute-js",
+            Self::InsertCss => "insert-css",
+        }
+    }
+}
+
+/// The `Extracted` struct is what was inserted into a patched file, read back by [extract]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extracted {
+    /// The CSS theme, or the javascript expression that picks between a light and dark theme
+    pub css: String,
+    /// The custom javascript that runs in Discord's window, if the file was patched with it
+    pub js: Option<String>,
+    /// The javascript that runs when Discord loads the file, if the file was patched with it
+    pub preload: Option<String>,
+    /// How the injection adds the theme, which is [Strategy::ExecuteJs] for injections made before the strategy was recorded
+    pub strategy: Strategy,
+    /// The title that the injection keeps Discord's window at, if the file was patched with one
+    pub title: Option<String>,
+}
+
+/// The `Error` enum is why the contents of a file couldn't be patched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The file doesn't have the [INJECTION_POINT], so it doesn't create Discord's window
+    NoInjectionPoint,
+    /// The file already has an injection, which [update] replaces
+    AlreadyPatched,
+    /// The file has no injection to remove
+    NotPatched,
+    /// The file has the [INJECTION_MARKER] but where the injection starts or ends can't be found
+    Unrecognized,
+    /// There is neither CSS nor custom javascript to insert
+    Empty,
+}
+
+/// The `Damage` enum is something wrong with an injection, which a run that was interrupted while writing it can leave behind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Damage {
+    /// The template literal that the injected code is in isn't opened or closed with a backtick
+    Unquoted,
+    /// The custom javascript block starts but never ends
+    MissingScriptEnd,
+    /// The custom javascript block ends before it starts, or ends without starting
+    ScriptEndBeforeBegin,
+    /// There is more than one injection or preload block in the file, with this many extra copies that were removed
+    Duplicate(usize),
+}
+
+/// Make the javascript that is inserted in place of the first [INJECTION_POINT] with [Strategy::ExecuteJs], which adds the CSS theme to
+/// Discord's window and runs the custom javascript. `css` is a javascript expression that evaluates to the theme inside of a template
+/// literal, and the block that runs the custom javascript is left out if `js` is `None`
+pub fn injection(css: &str, js: Option<&str>) -> String {
+    let js = match js {
+        Some(js) => format!(
+            "
+            //JS_SCRIPT_BEGIN 
+            {} 
+            //JS_SCRIPT_END ",
+            js
+        ),
+        None => String::new(),
+    };
+    format!(
+        "
+    mainWindow.webContents.on('dom-ready', () => {{
+        {strategy}
+        mainWindow.webContents.executeJavaScript(`
+            let CSS_INJECTION_USER_CSS = {css};  
+            const style = document.createElement('style');  
+            style.innerHTML = CSS_INJECTION_USER_CSS;  
+            document.head.appendChild(style);  
+              {js}
+        `);
+    }});mainWindow.webContents.
+    ",
+        strategy = strategy_comment(Strategy::ExecuteJs),
+        css = css,
+        js = js
+    )
+}
+
+/// Make the javascript that is inserted in place of the first [INJECTION_POINT] with [Strategy::InsertCss]. `css` is a javascript
+/// expression that evaluates to the theme in Discord's main process, and the custom javascript is run in the window only if `js` isn't
+/// `None`
+pub fn insert_css_injection(css: &str, js: Option<&str>) -> String {
+    let js = match js {
+        Some(js) => format!(
+            "mainWindow.webContents.executeJavaScript(`
+            //JS_SCRIPT_BEGIN 
+            {} 
+            //JS_SCRIPT_END `);",
+            js
+        ),
+        None => String::new(),
+    };
+    format!(
+        "
+    mainWindow.webContents.on('dom-ready', () => {{
+        {strategy}
+        let CSS_INJECTION_USER_CSS = {css};  
+        mainWindow.webContents.insertCSS(CSS_INJECTION_USER_CSS);  
+        {js}
+    }});mainWindow.webContents.
+    ",
+        strategy = strategy_comment(Strategy::InsertCss),
+        css = css,
+        js = js
+    )
+}
+
+/// Make the javascript that is inserted in place of the first [INJECTION_POINT] when there is no CSS, which only runs the custom
+/// javascript `js` in Discord's window. The strategy is recorded so that the injection matches the `inject-strategy` option, but no
+/// `<style>` element is made and nothing is given to `insertCSS`
+pub fn js_only_injection(js: &str, strategy: Strategy) -> String {
+    format!(
+        "
+    mainWindow.webContents.on('dom-ready', () => {{
+        {strategy}
+        mainWindow.webContents.executeJavaScript(`
+            {marker}
+            //JS_SCRIPT_BEGIN 
+            {js} 
+            //JS_SCRIPT_END `);
+    }});mainWindow.webContents.
+    ",
+        strategy = strategy_comment(strategy),
+        marker = JS_ONLY_MARKER,
+        js = js
+    )
+}
+
+/// Make the snippet that sets Discord's window title to `title` once the page is ready and stops the page from changing it again. It
+/// runs in Discord's main process, so the title is escaped as a plain string literal
+pub fn title_block(title: &str) -> String {
+    format!(
+        "
+        {begin}
+        mainWindow.setTitle({title});
+        if (!mainWindow.discordThemeKeepsTitle) {{
+            mainWindow.discordThemeKeepsTitle = true;
+            mainWindow.on('page-title-updated', (event) => {{
+                event.preventDefault();
+                mainWindow.setTitle({title});
+            }});
+        }}
+        {end}",
+        begin = TITLE_BEGIN,
+        end = TITLE_END,
+        title = escape::js_string(title),
+    )
+}
+
+/// Add the snippet that keeps the window title at `title` to an injection made by [block], right after the comment with its strategy.
+/// The injection is returned unchanged without a title
+pub fn titled(block: String, title: Option<&str>) -> String {
+    let at = block.find(STRATEGY_BEGIN).and_then(|start| block[start..].find('\n').map(|end| start + end));
+    match (title, at) {
+        (Some(title), Some(at)) => format!("{}{}{}", &block[..at], title_block(title), &block[at..]),
+        _ => block,
+    }
+}
+
+/// Make the comment that records the strategy at the top of an injection
+fn strategy_comment(strategy: Strategy) -> String {
+    format!("{}{}", STRATEGY_BEGIN, strategy.name())
+}
+
+/// Make the injection for `css` and the custom javascript `js` with the given strategy. Without CSS only the custom javascript is run,
+/// see [js_only_injection]
+pub fn block(css: &Css, js: Option<&str>, strategy: Strategy) -> String {
+    match (css, js, strategy) {
+        (Css::None, Some(js), _) => js_only_injection(js, strategy),
+        (_, _, Strategy::ExecuteJs) => injection(&css.expression(), js),
+        (_, _, Strategy::InsertCss) => insert_css_injection(&css.main_expression(), js),
+    }
+}
+
+/// Get the strategy of the first injection in the contents of a patched file from the comment at its top
+fn strategy(source: &str) -> Strategy {
+    let marker = source.find(INJECTION_MARKER).unwrap_or(source.len());
+    source[..marker]
+        .rfind(STRATEGY_BEGIN)
+        .map(|start| &source[start + STRATEGY_BEGIN.len()..marker])
+        .and_then(|rest| Strategy::from_name(rest.split_whitespace().next().unwrap_or_default()))
+        .unwrap_or_default()
+}
+
+/// Make the block that runs `preload` when the patched file is loaded, which goes at the top of the file
+pub fn preload_block(preload: &str) -> String {
+    format!("{}{}{}", PRELOAD_BEGIN, preload, PRELOAD_END)
+}
+
+/// Insert the preload block at the top of the file, after its `"use strict"` directive if it has one so that the file stays in strict
+/// mode
+fn insert_preload(source: &str, preload: &str) -> String {
+    let body = source.trim_start();
+    let directive = ["'use strict';", "\"use strict\";"]
+        .iter()
+        .find(|directive| body.starts_with(**directive))
+        .map(|directive| source.len() - body.len() + directive.len());
+    let at = match directive {
+        Some(end) if source[end..].starts_with('\n') => end + 1,
+        Some(end) => end,
+        None => 0,
+    };
+    format!("{}{}{}", &source[..at], preload_block(preload), &source[at..])
+}
+
+/// Insert `css` and the custom javascript `js`, which must already be escaped with [escape::template_literal], into the contents of an
+/// unpatched file with the given strategy, along with the `preload` javascript at the top of the file and the snippet that keeps the
+/// window `title`. The blocks that run custom javascript and the title snippet are left out if they are `None`
+pub fn inject(source: &str, css: &Css, js: Option<&str>, preload: Option<&str>, title: Option<&str>, strategy: Strategy) -> Result<String, Error> {
+    if source.contains(INJECTION_MARKER) || source.contains(PRELOAD_BEGIN) {
+        return Err(Error::AlreadyPatched);
+    }
+    if !source.contains(INJECTION_POINT) {
+        return Err(Error::NoInjectionPoint);
+    }
+    if *css == Css::None && js.is_none() {
+        return Err(Error::Empty);
+    }
+    let patched = source.replacen(INJECTION_POINT, &titled(block(css, js, strategy), title), 1);
+    Ok(match preload {
+        Some(preload) => insert_preload(&patched, preload),
+        None => patched,
+    })
+}
+
+/// Insert `css`, `js`, `preload`, and `title` like [inject], replacing every injection that the file already has, including ones made by
+/// older versions, damaged ones, and ones made with the other strategy. Returns the patched contents and what was wrong with the
+/// injections that were replaced
+pub fn update(
+    source: &str,
+    css: &Css,
+    js: Option<&str>,
+    preload: Option<&str>,
+    title: Option<&str>,
+    strategy: Strategy,
+) -> Result<(String, Vec<Damage>), Error> {
+    let (unpatched, damage) = match repair_injection(source) {
+        Some(repaired) => repaired,
+        None if source.contains(INJECTION_MARKER) || source.contains(PRELOAD_BEGIN) => return Err(Error::Unrecognized),
+        None => (source.to_owned(), Vec::new()),
+    };
+    Ok((inject(&unpatched, css, js, preload, title, strategy)?, damage))
+}
+
+/// Remove every injection from the contents of a patched file, returning the contents as they were before it was patched
+pub fn remove(source: &str) -> Result<String, Error> {
+    match strip_injection(source) {
+        Some(unpatched) => Ok(unpatched),
+        None if source.contains(INJECTION_MARKER) || source.contains(PRELOAD_BEGIN) => Err(Error::Unrecognized),
+        None => Err(Error::NotPatched),
+    }
+}
+
+/// Count the injections in the contents of a file, which is more than one if the theme was inserted again without removing it first.
+/// [update] collapses them into a single injection
+pub fn count(source: &str) -> usize {
+    count_injections(source)
+}
+
+/// Read the CSS and javascript back out of the injection in the contents of a patched file, returning `None` if it isn't patched. A
+/// single theme is returned as the CSS that was inserted, a light and dark theme is returned as the javascript expression that picks
+/// between them, and an injection that only runs custom javascript has empty CSS
+pub fn extract(source: &str) -> Option<Extracted> {
+    let strategy = strategy(source);
+    let css = match source.find(CSS_BEGIN) {
+        Some(start) => {
+            let start = start + CSS_BEGIN.len();
+            let line = source[start..start + source[start..].find('\n')?].trim_end().strip_suffix(';')?;
+            //Only the execute-js injection has the CSS inside of a template literal
+            let expression = match strategy {
+                Strategy::ExecuteJs => escape::cook(line),
+                Strategy::InsertCss => line.to_owned(),
+            };
+            serde_json::from_str::<String>(&expression).unwrap_or(expression)
+        }
+        None if source.contains(JS_ONLY_MARKER) => String::new(),
+        None => return None,
+    };
+    Some(Extracted {
+        css,
+        js: injected_js(source).map(escape::cook),
+        preload: injected_preload(source).map(str::to_owned),
+        strategy,
+        title: injected_title(source).and_then(|title| serde_json::from_str(title).ok()),
+    })
+}
+
+/// Check if the contents of a file are already patched with exactly `css`, `js`, `preload`, and `title`, so that patching it again
+/// would write the same contents. The payload read back by [extract] is compared first, then the injection has to be the one that
+/// [inject] makes now, so that injections made by older versions, damaged ones, and ones made with another strategy are still replaced
+pub fn up_to_date(source: &str, css: &Css, js: Option<&str>, preload: Option<&str>, title: Option<&str>, strategy: Strategy) -> bool {
+    let expected = Extracted {
+        css: match (css, strategy) {
+            (Css::Single(css), _) => css.clone(),
+            (Css::Scheduled { .. }, Strategy::ExecuteJs) => escape::cook(&css.expression()),
+            (Css::Scheduled { .. }, Strategy::InsertCss) => css.main_expression(),
+            (Css::None, _) => String::new(),
+        },
+        js: js.map(escape::cook),
+        preload: preload.map(str::to_owned),
+        strategy,
+        title: title.map(str::to_owned),
+    };
+    extract(source).as_ref() == Some(&expected)
+        && matches!(update(source, css, js, preload, title, strategy), Ok((patched, damage)) if damage.is_empty() && patched == source)
+}
+
+/// Find the first injection in the contents of a patched javascript file, returning where it starts and ends and what is wrong with it
+fn find_injection(js: &str) -> Option<(usize, usize, Vec<Damage>)> {
+    let marker = js.find(INJECTION_MARKER)?;
+    let start = js[..marker].rfind(INJECTION_START)?;
+    //The markers after the next injection's start belong to that injection
+    let limit = js[marker..].find(INJECTION_START).map(|i| marker + i).unwrap_or(js.len());
+    let mut damage = Vec::new();
+
+    //Injections made with --no-js have no javascript block, so their end is found from the marker instead
+    let script_end = match (js[marker..limit].find(JS_SCRIPT_BEGIN), js[marker..limit].find(JS_SCRIPT_END)) {
+        (Some(begin), Some(end)) if begin < end => marker + end,
+        (None, None) => marker,
+        (Some(_), None) => {
+            damage.push(Damage::MissingScriptEnd);
+            marker
+        }
+        (_, Some(_)) => {
+            damage.push(Damage::ScriptEndBeforeBegin);
+            marker
+        }
+    };
+    let end = script_end + js[script_end..].find(INJECTION_END)? + INJECTION_END.len();
+    //An insert-css injection only has a template literal around the custom javascript, and none at all without it
+    let quoted_from = match js[start..marker].contains(&format!("{}insert-css", STRATEGY_BEGIN)) {
+        true => js[marker..limit].find(JS_SCRIPT_BEGIN).map(|begin| marker + begin),
+        false => Some(marker),
+    };
+    if let Some(quoted_from) = quoted_from {
+        if !js[start..quoted_from].contains("executeJavaScript(`") || !js[quoted_from..end].contains("`);") {
+            damage.push(Damage::Unquoted);
+        }
+    }
+    Some((start, end, damage))
+}
+
+/// Remove every injection from the contents of a patched javascript file, including ones that are damaged, returning the unpatched
+/// contents and what was wrong with the injections. Returns `None` if the file isn't patched, or if it has the [INJECTION_MARKER] but
+/// where an injection starts or ends can't be found
+pub fn repair_injection(js: &str) -> Option<(String, Vec<Damage>)> {
+    let (js, preloads) = strip_preload(js)?;
+    let (mut js, mut damage, mut found) = (js, Vec::new(), 0);
+    if preloads > 1 {
+        damage.push(Damage::Duplicate(preloads - 1));
+    }
+    while js.contains(INJECTION_MARKER) {
+        let (unpatched, injection_damage) = remove_first(&js)?;
+        js = unpatched;
+        damage.extend(injection_damage);
+        found += 1;
+    }
+    //Every copy was removed above, and the caller inserts a single one again
+    if found > 1 {
+        damage.push(Damage::Duplicate(found - 1));
+    }
+    match (found, preloads) {
+        (0, 0) => None,
+        _ => Some((js, damage)),
+    }
+}
+
+/// Remove the first injection from the contents of a patched javascript file, putting back the text that it replaced
+fn remove_first(js: &str) -> Option<(String, Vec<Damage>)> {
+    let (start, end, damage) = find_injection(js)?;
+    //The injection is indented onto its own lines, which were not there before it was inserted
+    let before = js[..start].strip_suffix("\n    ").unwrap_or(&js[..start]);
+    let after = js[end..].strip_prefix("\n    ").unwrap_or(&js[end..]);
+    Some((format!("{}{}{}", before, INJECTION_POINT, after), damage))
+}
+
+/// Count the injections in the contents of a patched javascript file, which is more than one when older versions or interrupted runs
+/// inserted the theme again without removing it first. An injection can be inserted inside of another one's text, so they are counted
+/// by removing them one at a time like [repair_injection]
+pub fn count_injections(js: &str) -> usize {
+    let (mut js, mut count) = (js.to_owned(), 0);
+    while let Some((unpatched, _)) = remove_first(&js) {
+        js = unpatched;
+        count += 1;
+    }
+    count
+}
+
+/// Remove every preload block from the contents of a patched javascript file, returning the contents without them and how many were
+/// removed. Returns `None` if a block starts but never ends
+pub fn strip_preload(js: &str) -> Option<(String, usize)> {
+    let (mut js, mut found) = (js.to_owned(), 0);
+    while let Some(start) = js.find(PRELOAD_BEGIN) {
+        let end = start + js[start..].find(PRELOAD_END)? + PRELOAD_END.len();
+        js.replace_range(start..end, "");
+        found += 1;
+    }
+    Some((js, found))
+}
+
+/// Get the preload javascript in the contents of the patched javascript file, returning `None` if it has none
+pub fn injected_preload(js: &str) -> Option<&str> {
+    let start = js.find(PRELOAD_BEGIN)? + PRELOAD_BEGIN.len();
+    let end = start + js[start..].find(PRELOAD_END)?;
+    Some(&js[start..end])
+}
+
+/// Remove the CSS and javascript injection from the contents of the patched javascript file, returning `None` if it isn't there
+pub fn strip_injection(js: &str) -> Option<String> {
+    repair_injection(js).map(|(js, _)| js)
+}
+
+/// Get the custom javascript in the injection in the contents of the patched javascript file, returning `None` if it isn't patched or
+/// was patched without a javascript block
+pub fn injected_js(js: &str) -> Option<&str> {
+    let marker = js.find(INJECTION_MARKER)?;
+    let start = marker + js[marker..].find(JS_SCRIPT_BEGIN)? + JS_SCRIPT_BEGIN.len();
+    let end = start + js[start..].find(JS_SCRIPT_END)?;
+    Some(js[start..end].trim())
+}
+
+/// Get the window title that the first injection in the contents of the patched javascript file sets, as the javascript string literal
+/// that it was inserted as, returning `None` if it isn't patched or doesn't set a title
+pub fn injected_title(js: &str) -> Option<&str> {
+    let marker = js.find(INJECTION_MARKER)?;
+    let start = js[..marker].rfind(INJECTION_START)?;
+    let begin = start + js[start..marker].find(TITLE_BEGIN)? + TITLE_BEGIN.len();
+    let end = begin + js[begin..marker].find(TITLE_END)?;
+    let call = js[begin..end].trim_start().strip_prefix("mainWindow.setTitle(")?;
+    //A string literal can't have a line break in it, so the first one ends the call
+    Some(&call[..call.find(");\n")?])
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoInjectionPoint => write!(f, "The file doesn't create Discord's window, '{}' was not found in it", INJECTION_POINT),
+            Self::AlreadyPatched => write!(f, "The file is already patched"),
+            Self::NotPatched => write!(f, "The file isn't patched"),
+            Self::Unrecognized => write!(f, "The file is patched, but the injection in it isn't one that this program recognizes"),
+            Self::Empty => write!(f, "There is no CSS or custom javascript to insert"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Damage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unquoted => write!(f, "{}", msg!("damage-unquoted")),
+            Self::MissingScriptEnd => write!(f, "{}", msg!("damage-missing-script-end")),
+            Self::ScriptEndBeforeBegin => write!(f, "{}", msg!("damage-script-end-before-begin")),
+            Self::Duplicate(extra) => write!(f, "{}", msg!("damage-duplicate", extra)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::Schedule;
+    use crate::testutil::MAIN_SCREEN_JS;
+
+    /// The window creation code of several Discord versions, from the oldest to the newest layout
+    const VERSIONS: &[(&str, &str)] = &[
+        ("0.0.305", include_str!("../tests/fixtures/versions/0.0.305.js")),
+        ("1.0.9001", MAIN_SCREEN_JS),
+        ("1.0.9015", include_str!("../tests/fixtures/versions/1.0.9015.js")),
+    ];
+
+    #[test]
+    pub fn round_trip() {
+        let css = Css::from("a { content: \"`${x}` \\2014\"; }");
+        let extracted = |js: Option<&str>, preload: Option<&str>| Extracted {
+            css: "a { content: \"`${x}` \\2014\"; }".to_owned(),
+            js: js.map(str::to_owned),
+            preload: preload.map(str::to_owned),
+            strategy: Strategy::ExecuteJs,
+            title: None,
+        };
+        for (version, source) in VERSIONS {
+            let patched = inject(source, &css, Some("console.log(\\`hi\\`);"), None, None, Strategy::ExecuteJs).unwrap();
+            assert_eq!(patched.matches(INJECTION_MARKER).count(), 2, "{}", version);
+            assert_eq!(extract(&patched), Some(extracted(Some("console.log(`hi`);"), None)), "{}", version);
+            assert_eq!(remove(&patched).as_deref(), Ok(*source), "{}", version);
+
+            //Updating replaces the injection instead of adding another one
+            let (updated, damage) = update(&patched, &Css::from("b {}"), None, None, None, Strategy::ExecuteJs).unwrap();
+            assert!(damage.is_empty() && updated.matches(INJECTION_MARKER).count() == 2, "{}", version);
+            assert_eq!(extract(&updated).map(|found| (found.css, found.js)), Some(("b {}".to_owned(), None)), "{}", version);
+            assert_eq!(update(source, &Css::from("b {}"), None, None, None, Strategy::ExecuteJs).unwrap().0, updated, "{}", version);
+            assert_eq!(remove(&updated).as_deref(), Ok(*source), "{}", version);
+
+            //The preload block goes at the top of the file and is replaced and removed along with the rest
+            let preloaded = inject(source, &css, None, Some("globalThis.early = 1;"), None, Strategy::ExecuteJs).unwrap();
+            assert_eq!(extract(&preloaded), Some(extracted(None, Some("globalThis.early = 1;"))), "{}", version);
+            let (updated, _) = update(&preloaded, &css, None, Some("globalThis.early = 2;"), None, Strategy::ExecuteJs).unwrap();
+            assert_eq!(updated.matches(PRELOAD_BEGIN).count(), 1, "{}", version);
+            assert_eq!(extract(&updated).unwrap().preload.as_deref(), Some("globalThis.early = 2;"), "{}", version);
+            assert_eq!(remove(&updated).as_deref(), Ok(*source), "{}", version);
+        }
+    }
+
+    #[test]
+    pub fn preload_placement() {
+        let preloaded = inject(VERSIONS[0].1, &Css::from("a {}"), None, Some("early();"), None, Strategy::ExecuteJs).unwrap();
+        assert!(preloaded.starts_with("'use strict';\n//JS_PRELOAD_BEGIN\nearly();\n//JS_PRELOAD_END\n"));
+        let minified = inject(VERSIONS[2].1, &Css::from("a {}"), None, Some("early();"), None, Strategy::ExecuteJs).unwrap();
+        assert!(minified.starts_with("\"use strict\";//JS_PRELOAD_BEGIN\n"));
+        let plain = inject("mainWindow.webContents.on();", &Css::from("a {}"), None, Some("early();"), None, Strategy::ExecuteJs).unwrap();
+        assert!(plain.starts_with(PRELOAD_BEGIN));
+    }
+
+    #[test]
+    pub fn scheduled_extract() {
+        let css = Css::Scheduled { light: "l".into(), dark: "d".into(), schedule: Schedule::System };
+        let patched = inject(MAIN_SCREEN_JS, &css, None, None, None, Strategy::ExecuteJs).unwrap();
+        assert_eq!(extract(&patched).map(|found| found.css), Some(escape::cook(&css.expression())));
+    }
+
+    #[test]
+    pub fn insert_css_strategy() {
+        let css = Css::from("a { content: \"`${x}` \\2014\"; }");
+        for (version, source) in VERSIONS {
+            //The stylesheet is added with insertCSS, and javascript only runs in the window when there is custom javascript
+            let css_only = inject(source, &css, None, None, None, Strategy::InsertCss).unwrap();
+            assert!(css_only.contains("mainWindow.webContents.insertCSS(CSS_INJECTION_USER_CSS);"), "{}", version);
+            assert!(!css_only.contains("executeJavaScript"), "{}", version);
+            let found = extract(&css_only).unwrap();
+            assert_eq!((found.css.as_str(), found.js, found.strategy), ("a { content: \"`${x}` \\2014\"; }", None, Strategy::InsertCss));
+            assert_eq!(repair_injection(&css_only), Some((source.to_string(), Vec::new())), "{}", version);
+
+            let with_js = inject(source, &css, Some("console.log(\\`hi\\`);"), None, None, Strategy::InsertCss).unwrap();
+            assert_eq!(extract(&with_js).unwrap().js.as_deref(), Some("console.log(`hi`);"), "{}", version);
+            assert!(up_to_date(&with_js, &css, Some("console.log(\\`hi\\`);"), None, None, Strategy::InsertCss), "{}", version);
+            assert_eq!(remove(&with_js).as_deref(), Ok(*source), "{}", version);
+
+            //Switching the strategy replaces the injection made with the other one
+            assert!(!up_to_date(&with_js, &css, Some("console.log(\\`hi\\`);"), None, None, Strategy::ExecuteJs), "{}", version);
+            let (switched, damage) = update(&with_js, &css, None, None, None, Strategy::ExecuteJs).unwrap();
+            assert!(damage.is_empty() && !switched.contains("insertCSS"), "{}", version);
+            let (back, damage) = update(&switched, &css, None, None, None, Strategy::InsertCss).unwrap();
+            assert_eq!((back, damage), (css_only, Vec::new()), "{}", version);
+        }
+
+        let scheduled = Css::Scheduled { light: "l".into(), dark: "d".into(), schedule: Schedule::System };
+        let patched = inject(MAIN_SCREEN_JS, &scheduled, None, None, None, Strategy::InsertCss).unwrap();
+        assert_eq!(extract(&patched).map(|found| found.css), Some(scheduled.main_expression()));
+        assert!(up_to_date(&patched, &scheduled, None, None, None, Strategy::InsertCss));
+    }
+
+    #[test]
+    pub fn collapse_duplicates() {
+        let css = Css::from("a {}");
+        for (name, duplicated, copies) in [
+            ("duplicate.js", include_str!("../tests/fixtures/damaged/duplicate.js"), 2),
+            ("duplicate-apart.js", include_str!("../tests/fixtures/damaged/duplicate-apart.js"), 2),
+            ("triplicate.js", include_str!("../tests/fixtures/damaged/triplicate.js"), 3),
+        ] {
+            assert_eq!(count(duplicated), copies, "{}", name);
+            let (collapsed, damage) = update(duplicated, &css, None, None, None, Strategy::ExecuteJs).unwrap();
+            assert_eq!(damage, vec![Damage::Duplicate(copies - 1)], "{}", name);
+            assert_eq!(collapsed, inject(MAIN_SCREEN_JS, &css, None, None, None, Strategy::ExecuteJs).unwrap(), "{}", name);
+            assert_eq!(count(&collapsed), 1, "{}", name);
+        }
+        assert_eq!(count(MAIN_SCREEN_JS), 0);
+    }
+
+    #[test]
+    pub fn js_only() {
+        let js = Some("reconnect(\\`now\\`);");
+        for (version, source) in VERSIONS {
+            //Only the custom javascript is run, without making a style element or inserting CSS
+            let patched = inject(source, &Css::None, js, None, None, Strategy::ExecuteJs).unwrap();
+            assert!(!patched.contains("createElement('style')") && !patched.contains("insertCSS"), "{}", version);
+            assert_eq!(patched.matches("'dom-ready'").count(), source.matches("'dom-ready'").count() + 1, "{}", version);
+            let found = extract(&patched).unwrap();
+            assert_eq!((found.css.as_str(), found.js.as_deref()), ("", Some("reconnect(`now`);")), "{}", version);
+            assert!(up_to_date(&patched, &Css::None, js, None, None, Strategy::ExecuteJs), "{}", version);
+            assert!(!up_to_date(&patched, &Css::from(""), js, None, None, Strategy::ExecuteJs), "{}", version);
+            assert_eq!(repair_injection(&patched), Some((source.to_string(), Vec::new())), "{}", version);
+            assert_eq!(injected_js(&patched), Some("reconnect(\\`now\\`);"), "{}", version);
+
+            //Applying CSS later replaces the javascript-only injection where it is instead of adding another handler
+            let (themed, damage) = update(&patched, &Css::from("a {}"), js, None, None, Strategy::InsertCss).unwrap();
+            assert!(damage.is_empty() && themed.contains("insertCSS") && !themed.contains(JS_ONLY_MARKER), "{}", version);
+            assert_eq!(themed.matches("'dom-ready'").count(), patched.matches("'dom-ready'").count(), "{}", version);
+            assert_eq!(themed.find("'dom-ready'"), patched.find("'dom-ready'"), "{}", version);
+            assert_eq!(update(&themed, &Css::None, js, None, None, Strategy::ExecuteJs).map(|(js, _)| js), Ok(patched), "{}", version);
+        }
+        assert_eq!(inject(MAIN_SCREEN_JS, &Css::None, None, None, None, Strategy::ExecuteJs), Err(Error::Empty));
+    }
+
+    #[test]
+    pub fn up_to_date_payload() {
+        let css = Css::from("a { content: \"`\"; }");
+        let patched = inject(MAIN_SCREEN_JS, &css, Some("console.log(\\`hi\\`);"), Some("early();"), None, Strategy::ExecuteJs).unwrap();
+        assert!(up_to_date(&patched, &css, Some("console.log(\\`hi\\`);"), Some("early();"), None, Strategy::ExecuteJs));
+        let scheduled = Css::Scheduled { light: "l".into(), dark: "d".into(), schedule: Schedule::System };
+        assert!(up_to_date(&inject(MAIN_SCREEN_JS, &scheduled, None, None, None, Strategy::ExecuteJs).unwrap(), &scheduled, None, None, None, Strategy::ExecuteJs));
+
+        //Any difference in the CSS or either javascript block means that the file has to be written
+        assert!(!up_to_date(&patched, &Css::from("b {}"), Some("console.log(\\`hi\\`);"), Some("early();"), None, Strategy::ExecuteJs));
+        assert!(!up_to_date(&patched, &css, None, Some("early();"), None, Strategy::ExecuteJs));
+        assert!(!up_to_date(&patched, &css, Some("console.log(\\`hi\\`);"), None, None, Strategy::ExecuteJs));
+
+        //A file without an injection is never up to date
+        assert!(!up_to_date(MAIN_SCREEN_JS, &css, None, None, None, Strategy::ExecuteJs));
+        assert!(!up_to_date(MAIN_SCREEN_JS, &Css::from(""), None, None, None, Strategy::ExecuteJs));
+    }
+
+    #[test]
+    pub fn window_title() {
+        let title = "Discord \"Classic\" `${x}`";
+        let patched = inject(MAIN_SCREEN_JS, &Css::from("a {}"), Some("console.log(1);"), None, Some(title), Strategy::ExecuteJs).unwrap();
+        assert_eq!(patched, include_str!("../tests/fixtures/title/execute-js.js"));
+        let inserted = inject(MAIN_SCREEN_JS, &Css::from("a {}"), None, None, Some("Discord"), Strategy::InsertCss).unwrap();
+        assert_eq!(inserted, include_str!("../tests/fixtures/title/insert-css.js"));
+
+        let found = extract(&patched).unwrap();
+        assert_eq!((found.title.as_deref(), found.js.as_deref()), (Some(title), Some("console.log(1);")));
+        assert_eq!(extract(&inserted).unwrap().title.as_deref(), Some("Discord"));
+        assert!(up_to_date(&patched, &Css::from("a {}"), Some("console.log(1);"), None, Some(title), Strategy::ExecuteJs));
+        assert!(!up_to_date(&patched, &Css::from("a {}"), Some("console.log(1);"), None, None, Strategy::ExecuteJs));
+        assert_eq!(remove(&patched).as_deref(), Ok(MAIN_SCREEN_JS));
+
+        //Without a title the injection is exactly what it was before the option existed
+        let untitled = inject(MAIN_SCREEN_JS, &Css::from("a {}"), None, None, None, Strategy::ExecuteJs).unwrap();
+        assert_eq!(untitled, MAIN_SCREEN_JS.replacen(INJECTION_POINT, &block(&Css::from("a {}"), None, Strategy::ExecuteJs), 1));
+        assert!(!untitled.contains(TITLE_BEGIN) && extract(&untitled).unwrap().title.is_none());
+        let (updated, _) = update(&patched, &Css::from("a {}"), None, None, None, Strategy::ExecuteJs).unwrap();
+        assert_eq!(updated, untitled);
+    }
+
+    #[test]
+    pub fn errors() {
+        let patched = inject(MAIN_SCREEN_JS, &Css::from("a {}"), None, None, None, Strategy::ExecuteJs).unwrap();
+        assert_eq!(inject(&patched, &Css::from("a {}"), None, None, None, Strategy::ExecuteJs), Err(Error::AlreadyPatched));
+        assert_eq!(inject("window.show();", &Css::from("a {}"), None, None, None, Strategy::ExecuteJs), Err(Error::NoInjectionPoint));
+        assert_eq!(update("window.show();", &Css::from("a {}"), None, None, None, Strategy::ExecuteJs), Err(Error::NoInjectionPoint));
+        assert_eq!(remove(MAIN_SCREEN_JS), Err(Error::NotPatched));
+        assert_eq!(extract(MAIN_SCREEN_JS), None);
+
+        //An injection whose end was cut off can't be told apart from the rest of the file
+        let cut = &patched[..patched.find("});mainWindow.webContents.").unwrap()];
+        assert_eq!(remove(cut), Err(Error::Unrecognized));
+        assert_eq!(update(cut, &Css::from("a {}"), None, None, None, Strategy::ExecuteJs), Err(Error::Unrecognized));
+        let unclosed = format!("{}early();\n{}", PRELOAD_BEGIN, MAIN_SCREEN_JS);
+        assert_eq!(remove(&unclosed), Err(Error::Unrecognized));
+    }
+
+    #[test]
+    pub fn strip() {
+        let patched = MAIN_SCREEN_JS.replacen(INJECTION_POINT, &injection("a { b: c }", Some("let x = 1;")), 1);
+        assert_ne!(patched, MAIN_SCREEN_JS);
+        assert_eq!(strip_injection(&patched).unwrap(), MAIN_SCREEN_JS);
+        assert_eq!(strip_injection(MAIN_SCREEN_JS), None);
+        assert_eq!(injected_js(&patched), Some("let x = 1;"));
+
+        //Injections made with --no-js have no javascript block at all
+        let css_only = MAIN_SCREEN_JS.replacen(INJECTION_POINT, &injection("a { b: c }", None), 1);
+        assert!(!css_only.contains(JS_SCRIPT_BEGIN) && !css_only.contains(JS_SCRIPT_END));
+        assert_eq!(injected_js(&css_only), None);
+        assert_eq!(strip_injection(&css_only).unwrap(), MAIN_SCREEN_JS);
+    }
+
+    #[test]
+    pub fn repair_damaged() {
+        let fixtures = [
+            (include_str!("../tests/fixtures/damaged/unclosed.js"), vec![Damage::Unquoted]),
+            (include_str!("../tests/fixtures/damaged/missing-script-end.js"), vec![Damage::MissingScriptEnd]),
+            (include_str!("../tests/fixtures/damaged/script-end-before-begin.js"), vec![Damage::ScriptEndBeforeBegin]),
+            (include_str!("../tests/fixtures/damaged/duplicate.js"), vec![Damage::Duplicate(1)]),
+            (include_str!("../tests/fixtures/damaged/duplicate-apart.js"), vec![Damage::Duplicate(1)]),
+            (include_str!("../tests/fixtures/damaged/triplicate.js"), vec![Damage::Duplicate(2)]),
+        ];
+        for (damaged, expected) in fixtures.iter() {
+            assert_eq!(repair_injection(damaged), Some((MAIN_SCREEN_JS.to_owned(), expected.clone())));
+        }
+
+        //A clean injection has nothing to repair, and an injection whose end is gone can't be found at all
+        let patched = MAIN_SCREEN_JS.replacen(INJECTION_POINT, &injection("a { b: c }", Some("let x = 1;")), 1);
+        assert_eq!(repair_injection(&patched), Some((MAIN_SCREEN_JS.to_owned(), vec![])));
+        assert_eq!(repair_injection(&patched[..patched.find(INJECTION_END).unwrap()]), None);
+    }
+}
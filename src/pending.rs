@@ -0,0 +1,127 @@
+//! The `pending` module detects updates that Discord downloaded but hasn't installed yet. Discord installs them the next time that
+//! it starts, which replaces the patched `core.asar` with a fresh copy and makes the theme disappear right after it was applied. The
+//! old updater leaves downloaded modules in a `pending` folder, the new updater records them in `installer.db`, and Squirrel keeps
+//! downloaded Discord versions in the `packages` folder
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// The folders, relative to the version folder, that downloaded module updates wait in until Discord restarts
+const PENDING_DIRS: &[&str] = &["modules/pending", "pending"];
+
+/// The name of the new updater's database, which is in the root or version folder
+const UPDATER_DB: &str = "installer.db";
+
+/// The folder in the root folder that Squirrel downloads new Discord versions to on Windows
+const PACKAGES_DIR: &str = "packages";
+
+/// The `Marker` enum is how a pending update was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Marker {
+    /// A folder of downloaded modules that will be installed when Discord restarts
+    PendingDir,
+    /// The updater's database changed after the patched archive was last written
+    UpdaterDb,
+    /// A newer Discord version was downloaded, which will be installed to a new version folder
+    NewVersion(String),
+}
+
+/// The `Pending` struct is one update that is waiting for Discord to restart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pending {
+    pub path: PathBuf,
+    pub marker: Marker,
+}
+
+/// Check if a folder exists and has anything in it
+fn has_entries(dir: &Path) -> bool {
+    fs::read_dir(dir).map(|mut entries| entries.next().is_some()).unwrap_or(false)
+}
+
+/// Get when a file was last changed, if it exists
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Get the Discord version of a Squirrel package like `Discord-1.0.9005-full.nupkg`
+fn package_version(name: &str) -> Option<semver::Version> {
+    let stem = name.strip_suffix("-full.nupkg").or_else(|| name.strip_suffix("-delta.nupkg"))?;
+    semver::Version::parse(stem.rsplit('-').next()?).ok()
+}
+
+/// Find the updates that Discord will install the next time it starts, which would undo patching `archive`. `root` is the folder with
+/// every version folder in it and `version` is the installed version's folder, like `app-1.0.9001`
+pub fn detect(root: &Path, version: &Path, archive: &Path) -> Vec<Pending> {
+    let mut found = PENDING_DIRS
+        .iter()
+        .map(|dir| version.join(dir))
+        .filter(|dir| has_entries(dir))
+        .map(|path| Pending { path, marker: Marker::PendingDir })
+        .collect::<Vec<_>>();
+
+    //The database is always there with the new updater, so it only means something if it changed after the archive did
+    let archive_modified = modified(archive);
+    found.extend(
+        vec![root.join(UPDATER_DB), version.join(UPDATER_DB)]
+            .into_iter()
+            .filter(|db| matches!((modified(db), archive_modified), (Some(db), Some(archive)) if db > archive))
+            .map(|path| Pending { path, marker: Marker::UpdaterDb }),
+    );
+
+    let installed = version
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_prefix("app-"))
+        .and_then(|name| semver::Version::parse(name).ok());
+    if let (Some(installed), Ok(packages)) = (installed, fs::read_dir(root.join(PACKAGES_DIR))) {
+        for path in packages.filter_map(|entry| entry.ok().map(|e| e.path())) {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            if let Some(newer) = package_version(&name).filter(|newer| *newer > installed) {
+                found.push(Pending {
+                    path,
+                    marker: Marker::NewVersion(newer.to_string()),
+                });
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, FakeDiscord};
+    use std::time::Duration;
+
+    #[test]
+    pub fn detect_pending() {
+        let fake = FakeDiscord::create();
+        let version = fake.root.join(testutil::VERSION);
+        assert!(detect(&fake.root, &version, &fake.asar()).is_empty());
+
+        //An empty pending folder is left behind after every update is installed
+        fs::create_dir_all(version.join("modules/pending")).unwrap();
+        assert!(detect(&fake.root, &version, &fake.asar()).is_empty());
+        fs::write(version.join("modules/pending/discord_desktop_core-2.zip"), "module").unwrap();
+
+        //The updater database only counts if it changed after the archive was written
+        fs::write(fake.root.join(UPDATER_DB), "db").unwrap();
+        let past = SystemTime::now() - Duration::from_secs(60);
+        fs::File::options().write(true).open(fake.asar()).unwrap().set_modified(past).unwrap();
+
+        fs::create_dir_all(fake.root.join(PACKAGES_DIR)).unwrap();
+        for name in ["Discord-1.2.2-full.nupkg", "Discord-1.2.4-delta.nupkg", "RELEASES"] {
+            fs::write(fake.root.join(PACKAGES_DIR).join(name), "package").unwrap();
+        }
+
+        let found = detect(&fake.root, &version, &fake.asar());
+        assert_eq!(
+            found.iter().map(|pending| pending.marker.clone()).collect::<Vec<_>>(),
+            vec![Marker::PendingDir, Marker::UpdaterDb, Marker::NewVersion("1.2.4".to_owned())]
+        );
+        assert_eq!(found[0].path, version.join("modules/pending"));
+    }
+}
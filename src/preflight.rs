@@ -0,0 +1,193 @@
+//! The `preflight` module checks that patching can finish before anything is changed, so that a full disk or a read-only folder
+//! can't leave Discord half patched. The same checks are shown by the `doctor` command
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{asar, backup, messages::msg};
+
+/// How many times the size of `core.asar` must be free on its disk, for the backup, the re-packed archive, and some room to spare
+pub const SPACE_FACTOR: u64 = 3;
+
+/// The `Check` struct is one preflight check and what went wrong with it, if anything
+#[derive(Debug)]
+pub struct Check {
+    /// What was checked, like "core.asar is not empty"
+    pub name: String,
+    /// Why the check failed, or `None` if it passed
+    pub failure: Option<Failure>,
+}
+
+/// The `Failure` enum is every way that a preflight check can fail
+#[derive(Debug)]
+pub enum Failure {
+    /// A file can't be created in a folder that is written to while patching
+    NotWritable { dir: PathBuf, err: io::Error },
+    /// A file in the work folder can't be renamed to the folder that `core.asar` is in, usually because they are on different drives
+    NoRename { work: PathBuf, dir: PathBuf, err: io::Error },
+    /// There isn't enough free space on the disk that `core.asar` is on
+    LowSpace { dir: PathBuf, needed: u64, available: u64 },
+    /// `core.asar` is empty, which means that a previous run or a Discord update was interrupted
+    EmptyArchive { path: PathBuf },
+    /// `core.asar` isn't empty but isn't a readable archive either, usually because it was cut off part of the way through
+    CorruptArchive { path: PathBuf, err: asar::Error },
+    /// `core.asar` or its disk couldn't be read
+    IOErr { path: PathBuf, err: io::Error },
+}
+
+/// Check that `asar` isn't empty, that it is an archive that can be read, that there are [SPACE_FACTOR] times its size free on its disk, that every folder in `writable`
+/// can be written to, and that a file in the `work` folder can be renamed over it
+pub fn run(asar: &Path, writable: &[&Path], work: &Path) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let name = msg!("preflight-check-not-empty", asar.display());
+    let size = match fs::metadata(asar) {
+        Ok(meta) => {
+            checks.push(Check {
+                name,
+                failure: match meta.len() {
+                    0 => Some(Failure::EmptyArchive { path: asar.to_owned() }),
+                    _ => None,
+                },
+            });
+            Some(meta.len())
+        }
+        Err(err) => {
+            checks.push(Check {
+                name,
+                failure: Some(Failure::IOErr { path: asar.to_owned(), err }),
+            });
+            None
+        }
+    };
+
+    //Patching or backing up an archive that can't be read would only make things worse
+    if let Some(1..) = size {
+        checks.push(Check {
+            name: msg!("preflight-check-valid-archive", asar.display()),
+            failure: fs::File::open(asar)
+                .map_err(asar::Error::from)
+                .and_then(|mut file| asar::Archive::read(&mut file))
+                .err()
+                .map(|err| Failure::CorruptArchive { path: asar.to_owned(), err }),
+        });
+    }
+
+    if let (Some(size), Some(dir)) = (size, asar.parent()) {
+        checks.push(Check {
+            name: msg!("preflight-check-free-space", dir.display()),
+            failure: match fs2::available_space(dir) {
+                Ok(available) => check_space(dir, size, available),
+                Err(err) => Some(Failure::IOErr { path: dir.to_owned(), err }),
+            },
+        });
+    }
+
+    for dir in writable {
+        checks.push(Check {
+            name: msg!("preflight-check-writable", dir.display()),
+            failure: tempfile::NamedTempFile::new_in(dir)
+                .err()
+                .map(|err| Failure::NotWritable { dir: dir.to_path_buf(), err }),
+        });
+    }
+
+    //The patched archive is written in the work folder and renamed over the real one, which only works on the same drive
+    if let Some(dir) = asar.parent().filter(|dir| *dir != work) {
+        checks.push(Check {
+            name: msg!("preflight-check-rename", work.display(), dir.display()),
+            failure: backup::probe_rename(work, dir)
+                .err()
+                .map(|err| Failure::NoRename { work: work.to_owned(), dir: dir.to_owned(), err }),
+        });
+    }
+    checks
+}
+
+/// Check that `available` bytes are enough to patch an archive of `size` bytes in `dir`
+fn check_space(dir: &Path, size: u64, available: u64) -> Option<Failure> {
+    let needed = size.saturating_mul(SPACE_FACTOR);
+    match available < needed {
+        true => Some(Failure::LowSpace {
+            dir: dir.to_owned(),
+            needed,
+            available,
+        }),
+        false => None,
+    }
+}
+
+impl Failure {
+    /// Check if this failure means that a folder can only be written to by another user, usually because Discord was installed
+    /// system-wide by a package manager, which running as an administrator can get around
+    pub fn read_only(&self) -> bool {
+        matches!(self, Self::NotWritable { err, .. } if err.kind() == io::ErrorKind::PermissionDenied)
+    }
+
+    /// Check if this failure means that `core.asar` itself is broken, which restoring a backup of it can fix
+    pub const fn broken_archive(&self) -> bool {
+        matches!(self, Self::EmptyArchive { .. } | Self::CorruptArchive { .. })
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotWritable { dir, err } => write!(f, "{}", msg!("preflight-not-writable", dir.display(), err)),
+            Self::NoRename { work, dir, err } => write!(f, "{}", msg!("preflight-no-rename", work.display(), dir.display(), err)),
+            Self::LowSpace { dir, needed, available } => write!(f, "{}", msg!("preflight-low-space", available, dir.display(), needed)),
+            Self::EmptyArchive { path } => write!(f, "{}", msg!("preflight-empty-archive", path.display())),
+            Self::CorruptArchive { path, err } => write!(f, "{}", msg!("preflight-corrupt-archive", path.display(), err)),
+            Self::IOErr { path, err } => write!(f, "{}", msg!("preflight-io-error", path.display(), err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn free_space() {
+        let dir = Path::new("discord");
+        assert!(check_space(dir, 100, 300).is_none());
+        assert!(matches!(check_space(dir, 100, 299), Some(Failure::LowSpace { needed: 300, .. })));
+        assert!(check_space(dir, u64::MAX, u64::MAX).is_none());
+    }
+
+    #[test]
+    pub fn failed_checks() {
+        let dir = tempfile::tempdir().unwrap();
+        let asar = dir.path().join("core.asar");
+        fs::write(&asar, b"").unwrap();
+        let missing = dir.path().join("missing");
+
+        let checks = run(&asar, &[dir.path(), &missing], dir.path());
+        assert!(matches!(checks[0].failure, Some(Failure::EmptyArchive { .. })));
+        assert!(checks.iter().any(|check| check.name.contains("is writable") && check.failure.is_none()));
+        assert!(matches!(checks.last().unwrap().failure, Some(Failure::NotWritable { .. })));
+
+        //A cut off archive isn't empty but can't be patched either
+        fs::write(&asar, b"archive").unwrap();
+        let checks = run(&asar, &[dir.path()], dir.path());
+        assert!(matches!(checks[1].failure, Some(Failure::CorruptArchive { .. })));
+        assert!(checks[1].failure.as_ref().unwrap().broken_archive());
+
+        //Folders that belong to root can only be written to as an administrator
+        let denied = Failure::NotWritable { dir: missing.clone(), err: io::ErrorKind::PermissionDenied.into() };
+        let gone = Failure::NotWritable { dir: missing, err: io::ErrorKind::NotFound.into() };
+        assert!(denied.read_only() && !gone.read_only());
+
+        let mut archive = asar::Archive::new();
+        archive.add_dir("app");
+        archive.pack(&mut fs::File::create(&asar).unwrap(), false).unwrap();
+        assert!(run(&asar, &[dir.path()], dir.path()).iter().all(|check| check.failure.is_none()));
+
+        //A work folder that files can't be renamed out of fails the check and the patch never starts
+        let work = tempfile::tempdir().unwrap();
+        assert!(run(&asar, &[dir.path()], work.path()).iter().all(|check| check.failure.is_none()));
+        let checks = run(&asar, &[dir.path()], &work.path().join("gone"));
+        assert!(matches!(checks.last().unwrap().failure, Some(Failure::NoRename { .. })));
+    }
+}
@@ -0,0 +1,203 @@
+//! The `preview` module serves a theme on a local web page with markup like Discord's main window, so that a theme can be tried
+//! without patching Discord. The page is the checked in snapshot [PAGE], which loads the theme from `/theme.css` and asks for
+//! `/revision` twice a second, loading the theme again when the revision changes. [Watch] notices when the theme file is saved
+
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// The page with Discord's markup that the theme is applied to
+pub const PAGE: &str = include_str!("../assets/preview.html");
+
+/// The port that the preview is served on unless `--port` is given
+pub const DEFAULT_PORT: u16 = 7623;
+
+/// How often the theme file is checked for changes
+pub const POLL: Duration = Duration::from_millis(300);
+
+/// The `Sheet` struct is the theme that is being served and how many times it was changed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sheet {
+    /// The theme with its variables set
+    pub css: String,
+    /// Counts up every time the theme changes, so that the page knows to load it again
+    pub revision: u64,
+}
+
+/// The `Response` struct is what is sent back for one request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    /// The status line after the HTTP version, like `200 OK`
+    pub status: &'static str,
+    /// The `Content-Type` header
+    pub content_type: &'static str,
+    /// The body
+    pub body: Vec<u8>,
+}
+
+/// Get the response to a `GET` request for `path`, ignoring its query string
+pub fn response(path: &str, sheet: &Sheet) -> Response {
+    let ok = |content_type, body: &[u8]| Response { status: "200 OK", content_type, body: body.to_vec() };
+    match path.split('?').next().unwrap_or_default() {
+        "/" | "/index.html" => ok("text/html; charset=utf-8", PAGE.as_bytes()),
+        "/theme.css" => ok("text/css; charset=utf-8", sheet.css.as_bytes()),
+        "/revision" => ok("text/plain; charset=utf-8", sheet.revision.to_string().as_bytes()),
+        _ => Response { status: "404 Not Found", content_type: "text/plain; charset=utf-8", body: b"not found".to_vec() },
+    }
+}
+
+/// The `Server` struct is the preview's HTTP server, which only accepts connections from this computer
+#[derive(Debug)]
+pub struct Server {
+    listener: TcpListener,
+    addr: SocketAddr,
+    sheet: Arc<Mutex<Sheet>>,
+}
+
+impl Server {
+    /// Listen on `port` on the loopback address, or on any free port if it is 0, serving the theme `css`
+    pub fn bind(port: u16, css: String) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        let addr = listener.local_addr()?;
+        Ok(Self { listener, addr, sheet: Arc::new(Mutex::new(Sheet { css, revision: 0 })) })
+    }
+
+    /// Get the URL to open the preview at
+    pub fn url(&self) -> String {
+        format!("http://{}/", self.addr)
+    }
+
+    /// Serve a changed theme, which the page loads the next time it asks for the revision
+    pub fn update(&self, css: String) {
+        let mut sheet = self.sheet.lock().unwrap_or_else(|e| e.into_inner());
+        sheet.css = css;
+        sheet.revision += 1;
+    }
+
+    /// Answer requests on another thread until the program exits. Every connection is answered on its own thread so that a browser
+    /// holding one open doesn't stop the others
+    pub fn spawn(&self) -> io::Result<thread::JoinHandle<()>> {
+        let listener = self.listener.try_clone()?;
+        let sheet = self.sheet.clone();
+        Ok(thread::spawn(move || {
+            for stream in listener.incoming().filter_map(Result::ok) {
+                let sheet = sheet.clone();
+                thread::spawn(move || {
+                    let _ = handle(stream, &sheet);
+                });
+            }
+        }))
+    }
+}
+
+/// Read one request from `stream` and answer it
+fn handle(stream: TcpStream, sheet: &Mutex<Sheet>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    //The headers are read and ignored so that the browser doesn't see the connection reset before it finished sending them
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let res = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) => response(path, &sheet.lock().unwrap_or_else(|e| e.into_inner())),
+        _ => Response { status: "405 Method Not Allowed", content_type: "text/plain; charset=utf-8", body: b"only GET".to_vec() },
+    };
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        res.status,
+        res.content_type,
+        res.body.len()
+    )?;
+    stream.write_all(&res.body)?;
+    stream.flush()
+}
+
+/// The `Watch` struct notices when a file is saved by checking its modification time and size
+#[derive(Debug)]
+pub struct Watch {
+    path: PathBuf,
+    seen: Option<(SystemTime, u64)>,
+}
+
+impl Watch {
+    /// Start watching the file at `path` as it is now
+    pub fn new(path: &Path) -> Self {
+        Self { path: path.to_owned(), seen: stamp(path) }
+    }
+
+    /// Check if the file changed since this was last called. A file that is missing, like while an editor replaces it, is not a change
+    pub fn changed(&mut self) -> bool {
+        match stamp(&self.path) {
+            Some(now) if Some(now) != self.seen => {
+                self.seen = Some(now);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Get the modification time and size of a file
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::FakeDiscord;
+    use std::io::Read;
+
+    fn get(server: &Server, path: &str) -> String {
+        let mut stream = TcpStream::connect(server.addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut res = String::new();
+        stream.read_to_string(&mut res).unwrap();
+        res
+    }
+
+    #[test]
+    pub fn serve_and_reload() {
+        let server = Server::bind(0, "a { color: red; }".to_owned()).unwrap();
+        assert!(server.url().starts_with("http://127.0.0.1:"));
+        server.spawn().unwrap();
+
+        assert!(get(&server, "/").contains("href=\"/theme.css\""));
+        let css = get(&server, "/theme.css");
+        assert!(css.starts_with("HTTP/1.1 200 OK\r\n") && css.contains("text/css") && css.ends_with("\r\n\r\na { color: red; }"));
+        assert!(get(&server, "/revision").ends_with("\r\n\r\n0"));
+        assert!(get(&server, "/missing").starts_with("HTTP/1.1 404"));
+
+        server.update("a { color: blue; }".to_owned());
+        assert!(get(&server, "/revision").ends_with("\r\n\r\n1"));
+        assert!(get(&server, "/theme.css?r=1").ends_with("a { color: blue; }"));
+    }
+
+    #[test]
+    pub fn watch_file() {
+        let fake = FakeDiscord::create();
+        let path = fake.dir("theme.css");
+        fs::write(&path, "a {}").unwrap();
+        let mut watch = Watch::new(&path);
+        assert!(!watch.changed());
+
+        fs::write(&path, "a { color: red; }").unwrap();
+        assert!(watch.changed());
+        assert!(!watch.changed());
+        fs::remove_file(&path).unwrap();
+        assert!(!watch.changed());
+    }
+}
@@ -0,0 +1,147 @@
+//! The `schedule` module inserts a light and a dark theme into Discord at once, along with a few lines of javascript that pick one of
+//! them when Discord's window loads. The dark theme is picked during the hours given by the `dark-hours` option, or when the system
+//! prefers a dark color scheme if that isn't set
+
+use crate::escape;
+
+/// The `Schedule` enum is when the dark theme of a [Css::Scheduled] theme is used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    /// Follow the `prefers-color-scheme` of the system
+    System,
+    /// Use the dark theme from the hour `dark` until the hour `light`, like from 19:00 until 7:00
+    Hours { dark: u32, light: u32 },
+}
+
+/// The `Css` enum is the CSS that is inserted into Discord
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Css {
+    /// One theme that is always used
+    Single(String),
+    /// A light and a dark theme, picked by the [Schedule] every time Discord's window loads
+    Scheduled { light: String, dark: String, schedule: Schedule },
+    /// No theme, for an injection that only runs custom javascript
+    None,
+}
+
+impl Schedule {
+    /// Parse the `dark-hours` option, like `19-7` for dark from 19:00 until 7:00. No hours means following the system
+    pub fn parse(hours: Option<&str>) -> Result<Self, String> {
+        let hours = match hours {
+            Some(hours) => hours,
+            None => return Ok(Self::System),
+        };
+        let parse_hour = |hour: &str| hour.trim().parse::<u32>().ok().filter(|hour| *hour < 24);
+        match hours.split_once('-').map(|(dark, light)| (parse_hour(dark), parse_hour(light))) {
+            Some((Some(dark), Some(light))) if dark != light => Ok(Self::Hours { dark, light }),
+            Some((Some(_), Some(_))) => Err(format!("the dark hours '{}' start and end at the same hour", hours)),
+            _ => Err(format!("expected hours from 0 to 23 like \"19-7\", found '{}'", hours)),
+        }
+    }
+
+    /// Get the hours written like the `dark-hours` option, or `None` for following the system
+    pub fn hours(&self) -> Option<String> {
+        match self {
+            Self::System => None,
+            Self::Hours { dark, light } => Some(format!("{}-{}", dark, light)),
+        }
+    }
+
+    /// Get the javascript condition that is true when the dark theme should be used, which runs in Discord's window if `window` is set
+    /// and in its main process otherwise
+    fn condition(&self, window: bool) -> String {
+        match self {
+            Self::System if window => "window.matchMedia('(prefers-color-scheme: dark)').matches".to_owned(),
+            Self::System => "require('electron').nativeTheme.shouldUseDarkColors".to_owned(),
+            //A range that wraps around midnight is dark after it starts or before it ends
+            Self::Hours { dark, light } => format!(
+                "((hour) => hour >= {} {} hour < {})(new Date().getHours())",
+                dark,
+                if dark > light { "||" } else { "&&" },
+                light
+            ),
+        }
+    }
+}
+
+impl Css {
+    /// Get every stylesheet in this CSS
+    pub fn sheets(&self) -> Vec<&str> {
+        match self {
+            Self::Single(css) => vec![css],
+            Self::Scheduled { light, dark, .. } => vec![light, dark],
+            Self::None => Vec::new(),
+        }
+    }
+
+    /// Get how many bytes of CSS there are before escaping
+    pub fn bytes(&self) -> usize {
+        self.sheets().iter().map(|css| css.len()).sum()
+    }
+
+    /// Get the javascript expression that evaluates to the CSS to insert, with every stylesheet escaped by [escape::css_string]
+    pub fn expression(&self) -> String {
+        match self {
+            Self::Single(css) => escape::css_string(css),
+            Self::Scheduled { light, dark, schedule } => format!(
+                "({}) ? {} : {}",
+                schedule.condition(true),
+                escape::css_string(dark),
+                escape::css_string(light)
+            ),
+            Self::None => String::new(),
+        }
+    }
+
+    /// Get the javascript expression that evaluates to the CSS to insert in Discord's main process, which isn't inside of a template
+    /// literal so every stylesheet is only escaped by [escape::js_string]
+    pub fn main_expression(&self) -> String {
+        match self {
+            Self::Single(css) => escape::js_string(css),
+            Self::Scheduled { light, dark, schedule } => format!(
+                "({}) ? {} : {}",
+                schedule.condition(false),
+                escape::js_string(dark),
+                escape::js_string(light)
+            ),
+            Self::None => String::new(),
+        }
+    }
+}
+
+impl From<&str> for Css {
+    fn from(css: &str) -> Self {
+        Self::Single(css.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn parse_hours() {
+        assert_eq!(Schedule::parse(None), Ok(Schedule::System));
+        assert_eq!(Schedule::parse(Some("19-7")), Ok(Schedule::Hours { dark: 19, light: 7 }));
+        assert_eq!(Schedule::parse(Some(" 0 - 6 ")), Ok(Schedule::Hours { dark: 0, light: 6 }));
+        for invalid in ["19", "19-24", "7-7", "dusk-dawn", ""] {
+            assert!(Schedule::parse(Some(invalid)).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    pub fn expressions() {
+        assert_eq!(Css::from("a { b: c }").expression(), "\"a { b: c }\"");
+        let scheduled = |schedule| Css::Scheduled { light: "light".into(), dark: "dark".into(), schedule };
+        assert_eq!(
+            scheduled(Schedule::Hours { dark: 19, light: 7 }).expression(),
+            "(((hour) => hour >= 19 || hour < 7)(new Date().getHours())) ? \"dark\" : \"light\""
+        );
+        assert!(scheduled(Schedule::Hours { dark: 1, light: 6 }).expression().contains("hour >= 1 && hour < 6"));
+        assert!(scheduled(Schedule::System).expression().contains("prefers-color-scheme: dark"));
+        assert_eq!(scheduled(Schedule::System).bytes(), 9);
+        assert_eq!(Css::from("a { content: \"`\"; }").main_expression(), "\"a { content: \\\"`\\\"; }\"");
+        assert!(scheduled(Schedule::System).main_expression().contains("nativeTheme.shouldUseDarkColors"));
+        assert_eq!((Css::None.expression(), Css::None.bytes()), (String::new(), 0));
+    }
+}
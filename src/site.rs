@@ -0,0 +1,207 @@
+//! The `site` module finds where the theme is injected. Older Discord builds create their main window in `mainScreen.js` inside of
+//! `core.asar`, but newer ones move that code into `resources/app.asar` and leave `discord_desktop_core` as a thin shim, where
+//! patching `core.asar` has no visible effect. Both archives are searched for the window creation code and the one that has it is
+//! patched. The [Site] that was patched is recorded in the manifest so that restoring works on the same archive
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{asar, MAIN_SCREEN};
+
+/// The text in the window creation code that the injection is inserted in place of
+pub const INJECTION_POINT: &str = "mainWindow.webContents.";
+
+/// The variable in the injection that holds the CSS theme, which is only in files that were already patched
+pub const INJECTION_MARKER: &str = "CSS_INJECTION_USER_CSS";
+
+/// The `Kind` enum is which of Discord's archives is patched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    /// `core.asar` in the `discord_desktop_core` folder, which older Discord builds create their window in
+    Core,
+    /// `app.asar` in Discord's `resources` folder, which newer Discord builds create their window in
+    App,
+}
+
+impl Kind {
+    /// Get the file name of this kind of archive
+    pub const fn file_name(&self) -> &'static str {
+        match self {
+            Self::Core => "core.asar",
+            Self::App => "app.asar",
+        }
+    }
+
+    /// Get the path of this kind of archive from the `discord_desktop_core` folder and Discord's `resources` folder
+    pub fn path(&self, core: &Path, resources: &Path) -> PathBuf {
+        match self {
+            Self::Core => core.join(self.file_name()),
+            Self::App => resources.join(self.file_name()),
+        }
+    }
+}
+
+/// The `Layout` enum is how the folders of a Discord installation are arranged, which is recorded in the manifest so that restoring
+/// looks for the patched archive in the same place
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    /// Discord's files are in `app-x.y.z` version folders in the root folder, like every official Discord installation
+    Versioned,
+    /// Discord's files are directly in the root folder without any version folders, like portable builds and repacks of Discord
+    Portable,
+}
+
+impl Default for Layout {
+    /// Every installation was versioned before portable builds could be patched
+    fn default() -> Self {
+        Self::Versioned
+    }
+}
+
+impl Layout {
+    /// Get the name of this layout as it is written in the manifest
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Versioned => "versioned",
+            Self::Portable => "portable",
+        }
+    }
+}
+
+/// The `Site` struct is the archive and the javascript file in it that the theme is injected into
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Site {
+    pub archive: Kind,
+    /// The path of the javascript file inside of the archive, like `app/mainScreen.js`
+    pub file: String,
+}
+
+impl Default for Site {
+    /// `mainScreen.js` in `core.asar`, which every Discord build used before the window creation code moved
+    fn default() -> Self {
+        Self {
+            archive: Kind::Core,
+            file: MAIN_SCREEN.to_owned(),
+        }
+    }
+}
+
+impl Site {
+    /// Get the path of this site's archive, see [Kind::path]
+    pub fn path(&self, core: &Path, resources: &Path) -> PathBuf {
+        self.archive.path(core, resources)
+    }
+}
+
+/// Find the javascript file in an archive that creates Discord's main window, which is a file that was already patched or else the
+/// first file that contains the [INJECTION_POINT]. `mainScreen.js` is checked first because that is where it has always been
+pub fn find_file(archive: &asar::Archive) -> Option<String> {
+    let files = archive.files();
+    let scripts = || {
+        let main = files.iter().filter(|(path, _)| path == MAIN_SCREEN);
+        let others = files.iter().filter(|(path, _)| path != MAIN_SCREEN && path.ends_with(".js"));
+        main.chain(others)
+    };
+    let contains = |file: &asar::FileEntry, needle: &str| {
+        let needle = needle.as_bytes();
+        file.as_ref().windows(needle.len()).any(|window| window == needle)
+    };
+    scripts()
+        .find(|(_, file)| contains(file, INJECTION_MARKER))
+        .or_else(|| scripts().find(|(_, file)| contains(file, INJECTION_POINT)))
+        .map(|(path, _)| path.clone())
+}
+
+/// Search `core.asar` in the `discord_desktop_core` folder and then `app.asar` in the `resources` folder for the window creation code,
+/// returning `None` if neither archive has it
+pub fn probe(core: &Path, resources: &Path) -> Result<Option<Site>, asar::Error> {
+    for kind in [Kind::Core, Kind::App] {
+        let path = kind.path(core, resources);
+        //Portable builds may have only one of the archives
+        if !path.exists() {
+            continue;
+        }
+        let archive = asar::Archive::read(&mut fs::File::open(&path)?)?;
+        if let Some(file) = find_file(&archive) {
+            return Ok(Some(Site { archive: kind, file }));
+        }
+    }
+    Ok(None)
+}
+
+/// Find the highest numbered `discord_desktop_core-N/discord_desktop_core` folder in a `modules` folder. Both `discord_desktop_core-1`
+/// and `discord_desktop_core-2` are possible since Discord's new updater, so the highest number is the one that is used
+pub fn desktop_core(modules: &Path) -> io::Result<Option<PathBuf>> {
+    Ok(fs::read_dir(modules)?
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name();
+            let num = name.to_str()?.strip_prefix("discord_desktop_core-")?.parse::<u8>().ok()?;
+            Some((num, entry.path().join("discord_desktop_core")))
+        })
+        .max_by_key(|(num, _)| *num)
+        .map(|(_, path)| path))
+}
+
+/// Find the `discord_desktop_core` folder of a Discord installation without `app-x.y.z` version folders, like a portable build, where
+/// `modules` and `resources` are directly in the root folder. The root folder itself is returned when only an archive in `resources`
+/// has the window creation code, and `None` if there is nothing in the folder that the theme could be injected into
+pub fn portable_core(root: &Path) -> Option<PathBuf> {
+    if let Ok(Some(core)) = desktop_core(&root.join("modules")) {
+        if core.join(Kind::Core.file_name()).is_file() {
+            return Some(core);
+        }
+    }
+    let resources = root.join("resources");
+    let mut archives = fs::read_dir(&resources).ok()?.filter_map(Result::ok);
+    if !archives.any(|entry| entry.path().extension().is_some_and(|ext| ext == "asar")) {
+        return None;
+    }
+    probe(root, &resources).ok().flatten().map(|_| root.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, FakeDiscord};
+
+    #[test]
+    pub fn probe_archives() {
+        let fake = FakeDiscord::create();
+        let resources = fake.root.join(testutil::VERSION).join("resources");
+        assert_eq!(probe(&fake.core(), &resources).unwrap(), Some(Site::default()));
+
+        //A thin core.asar shim with the window created in app.asar
+        fs::write(fake.asar(), testutil::archive("module.exports = require('./app');")).unwrap();
+        assert_eq!(probe(&fake.core(), &resources).unwrap(), None);
+        testutil::app_asar(&fake);
+        let site = probe(&fake.core(), &resources).unwrap().unwrap();
+        assert_eq!(site, Site { archive: Kind::App, file: testutil::APP_WINDOW.to_owned() });
+        assert_eq!(site.path(&fake.core(), &resources), resources.join("app.asar"));
+        assert_eq!(serde_json::from_value::<Site>(serde_json::to_value(&site).unwrap()).unwrap(), site);
+    }
+
+    #[test]
+    pub fn portable_layouts() {
+        let fake = FakeDiscord::portable(testutil::PORTABLE_MODULES);
+        let core = fake.root.join("modules/discord_desktop_core-1/discord_desktop_core");
+        assert_eq!(portable_core(&fake.root), Some(core));
+
+        let fake = FakeDiscord::portable(testutil::PORTABLE_RESOURCES);
+        assert_eq!(portable_core(&fake.root), Some(fake.root.clone()));
+        let site = probe(&fake.root, &fake.root.join("resources")).unwrap().unwrap();
+        assert_eq!(site.archive, Kind::App);
+
+        //An app.asar that doesn't create Discord's window can't be patched, and neither can an installation with version folders
+        fs::write(fake.root.join("resources/app.asar"), testutil::archive("module.exports = {};")).unwrap();
+        assert_eq!(portable_core(&fake.root), None);
+        assert_eq!(portable_core(&FakeDiscord::create().root), None);
+        assert_eq!(serde_json::to_value(Layout::Portable).unwrap(), Layout::Portable.name());
+    }
+}
@@ -0,0 +1,116 @@
+//! The `symlink` module finds out if Discord's archive is reached through a symbolic link. Some Linux packages and mod managers link
+//! `core.asar` or the whole version folder to somewhere else, and writing the backup and renaming the patched archive next to the
+//! link would replace the link with a file instead of changing the archive it points to. The archive is always changed where it
+//! really is, and the link is recorded in the manifest so that restoring goes to the same place
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// The `Link` struct is an archive that is reached through a symbolic link
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Link {
+    /// The path to the archive through the link, which is where Discord is found
+    pub path: PathBuf,
+    /// The symbolic link that was found, which is the archive itself or one of the folders that it is in
+    pub link: PathBuf,
+    /// Where the archive really is with every link followed
+    pub target: PathBuf,
+    /// If the target is outside of Discord's installation
+    #[serde(default)]
+    pub outside: bool,
+}
+
+/// Check if `path` or any folder that it is in, up to and including `install`, is a symbolic link. Returns `None` if none of them
+/// are, or if `path` isn't in `install`
+pub fn find(path: &Path, install: &Path) -> io::Result<Option<Link>> {
+    let link = path.ancestors().take_while(|dir| dir.starts_with(install)).find(|dir| {
+        fs::symlink_metadata(dir).map(|meta| meta.file_type().is_symlink()).unwrap_or(false)
+    });
+    let link = match link {
+        Some(link) => link.to_owned(),
+        None => return Ok(None),
+    };
+    let target = fs::canonicalize(path)?;
+    //The installation folder can be a link itself, so its real location is what the target is compared to
+    let root = fs::canonicalize(install)?;
+    Ok(Some(Link {
+        path: path.to_owned(),
+        outside: !target.starts_with(root),
+        link,
+        target,
+    }))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// Make a Discord installation with a version folder and `core.asar`, with another folder next to it to link to
+    fn tree() -> (tempfile::TempDir, PathBuf, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Discord");
+        let core = root.join("app-1.0.9001/modules/discord_desktop_core-1/discord_desktop_core");
+        fs::create_dir_all(&core).unwrap();
+        fs::create_dir_all(dir.path().join("elsewhere")).unwrap();
+        (dir, root, core)
+    }
+
+    #[test]
+    pub fn plain_files() {
+        let (dir, root, core) = tree();
+        fs::write(core.join("core.asar"), b"asar").unwrap();
+        assert_eq!(find(&core.join("core.asar"), &root).unwrap(), None);
+        //Links outside of the installation don't matter
+        assert_eq!(find(&core.join("core.asar"), &root.join("app-1.0.9001")).unwrap(), None);
+
+        //Linking the whole installation moves it instead of reaching outside of it
+        let linked = dir.path().join("Linked");
+        symlink(&root, &linked).unwrap();
+        let link = find(&linked.join(core.strip_prefix(&root).unwrap()).join("core.asar"), &linked).unwrap().unwrap();
+        assert_eq!((link.link, link.outside), (linked, false));
+    }
+
+    #[test]
+    pub fn linked_archive() {
+        let (dir, root, core) = tree();
+        let real = dir.path().join("elsewhere/core.asar");
+        fs::write(&real, b"asar").unwrap();
+        symlink(&real, core.join("core.asar")).unwrap();
+
+        let link = find(&core.join("core.asar"), &root).unwrap().unwrap();
+        assert_eq!(link.link, core.join("core.asar"));
+        assert_eq!(link.target, real.canonicalize().unwrap());
+        assert!(link.outside);
+        assert_eq!(serde_json::from_value::<Link>(serde_json::to_value(&link).unwrap()).unwrap(), link);
+
+        //A link to another file in the installation is followed but isn't outside of it
+        let inside = root.join("core.asar");
+        fs::write(&inside, b"asar").unwrap();
+        fs::remove_file(core.join("core.asar")).unwrap();
+        symlink(&inside, core.join("core.asar")).unwrap();
+        let link = find(&core.join("core.asar"), &root).unwrap().unwrap();
+        assert_eq!((link.target, link.outside), (inside.canonicalize().unwrap(), false));
+    }
+
+    #[test]
+    pub fn linked_version_folder() {
+        let (dir, root, _) = tree();
+        let moved = dir.path().join("elsewhere/app-1.0.9001");
+        fs::rename(root.join("app-1.0.9001"), &moved).unwrap();
+        symlink(&moved, root.join("app-1.0.9001")).unwrap();
+        let path = root.join("app-1.0.9001/modules/discord_desktop_core-1/discord_desktop_core/core.asar");
+        fs::write(&path, b"asar").unwrap();
+
+        let link = find(&path, &root).unwrap().unwrap();
+        assert_eq!(link.link, root.join("app-1.0.9001"));
+        assert!(link.target.starts_with(moved.canonicalize().unwrap()) && link.outside);
+        //A broken link can't be followed
+        fs::remove_dir_all(&moved).unwrap();
+        assert!(find(&path, &root).is_err());
+    }
+}
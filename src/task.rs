@@ -0,0 +1,237 @@
+//! The `task` module decides what the `check` command does, which is meant to run from a scheduled task after logging in so that the
+//! theme comes back on its own after Discord updates. It also registers that task with the platform's scheduler: Task Scheduler on
+//! Windows, a systemd user timer on Linux, and a LaunchAgent on macOS
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    config::Flavor,
+    manifest::{AsarState, Manifest},
+};
+
+/// How often the systemd timer and the LaunchAgent run the check after the first run at login, in seconds
+const INTERVAL_SECS: u64 = 60 * 60;
+
+/// The `Verdict` enum is what the `check` command found the installed Discord archive to be in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// The archive has an injection, so there is nothing to do
+    Patched,
+    /// The archive is the original one that was backed up, because the user restored it on purpose
+    Restored,
+    /// The archive changed since it was patched and has no injection, so Discord updated and the theme should be applied again
+    Updated,
+}
+
+/// Decide what state the archive with the hash `current_hash` is in from the manifest of the installed version, which is `None` when
+/// the version was never patched like after Discord installs a new version folder. `injected` is if the archive has an injection
+pub fn verdict(manifest: Option<&Manifest>, current_hash: &str, injected: bool) -> Verdict {
+    match manifest.map(|manifest| manifest.asar_state(current_hash)) {
+        _ if injected => Verdict::Patched,
+        Some(AsarState::Patched) => Verdict::Patched,
+        Some(AsarState::Original) => Verdict::Restored,
+        Some(AsarState::Updated) | None => Verdict::Updated,
+    }
+}
+
+/// Get the name of the task for a flavor, which is also the name of its systemd units
+pub fn name(flavor: Flavor) -> String {
+    format!("discord-theme-check-{}", flavor.name())
+}
+
+/// Get the arguments that the task runs this program with
+pub fn args(flavor: Flavor, config: &Path, relaunch: bool) -> Vec<String> {
+    let mut args = vec!["check".to_owned(), "--flavor".to_owned(), flavor.name().to_owned()];
+    args.extend(["--config".to_owned(), config.display().to_string()]);
+    if relaunch {
+        args.push("--relaunch".to_owned());
+    }
+    args
+}
+
+/// Quote an argument for a command line if it has spaces in it
+fn quote(arg: &str) -> String {
+    match arg.contains(char::is_whitespace) {
+        true => format!("\"{}\"", arg.replace('"', "\\\"")),
+        false => arg.to_owned(),
+    }
+}
+
+/// Write out the command line that runs `exe` with `args`
+fn command_line(exe: &Path, args: &[String]) -> String {
+    std::iter::once(exe.display().to_string()).chain(args.iter().cloned()).map(|arg| quote(&arg)).collect::<Vec<_>>().join(" ")
+}
+
+/// Make the systemd user service and timer that run `exe` with `args` after logging in and every hour after that
+pub fn systemd_units(exe: &Path, args: &[String]) -> (String, String) {
+    let service = format!(
+        "[Unit]\nDescription=Apply the Discord theme again after Discord updates\n\n[Service]\nType=oneshot\nExecStart={}\n",
+        command_line(exe, args)
+    );
+    let timer = format!(
+        "[Unit]\nDescription=Check if Discord updated and removed the theme\n\n[Timer]\nOnStartupSec=120\nOnUnitActiveSec={}\n\n[Install]\nWantedBy=timers.target\n",
+        INTERVAL_SECS
+    );
+    (service, timer)
+}
+
+/// Escape text to be put in an XML property list
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Make the LaunchAgent property list with the label `label` that runs `exe` with `args` after logging in and every hour after that
+pub fn launch_agent(label: &str, exe: &Path, args: &[String]) -> String {
+    let arguments = std::iter::once(exe.display().to_string())
+        .chain(args.iter().cloned())
+        .map(|arg| format!("        <string>{}</string>\n", xml_escape(&arg)))
+        .collect::<String>();
+    format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n",
+            "<plist version=\"1.0\">\n<dict>\n",
+            "    <key>Label</key>\n    <string>{}</string>\n",
+            "    <key>ProgramArguments</key>\n    <array>\n{}    </array>\n",
+            "    <key>RunAtLoad</key>\n    <true/>\n",
+            "    <key>StartInterval</key>\n    <integer>{}</integer>\n",
+            "</dict>\n</plist>\n"
+        ),
+        xml_escape(label),
+        arguments,
+        INTERVAL_SECS
+    )
+}
+
+/// Get the arguments for `schtasks` that register a task named `name` that runs `exe` with `args` when the user logs in
+pub fn schtasks_create(name: &str, exe: &Path, args: &[String]) -> Vec<String> {
+    let run = std::iter::once(format!("\"{}\"", exe.display())).chain(args.iter().map(|arg| quote(arg))).collect::<Vec<_>>().join(" ");
+    ["/Create", "/F", "/SC", "ONLOGON", "/TN", name, "/TR", &run].iter().map(|arg| (*arg).to_owned()).collect()
+}
+
+/// Run a command, turning a failure into an error that names it
+fn run(program: &str, args: &[&str]) -> io::Result<()> {
+    let status = Command::new(program).args(args).status()?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(io::Error::other(format!("'{} {}' exited with {}", program, args.join(" "), status))),
+    }
+}
+
+/// Get the folder that systemd user units are kept in
+fn systemd_dir() -> io::Result<PathBuf> {
+    dirs::config_dir()
+        .map(|dir| dir.join("systemd").join("user"))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the config folder wasn't found"))
+}
+
+/// Get the path of the LaunchAgent property list with the label `label`
+fn launch_agent_path(label: &str) -> io::Result<PathBuf> {
+    dirs::home_dir()
+        .map(|dir| dir.join("Library").join("LaunchAgents").join(format!("{}.plist", label)))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the home folder wasn't found"))
+}
+
+/// Get the label of the LaunchAgent for a task name
+fn label(name: &str) -> String {
+    format!("io.github.bendi11.{}", name)
+}
+
+/// Register the task named `name` that runs `exe` with `args`, replacing it if it is already registered. Returns what was registered,
+/// which is a file for systemd and launchd and the task name for Task Scheduler
+pub fn install(name: &str, exe: &Path, args: &[String]) -> io::Result<String> {
+    if cfg!(target_os = "windows") {
+        let args = schtasks_create(name, exe, args);
+        run("schtasks", &args.iter().map(String::as_str).collect::<Vec<_>>())?;
+        Ok(name.to_owned())
+    } else if cfg!(target_os = "macos") {
+        let path = launch_agent_path(&label(name))?;
+        fs::create_dir_all(path.parent().unwrap_or(&path))?;
+        //Loading an agent that is already loaded fails, so it is unloaded first
+        let _ = run("launchctl", &["unload", "-w", &path.to_string_lossy()]);
+        fs::write(&path, launch_agent(&label(name), exe, args))?;
+        run("launchctl", &["load", "-w", &path.to_string_lossy()])?;
+        Ok(path.display().to_string())
+    } else {
+        let dir = systemd_dir()?;
+        fs::create_dir_all(&dir)?;
+        let (service, timer) = systemd_units(exe, args);
+        fs::write(dir.join(format!("{}.service", name)), service)?;
+        fs::write(dir.join(format!("{}.timer", name)), timer)?;
+        run("systemctl", &["--user", "daemon-reload"])?;
+        run("systemctl", &["--user", "enable", "--now", &format!("{}.timer", name)])?;
+        Ok(dir.join(format!("{}.timer", name)).display().to_string())
+    }
+}
+
+/// Remove the task named `name`, returning what was removed like [install], or `None` if it wasn't registered
+pub fn uninstall(name: &str) -> io::Result<Option<String>> {
+    if cfg!(target_os = "windows") {
+        //schtasks fails both when the task doesn't exist and when it can't be deleted, so check if it exists first
+        if run("schtasks", &["/Query", "/TN", name]).is_err() {
+            return Ok(None);
+        }
+        run("schtasks", &["/Delete", "/F", "/TN", name])?;
+        Ok(Some(name.to_owned()))
+    } else if cfg!(target_os = "macos") {
+        let path = launch_agent_path(&label(name))?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let _ = run("launchctl", &["unload", "-w", &path.to_string_lossy()]);
+        fs::remove_file(&path)?;
+        Ok(Some(path.display().to_string()))
+    } else {
+        let dir = systemd_dir()?;
+        let timer = dir.join(format!("{}.timer", name));
+        if !timer.exists() {
+            return Ok(None);
+        }
+        let _ = run("systemctl", &["--user", "disable", "--now", &format!("{}.timer", name)]);
+        fs::remove_file(&timer)?;
+        let _ = fs::remove_file(dir.join(format!("{}.service", name)));
+        let _ = run("systemctl", &["--user", "daemon-reload"]);
+        Ok(Some(timer.display().to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn verdicts() {
+        let mut manifest = Manifest::new("app-1.0.9001", "original");
+        assert_eq!(verdict(None, "new", false), Verdict::Updated);
+        assert_eq!(verdict(None, "new", true), Verdict::Patched);
+        assert_eq!(verdict(Some(&manifest), "original", false), Verdict::Restored);
+        manifest.patched_hash = Some("patched".into());
+        assert_eq!(verdict(Some(&manifest), "patched", false), Verdict::Patched);
+        assert_eq!(verdict(Some(&manifest), "updated", false), Verdict::Updated);
+        assert_eq!(verdict(Some(&manifest), "updated", true), Verdict::Patched);
+    }
+
+    #[test]
+    pub fn task_files() {
+        let exe = Path::new("/opt/my apps/discord-theme");
+        let args = args(Flavor::Canary, Path::new("/home/a/.config/discord-theme/config.toml"), true);
+        assert_eq!(args[..3], ["check", "--flavor", "canary"]);
+        assert_eq!(args.last().map(String::as_str), Some("--relaunch"));
+
+        let (service, timer) = systemd_units(exe, &args);
+        assert!(service.contains("ExecStart=\"/opt/my apps/discord-theme\" check --flavor canary --config /home/a/.config/"));
+        assert!(timer.contains("OnUnitActiveSec=3600") && timer.contains("WantedBy=timers.target"));
+
+        let plist = launch_agent(&label(&name(Flavor::Canary)), exe, &args);
+        assert!(plist.contains("<string>io.github.bendi11.discord-theme-check-canary</string>"));
+        assert!(plist.contains("        <string>/opt/my apps/discord-theme</string>\n        <string>check</string>\n"));
+
+        let schtasks = schtasks_create("discord-theme-check-canary", exe, &args);
+        assert_eq!(schtasks[..6], ["/Create", "/F", "/SC", "ONLOGON", "/TN", "discord-theme-check-canary"]);
+        assert!(schtasks[7].starts_with("\"/opt/my apps/discord-theme\" check --flavor canary"));
+    }
+}
@@ -0,0 +1,164 @@
+//! The `testutil` module builds a fake Discord installation in a temporary folder so that tests can patch and restore it without
+//! touching a real one. It is laid out like Discord on Windows, `<root>/app-1.2.3/modules/discord_desktop_core-1/discord_desktop_core/core.asar`,
+//! and its `core.asar` is built with [asar::Archive] around a `mainScreen.js` taken from a real Discord build. Portable builds without
+//! version folders are laid out from the file lists in `tests/fixtures/layouts` with [FakeDiscord::portable]
+
+use std::{
+    fs,
+    io::{Cursor, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use tempfile::TempDir;
+
+use crate::{asar, config::Config, icon, MAIN_SCREEN};
+
+/// The `mainScreen.js` file that the fake installation's `core.asar` contains
+pub const MAIN_SCREEN_JS: &str = include_str!("../tests/fixtures/mainScreen.js");
+
+/// The file in the `app.asar` written by [app_asar] that creates Discord's window
+pub const APP_WINDOW: &str = "app_bootstrap/window.js";
+
+/// The version folder of the fake installation
+pub const VERSION: &str = "app-1.2.3";
+
+/// The file list of a portable build with `modules` in its root folder, see [FakeDiscord::portable]
+pub const PORTABLE_MODULES: &str = include_str!("../tests/fixtures/layouts/portable-modules.txt");
+
+/// The file list of a portable build with only `resources/app.asar` in its root folder, see [FakeDiscord::portable]
+pub const PORTABLE_RESOURCES: &str = include_str!("../tests/fixtures/layouts/portable-resources.txt");
+
+/// The contents of the fake installation's icon file
+pub const ICON: &[u8] = b"original icon";
+
+/// The `FakeDiscord` struct is a Discord installation in a temporary folder that is deleted when it is dropped
+pub struct FakeDiscord {
+    dir: TempDir,
+    /// The root folder that is passed with `--root`, containing the version folder
+    pub root: PathBuf,
+}
+
+impl FakeDiscord {
+    /// Lay out a fake installation whose `core.asar` contains [MAIN_SCREEN_JS]
+    pub fn create() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Discord");
+        let fake = Self { dir, root };
+        fs::create_dir_all(fake.core()).unwrap();
+        fs::write(fake.asar(), archive(MAIN_SCREEN_JS)).unwrap();
+        fs::write(fake.core().join("index.js"), "module.exports = require('./core.asar');").unwrap();
+        fs::write(fake.root.join(icon::ICON_NAME), ICON).unwrap();
+        fake
+    }
+
+    /// Lay out a fake portable build from one of the file lists in `tests/fixtures/layouts`, which has one path relative to the root
+    /// folder on each line. `core.asar` is built like [FakeDiscord::create] and `app.asar` like [app_asar], every other file is empty
+    pub fn portable(layout: &str) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Discord");
+        for file in layout.lines().filter(|line| !line.trim().is_empty()) {
+            let path = root.join(file.trim());
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            let contents = match path.file_name().and_then(|name| name.to_str()) {
+                Some("core.asar") => archive(MAIN_SCREEN_JS),
+                Some("app.asar") => app_archive(),
+                _ => Vec::new(),
+            };
+            fs::write(path, contents).unwrap();
+        }
+        Self { dir, root }
+    }
+
+    /// Get the `discord_desktop_core` folder that `core.asar` is in
+    pub fn core(&self) -> PathBuf {
+        self.root.join(VERSION).join("modules/discord_desktop_core-1/discord_desktop_core")
+    }
+
+    /// Get the path of the fake `core.asar`
+    pub fn asar(&self) -> PathBuf {
+        self.core().join("core.asar")
+    }
+
+    /// Read `mainScreen.js` out of the fake `core.asar`
+    pub fn main_screen(&self) -> String {
+        main_screen(&self.asar())
+    }
+
+    /// Get a folder in the temporary folder, outside of the fake installation, for backups and the like
+    pub fn dir(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    /// Get the default config with the fake installation as Discord's root folder and the backups and config file kept next to it
+    pub fn config(&self) -> Config {
+        Config {
+            path: self.dir("config.toml"),
+            root: Some(self.root.clone()),
+            backup_dir: Some(self.dir("backups")),
+            mac_bundle: Some(self.dir("Discord.app")),
+            replace_icon: false,
+            ..Config::default()
+        }
+    }
+}
+
+/// Build a `core.asar` archive containing the given `mainScreen.js` and another file that is never patched
+pub fn archive(main_screen: &str) -> Vec<u8> {
+    let mut archive = asar::Archive::new();
+    archive.add_dir("app");
+    for (path, contents) in [(MAIN_SCREEN, main_screen), ("app/index.js", "require('./mainScreen');")] {
+        archive.add_file(path);
+        archive.get_file_mut(path).unwrap().write_all(contents.as_bytes()).unwrap();
+    }
+    let mut buf = Cursor::new(Vec::new());
+    archive.pack(&mut buf, false).unwrap();
+    buf.into_inner()
+}
+
+/// Read `mainScreen.js` out of the archive at the given path
+pub fn main_screen(path: &Path) -> String {
+    read_file(path, MAIN_SCREEN)
+}
+
+/// Read the file at `file` out of the archive at the given path
+pub fn read_file(path: &Path, file: &str) -> String {
+    let mut archive = asar::Archive::read(&mut fs::File::open(path).unwrap()).unwrap();
+    let mut js = String::new();
+    archive.get_file_mut(file).unwrap().read_to_string(&mut js).unwrap();
+    js
+}
+
+/// Write an `app.asar` to the fake installation's `resources` folder that creates Discord's window in [APP_WINDOW] like newer Discord
+/// builds, returning its path
+pub fn app_asar(fake: &FakeDiscord) -> PathBuf {
+    let resources = fake.root.join(VERSION).join("resources");
+    fs::create_dir_all(&resources).unwrap();
+    fs::write(resources.join("app.asar"), app_archive()).unwrap();
+    resources.join("app.asar")
+}
+
+/// Build an `app.asar` archive that creates Discord's window in [APP_WINDOW]
+pub fn app_archive() -> Vec<u8> {
+    let mut archive = asar::Archive::new();
+    archive.add_dir("app_bootstrap");
+    for (path, contents) in [("app_bootstrap/index.js", "require('./window');"), (APP_WINDOW, MAIN_SCREEN_JS)] {
+        archive.add_file(path);
+        archive.get_file_mut(path).unwrap().write_all(contents.as_bytes()).unwrap();
+    }
+    let mut buf = Cursor::new(Vec::new());
+    archive.pack(&mut buf, false).unwrap();
+    buf.into_inner()
+}
+
+/// Check if `pat` is anywhere in the escaped text of a template literal where it isn't escaped by a backslash
+pub fn unescaped(text: &str, pat: &str) -> bool {
+    let mut idx = 0;
+    while idx < text.len() {
+        match text[idx..].starts_with('\\') {
+            true => idx += 1 + text[idx + 1..].chars().next().map_or(0, char::len_utf8),
+            false if text[idx..].starts_with(pat) => return true,
+            false => idx += text[idx..].chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    false
+}
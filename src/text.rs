@@ -0,0 +1,152 @@
+//! The `text` module reads themes and custom javascript the same way whether they come from a file, stdin, a download, or a theme
+//! pack. Editors on Windows often save files with a UTF-8 byte order mark and CRLF line endings, which would end up inside the
+//! injection, so the mark is removed and line endings become LF. Files saved as UTF-16 are converted with a warning, instead of
+//! being inserted as garbage
+
+use std::fmt;
+
+use console::{style, Color};
+
+use crate::{
+    messages::msg,
+    output::{self, Category},
+};
+
+/// The byte order mark that some editors put at the start of UTF-8 files
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// The `Encoding` enum is what a text file was saved as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+/// The `Decoded` struct is text that was read by [decode]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoded {
+    /// The text without a byte order mark and with LF line endings
+    pub text: String,
+    /// What the text was saved as
+    pub encoding: Encoding,
+}
+
+/// Guess if `data` is UTF-16 from its byte order mark, or from it being mostly ASCII with every other byte zero if it has none.
+/// Returns `None` for anything else
+fn detect_utf16(data: &[u8]) -> Option<Encoding> {
+    if data.len() < 2 || !data.len().is_multiple_of(2) {
+        return None;
+    }
+    match data {
+        [0xFF, 0xFE, ..] => return Some(Encoding::Utf16Le),
+        [0xFE, 0xFF, ..] => return Some(Encoding::Utf16Be),
+        _ => (),
+    }
+    //CSS and javascript are mostly ASCII, so one byte of nearly every pair is zero in UTF-16
+    let pairs = data.len() / 2;
+    let zero_at = |i: usize| data.iter().skip(i).step_by(2).filter(|b| **b == 0).count();
+    match (zero_at(0), zero_at(1)) {
+        (_, odd) if odd * 10 >= pairs * 9 => Some(Encoding::Utf16Le),
+        (even, _) if even * 10 >= pairs * 9 => Some(Encoding::Utf16Be),
+        _ => None,
+    }
+}
+
+/// Decode a theme or script, removing a byte order mark and converting CRLF line endings to LF
+pub fn decode(data: Vec<u8>) -> Result<Decoded, Error> {
+    let (text, encoding) = match detect_utf16(&data) {
+        Some(encoding) => {
+            let units = data
+                .chunks_exact(2)
+                .map(|pair| match encoding {
+                    Encoding::Utf16Be => u16::from_be_bytes([pair[0], pair[1]]),
+                    _ => u16::from_le_bytes([pair[0], pair[1]]),
+                })
+                .collect::<Vec<_>>();
+            (String::from_utf16(&units).map_err(|_| Error::InvalidUtf16)?, encoding)
+        }
+        None => {
+            let data = data.strip_prefix(UTF8_BOM).map(<[u8]>::to_vec).unwrap_or(data);
+            (String::from_utf8(data).map_err(|_| Error::NotUtf8)?, Encoding::Utf8)
+        }
+    };
+    Ok(Decoded {
+        text: normalize(text),
+        encoding,
+    })
+}
+
+/// Remove a byte order mark from text that was already decoded and convert its CRLF line endings to LF
+pub fn normalize(text: String) -> String {
+    let text = match text.strip_prefix('\u{feff}') {
+        Some(rest) => rest.to_owned(),
+        None => text,
+    };
+    match text.contains("\r\n") {
+        true => text.replace("\r\n", "\n"),
+        false => text,
+    }
+}
+
+/// Decode the theme or script read from `source` with [decode], warning that it should be saved as UTF-8 if it was UTF-16
+pub fn load(source: &str, data: Vec<u8>) -> Result<String, Error> {
+    let decoded = decode(data)?;
+    if decoded.encoding != Encoding::Utf8 {
+        output::warning(Category::Theme, style(msg!("text-was-utf16", source)).fg(Color::Color256(172)));
+    }
+    Ok(decoded.text)
+}
+
+/// The `Error` enum is why a theme or script can't be read as text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The file isn't UTF-8 and doesn't look like UTF-16
+    NotUtf8,
+    /// The file looks like UTF-16 but isn't valid UTF-16
+    InvalidUtf16,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotUtf8 => write!(f, "the file is not UTF-8 text, save it as UTF-8 and try again"),
+            Self::InvalidUtf16 => write!(f, "the file looks like UTF-16 text but is not valid UTF-16, save it as UTF-8 and try again"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str, bom: bool, big_endian: bool) -> Vec<u8> {
+        let units = bom.then_some(0xFEFF).into_iter().chain(text.encode_utf16());
+        units.flat_map(|unit| if big_endian { unit.to_be_bytes() } else { unit.to_le_bytes() }).collect()
+    }
+
+    #[test]
+    pub fn bom_and_crlf() {
+        let decoded = decode(b"\xEF\xBB\xBFa {\r\n  color: red;\r\n}\r\n".to_vec()).unwrap();
+        assert_eq!(decoded, Decoded { text: "a {\n  color: red;\n}\n".into(), encoding: Encoding::Utf8 });
+        //A lone CR and a BOM that isn't at the start are kept
+        assert_eq!(decode("a\rb\u{feff}".as_bytes().to_vec()).unwrap().text, "a\rb\u{feff}");
+        assert_eq!(normalize("\u{feff}`a`\r\n".into()), "`a`\n");
+        assert_eq!(decode(b"\xff\xfe\xff".to_vec()), Err(Error::NotUtf8));
+    }
+
+    #[test]
+    pub fn utf16_is_converted() {
+        let css = "a {\r\n  content: \"é\";\r\n}";
+        for (bom, big_endian, encoding) in [(true, false, Encoding::Utf16Le), (true, true, Encoding::Utf16Be), (false, false, Encoding::Utf16Le), (false, true, Encoding::Utf16Be)] {
+            let decoded = decode(utf16(css, bom, big_endian)).unwrap();
+            assert_eq!(decoded, Decoded { text: "a {\n  content: \"é\";\n}".into(), encoding });
+        }
+        //A lone surrogate is refused instead of becoming garbage
+        assert_eq!(decode(vec![0xFF, 0xFE, 0x00, 0xD8]), Err(Error::InvalidUtf16));
+        //Text with a few zero bytes isn't mistaken for UTF-16
+        assert_eq!(decode(b"a\0b {}".to_vec()).unwrap().encoding, Encoding::Utf8);
+    }
+}
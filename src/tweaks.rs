@@ -0,0 +1,75 @@
+//! The `tweaks` module holds the optional snippets that can be added to the end of the old theme, like compact mode or hiding the gift
+//! button. The tweaks are compiled into this program, and programs with network support download the index of them that is published
+//! next to the theme so that the list stays current between releases
+
+use serde::{Deserialize, Serialize};
+
+/// The tweaks compiled into this program as their name, description, and CSS. Adding a tweak only takes its file in `assets/tweaks`
+/// and a line here, after which `assets/tweaks.json` is published with it
+pub const BUNDLED: &[(&str, &str, &str)] = &[
+    ("compact", "Compact mode, with less space between messages", include_str!("../assets/tweaks/compact.css")),
+    ("hide-gift-button", "Hide the gift button next to the message box", include_str!("../assets/tweaks/hide-gift-button.css")),
+    ("old-font", "Use Whitney, the font from before gg sans", include_str!("../assets/tweaks/old-font.css")),
+];
+
+/// The `Tweak` struct is one optional snippet of CSS
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tweak {
+    /// The name that the tweak is saved as in the `tweaks` option
+    pub name: String,
+    pub description: String,
+    pub css: String,
+}
+
+/// The `Index` struct is the list of tweaks that is published next to the theme
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Index {
+    tweaks: Vec<Tweak>,
+}
+
+/// Get the tweaks compiled into this program
+pub fn bundled() -> Vec<Tweak> {
+    BUNDLED
+        .iter()
+        .map(|(name, description, css)| Tweak {
+            name: (*name).to_owned(),
+            description: (*description).to_owned(),
+            css: (*css).to_owned(),
+        })
+        .collect()
+}
+
+/// Parse the index of tweaks, which is an object with a `tweaks` array
+pub fn parse_index(text: &str) -> Result<Vec<Tweak>, serde_json::Error> {
+    serde_json::from_str::<Index>(text).map(|index| index.tweaks)
+}
+
+/// Add the CSS of every selected tweak to the end of a theme, in the order that they are available in so that the result doesn't
+/// depend on the order they were picked in. Selected tweaks that aren't available anymore are skipped
+pub fn apply(theme: &str, available: &[Tweak], selected: &[String]) -> String {
+    available
+        .iter()
+        .filter(|tweak| selected.contains(&tweak.name))
+        .fold(theme.to_owned(), |theme, tweak| format!("{}\n\n{}", theme.trim_end(), tweak.css.trim_end()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn published_index_is_current() {
+        assert_eq!(parse_index(include_str!("../assets/tweaks.json")).unwrap(), bundled());
+        assert!(parse_index("{\"tweaks\": [{\"name\": \"a\"}]}").is_err());
+    }
+
+    #[test]
+    pub fn apply_selected() {
+        let available = bundled();
+        assert_eq!(apply("body {}", &available, &[]), "body {}");
+        let selected = vec!["old-font".to_owned(), "gone".to_owned(), "compact".to_owned()];
+        let themed = apply("body {}\n", &available, &selected);
+        assert!(themed.starts_with("body {}\n\n/* Compact mode"));
+        assert!(themed.ends_with(BUNDLED[2].2.trim_end()) && !themed.contains("gift"));
+    }
+}
@@ -0,0 +1,136 @@
+//! The `ui` module makes every progress bar and spinner, so that they all look the same and none of them can crash the program. Each
+//! [Kind] of bar has one template, which is checked once before it is used and replaced with indicatif's default style if it is
+//! malformed. Bars are hidden when `--quiet` is passed, when stdout isn't a terminal, and in JSON output mode
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    OnceLock,
+};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::output;
+
+/// Set by [set_quiet] when progress bars shouldn't be drawn
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// The style of each [Kind], built the first time a bar is made
+static STYLES: OnceLock<Vec<ProgressStyle>> = OnceLock::new();
+
+/// The frames of the spinner animation
+const TICKS: &[&str] = &["[>---]", "[=>--]", "[==>-]", "[===>]", "[-===]", "[--==]", "[---=]", "[----]"];
+
+/// The keys that can be used in a template, see indicatif's `ProgressStyle::template`
+const KEYS: &[&str] = &[
+    "bar", "wide_bar", "spinner", "prefix", "msg", "wide_msg", "pos", "len", "bytes", "total_bytes", "percent", "elapsed",
+    "elapsed_precise", "eta", "eta_precise", "duration", "duration_precise", "per_sec", "bytes_per_sec", "binary_bytes_per_sec",
+];
+
+/// The `Kind` enum is what a progress bar shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// A step that doesn't know how long it will take
+    Spinner,
+    /// Copying a file, like making or restoring a backup
+    Copy,
+    /// Writing or downloading a file with a known size
+    Transfer,
+    /// Packing the files of an archive
+    Files,
+}
+
+impl Kind {
+    /// Every kind, in the order of [STYLES]
+    const ALL: [Self; 4] = [Self::Spinner, Self::Copy, Self::Transfer, Self::Files];
+
+    /// Get the indicatif template of this kind of bar
+    const fn template(&self) -> &'static str {
+        match self {
+            Self::Spinner => "{spinner} - {msg}",
+            Self::Copy => "{bar} {bytes}/{total_bytes} - {binary_bytes_per_sec}: {msg}",
+            Self::Transfer => "[{bar}] {bytes}/{total_bytes} - {binary_bytes_per_sec}: {msg}",
+            Self::Files => "[{bar}] {pos}/{len} files - {per_sec}: {msg}",
+        }
+    }
+
+    /// Build the style of this kind of bar, using the default style if its template is malformed
+    fn build(&self) -> ProgressStyle {
+        let default = match self {
+            Self::Spinner => ProgressStyle::default_spinner().tick_strings(TICKS),
+            Self::Copy => ProgressStyle::default_bar(),
+            Self::Transfer | Self::Files => ProgressStyle::default_bar().progress_chars("=>."),
+        };
+        match check_template(self.template()) {
+            Ok(()) => default.template(self.template()),
+            Err(_) => default,
+        }
+    }
+}
+
+/// Check that every `{}` placeholder in a template is closed and names a key that indicatif knows, ignoring the style and alignment
+/// after a `:` like in `{bar:40.cyan}`
+pub fn check_template(template: &str) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..].find('}').ok_or_else(|| format!("'{}' is not closed", &rest[start..]))? + start;
+        let key = rest[start + 1..end].split(':').next().unwrap_or_default();
+        if !KEYS.contains(&key) {
+            return Err(format!("'{}' is not a progress bar key", key));
+        }
+        rest = &rest[end + 1..];
+    }
+    match rest.contains('}') {
+        true => Err("there is a '}' without a '{'".to_owned()),
+        false => Ok(()),
+    }
+}
+
+/// Hide every progress bar made after this is called, for `--quiet`
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Check if progress bars are hidden because of `--quiet`, because stdout isn't a terminal, or because of JSON output
+pub fn hidden() -> bool {
+    QUIET.load(Ordering::Relaxed) || output::is_json() || !console::user_attended()
+}
+
+/// Get the style of a kind of bar, for bars that change what they show
+pub fn style(kind: Kind) -> ProgressStyle {
+    STYLES.get_or_init(|| Kind::ALL.iter().map(Kind::build).collect())[kind as usize].clone()
+}
+
+/// Make a progress bar of the given kind that counts up to `len`, which is ignored by spinners. The bar is hidden if [hidden]
+pub fn bar(kind: Kind, len: u64) -> ProgressBar {
+    let bar = match (hidden(), kind) {
+        (true, _) => return ProgressBar::hidden(),
+        (false, Kind::Spinner) => ProgressBar::new_spinner(),
+        (false, _) => ProgressBar::new(len),
+    };
+    bar.set_style(style(kind));
+    bar
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn templates() {
+        for kind in Kind::ALL {
+            assert_eq!(check_template(kind.template()), Ok(()), "{:?}", kind);
+            assert_eq!(Kind::ALL[kind as usize], kind);
+        }
+        assert!(check_template("{bar:40.cyan/blue} {wide_msg}").is_ok());
+        assert!(check_template("{bar").is_err());
+        assert!(check_template("{bra} {msg}").is_err());
+        assert!(check_template("done}").is_err());
+    }
+
+    #[test]
+    pub fn quiet_bars_are_hidden() {
+        set_quiet(true);
+        assert!(bar(Kind::Transfer, 10).is_hidden() && bar(Kind::Spinner, 0).is_hidden());
+        set_quiet(false);
+    }
+}
@@ -0,0 +1,261 @@
+//! The `uninstall` module puts Discord's `core.asar` back the way it was before it was ever patched and removes the files that this
+//! program created. Only files at paths that this program names itself are deleted, so anything else that is in Discord's folders or
+//! in a backup folder that is shared with other files is left alone
+
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use indicatif::ProgressBar;
+use tempfile::NamedTempFile;
+
+use crate::{
+    asar,
+    backup::{
+        self, BACKUP_NAME, COMPRESSED_BACKUP_NAME, DESKTOP_BACKUP_NAME, DESKTOP_ICON_BACKUP_NAME, FILE_BACKUP_NAME,
+        ICNS_BACKUP_NAME, ICON_BACKUP_NAME, ICON_VARIANTS_DIR,
+    },
+    lasttheme::LAST_THEME_NAME,
+    manifest::MANIFEST_NAME,
+    patch,
+    work::WORK_NAME,
+};
+
+/// The files in a version's backup folder that hold backups
+const VERSION_BACKUPS: &[&str] = &[BACKUP_NAME, COMPRESSED_BACKUP_NAME, FILE_BACKUP_NAME, ICON_VARIANTS_DIR];
+
+/// The files in a flavor's backup folder that hold backups shared by all of its versions
+const FLAVOR_BACKUPS: &[&str] = &[ICON_BACKUP_NAME, ICNS_BACKUP_NAME, DESKTOP_ICON_BACKUP_NAME, DESKTOP_BACKUP_NAME];
+
+/// The `Status` enum is what happened to one file while uninstalling
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// The file was put back the way it was before it was patched
+    Restored,
+    /// The file was deleted
+    Removed,
+    /// The file was kept because the user asked to keep it
+    Kept,
+    /// The file doesn't exist, so there was nothing to do
+    Missing,
+    /// The file exists but was never changed, so there was nothing to do
+    Unchanged,
+    /// The file couldn't be restored or deleted
+    Failed(String),
+}
+
+/// The `Item` struct is one line of the checklist that is printed after uninstalling
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    pub path: PathBuf,
+    pub status: Status,
+}
+
+/// The `Reverted` enum is how [revert_asar] put `core.asar` back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reverted {
+    /// The whole archive was copied back from the backup at the given path
+    Archive(PathBuf),
+    /// Only the patched file was copied back from the backup at the given path
+    File(PathBuf),
+    /// There was no backup, so the injection was removed from the patched file
+    Stripped,
+    /// There was no backup and the archive isn't patched
+    Unpatched,
+}
+
+/// Put the archive at `asar` back the way it was before the javascript file at `path` in it was patched, using the backups in the given
+/// version backup folder or removing the injection if there are none. `progress` shows how much of a whole archive backup was copied
+pub fn revert_asar(asar: &Path, version_dir: &Path, path: &str, progress: &ProgressBar) -> Result<Reverted, asar::Error> {
+    if let Some(archive) = backup::find(version_dir) {
+        backup::restore(&archive, asar, progress)?;
+        return Ok(Reverted::Archive(archive));
+    }
+    if let Some(file) = backup::find_file(version_dir) {
+        backup::restore_file(&file, asar, path)?;
+        return Ok(Reverted::File(file));
+    }
+
+    let mut archive = asar::Archive::read(&mut fs::File::open(asar)?)?;
+    let file = archive.get_file_mut(path).ok_or(asar::Error::NoFile)?;
+    let mut js = String::new();
+    file.read_to_string(&mut js)?;
+    let stripped = match patch::strip_injection(&js) {
+        Some(stripped) => stripped,
+        None => return Ok(Reverted::Unpatched),
+    };
+    file.replace_contents(stripped.as_bytes())?;
+
+    //Check the new archive before it replaces the real one, like restoring a backup
+    let mut temp = NamedTempFile::new_in(asar.parent().unwrap_or_else(|| Path::new(".")))?;
+    archive.pack(temp.as_file_mut(), false)?;
+    temp.as_file_mut().seek(SeekFrom::Start(0))?;
+    asar::Archive::read(temp.as_file_mut())?;
+    temp.persist(asar).map_err(|e| e.error)?;
+    Ok(Reverted::Stripped)
+}
+
+/// Delete the file or folder at `path`
+pub fn remove(path: &Path) -> Item {
+    let removed = match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) => Err(e),
+    };
+    Item {
+        path: path.to_owned(),
+        status: match removed {
+            Ok(()) => Status::Removed,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Status::Missing,
+            Err(e) => Status::Failed(e.to_string()),
+        },
+    }
+}
+
+/// Delete every file in `paths` that exists, leaving out the ones that don't from the checklist
+pub fn remove_existing(paths: impl IntoIterator<Item = PathBuf>) -> Vec<Item> {
+    paths
+        .into_iter()
+        .filter(|path| fs::symlink_metadata(path).is_ok())
+        .map(|path| remove(&path))
+        .collect()
+}
+
+/// Delete the given folders if they are empty, which leaves folders that something else put files in alone
+pub fn prune<'a>(dirs: impl IntoIterator<Item = &'a Path>) {
+    for dir in dirs {
+        let _ = fs::remove_dir(dir);
+    }
+}
+
+/// Delete the manifests in every version folder of a flavor's backup folder and, unless `keep_backups` is set, the backups in them
+/// and the backups of the flavor's icons. Folders that end up empty are deleted too
+pub fn remove_backups(flavor_dir: &Path, keep_backups: bool) -> Vec<Item> {
+    let versions = match fs::read_dir(flavor_dir) {
+        Ok(dir) => dir
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.is_dir())
+            .collect::<Vec<_>>(),
+        Err(_) => {
+            return vec![Item {
+                path: flavor_dir.to_owned(),
+                status: Status::Missing,
+            }]
+        }
+    };
+
+    let backups = versions
+        .iter()
+        .flat_map(|version| VERSION_BACKUPS.iter().map(move |name| version.join(name)))
+        .chain(FLAVOR_BACKUPS.iter().map(|name| flavor_dir.join(name)));
+    let mut items = remove_existing(
+        versions
+            .iter()
+            .flat_map(|version| [version.join(MANIFEST_NAME), version.join(WORK_NAME)])
+            .chain(std::iter::once(flavor_dir.join(LAST_THEME_NAME))),
+    );
+    items.extend(remove_or_keep(backups, keep_backups));
+
+    prune(versions.iter().map(PathBuf::as_path).chain(std::iter::once(flavor_dir)));
+    items
+}
+
+/// Delete the manifest in the backup folder `dir` of one archive given with `--asar` and, unless `keep_backups` is set, the backups in
+/// it. The backups of other archives in the same folder are left alone, and the last theme that they share is only deleted with the
+/// last of them. Folders that end up empty are deleted too
+pub fn remove_archive_backups(dir: &Path, keep_backups: bool) -> Vec<Item> {
+    if !dir.is_dir() {
+        return vec![Item {
+            path: dir.to_owned(),
+            status: Status::Missing,
+        }];
+    }
+    let mut items = remove_existing([dir.join(MANIFEST_NAME), dir.join(WORK_NAME)]);
+    items.extend(remove_or_keep(VERSION_BACKUPS.iter().map(|name| dir.join(name)), keep_backups));
+    prune([dir]);
+
+    if let Some(parent) = dir.parent() {
+        let others = fs::read_dir(parent).map(|entries| entries.filter_map(Result::ok).any(|entry| entry.path().is_dir())).unwrap_or(true);
+        if !others {
+            items.extend(remove_existing([parent.join(LAST_THEME_NAME)]));
+            prune([parent]);
+        }
+    }
+    items
+}
+
+/// Delete the backups at the given paths, or list the ones that exist as kept if `keep_backups` is set
+fn remove_or_keep(backups: impl Iterator<Item = PathBuf>, keep_backups: bool) -> Vec<Item> {
+    match keep_backups {
+        true => backups
+            .filter(|path| path.exists())
+            .map(|path| Item {
+                path,
+                status: Status::Kept,
+            })
+            .collect(),
+        false => remove_existing(backups),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        site::INJECTION_POINT,
+        testutil::{self, FakeDiscord, MAIN_SCREEN_JS},
+        MAIN_SCREEN,
+    };
+
+    /// Patch the fake installation's `core.asar` and lay out a backup folder like the ones made by patching it, without a backup of
+    /// `core.asar` itself, returning the version's backup folder
+    fn patch(fake: &FakeDiscord) -> PathBuf {
+        let version_dir = fake.dir("backups/Discord").join(testutil::VERSION);
+        fs::create_dir_all(version_dir.join(ICON_VARIANTS_DIR).join(testutil::VERSION)).unwrap();
+
+        let patched = MAIN_SCREEN_JS.replacen(INJECTION_POINT, &crate::patch::injection("body { color: red; }", Some("console.log(1);")), 1);
+        fs::write(fake.asar(), testutil::archive(&patched)).unwrap();
+        fs::write(version_dir.join(MANIFEST_NAME), "{}").unwrap();
+        fs::write(version_dir.join(ICON_VARIANTS_DIR).join(testutil::VERSION).join("app.ico"), "icon").unwrap();
+        fs::write(fake.dir("backups/Discord").join(ICON_BACKUP_NAME), "icon").unwrap();
+        fs::write(fake.dir("backups/Discord/notes.txt"), "not ours").unwrap();
+        version_dir
+    }
+
+    #[test]
+    pub fn uninstall_fake_install() {
+        let fake = FakeDiscord::create();
+        let (asar, version_dir) = (fake.asar(), patch(&fake));
+        let flavor_dir = version_dir.parent().unwrap().to_owned();
+
+        //Without a backup the injection is removed from the patched archive
+        assert_eq!(revert_asar(&asar, &version_dir, MAIN_SCREEN, &ProgressBar::hidden()).unwrap(), Reverted::Stripped);
+        assert_eq!(fake.main_screen(), MAIN_SCREEN_JS);
+        assert_eq!(revert_asar(&asar, &version_dir, MAIN_SCREEN, &ProgressBar::hidden()).unwrap(), Reverted::Unpatched);
+
+        //A backup of the whole archive is copied back over it
+        fs::write(version_dir.join(BACKUP_NAME), fs::read(&asar).unwrap()).unwrap();
+        fs::write(&asar, testutil::archive("patched")).unwrap();
+        let reverted = revert_asar(&asar, &version_dir, MAIN_SCREEN, &ProgressBar::hidden()).unwrap();
+        assert_eq!(reverted, Reverted::Archive(version_dir.join(BACKUP_NAME)));
+        assert_eq!(fake.main_screen(), MAIN_SCREEN_JS);
+
+        //Kept backups stay, but the manifest doesn't
+        let items = remove_backups(&flavor_dir, true);
+        assert!(items.contains(&Item { path: version_dir.join(MANIFEST_NAME), status: Status::Removed }));
+        assert!(items.contains(&Item { path: version_dir.join(BACKUP_NAME), status: Status::Kept }));
+        assert!(version_dir.join(BACKUP_NAME).exists() && !version_dir.join(MANIFEST_NAME).exists());
+
+        let items = remove_backups(&flavor_dir, false);
+        assert_eq!(items.len(), 3);
+        assert!(items.iter().all(|item| item.status == Status::Removed));
+        assert!(!version_dir.exists());
+
+        //Files that we didn't create are never deleted
+        assert!(flavor_dir.join("notes.txt").exists());
+        assert!(fake.core().join("index.js").exists());
+        assert_eq!(remove(&version_dir).status, Status::Missing);
+    }
+}
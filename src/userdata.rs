@@ -0,0 +1,65 @@
+//! The `userdata` module finds the folders that this program keeps its own files in, like the config file, backups, and downloaded
+//! themes. They are always in the current user's folders so that accounts on a shared computer don't share or overwrite each other's
+//! files, and the folder names never include the user's name. [CONFIG_DIR_ENV] and [DATA_DIR_ENV] replace the folders, which is how
+//! tests and portable setups move them
+
+use std::{env, ffi::OsString, path::PathBuf};
+
+/// The environment variable that replaces the folder that the config file is kept in
+pub const CONFIG_DIR_ENV: &str = "DISCORD_THEME_CONFIG_DIR";
+
+/// The environment variable that replaces the folder that backups and downloads are kept in
+pub const DATA_DIR_ENV: &str = "DISCORD_THEME_DATA_DIR";
+
+/// The folder in the platform's per-user folders that this program's files are kept in
+const APP_DIR: &str = "discord-theme";
+
+/// The folder in the user's home folder that is used when the platform has no per-user config or data folder
+const HOME_APP_DIR: &str = ".discord-theme";
+
+/// Get the folder that the config file is kept in, like `%APPDATA%/discord-theme` or `~/.config/discord-theme`
+pub fn config_dir() -> PathBuf {
+    scoped(env::var_os(CONFIG_DIR_ENV), dirs::config_dir(), dirs::home_dir())
+}
+
+/// Get the folder that backups and downloads are kept in, like `%APPDATA%/discord-theme` or `~/.local/share/discord-theme`
+pub fn data_dir() -> PathBuf {
+    scoped(env::var_os(DATA_DIR_ENV), dirs::data_dir(), dirs::home_dir())
+}
+
+/// Pick the folder for this program's files: the one from the environment variable if it is set, the `discord-theme` folder in the
+/// platform's folder, or a hidden folder in the home folder. The current folder is only used when the user has no home folder at all
+fn scoped(var: Option<OsString>, platform: Option<PathBuf>, home: Option<PathBuf>) -> PathBuf {
+    match (var.filter(|var| !var.is_empty()), platform, home) {
+        (Some(var), _, _) => PathBuf::from(var),
+        (None, Some(platform), _) => platform.join(APP_DIR),
+        (None, None, Some(home)) => home.join(HOME_APP_DIR),
+        (None, None, None) => PathBuf::from(".").join(APP_DIR),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn scoped_dirs() {
+        let (platform, home) = (Some(PathBuf::from("/home/a/.local/share")), Some(PathBuf::from("/home/a")));
+        assert_eq!(scoped(Some("/portable/state".into()), platform.clone(), home.clone()), PathBuf::from("/portable/state"));
+        assert_eq!(scoped(Some("".into()), platform.clone(), home.clone()), PathBuf::from("/home/a/.local/share/discord-theme"));
+        assert_eq!(scoped(None, platform, home.clone()), PathBuf::from("/home/a/.local/share/discord-theme"));
+        assert_eq!(scoped(None, None, home), PathBuf::from("/home/a/.discord-theme"));
+        assert_eq!(scoped(None, None, None), PathBuf::from("./discord-theme"));
+    }
+
+    #[test]
+    pub fn data_dir_env() {
+        //No other test reads the default data folder, so replacing it here doesn't change where they keep their files
+        let dir = tempfile::tempdir().unwrap();
+        env::set_var(DATA_DIR_ENV, dir.path());
+        let backups = crate::backup::default_dir();
+        env::remove_var(DATA_DIR_ENV);
+        assert_eq!(backups, dir.path().join("backups"));
+        assert_ne!(data_dir(), dir.path());
+    }
+}
@@ -0,0 +1,260 @@
+//! The `variables` module reads the variables that a theme declares in its leading comments, like
+//! `/* @var accent color "Accent color" #ff0066 */`, and sets them as CSS custom properties so that the theme can use them with
+//! `var(--accent)`. Values that aren't in the config file are asked for once and saved under `[variables]` for next time
+
+use std::collections::BTreeMap;
+
+/// The word that starts a variable declaration in a comment
+const DECLARATION: &str = "@var";
+
+/// The `Kind` enum is what values a variable can have, which decides how they are checked and written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// A CSS color, like `#ff0066`, `rgb(255, 0, 102)`, or `hotpink`
+    Color,
+    /// A number with an optional unit, like `12`, `1.5em`, or `50%`
+    Number,
+    /// Any single line of text, which is written as a quoted CSS string
+    Text,
+}
+
+/// The `Declaration` struct is one variable declared by a theme
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Declaration {
+    /// The name of the variable, which is the `--name` custom property
+    pub name: String,
+    pub kind: Kind,
+    /// What the variable is shown as when its value is asked for
+    pub label: String,
+    pub default: String,
+}
+
+/// The `Resolved` struct is the value of every variable a theme declares and where each one came from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Resolved {
+    pub values: BTreeMap<String, String>,
+    /// The variables whose values were asked for, which should be saved to the config file
+    pub asked: Vec<String>,
+    /// The variables that used their default because nobody could be asked
+    pub defaulted: Vec<String>,
+}
+
+impl Kind {
+    /// Get a kind from how it is written in a declaration
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "color" => Some(Self::Color),
+            "number" => Some(Self::Number),
+            "text" => Some(Self::Text),
+            _ => None,
+        }
+    }
+
+    /// Check that a value is valid for this kind, returning what is wrong with it if it isn't
+    pub fn validate(&self, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        let valid = match self {
+            Self::Color => is_color(value),
+            Self::Number => {
+                let unit = value.trim_end_matches(|c: char| c.is_ascii_alphabetic() || c == '%');
+                !unit.is_empty() && unit.parse::<f64>().map(f64::is_finite).unwrap_or(false)
+            }
+            Self::Text => !value.contains(|c: char| c.is_control()),
+        };
+        match (valid, self) {
+            (true, _) => Ok(()),
+            (false, Self::Color) => Err(format!("'{}' is not a color like #ff0066 or rgb(255, 0, 102)", value)),
+            (false, Self::Number) => Err(format!("'{}' is not a number like 12 or 1.5em", value)),
+            (false, Self::Text) => Err("the text must be a single line".to_owned()),
+        }
+    }
+
+    /// Write a value of this kind as the value of a CSS custom property
+    fn css(&self, value: &str) -> String {
+        match self {
+            Self::Text => format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+            _ => value.trim().to_owned(),
+        }
+    }
+}
+
+/// Check if a value is a hex color, a color function like `rgb(..)`, or a color keyword. Color functions can't contain anything
+/// that would end the custom property they are written to
+fn is_color(value: &str) -> bool {
+    if let Some(hex) = value.strip_prefix('#') {
+        return [3, 4, 6, 8].contains(&hex.len()) && hex.chars().all(|c| c.is_ascii_hexdigit());
+    }
+    match value.find('(') {
+        Some(open) => {
+            ["rgb", "rgba", "hsl", "hsla", "hwb", "lab", "lch", "oklab", "oklch"].contains(&&value[..open])
+                && value.ends_with(')')
+                && value[open + 1..value.len() - 1]
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || " ,./%-+".contains(c))
+        }
+        None => !value.is_empty() && value.chars().all(|c| c.is_ascii_alphabetic()),
+    }
+}
+
+/// Parse the part of a comment line after [DECLARATION], like `accent color "Accent color" #ff0066`
+fn parse_declaration(line: &str) -> Result<Declaration, String> {
+    let mut words = line.trim().splitn(3, char::is_whitespace);
+    let name = words.next().unwrap_or_default();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("'{}' is not a valid variable name", name));
+    }
+    let kind = words.next().unwrap_or_default();
+    let kind = Kind::from_name(kind).ok_or_else(|| format!("'{}' is not a variable kind, expected color, number, or text", kind))?;
+
+    let rest = words.next().unwrap_or_default().trim_start();
+    let (label, default) = match rest.strip_prefix('"').and_then(|rest| rest.split_once('"')) {
+        Some((label, default)) => (label.to_owned(), default.trim()),
+        None => return Err(format!("the label of '{}' must be in double quotes", name)),
+    };
+    if default.is_empty() {
+        return Err(format!("'{}' has no default value", name));
+    }
+    kind.validate(default).map_err(|e| format!("the default of '{}' is invalid: {}", name, e))?;
+    Ok(Declaration {
+        name: name.to_owned(),
+        kind,
+        label,
+        default: default.to_owned(),
+    })
+}
+
+/// Find the variables declared in the comments at the start of a theme, before its first rule. Every line of those comments that
+/// starts with [DECLARATION] declares one variable, and a malformed declaration is an error so that theme authors notice it
+pub fn declarations(css: &str) -> Result<Vec<Declaration>, String> {
+    let mut found = Vec::<Declaration>::new();
+    let mut rest = css.trim_start_matches('\u{feff}').trim_start();
+    while let Some(comment) = rest.strip_prefix("/*") {
+        let end = comment.find("*/").ok_or_else(|| "a comment at the start of the theme is never closed".to_owned())?;
+        for line in comment[..end].lines() {
+            let line = line.trim().trim_start_matches('*').trim_start();
+            if let Some(declaration) = line.strip_prefix(DECLARATION).filter(|rest| rest.starts_with(char::is_whitespace)) {
+                let declaration = parse_declaration(declaration)?;
+                if found.iter().any(|other| other.name == declaration.name) {
+                    return Err(format!("'{}' is declared more than once", declaration.name));
+                }
+                found.push(declaration);
+            }
+        }
+        rest = comment[end + 2..].trim_start();
+    }
+    Ok(found)
+}
+
+/// Get the value of every declared variable. Valid values from the config file are used first, then `ask` is called for the rest
+/// if someone can answer, and otherwise the declared defaults are used
+pub fn resolve(
+    declarations: &[Declaration],
+    saved: &BTreeMap<String, String>,
+    mut ask: Option<&mut dyn FnMut(&Declaration) -> String>,
+) -> Resolved {
+    let mut resolved = Resolved::default();
+    for declaration in declarations {
+        let saved = saved.get(&declaration.name).filter(|value| declaration.kind.validate(value).is_ok());
+        let value = match (saved, ask.as_mut()) {
+            (Some(value), _) => value.clone(),
+            (None, Some(ask)) => {
+                resolved.asked.push(declaration.name.clone());
+                ask(declaration)
+            }
+            (None, None) => {
+                resolved.defaulted.push(declaration.name.clone());
+                declaration.default.clone()
+            }
+        };
+        resolved.values.insert(declaration.name.clone(), value);
+    }
+    resolved
+}
+
+/// Add a `:root` rule setting every variable to the end of a theme. It goes at the end so that it wins over the defaults that the
+/// theme sets for itself, and so that it can't come before the theme's `@import` rules
+pub fn apply(css: &str, declarations: &[Declaration], values: &BTreeMap<String, String>) -> String {
+    if declarations.is_empty() {
+        return css.to_owned();
+    }
+    let properties = declarations
+        .iter()
+        .map(|declaration| {
+            let value = values.get(&declaration.name).unwrap_or(&declaration.default);
+            format!("  --{}: {};\n", declaration.name, declaration.kind.css(value))
+        })
+        .collect::<String>();
+    format!("{}\n\n:root {{\n{}}}\n", css.trim_end(), properties)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THEME: &str = "/* My theme\n * @var accent color \"Accent color\" #ff0066\n * @var radius number \"Corner radius\" 4px\n */\n/* @var font text \"Font\" Comic Sans */\nbody { color: var(--accent); }\n/* @var ignored color \"Not leading\" red */";
+
+    #[test]
+    pub fn parse_declarations() {
+        let found = declarations(THEME).unwrap();
+        assert_eq!(found.iter().map(|d| d.name.as_str()).collect::<Vec<_>>(), vec!["accent", "radius", "font"]);
+        assert_eq!(
+            found[0],
+            Declaration {
+                name: "accent".into(),
+                kind: Kind::Color,
+                label: "Accent color".into(),
+                default: "#ff0066".into(),
+            }
+        );
+        assert_eq!(found[2].default, "Comic Sans");
+        assert!(declarations("body {}").unwrap().is_empty());
+
+        assert!(declarations("/* @var accent colour \"Accent\" red */").is_err());
+        assert!(declarations("/* @var accent color Accent red */").is_err());
+        assert!(declarations("/* @var accent color \"Accent\" #ff00 */").is_ok());
+        assert!(declarations("/* @var accent color \"Accent\" #ff0 ; } */").is_err());
+        assert!(declarations("/* @var a number \"A\" 1\n@var a number \"A\" 2 */").is_err());
+        assert!(declarations("/* @var a number \"A\" 1").is_err());
+    }
+
+    #[test]
+    pub fn validate_values() {
+        for color in ["#fff", "#ff006680", "rgb(255, 0, 102)", "hsl(330 100% 50% / 0.5)", "hotpink"] {
+            assert_eq!(Kind::Color.validate(color), Ok(()), "{}", color);
+        }
+        for color in ["#ffff0", "rgb(0;}", "red; } body { x: y", "url(x)"] {
+            assert!(Kind::Color.validate(color).is_err(), "{}", color);
+        }
+        assert!(Kind::Number.validate("1.5em").is_ok() && Kind::Number.validate("-2").is_ok() && Kind::Number.validate("50%").is_ok());
+        assert!(Kind::Number.validate("px").is_err() && Kind::Number.validate("1;}").is_err());
+        assert!(Kind::Text.validate("a \"quoted\" name").is_ok() && Kind::Text.validate("two\nlines").is_err());
+    }
+
+    #[test]
+    pub fn prompt_flow() {
+        let found = declarations(THEME).unwrap();
+        let mut saved = BTreeMap::new();
+        saved.insert("accent".to_owned(), "#00ff00".to_owned());
+        saved.insert("radius".to_owned(), "not a number".to_owned());
+
+        //Only the variables without a valid saved value are asked for
+        let mut questions = Vec::new();
+        let mut ask = |declaration: &Declaration| {
+            questions.push(declaration.label.clone());
+            format!("{} answer", declaration.name)
+        };
+        let resolved = resolve(&found, &saved, Some(&mut ask));
+        assert_eq!(questions, vec!["Corner radius", "Font"]);
+        assert_eq!(resolved.asked, vec!["radius", "font"]);
+        assert_eq!(resolved.values["accent"], "#00ff00");
+        assert_eq!(resolved.values["font"], "font answer");
+
+        //Without anyone to ask, the defaults are used and reported
+        let resolved = resolve(&found, &saved, None);
+        assert_eq!((resolved.asked.len(), resolved.defaulted.clone()), (0, vec!["radius".to_owned(), "font".to_owned()]));
+        let css = apply(THEME, &found, &resolved.values);
+        assert!(css.starts_with(THEME));
+        assert!(css.ends_with(":root {\n  --accent: #00ff00;\n  --radius: 4px;\n  --font: \"Comic Sans\";\n}\n"));
+        assert_eq!(apply("body {}", &[], &resolved.values), "body {}");
+    }
+}
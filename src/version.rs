@@ -0,0 +1,93 @@
+//! The `version` module compares the versions in the names of Discord's `app-<version>` folders. Discord's versions are usually semantic
+//! versions, but some channels have shipped folders like `app-1.0.9013.1` with four components, which are compared component by
+//! component instead of being rejected
+
+use std::cmp::Ordering;
+
+/// The prefix of the folders that each installed Discord version is in
+pub const APP_PREFIX: &str = "app-";
+
+/// Compare two version strings. Semantic versions are compared by their rules, and anything else is split on dots and compared one
+/// component at a time, numerically if both components are numbers and lexicographically if they aren't. A version that runs out of
+/// components first is the smaller one
+pub fn compare(version: &str, other: &str) -> Ordering {
+    if let (Ok(version), Ok(other)) = (semver::Version::parse(version), semver::Version::parse(other)) {
+        return version.cmp(&other);
+    }
+    let mut components = version.split('.');
+    let mut others = other.split('.');
+    loop {
+        match (components.next(), others.next()) {
+            (Some(component), Some(other)) => match compare_component(component, other) {
+                Ordering::Equal => continue,
+                ordering => return ordering,
+            },
+            (Some(_), None) => return Ordering::Greater,
+            (None, Some(_)) => return Ordering::Less,
+            (None, None) => return Ordering::Equal,
+        }
+    }
+}
+
+/// Compare one component of a version, ignoring leading zeros in numbers so that numbers of any length compare by their value
+fn compare_component(component: &str, other: &str) -> Ordering {
+    let numeric = |text: &str| !text.is_empty() && text.bytes().all(|c| c.is_ascii_digit());
+    match numeric(component) && numeric(other) {
+        true => {
+            let (component, other) = (trim_zeros(component), trim_zeros(other));
+            component.len().cmp(&other.len()).then_with(|| component.cmp(other))
+        }
+        false => component.cmp(other),
+    }
+}
+
+/// Remove the leading zeros of a number, keeping a single zero
+fn trim_zeros(number: &str) -> &str {
+    match number.trim_start_matches('0') {
+        "" => "0",
+        trimmed => trimmed,
+    }
+}
+
+/// Pick the folder of the newest Discord version out of the names of the folders in Discord's root folder. Folders that start with
+/// [APP_PREFIX] are newer than any other folder, and `None` is returned if there are no folders
+pub fn newest<'a>(names: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    names.into_iter().max_by(|name, next| match (name.strip_prefix(APP_PREFIX), next.strip_prefix(APP_PREFIX)) {
+        (Some(version), Some(next_version)) => compare(version, next_version),
+        (Some(_), None) => Ordering::Greater, //If the next folder doesn't start with the prefix, this one is automatically newer
+        (None, Some(_)) => Ordering::Less,    //Same but in reverse
+        (None, None) => Ordering::Equal,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn three_and_four_components() {
+        assert_eq!(compare("1.0.9013", "1.0.9012"), Ordering::Greater);
+        assert_eq!(compare("1.0.9013.1", "1.0.9013"), Ordering::Greater);
+        assert_eq!(compare("1.0.9013.1", "1.0.9014"), Ordering::Less);
+        assert_eq!(compare("1.0.9013.10", "1.0.9013.9"), Ordering::Greater);
+        assert_eq!(compare("1.0.9013.1", "1.0.9013.1"), Ordering::Equal);
+        assert_eq!(compare("1.0.0-beta", "1.0.0"), Ordering::Less);
+    }
+
+    #[test]
+    pub fn leading_zeros_and_words() {
+        assert_eq!(compare("1.0.09013.1", "1.0.9013.1"), Ordering::Equal);
+        assert_eq!(compare("1.0.0100.0", "1.0.99.0"), Ordering::Greater);
+        assert_eq!(compare("1.0.9013.b", "1.0.9013.a"), Ordering::Greater);
+        assert_eq!(compare("1.0.x.1", "1.0.9013.1"), Ordering::Greater);
+    }
+
+    #[test]
+    pub fn mixed_folders() {
+        let names = ["packages", "app-1.0.9012", "app-1.0.9013.1", "app-1.0.9013", "Update.exe"];
+        assert_eq!(newest(names.iter().copied()), Some("app-1.0.9013.1"));
+        assert_eq!(newest(["packages", "app-0.0.309", "app-0.0.310"].iter().copied()), Some("app-0.0.310"));
+        assert_eq!(newest(["packages"].iter().copied()), Some("packages"));
+        assert_eq!(newest(std::iter::empty()), None);
+    }
+}
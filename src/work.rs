@@ -0,0 +1,126 @@
+//! The `work` module remembers where the injection is in the patched archive between runs, so that applying a theme again to an
+//! archive that hasn't changed since the last patch can replace the injection without searching the patched file for it. The state is
+//! kept next to the manifest and is thrown away as soon as the archive's hash doesn't match, like after a Discord update
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    patch,
+    site::{INJECTION_MARKER, INJECTION_POINT},
+};
+
+/// The name of the file in a version's backup folder that the [Work] is saved in
+pub const WORK_NAME: &str = "work.json";
+
+/// The text that every injection starts with, including the line break that it is indented onto
+const INJECTION_START: &str = "\n    mainWindow.webContents.on('dom-ready'";
+
+/// The text that every injection ends with, including the line break after it
+const INJECTION_END: &str = "});mainWindow.webContents.\n    ";
+
+/// The `Work` struct is what was found in the archive that the last apply patched
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Work {
+    /// The SHA-256 hash of the patched archive, which must match for the rest of the state to be used
+    pub archive_hash: String,
+    /// The path of the patched javascript file inside of the archive
+    pub file: String,
+    /// The byte range of the injection in the patched file, including the line breaks around it
+    pub span: (usize, usize),
+    /// How long finding the injection took without this state, in microseconds
+    pub search_micros: u64,
+}
+
+impl Work {
+    /// Get the path of the state file in a version's backup folder
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join(WORK_NAME)
+    }
+
+    /// Load the state saved in a version's backup folder if it is for an archive with the given hash. State for another archive is
+    /// deleted, and state that can't be read is ignored because it can always be found again
+    pub fn load(dir: &Path, archive_hash: &str) -> Option<Self> {
+        let work = serde_json::from_slice::<Self>(&fs::read(Self::path(dir)).ok()?).ok();
+        match work {
+            Some(work) if work.archive_hash == archive_hash => Some(work),
+            _ => {
+                let _ = fs::remove_file(Self::path(dir));
+                None
+            }
+        }
+    }
+
+    /// Save the state to a version's backup folder
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        fs::write(Self::path(dir), serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Find the injection in the contents of a patched file, returning the [Work] for it without a hash, or `None` unless there is
+    /// exactly one injection that starts and ends like the ones that this program makes
+    pub fn find(file: &str, js: &str) -> Option<Self> {
+        let start = js.find(INJECTION_START)?;
+        let end = start + js[start..].find(INJECTION_END)? + INJECTION_END.len();
+        Some(Self { archive_hash: String::new(), file: file.to_owned(), span: (start, end), search_micros: 0 })
+            .filter(|work| work.unpatched(js).is_some())
+    }
+
+    /// Remove the injection from the contents of the patched file using the saved span, along with the preload block at the top of the
+    /// file, returning `None` if the span doesn't hold the only injection in the file
+    pub fn unpatched(&self, js: &str) -> Option<String> {
+        let (start, end) = self.span;
+        let injection = js.get(start..end)?;
+        let valid = injection.starts_with(INJECTION_START)
+            && injection.ends_with(INJECTION_END)
+            && injection.matches(INJECTION_MARKER).count() == js.matches(INJECTION_MARKER).count()
+            && injection.matches(INJECTION_START).count() == 1;
+        let spliced = valid.then(|| format!("{}{}{}", &js[..start], INJECTION_POINT, &js[end..]))?;
+        patch::strip_preload(&spliced).map(|(unpatched, _)| unpatched)
+    }
+
+    /// Get how long finding the injection took without this state
+    pub fn search_time(&self) -> Duration {
+        Duration::from_micros(self.search_micros)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        patch,
+        schedule::Css,
+        testutil::MAIN_SCREEN_JS,
+    };
+
+    #[test]
+    pub fn reuse_span() {
+        let patched = patch::inject(MAIN_SCREEN_JS, &Css::from("a {}"), Some("let x = 1;"), None, None, patch::Strategy::ExecuteJs).unwrap();
+        let mut work = Work::find("app/mainScreen.js", &patched).unwrap();
+        assert_eq!(work.unpatched(&patched).as_deref(), Some(MAIN_SCREEN_JS));
+        let preloaded = patch::inject(MAIN_SCREEN_JS, &Css::from("a {}"), None, Some("early();"), None, patch::Strategy::ExecuteJs).unwrap();
+        let preloaded_work = Work::find("app/mainScreen.js", &preloaded).unwrap();
+        assert_eq!(preloaded_work.unpatched(&preloaded).as_deref(), Some(MAIN_SCREEN_JS));
+        assert_eq!(Work::find("app/mainScreen.js", MAIN_SCREEN_JS), None);
+
+        //A span that no longer holds the injection is never used
+        assert_eq!(work.unpatched(&format!(" {}", patched)), None);
+        assert_eq!(work.unpatched(&patched[..work.span.1 - 1]), None);
+        let twice = patched.replacen("mainWindow.webContents.send", &patch::injection("\"b {}\"", None), 1);
+        assert_eq!(work.unpatched(&twice), None);
+
+        //State is only loaded for the archive it was saved for
+        let dir = tempfile::tempdir().unwrap();
+        work.archive_hash = "a".repeat(64);
+        work.save(dir.path()).unwrap();
+        assert_eq!(Work::load(dir.path(), &"a".repeat(64)), Some(work));
+        assert_eq!(Work::load(dir.path(), &"b".repeat(64)), None);
+        assert!(!Work::path(dir.path()).exists());
+    }
+}
@@ -0,0 +1,136 @@
+// Writes the .asar fixtures in this folder the way `asar pack` from @electron/asar 3.2 does: the header is a chromium pickle with the
+// JSON string of the file tree, file entries list `size`, `offset` (or `unpacked`), `integrity`, and `executable` in that order, and
+// file data follows the header in the order that the files were inserted. Run it with `node generate.js` from this folder
+'use strict';
+
+const crypto = require('crypto');
+const fs = require('fs');
+const path = require('path');
+
+const BLOCK_SIZE = 4 * 1024 * 1024;
+
+function hash(data) {
+  return crypto.createHash('sha256').update(data).digest('hex');
+}
+
+// Like getFileIntegrity, which hashes every full block and then the rest of the file, even if the rest is empty
+function integrity(data) {
+  const blocks = [];
+  let start = 0;
+  for (; start + BLOCK_SIZE <= data.length; start += BLOCK_SIZE) {
+    blocks.push(hash(data.subarray(start, start + BLOCK_SIZE)));
+  }
+  blocks.push(hash(data.subarray(start)));
+  return { algorithm: 'SHA256', hash: hash(data), blockSize: BLOCK_SIZE, blocks };
+}
+
+// Like Pickle.writeString, which writes the length and the string padded to 4 bytes with zeros after the payload size
+function pickleString(text) {
+  const bytes = Buffer.from(text, 'utf8');
+  const padded = Math.ceil(bytes.length / 4) * 4;
+  const buf = Buffer.alloc(8 + padded);
+  buf.writeUInt32LE(4 + padded, 0);
+  buf.writeInt32LE(bytes.length, 4);
+  bytes.copy(buf, 8);
+  return buf;
+}
+
+// Like Pickle.writeUInt32
+function pickleUInt32(value) {
+  const buf = Buffer.alloc(8);
+  buf.writeUInt32LE(4, 0);
+  buf.writeUInt32LE(value, 4);
+  return buf;
+}
+
+// Pack entries given in the order that `asar pack` walks them, which is a directory before the files in it. Each entry is
+// [path, contents] for a file, [path] for a directory, and [path, contents, options] for an executable or unpacked file. The data is
+// written in the order of the entries unless `order` lists the paths of the packed files in another order
+function pack(name, entries, order) {
+  const header = { files: {} };
+  const packed = [];
+  const node = (p) => p.split('/').reduce((dir, part) => dir.files[part] || (dir.files[part] = {}), header);
+
+  for (const [p, contents, options = {}] of entries) {
+    const entry = node(p);
+    if (contents === undefined) {
+      entry.files = entry.files || {};
+      continue;
+    }
+    const bytes = Buffer.from(contents, 'utf8');
+    entry.size = bytes.length;
+    if (options.unpacked) {
+      entry.unpacked = true;
+      entry.integrity = integrity(bytes);
+      continue;
+    }
+    entry.offset = '';
+    entry.integrity = integrity(bytes);
+    if (options.executable) {
+      entry.executable = true;
+    }
+    packed.push({ path: p, entry, bytes });
+  }
+
+  const data = [];
+  let offset = 0;
+  for (const file of order ? order.map((p) => packed.find((file) => file.path === p)) : packed) {
+    file.entry.offset = offset.toString();
+    data.push(file.bytes);
+    offset += file.bytes.length;
+  }
+
+  const headerBuf = pickleString(JSON.stringify(header));
+  fs.writeFileSync(path.join(__dirname, name), Buffer.concat([pickleUInt32(headerBuf.length), headerBuf, ...data]));
+}
+
+pack('basic.asar', [
+  ['app'],
+  ['app/index.js', "require('./lib/util.js').start();\n"],
+  ['app/lib'],
+  ['app/lib/util.js', 'exports.start = () => console.log("started");\n'],
+  ['app/style.css', 'body { color: red; }\n'],
+  ['package.json', '{"name":"fixture","main":"app/index.js"}\n'],
+]);
+
+pack('metadata.asar', [
+  ['bin'],
+  ['bin/run.sh', '#!/bin/sh\nexec ./discord\n', { executable: true }],
+  ['empty.txt', ''],
+  ['emptydir'],
+  ['native'],
+  ['native/addon.node', 'not really a native module', { unpacked: true }],
+  ['readme.md', '# metadata\n'],
+]);
+
+pack('names.asar', [
+  ['a b'],
+  ['a b/c "d".txt', 'quoted\n'],
+  ['x.js', 'x'],
+  ['ünïcode.js', 'module.exports = "ü";\n'],
+]);
+
+// The data is in a different order than the header lists the files, like an archive that another tool changed in place
+pack('shuffled.asar', [
+  ['a.js', 'first in the header\n'],
+  ['lib'],
+  ['lib/b.js', 'second\n'],
+  ['lib/c.js', 'third in the header, first in the data\n'],
+  ['z.js', 'last\n'],
+], ['lib/c.js', 'z.js', 'a.js', 'lib/b.js']);
+
+// Copies a fixture with `padding` more zero bytes after the header JSON and `jsonSize` as the declared size of the JSON string, like a
+// third-party packer that counted the JSON wrong. The data still follows the header, so only the JSON size field is wrong
+function misdeclare(name, from, jsonSize, padding) {
+  const original = fs.readFileSync(path.join(__dirname, from));
+  const headerSize = original.readUInt32LE(4);
+  const copy = Buffer.concat([original.subarray(0, 8 + headerSize), Buffer.alloc(padding), original.subarray(8 + headerSize)]);
+  copy.writeUInt32LE(headerSize + padding, 4);
+  copy.writeUInt32LE(headerSize + padding - 4, 8);
+  copy.writeUInt32LE(jsonSize, 12);
+  fs.writeFileSync(path.join(__dirname, name), copy);
+}
+
+const basicJson = fs.readFileSync(path.join(__dirname, 'basic.asar')).readUInt32LE(12);
+misdeclare('short-json-size.asar', 'basic.asar', basicJson - 40, 0);
+misdeclare('long-json-size.asar', 'basic.asar', basicJson + 24, 32);
@@ -0,0 +1,162 @@
+"use strict";
+
+Object.defineProperty(exports, "__esModule", {
+  value: true
+});
+exports.init = init;
+exports.getMainWindowId = getMainWindowId;
+exports.webContentsSend = webContentsSend;
+
+var _electron = require("electron");
+var _path = _interopRequireDefault(require("path"));
+var _url = _interopRequireDefault(require("url"));
+var _Backoff = _interopRequireDefault(require("../common/Backoff"));
+var _appSettings = require("./appSettings");
+var _appFeatures = require("./appFeatures");
+var _mainScreenPreload = require("./mainScreenPreload");
+var paths = _interopRequireWildcard(require("../common/paths"));
+var _securityUtils = require("../common/securityUtils");
+
+function _interopRequireDefault(obj) { return obj && obj.__esModule ? obj : { default: obj }; }
+
+const settings = (0, _appSettings.getSettings)();
+const connectionBackoff = new _Backoff.default(1000, 20000);
+const DISCORD_NAMESPACE = 'DISCORD_';
+const MIN_WIDTH = settings.get('MIN_WIDTH', 940);
+const MIN_HEIGHT = settings.get('MIN_HEIGHT', 500);
+const ACCOUNT_GREY = '#2f3136';
+
+let mainWindow = null;
+let mainWindowId = null;
+let lastPageLoadFailed = false;
+
+function getMainWindowId() {
+  return mainWindowId;
+}
+
+function webContentsSend(...args) {
+  if (mainWindow != null && mainWindow.webContents != null) {
+    const [event, ...options] = args;
+    
+    
+    
+    mainWindow.webContents.on('dom-ready', () => {
+        mainWindow.webContents.executeJavaScript(`
+            let CSS_INJECTION_USER_CSS = "body { color: red; }";  
+            const style = document.createElement('style');  
+            style.innerHTML = CSS_INJECTION_USER_CSS;  
+            document.head.appendChild(style);  
+              
+            //JS_SCRIPT_BEGIN 
+            console.log(1); 
+            //JS_SCRIPT_END 
+        `);
+    });mainWindow.webContents.
+    on('dom-ready', () => {
+        mainWindow.webContents.executeJavaScript(`
+            let CSS_INJECTION_USER_CSS = "body { color: red; }";  
+            const style = document.createElement('style');  
+            style.innerHTML = CSS_INJECTION_USER_CSS;  
+            document.head.appendChild(style);  
+              
+            //JS_SCRIPT_BEGIN 
+            console.log(1); 
+            //JS_SCRIPT_END 
+        `);
+    });mainWindow.webContents.
+    on('dom-ready', () => {
+        mainWindow.webContents.executeJavaScript(`
+            let CSS_INJECTION_USER_CSS = "body { color: red; }";  
+            const style = document.createElement('style');  
+            style.innerHTML = CSS_INJECTION_USER_CSS;  
+            document.head.appendChild(style);  
+              
+            //JS_SCRIPT_BEGIN 
+            console.log(1); 
+            //JS_SCRIPT_END 
+        `);
+    });mainWindow.webContents.
+    send(`${DISCORD_NAMESPACE}${event}`, ...options);
+  }
+}
+
+function launchMainAppWindow(isVisible) {
+  if (mainWindow) {
+    mainWindow.destroy();
+  }
+
+  const mainWindowOptions = {
+    title: 'Discord',
+    backgroundColor: ACCOUNT_GREY,
+    width: MIN_WIDTH,
+    height: MIN_HEIGHT,
+    minWidth: MIN_WIDTH,
+    minHeight: MIN_HEIGHT,
+    transparent: false,
+    frame: false,
+    resizable: true,
+    show: isVisible,
+    webPreferences: {
+      blinkFeatures: 'EnumerateDevices,AudioOutputDevices',
+      nodeIntegration: false,
+      preload: _path.default.join(__dirname, 'mainScreenPreload.js'),
+      nativeWindowOpen: true,
+      enableRemoteModule: false,
+      spellcheck: true,
+      contextIsolation: true
+    }
+  };
+
+  mainWindow = new _electron.BrowserWindow(mainWindowOptions);
+  mainWindowId = mainWindow.id;
+  global.mainWindowId = mainWindowId;
+  mainWindow.setMenuBarVisibility(false);
+
+  mainWindow.webContents.on('new-window', (e, windowURL, frameName, disposition, options) => {
+    e.preventDefault();
+    if (frameName.startsWith(DISCORD_NAMESPACE) && (0, _securityUtils.checkUrlOriginMatches)(windowURL, WEBAPP_ENDPOINT)) {
+      mainWindow.webContents.send(`${DISCORD_NAMESPACE}POPOUT_OPEN`, frameName);
+    } else {
+      (0, _securityUtils.saferShellOpenExternal)(windowURL).catch(err => {
+        console.error('Error opening external url', windowURL, err);
+      });
+    }
+  });
+
+  mainWindow.webContents.on('did-fail-load', (e, errCode, errDesc, validatedUrl) => {
+    if (errCode === -3) return;
+    lastPageLoadFailed = true;
+    connectionBackoff.fail(() => {
+      loadMainPage();
+    });
+  });
+
+  mainWindow.webContents.on('did-finish-load', () => {
+    lastPageLoadFailed = false;
+    connectionBackoff.succeed();
+  });
+
+  mainWindow.on('focus', () => {
+    webContentsSend('MAIN_WINDOW_FOCUS');
+  });
+
+  mainWindow.on('blur', () => {
+    webContentsSend('MAIN_WINDOW_BLUR');
+  });
+
+  loadMainPage();
+}
+
+function loadMainPage() {
+  lastPageLoadFailed = false;
+  mainWindow.loadURL(URL_TO_LOAD);
+}
+
+function init() {
+  _electron.app.on('second-instance', () => {
+    if (mainWindow != null) {
+      mainWindow.show();
+    }
+  });
+  launchMainAppWindow(false);
+}
@@ -0,0 +1,71 @@
+'use strict';
+
+Object.defineProperty(exports, "__esModule", {
+  value: true
+});
+exports.init = init;
+exports.getMainWindowId = getMainWindowId;
+
+var _electron = require('electron');
+
+var _path = require('path');
+
+var _path2 = _interopRequireDefault(_path);
+
+function _interopRequireDefault(obj) { return obj && obj.__esModule ? obj : { default: obj }; }
+
+var DISCORD_NAMESPACE = 'DISCORD_';
+var MIN_WIDTH = 940;
+var MIN_HEIGHT = 500;
+
+var mainWindow = null;
+
+function webContentsSend() {
+  if (mainWindow != null && mainWindow.webContents != null) {
+    var _mainWindow$webContents;
+
+    for (var _len = arguments.length, args = Array(_len), _key = 0; _key < _len; _key++) {
+      args[_key] = arguments[_key];
+    }
+
+    var event = args[0],
+        options = args.slice(1);
+    (_mainWindow$webContents = mainWindow.webContents).send.apply(_mainWindow$webContents, ['' + DISCORD_NAMESPACE + event].concat(options));
+  }
+}
+
+function launchMainAppWindow(isVisible) {
+  var mainWindowOptions = {
+    title: 'Discord',
+    backgroundColor: '#2f3136',
+    width: MIN_WIDTH,
+    height: MIN_HEIGHT,
+    minWidth: MIN_WIDTH,
+    minHeight: MIN_HEIGHT,
+    transparent: false,
+    frame: false,
+    resizable: true,
+    show: isVisible,
+    webPreferences: {
+      preload: _path2.default.join(__dirname, 'mainScreenPreload.js')
+    }
+  };
+
+  mainWindow = new _electron.BrowserWindow(mainWindowOptions);
+
+  mainWindow.webContents.on('did-finish-load', function () {
+    webContentsSend('WINDOW_READY');
+  });
+
+  mainWindow.on('focus', function () {
+    webContentsSend('MAIN_WINDOW_FOCUS');
+  });
+}
+
+function init() {
+  launchMainAppWindow(true);
+}
+
+function getMainWindowId() {
+  return mainWindow != null ? mainWindow.id : 0;
+}
@@ -0,0 +1,5 @@
+"use strict";Object.defineProperty(exports,"__esModule",{value:!0}),exports.getMainWindowId=getMainWindowId,exports.init=init;var _electron=require("electron"),_path=_interopRequireDefault(require("path"));function _interopRequireDefault(e){return e&&e.__esModule?e:{default:e}}
+const DISCORD_NAMESPACE="DISCORD_",MIN_WIDTH=940,MIN_HEIGHT=500;let mainWindow=null;
+function webContentsSend(e,...n){null!=mainWindow&&null!=mainWindow.webContents&&mainWindow.webContents.send(`${DISCORD_NAMESPACE}${e}`,...n)}
+function launchMainAppWindow(e){const n={title:"Discord",backgroundColor:"#313338",width:MIN_WIDTH,height:MIN_HEIGHT,minWidth:MIN_WIDTH,minHeight:MIN_HEIGHT,frame:!1,resizable:!0,show:e,webPreferences:{preload:_path.default.join(__dirname,"mainScreenPreload.js"),contextIsolation:!0,sandbox:!1}};mainWindow=new _electron.BrowserWindow(n),mainWindow.webContents.on("did-finish-load",()=>{webContentsSend("WINDOW_READY")}),mainWindow.on("focus",()=>{webContentsSend("MAIN_WINDOW_FOCUS")})}
+function init(){launchMainAppWindow(!0)}function getMainWindowId(){return null!=mainWindow?mainWindow.id:0}